@@ -22,8 +22,8 @@ fn field_parse(field: &Field) -> proc_macro2::TokenStream {
                 _ => {
                     quote!(
                         {
-                            let mut v = Vec::with_capacity((offset + len - mp4.reader.buffer.stream_position()?) as usize / std::mem::size_of::<#ty>());
-                            while (offset + len - mp4.reader.buffer.stream_position()?) > 0 {
+                            let mut v = Vec::with_capacity((offset + len).saturating_sub(mp4.reader.buffer.stream_position()?) as usize / std::mem::size_of::<#ty>());
+                            while mp4.reader.buffer.stream_position()? < offset + len {
                                 v.push(<#ty as crate::Parse>::parse(mp4)?);
                             }
                             v
@@ -39,7 +39,10 @@ fn field_parse(field: &Field) -> proc_macro2::TokenStream {
                     mp4.reader
                         .read_bytes_dyn((offset + len).saturating_sub(current_pos) as usize)?,
                 )
-                .unwrap()
+                .map_err(|_| crate::Mp4Error::InvalidUtf8 {
+                    offset: current_pos,
+                    path: mp4.atom_path(),
+                })?
             })
         }
         _ => {
@@ -49,6 +52,20 @@ fn field_parse(field: &Field) -> proc_macro2::TokenStream {
     }
 }
 
+fn field_serialize(field: &Field) -> proc_macro2::TokenStream {
+    let name = &field.ident;
+
+    match &field.ty {
+        Type::Path(TypePath { path, .. })
+            if path.segments.last().unwrap().ident == "Vec"
+                && matches!(get_generic(path), Some(Type::Path(TypePath { path, .. })) if path.is_ident("u8")) =>
+        {
+            quote!(std::io::Write::write_all(&mut body, &self.#name)?;)
+        }
+        _ => quote!(crate::Serialize::serialize(&self.#name, mp4, &mut body)?;),
+    }
+}
+
 #[proc_macro_attribute]
 pub fn mp4_atom(_attr: TokenStream, item: TokenStream) -> TokenStream {
     let x = item.clone();
@@ -62,6 +79,7 @@ pub fn mp4_atom(_attr: TokenStream, item: TokenStream) -> TokenStream {
         .collect::<Vec<_>>();
 
     let struct_field_parse = item_struct.fields.iter().map(field_parse);
+    let struct_field_serialize = item_struct.fields.iter().map(field_serialize);
 
     let name = item_struct.ident;
 
@@ -70,31 +88,36 @@ pub fn mp4_atom(_attr: TokenStream, item: TokenStream) -> TokenStream {
         #input
 
         impl crate::Parse for #name {
-            fn parse<R: std::io::BufRead + std::io::Seek>(mp4: &mut crate::Mp4<'_, R>) -> std::io::Result<Self>
+            fn parse<R: std::io::BufRead + std::io::Seek>(mp4: &mut crate::Mp4<'_, R>) -> crate::Mp4Result<Self>
                 where Self: Sized {
                 let offset = mp4.reader.buffer.stream_position()?;
-                let len = mp4.read_atom_len()?;
-                mp4.expect_header(Self::HEADER)?;
-
-                #(
-                    let #struct_field_names = #struct_field_parse;
-                )*
-
-                if offset != mp4.reader.buffer.stream_position()?.saturating_sub(len as u64) {
-                    dbg!(#(
-                        &#struct_field_names,
-                    )*);
-                }
+                let len = mp4.expect_atom(Self::HEADER)?;
 
-                assert_eq!(offset, mp4.reader.buffer.stream_position()?.saturating_sub(len as u64));
-
-                Ok(Self {
+                mp4.in_atom(offset, len, Self::HEADER, |mp4| {
                     #(
-                        #struct_field_names,
+                        let #struct_field_names = #struct_field_parse;
                     )*
+
+                    Ok(Self {
+                        #(
+                            #struct_field_names,
+                        )*
+                    })
                 })
             }
         }
+
+        impl crate::Serialize for #name {
+            fn serialize<R: std::io::BufRead + std::io::Seek, W: std::io::Write>(&self, mp4: &mut crate::Mp4<'_, R>, writer: &mut W) -> crate::Mp4Result<()> {
+                let mut body = Vec::new();
+
+                #(
+                    #struct_field_serialize
+                )*
+
+                crate::write_atom(writer, Self::HEADER, &body)
+            }
+        }
     ).into()
 }
 
@@ -108,6 +131,38 @@ fn get_generic(path: &Path) -> Option<&Type> {
     }
 }
 
+enum ChildKind<'a> {
+    Vec(&'a Type),
+    Option(&'a Type),
+    Required(&'a Type),
+}
+
+/// Determines how a container field is populated and the type of atom it
+/// refers to, e.g. `Vec<Reference<Trak>>` is every `Trak` child
+fn child_kind(field: &Field) -> ChildKind<'_> {
+    fn reference_generic(ty: &Type) -> &Type {
+        get_generic(if let Type::Path(TypePath { path, .. }) = ty {
+            path
+        } else {
+            panic!()
+        })
+        .unwrap()
+    }
+
+    match &field.ty {
+        Type::Path(TypePath { path, .. }) if path.segments.last().unwrap().ident == "Vec" => {
+            ChildKind::Vec(reference_generic(get_generic(path).unwrap()))
+        }
+        Type::Path(TypePath { path, .. }) if path.segments.last().unwrap().ident == "Option" => {
+            ChildKind::Option(reference_generic(get_generic(path).unwrap()))
+        }
+        Type::Path(TypePath { path, .. }) if path.segments.last().unwrap().ident == "Reference" => {
+            ChildKind::Required(get_generic(path).unwrap())
+        }
+        _ => panic!("expected reference, vec, or option"),
+    }
+}
+
 #[proc_macro_attribute]
 pub fn mp4_container_atom(_attr: TokenStream, item: TokenStream) -> TokenStream {
     let item_struct = parse_macro_input!(item as ItemStruct);
@@ -122,102 +177,213 @@ pub fn mp4_container_atom(_attr: TokenStream, item: TokenStream) -> TokenStream
         .iter()
         .map(|f| &f.ident)
         .collect::<Vec<_>>();
-    let struct_field_types = item_struct.fields.iter().map(|f| &f.ty).collect::<Vec<_>>();
     // let struct_field_vis = item_struct.fields.iter().map(|f| &f.vis);
 
-    let struct_field_search = item_struct.fields.iter().map(|field| match &field.ty {
-        Type::Path(TypePath { path, .. }) if path.segments.last().unwrap().ident == "Vec" => {
-            let generic = get_generic(path).unwrap();
-            let ref_generic = get_generic(if let Type::Path(TypePath { path, .. }) = generic {
-                path
-            } else {
-                panic!()
-            })
-            .unwrap();
-
-            quote!(
-                self.unparsed_atoms.drain_filter(|atom|{
-                    mp4.jump_to(atom.offset).unwrap();
-                    mp4.peek_header().unwrap() == <#ref_generic>::HEADER
-                }).map(|atom| atom.into_ref::<#ref_generic>()).collect()
-            )
+    let internal_field_types = item_struct.fields.iter().map(|field| {
+        let ty = &field.ty;
+        match child_kind(field) {
+            ChildKind::Required(..) => quote!(Option<#ty>),
+            ChildKind::Vec(..) | ChildKind::Option(..) => quote!(#ty),
         }
-        Type::Path(TypePath { path, .. }) if path.segments.last().unwrap().ident == "Option" => {
-            let generic = get_generic(path).unwrap();
-            let ref_generic = get_generic(if let Type::Path(TypePath { path, .. }) = generic {
-                path
-            } else {
-                panic!()
-            })
-            .unwrap();
+    });
 
+    let struct_field_accessors = item_struct.fields.iter().map(|field| {
+        let name = &field.ident;
+        let ty = &field.ty;
+
+        let search = |generic: &Type| {
             quote!(
-                self.unparsed_atoms.drain_filter(|atom|{
-                    mp4.jump_to(atom.offset).unwrap();
-                    mp4.peek_header().unwrap() == <#ref_generic>::HEADER
-                }).map(|atom| atom.into_ref::<#ref_generic>()).next()
+                self.unparsed_atoms
+                    .drain_filter(|atom| atom.header == <#generic>::HEADER)
+                    .map(|atom| atom.into_ref::<#generic>())
             )
+        };
+
+        match child_kind(field) {
+            ChildKind::Vec(generic) => {
+                let search = search(generic);
+                quote!(
+                    pub fn #name<R: std::io::BufRead + std::io::Seek>(&mut self, _mp4: &mut Mp4<'_, R>) -> &#ty {
+                        match self.__internal.#name {
+                            InternalElement::Searched(ref v) => v,
+                            InternalElement::NotSearched => {
+                                self.__internal.#name = InternalElement::Searched(#search.collect());
+                                self.#name(_mp4)
+                            },
+                        }
+                    }
+                )
+            }
+            ChildKind::Option(generic) => {
+                let search = search(generic);
+                quote!(
+                    pub fn #name<R: std::io::BufRead + std::io::Seek>(&mut self, _mp4: &mut Mp4<'_, R>) -> &#ty {
+                        match self.__internal.#name {
+                            InternalElement::Searched(ref v) => v,
+                            InternalElement::NotSearched => {
+                                self.__internal.#name = InternalElement::Searched(#search.next());
+                                self.#name(_mp4)
+                            },
+                        }
+                    }
+                )
+            }
+            ChildKind::Required(generic) => {
+                let search = search(generic);
+                quote!(
+                    pub fn #name<R: std::io::BufRead + std::io::Seek>(&mut self, _mp4: &mut Mp4<'_, R>) -> crate::Mp4Result<&#ty> {
+                        match self.__internal.#name {
+                            InternalElement::Searched(Some(ref v)) => Ok(v),
+                            InternalElement::Searched(None) => Err(crate::Mp4Error::MissingAtom {
+                                offset: self.offset,
+                                path: self.path.clone(),
+                                header: <#generic>::HEADER,
+                            }),
+                            InternalElement::NotSearched => {
+                                self.__internal.#name = InternalElement::Searched(#search.next());
+                                self.#name(_mp4)
+                            },
+                        }
+                    }
+                )
+            }
         }
-        Type::Path(TypePath { path, .. }) if path.segments.last().unwrap().ident == "Reference" => {
-            let generic = get_generic(path).unwrap();
-            quote!(
-                self.unparsed_atoms.drain_filter(|atom|{
-                    mp4.jump_to(atom.offset).unwrap();
-                    mp4.peek_header().unwrap() == <#generic>::HEADER
-                }).map(|atom| atom.into_ref::<#generic>()).next().unwrap()
-            )
+    });
+
+    let child_headers = item_struct.fields.iter().map(|field| match child_kind(field) {
+        ChildKind::Vec(generic) | ChildKind::Option(generic) | ChildKind::Required(generic) => {
+            quote!(<#generic>::HEADER)
         }
-        _ => panic!("expected reference, vec, or option"),
     });
 
     quote!(
         #[derive(Debug, Clone)]
         struct #internal_name {
             #(
-                #struct_field_names: InternalElement<#struct_field_types>,
+                #struct_field_names: InternalElement<#internal_field_types>,
             )*
         }
 
         #[derive(Debug, Clone)]
         #vis struct #struct_name {
             unparsed_atoms: Vec<UnparsedAtom>,
+            /// Every child in file order, as it will be serialized
+            children: Vec<crate::ChildAtom>,
+            pub(crate) offset: u64,
+            pub(crate) path: crate::AtomPath,
+            header_len: u64,
             __internal: #internal_name,
         }
 
         impl #struct_name {
             #(
-                pub fn #struct_field_names<R: std::io::BufRead + std::io::Seek>(&mut self, mp4: &mut Mp4<'_, R>) -> &#struct_field_types {
-                    match self.__internal.#struct_field_names {
-                        InternalElement::Searched(ref v) => v,
-                        InternalElement::NotSearched => {
-                            self.__internal.#struct_field_names = InternalElement::Searched(#struct_field_search);
-                            self.#struct_field_names(mp4)
-                        },
-                    }
-                }
+                #struct_field_accessors
             )*
+
+            /// The children of the container that aren't among its known child
+            /// atoms, in file order
+            pub fn unknown_children(&self) -> Vec<UnparsedAtom> {
+                let known: &[crate::Header] = &[#(#child_headers),*];
+
+                self.children
+                    .iter()
+                    .filter_map(|child| match child {
+                        crate::ChildAtom::Source(atom) if !known.contains(&atom.header) => {
+                            Some(atom.clone())
+                        }
+                        _ => None,
+                    })
+                    .collect()
+            }
+
+            /// The first child of the container with the given header, known
+            /// or not
+            pub fn child(&self, header: crate::Header) -> Option<UnparsedAtom> {
+                self.children.iter().find_map(|child| match child {
+                    crate::ChildAtom::Source(atom) if atom.header == header => Some(atom.clone()),
+                    _ => None,
+                })
+            }
+
+            /// Replaces the child atom `child` with `atom`, which need not be
+            /// of the same type, when the container is serialized, returning
+            /// whether `child` is a child of the container
+            pub fn replace_child<P: crate::Parse, S: crate::Serialize, R: std::io::BufRead + std::io::Seek>(
+                &mut self,
+                child: Reference<P>,
+                atom: &S,
+                mp4: &mut Mp4<'_, R>,
+            ) -> crate::Mp4Result<bool> {
+                let mut bytes = Vec::new();
+                atom.serialize(mp4, &mut bytes)?;
+
+                Ok(crate::replace_child(&mut self.children, child.offset, Some(bytes)))
+            }
+
+            /// Leaves the child atom `child` out when the container is
+            /// serialized, returning whether `child` is a child of the
+            /// container
+            pub fn remove_child<P: crate::Parse>(&mut self, child: Reference<P>) -> bool {
+                crate::replace_child(&mut self.children, child.offset, None)
+            }
+
+            /// Adds `atom` after the other children when the container is
+            /// serialized
+            pub fn push_child<P: crate::Serialize, R: std::io::BufRead + std::io::Seek>(
+                &mut self,
+                atom: &P,
+                mp4: &mut Mp4<'_, R>,
+            ) -> crate::Mp4Result<()> {
+                let mut bytes = Vec::new();
+                atom.serialize(mp4, &mut bytes)?;
+                self.children.push(crate::ChildAtom::Serialized(bytes));
+
+                Ok(())
+            }
+        }
+
+        impl crate::Serialize for #struct_name {
+            fn serialize<R: std::io::BufRead + std::io::Seek, W: std::io::Write>(&self, mp4: &mut crate::Mp4<'_, R>, writer: &mut W) -> crate::Mp4Result<()> {
+                crate::write_container(mp4, writer, Self::HEADER, self.header_len, &self.children)
+            }
         }
 
         impl crate::Parse for #struct_name {
-            fn parse<R: std::io::BufRead + std::io::Seek>(mp4: &mut crate::Mp4<'_, R>) -> std::io::Result<Self>
+            fn parse<R: std::io::BufRead + std::io::Seek>(mp4: &mut crate::Mp4<'_, R>) -> crate::Mp4Result<Self>
                     where Self: Sized {
                 let offset = mp4.reader.buffer.stream_position()?;
-                let len = mp4.read_atom_len()?;
-                let header = Header(mp4.reader.read_bytes_const::<4>()?);
+                let len = mp4.expect_atom(Self::HEADER)?;
+                let header_len = mp4.reader.buffer.stream_position()? - offset;
 
-                let mut unparsed_atoms = Vec::new();
+                mp4.in_atom(offset, len, Self::HEADER, |mp4| {
+                    let mut unparsed_atoms = Vec::new();
 
-                while offset + len - mp4.reader.buffer.stream_position()? > 0 {
-                    unparsed_atoms.push(UnparsedAtom::parse(mp4)?);
-                }
+                    while mp4.reader.buffer.stream_position()? < offset + len {
+                        let atom = UnparsedAtom::parse(mp4)?;
 
-                Ok(Self {
-                    unparsed_atoms,
-                    __internal: #internal_name {
-                        #(
-                            #struct_field_names: InternalElement::NotSearched,
-                        )*
+                        if atom.offset + atom.len > offset + len {
+                            return Err(crate::Mp4Error::TruncatedAtom {
+                                offset: atom.offset,
+                                path: mp4.atom_path(),
+                                header: atom.header,
+                            });
+                        }
+
+                        unparsed_atoms.push(atom);
                     }
+
+                    Ok(Self {
+                        children: unparsed_atoms.iter().cloned().map(crate::ChildAtom::Source).collect(),
+                        unparsed_atoms,
+                        offset,
+                        path: mp4.atom_path(),
+                        header_len,
+                        __internal: #internal_name {
+                            #(
+                                #struct_field_names: InternalElement::NotSearched,
+                            )*
+                        }
+                    })
                 })
             }
         }
@@ -236,7 +402,18 @@ pub fn mp4_media_data_type_atom(_attr: TokenStream, item: TokenStream) -> TokenS
         .map(|f| &f.ident)
         .collect::<Vec<_>>();
 
-    let struct_field_parse = item_struct.fields.iter().map(field_parse);
+    // the first field of a media data type atom is its data format, which is
+    // stored in place of the atom type and so is taken from the atom header
+    let struct_field_parse = item_struct
+        .fields
+        .iter()
+        .enumerate()
+        .map(|(idx, field)| match idx {
+            0 => quote!(header.0),
+            _ => field_parse(field),
+        });
+    let struct_field_serialize = item_struct.fields.iter().skip(1).map(field_serialize);
+    let data_format = &struct_field_names[0];
 
     let name = item_struct.ident;
 
@@ -245,23 +422,35 @@ pub fn mp4_media_data_type_atom(_attr: TokenStream, item: TokenStream) -> TokenS
         #input
 
         impl crate::Parse for #name {
-            fn parse<R: std::io::BufRead + std::io::Seek>(mp4: &mut crate::Mp4<'_, R>) -> std::io::Result<Self>
+            fn parse<R: std::io::BufRead + std::io::Seek>(mp4: &mut crate::Mp4<'_, R>) -> crate::Mp4Result<Self>
                 where Self: Sized {
                 let offset = mp4.reader.buffer.stream_position()?;
-                let len = mp4.reader.read_u32()? as u64;
+                let (len, header) = mp4.read_atom_header()?;
 
-                #(
-                    let #struct_field_names = #struct_field_parse;
-                )*
-
-                assert_eq!(offset, mp4.reader.buffer.stream_position()?.saturating_sub(len as u64));
-
-                Ok(Self {
+                mp4.in_atom(offset, len, header, |mp4| {
                     #(
-                        #struct_field_names,
+                        let #struct_field_names = #struct_field_parse;
                     )*
+
+                    Ok(Self {
+                        #(
+                            #struct_field_names,
+                        )*
+                    })
                 })
             }
         }
+
+        impl crate::Serialize for #name {
+            fn serialize<R: std::io::BufRead + std::io::Seek, W: std::io::Write>(&self, mp4: &mut crate::Mp4<'_, R>, writer: &mut W) -> crate::Mp4Result<()> {
+                let mut body = Vec::new();
+
+                #(
+                    #struct_field_serialize
+                )*
+
+                crate::write_atom(writer, crate::Header(self.#data_format), &body)
+            }
+        }
     ).into()
 }
@@ -16,7 +16,7 @@ use std::{
 // use openh264::{decoder::Decoder, to_bitstream_with_001_be};
 
 use bitvec::{field::BitField, macros::internal::funty::Integral, slice::BitSlice};
-use mp4_parser::{Mdat, Moov, Mp4, Parse, SampleDescriptionTable};
+use mp4_parser::{Mp4, RootAtom, SampleDescriptionTable, SampleTable};
 
 use num_traits::{One, Pow, Signed, Unsigned};
 use sei::{SeiMessage, UserDataUnregistered};
@@ -1199,27 +1199,28 @@ fn main() -> io::Result<()> {
     let buffer =
         fs::File::open("Y2Mate.is - TRVE DATA demo-26hinlQTrys-360p-1658850169678.mp4").unwrap();
 
-    // ftyp 
-    mp4.skip_chunk()?;
-    let mut moov = Moov::parse(&mut mp4)?;
-    let mut mdat = Mdat::parse(&mut mp4)?;
+    let root = mp4.root()?;
+    let mut moov = root
+        .iter()
+        .find_map(|atom| match atom {
+            RootAtom::Moov(moov) => Some(*moov),
+            _ => None,
+        })
+        .unwrap()
+        .parse(&mut mp4)?;
     // let mut header = moov.movie_header(&mut mp4).parse(&mut mp4)?;
     let mut trak = moov.trak(&mut mp4)[0].parse(&mut mp4)?;
-    let track_id = trak.track_header(&mut mp4).parse(&mut mp4)?.track_id;
-    let mut mdia = trak.mdia(&mut mp4).parse(&mut mp4)?;
-    let mut media_header = mdia.mdhd(&mut mp4).parse(&mut mp4)?;
+    let track_id = trak.track_header(&mut mp4)?.parse(&mut mp4)?.track_id;
+    let mut mdia = trak.mdia(&mut mp4)?.parse(&mut mp4)?;
+    let mut media_header = mdia.mdhd(&mut mp4)?.parse(&mut mp4)?;
     let mut minf = mdia.minf(&mut mp4).unwrap().parse(&mut mp4)?;
     let mut dinf = minf.dinf(&mut mp4).unwrap().parse(&mut mp4)?;
-    let mut dref = dinf.data_reference(&mut mp4).parse(&mut mp4)?;
+    let mut dref = dinf.data_reference(&mut mp4)?.parse(&mut mp4)?;
     let mut stbl = minf.stbl(&mut mp4).unwrap().parse(&mut mp4)?;
 
     let mut hdlr = mdia.hdlr(&mut mp4).unwrap().parse(&mut mp4)?;
     let mut stsd = stbl.sample_description(&mut mp4).unwrap().parse(&mut mp4)?;
-    let mut stco = stbl.chunk_offset(&mut mp4).unwrap().parse(&mut mp4)?;
-    // let mut ctts = stbl.composition_offset(&mut mp4).unwrap().parse(&mut mp4)?;
-    let mut stts = stbl.time_to_sample(&mut mp4).unwrap().parse(&mut mp4)?;
-    let mut stsc = stbl.sample_to_chunk(&mut mp4).unwrap().parse(&mut mp4)?;
-    let mut stsz = stbl.sample_size(&mut mp4).unwrap().parse(&mut mp4)?;
+    let sample_table = SampleTable::new(&mut stbl, &mut mp4)?;
 
     // dbg!(trak.edts(&mut mp4).unwrap().parse(&mut mp4)?);
 
@@ -1232,12 +1233,10 @@ fn main() -> io::Result<()> {
 
     let time = 0; // media_header.convert_to_media_time(Duration::from_secs(5)) as u32;
                   // let time = media_header.time_scale * 3;
-    let sample_id = stts.lookup_time(time);
-    let chunk_id = stsc.lookup_chunk(sample_id);
-    let chunk_offset = stco.chunk_offset(chunk_id);
-    let sample_len = stsz.sample_size(sample_id);
+    let sample = sample_table.sample_at_decode_time(time).unwrap();
+    let sample_len = sample.size;
 
-    let mut sample_offset = chunk_offset;
+    let mut sample_offset = sample.offset;
 
     // dbg!(chunk_offset, sample_len);
     // dbg!(stsz.number_of_entries);
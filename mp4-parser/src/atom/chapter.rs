@@ -0,0 +1,290 @@
+use std::{
+    io::{BufRead, Seek, Write},
+    time::Duration,
+};
+
+use crate::{write_atom, Mp4, Mp4Result, Parse, Serialize};
+
+use super::{decode_text_sample, short_string, Moov, SampleTable, Trak, CHPL};
+
+/// Nero chapter list atom, found in `udta`
+#[derive(Debug, Clone)]
+pub struct Chpl {
+    pub version: u8,
+    pub flags: [u8; 3],
+    /// At most 255 chapters are written, with titles cut to 255 bytes
+    pub chapters: Vec<Chapter>,
+}
+
+impl Parse for Chpl {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(CHPL)?;
+
+        mp4.in_atom(offset, len, CHPL, |mp4| {
+            let version = mp4.reader.read_u8()?;
+            let flags = mp4.reader.read_bytes_const::<3>()?;
+            if version == 1 {
+                let _reserved = mp4.reader.read_u32()?;
+            }
+            let chapter_count = mp4.reader.read_u8()?;
+
+            let mut chapters = Vec::new();
+            for _ in 0..chapter_count {
+                // in units of 100 nanoseconds
+                let start = mp4.reader.read_u64()?;
+                let title_len = mp4.reader.read_u8()?;
+                let title = mp4.reader.read_bytes_dyn(title_len as usize)?;
+
+                chapters.push(Chapter {
+                    start: Duration::from_nanos(start.saturating_mul(100)),
+                    title: String::from_utf8_lossy(&title).into_owned(),
+                });
+            }
+
+            Ok(Self {
+                version,
+                flags,
+                chapters,
+            })
+        })
+    }
+}
+
+impl Serialize for Chpl {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let mut body = vec![self.version];
+        body.extend(self.flags);
+        if self.version == 1 {
+            body.extend([0; 4]);
+        }
+        let chapters = &self.chapters[..self.chapters.len().min(u8::MAX as usize)];
+        body.push(chapters.len() as u8);
+
+        for chapter in chapters {
+            let title = short_string(&chapter.title);
+
+            body.extend(((chapter.start.as_nanos() / 100) as u64).to_be_bytes());
+            body.push(title.len() as u8);
+            body.extend(title.as_bytes());
+        }
+
+        write_atom(writer, CHPL, &body)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chapter {
+    /// Start of the chapter from the start of the movie
+    pub start: Duration,
+    pub title: String,
+}
+
+impl Moov {
+    /// The chapters of the movie, in order. They come from the QuickTime
+    /// chapter track referenced by a track's `tref` atom if there is one, and
+    /// otherwise from a Nero `chpl` atom
+    pub fn chapters<R: BufRead + Seek>(&mut self, mp4: &mut Mp4<'_, R>) -> Mp4Result<Vec<Chapter>> {
+        let mut traks = Vec::new();
+        for trak in self.trak(mp4).clone() {
+            traks.push(trak.parse(mp4)?);
+        }
+
+        let mut chapter_track_id = None;
+        for trak in &mut traks {
+            if let Some(tref) = *trak.tref(mp4) {
+                if let Some(&track_id) = tref.parse(mp4)?.track_ids(*b"chap").first() {
+                    chapter_track_id = Some(track_id);
+                    break;
+                }
+            }
+        }
+
+        if let Some(track_id) = chapter_track_id {
+            for trak in &mut traks {
+                if trak.track_header(mp4)?.parse(mp4)?.track_id == track_id {
+                    return self.chapter_track(trak, mp4);
+                }
+            }
+        }
+
+        if let Some(udta) = *self.udta(mp4) {
+            if let Some(chpl) = *udta.parse(mp4)?.chpl(mp4) {
+                return Ok(chpl.parse(mp4)?.chapters);
+            }
+        }
+
+        Ok(Vec::new())
+    }
+
+    /// Reads the chapters from the text samples of a chapter track, each
+    /// sample being the title of the chapter starting at its presentation time
+    fn chapter_track<R: BufRead + Seek>(
+        &mut self,
+        trak: &mut Trak,
+        mp4: &mut Mp4<'_, R>,
+    ) -> Mp4Result<Vec<Chapter>> {
+        let mdhd = trak.mdia(mp4)?.parse(mp4)?.mdhd(mp4)?.parse(mp4)?;
+        let sample_table = SampleTable::for_track(self, trak, mp4)?;
+
+        let mut chapters = Vec::new();
+        for sample in sample_table.iter() {
+            mp4.jump_to(sample.offset)?;
+            let data = mp4.read_slice(sample.size as usize)?;
+
+            let start = sample.presentation_time.max(0) as u64;
+
+            chapters.push(Chapter {
+                start: Duration::from_secs_f64(start as f64 / mdhd.time_scale.max(1) as f64),
+                title: decode_text_sample(&data).unwrap_or_default(),
+            });
+        }
+
+        Ok(chapters)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        test_util::{atom, full_atom},
+        Serialize,
+    };
+
+    fn tkhd(track_id: u32) -> Vec<u8> {
+        let mut tkhd = vec![0; 12];
+        tkhd.extend(track_id.to_be_bytes());
+        tkhd.extend([0; 68]);
+        atom(b"tkhd", &tkhd)
+    }
+
+    /// A movie whose first track refers to its second, a text track of two
+    /// samples lasting 1.5 s and 1 s, stored in an `mdat` after the `moov`
+    fn chaptered_movie() -> Vec<u8> {
+        let samples = [&b"\0\x05Intro"[..], b"\0\x04Main"];
+
+        let moov = |data_offset: u32| {
+            let mut mvhd = vec![0; 12];
+            mvhd.extend(1000u32.to_be_bytes());
+            mvhd.extend([0; 84]);
+
+            let video = [tkhd(1), atom(b"tref", &atom(b"chap", &2u32.to_be_bytes()))].concat();
+
+            let stbl = [
+                full_atom(b"stsd", 0, &[0]),
+                full_atom(b"stts", 0, &[2, 1, 1500, 1, 1000]),
+                full_atom(b"stsc", 0, &[1, 1, 2, 1]),
+                full_atom(b"stsz", 0, &[0, 2, 7, 6]),
+                full_atom(b"stco", 0, &[1, data_offset]),
+            ]
+            .concat();
+            let mdia = [
+                full_atom(b"mdhd", 0, &[0, 0, 1000, 2500, 0]),
+                atom(b"minf", &atom(b"stbl", &stbl)),
+            ]
+            .concat();
+            let text = [tkhd(2), atom(b"mdia", &mdia)].concat();
+
+            atom(
+                b"moov",
+                &[
+                    atom(b"mvhd", &mvhd),
+                    atom(b"trak", &video),
+                    atom(b"trak", &text),
+                ]
+                .concat(),
+            )
+        };
+
+        let data_offset = moov(0).len() as u32 + 8;
+        let mut file = moov(data_offset);
+        file.extend(atom(b"mdat", &samples.concat()));
+        file
+    }
+
+    #[test]
+    fn reads_chpl_of_each_version() {
+        for (version, reserved) in [(0, &[][..]), (1, &[0xff; 4][..])] {
+            let mut body = vec![version, 0, 0, 0];
+            body.extend(reserved);
+            body.push(2);
+            body.extend(0u64.to_be_bytes());
+            body.push(5);
+            body.extend(b"Intro");
+            body.extend(15_000_000u64.to_be_bytes());
+            body.push(4);
+            body.extend(b"Main");
+
+            let mut mp4 = Mp4::new(Cursor::new(atom(b"chpl", &body)));
+            let chpl = Chpl::parse(&mut mp4).unwrap();
+            assert_eq!(chpl.version, version);
+            assert_eq!(
+                chpl.chapters,
+                [
+                    Chapter {
+                        start: Duration::ZERO,
+                        title: "Intro".to_string(),
+                    },
+                    Chapter {
+                        start: Duration::from_millis(1500),
+                        title: "Main".to_string(),
+                    },
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn reads_chapters_from_the_referenced_text_track() {
+        let mut mp4 = Mp4::new(Cursor::new(chaptered_movie()));
+        let mut moov = Moov::parse(&mut mp4).unwrap();
+
+        assert_eq!(
+            moov.chapters(&mut mp4).unwrap(),
+            [
+                Chapter {
+                    start: Duration::ZERO,
+                    title: "Intro".to_string(),
+                },
+                Chapter {
+                    start: Duration::from_millis(1500),
+                    title: "Main".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_at_most_255_chapters_with_short_titles() {
+        let chpl = Chpl {
+            version: 0,
+            flags: [0; 3],
+            chapters: (0..300)
+                .map(|idx| Chapter {
+                    start: Duration::from_secs(idx),
+                    title: "é".repeat(200),
+                })
+                .collect(),
+        };
+
+        let mut mp4 = Mp4::new(Cursor::new(Vec::new()));
+        let mut written = Vec::new();
+        chpl.serialize(&mut mp4, &mut written).unwrap();
+
+        let mut mp4 = Mp4::new(Cursor::new(written));
+        let chapters = Chpl::parse(&mut mp4).unwrap().chapters;
+        assert_eq!(chapters.len(), 255);
+        assert_eq!(chapters[254].start, Duration::from_secs(254));
+        assert_eq!(chapters[0].title, "é".repeat(127));
+    }
+}
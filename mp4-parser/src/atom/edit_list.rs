@@ -0,0 +1,415 @@
+use std::io::{BufRead, Seek, Write};
+
+use crate::{write_atom, Fixed32, Mp4, Mp4Result, Parse, Serialize};
+
+use super::{Moov, SampleTable, Trak, ELST};
+
+/// Edit list atom, mapping the movie's timeline onto the track's media
+#[derive(Debug, Clone)]
+pub struct Elst {
+    pub version: u8,
+    pub flags: [u8; 3],
+    pub number_of_entries: u32,
+    pub edit_list_table: Vec<EditListEntry>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EditListEntry {
+    /// Duration of the edit, in the movie's time scale
+    pub track_duration: u64,
+    /// Start of the edit in the media, in the media's time scale, or `-1` for
+    /// an empty edit
+    pub media_time: i64,
+    /// Signed fixed point rate the media is played at, where `0` dwells on the
+    /// sample at `media_time` for the whole edit
+    pub media_rate: Fixed32,
+}
+
+impl EditListEntry {
+    /// Whether no media is presented during the edit
+    pub fn is_empty(&self) -> bool {
+        self.media_time == -1
+    }
+}
+
+impl Parse for Elst {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(ELST)?;
+
+        mp4.in_atom(offset, len, ELST, |mp4| {
+            let version = mp4.reader.read_u8()?;
+            let flags = mp4.reader.read_bytes_const::<3>()?;
+            let number_of_entries = mp4.reader.read_u32()?;
+
+            let mut edit_list_table = Vec::new();
+            for _ in 0..number_of_entries {
+                let (track_duration, media_time) = match version {
+                    1 => (mp4.reader.read_u64()?, mp4.reader.read_u64()? as i64),
+                    _ => (mp4.reader.read_u32()? as u64, mp4.reader.read_i32()? as i64),
+                };
+                let media_rate = mp4.reader.read_u32()?;
+
+                edit_list_table.push(EditListEntry {
+                    track_duration,
+                    media_time,
+                    media_rate,
+                });
+            }
+
+            Ok(Self {
+                version,
+                flags,
+                number_of_entries,
+                edit_list_table,
+            })
+        })
+    }
+}
+
+impl Serialize for Elst {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let mut body = vec![self.version];
+        body.extend(self.flags);
+        body.extend(self.number_of_entries.to_be_bytes());
+
+        for entry in &self.edit_list_table {
+            match self.version {
+                1 => {
+                    body.extend(entry.track_duration.to_be_bytes());
+                    body.extend(entry.media_time.to_be_bytes());
+                }
+                _ => {
+                    body.extend((entry.track_duration as u32).to_be_bytes());
+                    body.extend((entry.media_time as i32).to_be_bytes());
+                }
+            }
+            body.extend(entry.media_rate.to_be_bytes());
+        }
+
+        write_atom(writer, ELST, &body)
+    }
+}
+
+/// The edits of a track laid out on the movie's timeline
+#[derive(Debug, Clone)]
+pub struct EditList {
+    pub movie_timescale: u32,
+    pub media_timescale: u32,
+    /// The edits in presentation order. Without any, the media is presented
+    /// as is from the start of the movie
+    pub segments: Vec<EditSegment>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EditSegment {
+    /// Start of the edit on the movie's timeline, in the movie's time scale
+    pub movie_start: u64,
+    /// Duration of the edit, in the movie's time scale. A duration of `0` on
+    /// the last edit extends it to the end of the media, as written by some
+    /// fragmenters
+    pub duration: u64,
+    /// Start of the edit in the media, in the media's time scale, or `None`
+    /// for an empty edit
+    pub media_time: Option<i64>,
+    /// Signed 16.16 fixed point rate
+    pub media_rate: i32,
+}
+
+impl EditSegment {
+    /// Whether the edit holds the sample at `media_time` for its whole
+    /// duration
+    pub fn is_dwell(&self) -> bool {
+        self.media_time.is_some() && self.media_rate == 0
+    }
+}
+
+/// A sample placed on the movie's timeline by the edit list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PresentedSample {
+    /// Zero-based index of the sample within its track
+    pub index: u32,
+    /// Presentation time stamp, in the media's time scale, relative to the
+    /// start of the movie. Negative for the part of a sample that an edit cuts
+    /// off the start
+    pub presentation_time: i64,
+    /// How long the sample is presented for, in the media's time scale
+    pub duration: u64,
+}
+
+impl EditList {
+    /// Lays out `entries` on the movie's timeline. An edit with a negative
+    /// media time other than `-1` is malformed, and is treated as an empty
+    /// edit
+    pub fn new(entries: &[EditListEntry], movie_timescale: u32, media_timescale: u32) -> Self {
+        let mut movie_start = 0;
+
+        let segments = entries
+            .iter()
+            .map(|entry| {
+                let segment = EditSegment {
+                    movie_start,
+                    duration: entry.track_duration,
+                    media_time: (entry.media_time >= 0).then_some(entry.media_time),
+                    media_rate: entry.media_rate as i32,
+                };
+                movie_start = movie_start.saturating_add(entry.track_duration);
+
+                segment
+            })
+            .collect();
+
+        Self {
+            movie_timescale,
+            media_timescale,
+            segments,
+        }
+    }
+
+    /// Reads the edit list of a track along with the movie and media time
+    /// scales it is expressed in
+    pub fn for_track<R: BufRead + Seek>(
+        moov: &mut Moov,
+        trak: &mut Trak,
+        mp4: &mut Mp4<'_, R>,
+    ) -> Mp4Result<Self> {
+        let movie_timescale = moov.movie_header(mp4)?.parse(mp4)?.time_scale;
+        let media_timescale = trak
+            .mdia(mp4)?
+            .parse(mp4)?
+            .mdhd(mp4)?
+            .parse(mp4)?
+            .time_scale;
+
+        let mut entries = Vec::new();
+        if let Some(edts) = *trak.edts(mp4) {
+            for elst in edts.parse(mp4)?.edit_list {
+                entries.extend(elst.edit_list_table);
+            }
+        }
+
+        Ok(Self::new(&entries, movie_timescale, media_timescale))
+    }
+
+    /// Converts a time in the movie's time scale to the media's
+    pub fn movie_to_media_timescale(&self, movie_time: u64) -> u64 {
+        u64::try_from(
+            movie_time as u128 * self.media_timescale as u128 / self.movie_timescale.max(1) as u128,
+        )
+        .unwrap_or(u64::MAX)
+    }
+
+    /// The media time presented at the given movie time, both in their own
+    /// time scales, or `None` if nothing is presented then
+    pub fn movie_to_media(&self, movie_time: u64) -> Option<i64> {
+        if self.segments.is_empty() {
+            return i64::try_from(self.movie_to_media_timescale(movie_time)).ok();
+        }
+
+        let index = self
+            .segments
+            .iter()
+            .enumerate()
+            .position(|(index, segment)| {
+                movie_time >= segment.movie_start
+                    && (movie_time - segment.movie_start < segment.duration
+                        || self.is_open_ended(index))
+            })?;
+        let segment = &self.segments[index];
+        let media_time = segment.media_time?;
+
+        if segment.is_dwell() {
+            return Some(media_time);
+        }
+
+        let elapsed = self.movie_to_media_timescale(movie_time - segment.movie_start) as i128;
+
+        i64::try_from(media_time as i128 + elapsed * segment.media_rate as i128 / 0x1_0000).ok()
+    }
+
+    /// Places the samples of the track on the movie's timeline, in
+    /// presentation order. Samples outside every edit are left out, and a
+    /// sample appears once for every edit that presents it
+    pub fn presentation_times(&self, sample_table: &SampleTable) -> Vec<PresentedSample> {
+        if self.segments.is_empty() {
+            let mut samples = sample_table
+                .iter()
+                .map(|sample| PresentedSample {
+                    index: sample.index,
+                    presentation_time: sample.presentation_time,
+                    duration: sample.duration as u64,
+                })
+                .collect::<Vec<_>>();
+            samples.sort_by_key(|sample| sample.presentation_time);
+
+            return samples;
+        }
+
+        let mut samples = Vec::new();
+
+        for (index, segment) in self.segments.iter().enumerate() {
+            let media_time = match segment.media_time {
+                Some(media_time) => media_time,
+                None => continue,
+            };
+
+            let start = self.movie_to_media_timescale(segment.movie_start);
+            let start = i64::try_from(start).unwrap_or(i64::MAX);
+            let duration = self.movie_to_media_timescale(segment.duration);
+
+            if segment.is_dwell() {
+                if let Some(sample) = sample_table.sample_at_presentation_time(media_time) {
+                    samples.push(PresentedSample {
+                        index: sample.index,
+                        presentation_time: start,
+                        duration,
+                    });
+                }
+                continue;
+            }
+
+            // reverse playback isn't supported by any real player either
+            if segment.media_rate < 0 {
+                continue;
+            }
+
+            let rate = segment.media_rate as i128;
+            let media_end = match self.is_open_ended(index) {
+                true => i64::MAX,
+                false => clamp_time(media_time as i128 + duration as i128 * rate / 0x1_0000),
+            };
+
+            let mut presented = sample_table
+                .iter()
+                .filter(|sample| {
+                    sample.presentation_time < media_end
+                        && sample
+                            .presentation_time
+                            .saturating_add(sample.duration as i64)
+                            > media_time
+                })
+                .map(|sample| PresentedSample {
+                    index: sample.index,
+                    presentation_time: clamp_time(
+                        start as i128
+                            + (sample.presentation_time as i128 - media_time as i128) * 0x1_0000
+                                / rate,
+                    ),
+                    duration: (sample.duration as i128 * 0x1_0000 / rate) as u64,
+                })
+                .collect::<Vec<_>>();
+            presented.sort_by_key(|sample| sample.presentation_time);
+
+            samples.extend(presented);
+        }
+
+        samples
+    }
+
+    fn is_open_ended(&self, index: usize) -> bool {
+        index + 1 == self.segments.len() && self.segments[index].duration == 0
+    }
+}
+
+/// Narrows a time worked out in 128 bits to the range of an `i64`
+pub(crate) fn clamp_time(time: i128) -> i64 {
+    time.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{test_util::movie, Parse};
+
+    fn entry(track_duration: u64, media_time: i64, media_rate: u32) -> EditListEntry {
+        EditListEntry {
+            track_duration,
+            media_time,
+            media_rate,
+        }
+    }
+
+    #[test]
+    fn maps_movie_time_to_media_time() {
+        // an empty edit of half a second, then the media from one second on
+        // at a 90 kHz media time scale, then a dwell on the first sample
+        let edits = EditList::new(
+            &[
+                entry(300, -1, 0x1_0000),
+                entry(600, 90_000, 0x1_0000),
+                entry(600, 0, 0),
+            ],
+            600,
+            90_000,
+        );
+
+        assert_eq!(edits.movie_to_media(0), None);
+        assert_eq!(edits.movie_to_media(300), Some(90_000));
+        assert_eq!(edits.movie_to_media(450), Some(112_500));
+        assert_eq!(edits.movie_to_media(1000), Some(0));
+        assert_eq!(edits.movie_to_media(1500), None);
+    }
+
+    #[test]
+    fn places_samples_on_the_movie_timeline() {
+        let mut mp4 = Mp4::new(Cursor::new(movie(0, 10)));
+        let mut moov = Moov::parse(&mut mp4).unwrap();
+        let mut trak = moov.trak(&mut mp4)[0].parse(&mut mp4).unwrap();
+        let mut mdia = trak.mdia(&mut mp4).unwrap().parse(&mut mp4).unwrap();
+        let mut minf = mdia.minf(&mut mp4).unwrap().parse(&mut mp4).unwrap();
+        let mut stbl = minf.stbl(&mut mp4).unwrap().parse(&mut mp4).unwrap();
+        let table = SampleTable::new(&mut stbl, &mut mp4).unwrap();
+
+        // at a 600 Hz movie time scale over the media's 1 kHz: half a second
+        // of nothing, half a second of the media from 200 ms, then a dwell on
+        // the sample at 250 ms
+        let edits = EditList::new(
+            &[
+                entry(300, -1, 0x1_0000),
+                entry(300, 200, 0x1_0000),
+                entry(120, 250, 0),
+            ],
+            600,
+            1000,
+        );
+
+        let samples = edits
+            .presentation_times(&table)
+            .into_iter()
+            .map(|sample| (sample.index, sample.presentation_time, sample.duration))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            samples,
+            [
+                (2, 500, 100),
+                (3, 600, 100),
+                (4, 700, 100),
+                (5, 800, 100),
+                (6, 900, 100),
+                (2, 1000, 200),
+            ]
+        );
+    }
+
+    #[test]
+    fn saturates_times_of_huge_edits() {
+        let edits = EditList::new(
+            &[entry(u64::MAX, i64::MAX, 0x1_0000), entry(10, 0, 0x1_0000)],
+            1,
+            1000,
+        );
+
+        assert_eq!(edits.segments[1].movie_start, u64::MAX);
+        assert_eq!(edits.movie_to_media(1), None);
+        assert_eq!(edits.movie_to_media(u64::MAX), Some(0));
+    }
+}
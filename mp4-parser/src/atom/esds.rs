@@ -0,0 +1,714 @@
+use std::io::{BufRead, Seek, Write};
+
+use crate::{write_atom, Mp4, Mp4Error, Mp4Result, Parse, Serialize};
+
+use super::ESDS;
+
+const ES_DESCRIPTOR_TAG: u8 = 0x03;
+const DECODER_CONFIG_DESCRIPTOR_TAG: u8 = 0x04;
+const DECODER_SPECIFIC_INFO_TAG: u8 = 0x05;
+const SL_CONFIG_DESCRIPTOR_TAG: u8 = 0x06;
+
+/// Object type indication of MPEG-4 audio, whose decoder specific info is an
+/// `AudioSpecificConfig`
+const MPEG4_AUDIO: u8 = 0x40;
+
+/// Object type indications of the MPEG-2 AAC profiles, which also use an
+/// `AudioSpecificConfig`
+const MPEG2_AAC: [u8; 3] = [0x66, 0x67, 0x68];
+
+const SAMPLING_FREQUENCIES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Elementary stream descriptor atom, holding the MPEG-4 descriptors that
+/// configure the decoder of `mp4a` and `mp4v` sample descriptions
+#[derive(Debug, Clone)]
+pub struct Esds {
+    pub version: u8,
+    pub flags: [u8; 3],
+    pub es_descriptor: EsDescriptor,
+    /// Padding some muxers leave after the descriptor
+    pub padding: Vec<u8>,
+}
+
+impl Esds {
+    pub fn decoder_config(&self) -> Option<&DecoderConfigDescriptor> {
+        self.es_descriptor.decoder_config.as_ref()
+    }
+
+    pub fn audio_specific_config(&self) -> Option<&AudioSpecificConfig> {
+        self.decoder_config()?.audio_specific_config.as_ref()
+    }
+}
+
+impl Parse for Esds {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(ESDS)?;
+
+        mp4.in_atom(offset, len, ESDS, |mp4| {
+            let version = mp4.reader.read_u8()?;
+            let flags = mp4.reader.read_bytes_const::<3>()?;
+
+            let (tag, size, size_len) = read_descriptor_header(mp4, offset + len)?;
+            if tag != ES_DESCRIPTOR_TAG {
+                return invalid(mp4, "esds does not start with an ES_Descriptor");
+            }
+
+            let es_descriptor = EsDescriptor::parse(mp4, size, size_len)?;
+            let padding_len = (offset + len).saturating_sub(mp4.position()?);
+            let padding = mp4.reader.read_bytes_dyn(padding_len as usize)?;
+
+            Ok(Self {
+                version,
+                flags,
+                es_descriptor,
+                padding,
+            })
+        })
+    }
+}
+
+impl Serialize for Esds {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let mut body = vec![self.version];
+        body.extend(self.flags);
+        write_descriptor(
+            &mut body,
+            ES_DESCRIPTOR_TAG,
+            self.es_descriptor.size_len,
+            &self.es_descriptor.to_bytes(),
+        );
+        body.extend(&self.padding);
+
+        write_atom(writer, ESDS, &body)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EsDescriptor {
+    /// The number of bytes the size of the descriptor was encoded in. Encoders
+    /// often pad sizes to four bytes, and sizes are written back as wide
+    pub size_len: u8,
+    pub es_id: u16,
+    pub stream_priority: u8,
+    pub depends_on_es_id: Option<u16>,
+    pub url: Option<Vec<u8>>,
+    pub ocr_es_id: Option<u16>,
+    pub decoder_config: Option<DecoderConfigDescriptor>,
+    pub sl_config: Option<SlConfigDescriptor>,
+    /// IPI pointers, IP identification, language descriptors and any other
+    /// descriptors, as they were read
+    pub other_descriptors: Vec<RawDescriptor>,
+}
+
+impl EsDescriptor {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>, size: u64, size_len: u8) -> Mp4Result<Self> {
+        let end = mp4.position()? + size;
+
+        let es_id = mp4.reader.read_u16()?;
+        let flags = mp4.reader.read_u8()?;
+        let depends_on_es_id = match flags & 0x80 {
+            0 => None,
+            _ => Some(mp4.reader.read_u16()?),
+        };
+        let url = match flags & 0x40 {
+            0 => None,
+            _ => {
+                let url_len = mp4.reader.read_u8()?;
+                Some(mp4.reader.read_bytes_dyn(url_len as usize)?)
+            }
+        };
+        let ocr_es_id = match flags & 0x20 {
+            0 => None,
+            _ => Some(mp4.reader.read_u16()?),
+        };
+
+        let mut decoder_config = None;
+        let mut sl_config = None;
+        let mut other_descriptors = Vec::new();
+
+        while mp4.position()? < end {
+            let (tag, size, size_len) = read_descriptor_header(mp4, end)?;
+            let descriptor_end = mp4.position()? + size;
+
+            match tag {
+                DECODER_CONFIG_DESCRIPTOR_TAG => {
+                    decoder_config = Some(DecoderConfigDescriptor::parse(mp4, size, size_len)?)
+                }
+                SL_CONFIG_DESCRIPTOR_TAG if size > 0 => {
+                    sl_config = Some(SlConfigDescriptor {
+                        size_len,
+                        predefined: mp4.reader.read_u8()?,
+                        rest: mp4.reader.read_bytes_dyn(size as usize - 1)?,
+                    })
+                }
+                _ => other_descriptors.push(RawDescriptor {
+                    tag,
+                    size_len,
+                    data: mp4.reader.read_bytes_dyn(size as usize)?,
+                }),
+            }
+
+            mp4.jump_to(descriptor_end)?;
+        }
+
+        Ok(Self {
+            size_len,
+            es_id,
+            stream_priority: flags & 0x1f,
+            depends_on_es_id,
+            url,
+            ocr_es_id,
+            decoder_config,
+            sl_config,
+            other_descriptors,
+        })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let flags = (self.depends_on_es_id.is_some() as u8) << 7
+            | (self.url.is_some() as u8) << 6
+            | (self.ocr_es_id.is_some() as u8) << 5
+            | self.stream_priority;
+
+        let mut bytes = self.es_id.to_be_bytes().to_vec();
+        bytes.push(flags);
+        if let Some(depends_on_es_id) = self.depends_on_es_id {
+            bytes.extend(depends_on_es_id.to_be_bytes());
+        }
+        if let Some(url) = &self.url {
+            bytes.push(url.len() as u8);
+            bytes.extend(url);
+        }
+        if let Some(ocr_es_id) = self.ocr_es_id {
+            bytes.extend(ocr_es_id.to_be_bytes());
+        }
+
+        if let Some(decoder_config) = &self.decoder_config {
+            write_descriptor(
+                &mut bytes,
+                DECODER_CONFIG_DESCRIPTOR_TAG,
+                decoder_config.size_len,
+                &decoder_config.to_bytes(),
+            );
+        }
+        if let Some(sl_config) = &self.sl_config {
+            let mut sl_bytes = vec![sl_config.predefined];
+            sl_bytes.extend(&sl_config.rest);
+            write_descriptor(
+                &mut bytes,
+                SL_CONFIG_DESCRIPTOR_TAG,
+                sl_config.size_len,
+                &sl_bytes,
+            );
+        }
+        for descriptor in &self.other_descriptors {
+            descriptor.write(&mut bytes);
+        }
+
+        bytes
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DecoderConfigDescriptor {
+    /// The number of bytes the size of the descriptor was encoded in
+    pub size_len: u8,
+    /// Identifies the codec, e.g. `0x40` for MPEG-4 audio or `0x20` for MPEG-4
+    /// visual
+    pub object_type_indication: u8,
+    pub stream_type: u8,
+    pub up_stream: bool,
+    pub buffer_size_db: u32,
+    pub max_bitrate: u32,
+    pub avg_bitrate: u32,
+    pub decoder_specific_info: Option<DecoderSpecificInfo>,
+    /// The decoded decoder specific info of MPEG-4 audio and MPEG-2 AAC
+    /// streams, or `None` if it couldn't be decoded
+    pub audio_specific_config: Option<AudioSpecificConfig>,
+    /// Profile level indication index descriptors and any other descriptors,
+    /// as they were read
+    pub other_descriptors: Vec<RawDescriptor>,
+}
+
+impl DecoderConfigDescriptor {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>, size: u64, size_len: u8) -> Mp4Result<Self> {
+        let end = mp4.position()? + size;
+
+        let object_type_indication = mp4.reader.read_u8()?;
+        let stream = mp4.reader.read_u8()?;
+        let [a, b, c] = mp4.reader.read_bytes_const::<3>()?;
+        let buffer_size_db = u32::from_be_bytes([0, a, b, c]);
+        let max_bitrate = mp4.reader.read_u32()?;
+        let avg_bitrate = mp4.reader.read_u32()?;
+
+        let mut decoder_specific_info = None;
+        let mut other_descriptors = Vec::new();
+
+        while mp4.position()? < end {
+            let (tag, size, size_len) = read_descriptor_header(mp4, end)?;
+            let data = mp4.reader.read_bytes_dyn(size as usize)?;
+
+            match tag {
+                DECODER_SPECIFIC_INFO_TAG => {
+                    decoder_specific_info = Some(DecoderSpecificInfo { size_len, data })
+                }
+                _ => other_descriptors.push(RawDescriptor {
+                    tag,
+                    size_len,
+                    data,
+                }),
+            }
+        }
+
+        let is_aac =
+            object_type_indication == MPEG4_AUDIO || MPEG2_AAC.contains(&object_type_indication);
+
+        // a config that can't be decoded is still kept as decoder specific
+        // info, so the stream stays usable with a less specific codec string
+        let audio_specific_config = match &decoder_specific_info {
+            Some(info) if is_aac => AudioSpecificConfig::decode(&info.data),
+            _ => None,
+        };
+
+        Ok(Self {
+            size_len,
+            object_type_indication,
+            stream_type: stream >> 2,
+            up_stream: stream & 0x02 != 0,
+            buffer_size_db,
+            max_bitrate,
+            avg_bitrate,
+            decoder_specific_info,
+            audio_specific_config,
+            other_descriptors,
+        })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![
+            self.object_type_indication,
+            // the lowest bit is reserved and always set
+            (self.stream_type << 2) | ((self.up_stream as u8) << 1) | 1,
+        ];
+        bytes.extend(&self.buffer_size_db.to_be_bytes()[1..]);
+        bytes.extend(self.max_bitrate.to_be_bytes());
+        bytes.extend(self.avg_bitrate.to_be_bytes());
+
+        if let Some(info) = &self.decoder_specific_info {
+            write_descriptor(
+                &mut bytes,
+                DECODER_SPECIFIC_INFO_TAG,
+                info.size_len,
+                &info.data,
+            );
+        }
+        for descriptor in &self.other_descriptors {
+            descriptor.write(&mut bytes);
+        }
+
+        bytes
+    }
+}
+
+/// Codec specific configuration, e.g. an `AudioSpecificConfig` for AAC or the
+/// visual object sequence and video object layer headers for MPEG-4 visual
+#[derive(Debug, Clone)]
+pub struct DecoderSpecificInfo {
+    /// The number of bytes the size of the descriptor was encoded in
+    pub size_len: u8,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SlConfigDescriptor {
+    /// The number of bytes the size of the descriptor was encoded in
+    pub size_len: u8,
+    /// `2` for the predefined configuration used by MP4 files, in which case
+    /// `rest` is empty
+    pub predefined: u8,
+    pub rest: Vec<u8>,
+}
+
+/// A descriptor that isn't decoded, kept so that it is written back as is
+#[derive(Debug, Clone)]
+pub struct RawDescriptor {
+    pub tag: u8,
+    /// The number of bytes the size of the descriptor was encoded in
+    pub size_len: u8,
+    pub data: Vec<u8>,
+}
+
+impl RawDescriptor {
+    fn write(&self, bytes: &mut Vec<u8>) {
+        write_descriptor(bytes, self.tag, self.size_len, &self.data);
+    }
+}
+
+/// The decoder configuration of an MPEG-4 audio stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioSpecificConfig {
+    /// The core object type, e.g. `2` for AAC LC, even when SBR or PS are
+    /// signalled hierarchically through object types 5 or 29
+    pub audio_object_type: u8,
+    pub sampling_frequency_index: u8,
+    pub sampling_frequency: u32,
+    pub channel_configuration: u8,
+    /// `5` for SBR or `22` for BSAC extensions, if signalled
+    pub extension_audio_object_type: Option<u8>,
+    pub extension_sampling_frequency: Option<u32>,
+    /// Whether SBR is present, or `None` if the configuration doesn't say, in
+    /// which case SBR may still be found implicitly in the stream
+    pub sbr_present: Option<bool>,
+    /// Whether parametric stereo is present, or `None` if not signalled
+    pub ps_present: Option<bool>,
+}
+
+impl AudioSpecificConfig {
+    /// Decodes an `AudioSpecificConfig` from the data of a decoder specific
+    /// info descriptor
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let mut bits = BitReader::new(data);
+
+        let mut audio_object_type = read_audio_object_type(&mut bits)?;
+        let (sampling_frequency_index, sampling_frequency) = read_sampling_frequency(&mut bits)?;
+        let channel_configuration = bits.read(4)? as u8;
+
+        let mut extension_audio_object_type = None;
+        let mut extension_sampling_frequency = None;
+        let mut sbr_present = None;
+        let mut ps_present = None;
+
+        // explicit hierarchical signalling of SBR and PS
+        if audio_object_type == 5 || audio_object_type == 29 {
+            extension_audio_object_type = Some(5);
+            sbr_present = Some(true);
+            if audio_object_type == 29 {
+                ps_present = Some(true);
+            }
+            extension_sampling_frequency = Some(read_sampling_frequency(&mut bits)?.1);
+
+            audio_object_type = read_audio_object_type(&mut bits)?;
+            if audio_object_type == 22 {
+                let _extension_channel_configuration = bits.read(4)?;
+            }
+        }
+
+        let mut config = Self {
+            audio_object_type,
+            sampling_frequency_index,
+            sampling_frequency,
+            channel_configuration,
+            extension_audio_object_type,
+            extension_sampling_frequency,
+            sbr_present,
+            ps_present,
+        };
+
+        // explicit backward compatible signalling follows the codec specific
+        // config, so can only be found when that config is understood. A
+        // program config element (channel configuration 0) isn't
+        if extension_audio_object_type.is_none()
+            && channel_configuration != 0
+            && skip_ga_specific_config(&mut bits, audio_object_type, channel_configuration)
+                .is_some()
+        {
+            config.read_sync_extension(&mut bits);
+        }
+
+        Some(config)
+    }
+
+    fn read_sync_extension(&mut self, bits: &mut BitReader<'_>) -> Option<()> {
+        if bits.remaining() < 16 || bits.read(11)? != 0x2b7 {
+            return None;
+        }
+
+        let extension_audio_object_type = read_audio_object_type(bits)?;
+
+        match extension_audio_object_type {
+            5 => {
+                self.extension_audio_object_type = Some(5);
+
+                let sbr_present = bits.read(1)? != 0;
+                self.sbr_present = Some(sbr_present);
+
+                if sbr_present {
+                    self.extension_sampling_frequency = Some(read_sampling_frequency(bits)?.1);
+
+                    if bits.remaining() >= 12 && bits.read(11)? == 0x548 {
+                        self.ps_present = Some(bits.read(1)? != 0);
+                    }
+                }
+            }
+            22 => {
+                self.extension_audio_object_type = Some(22);
+
+                let sbr_present = bits.read(1)? != 0;
+                self.sbr_present = Some(sbr_present);
+
+                if sbr_present {
+                    self.extension_sampling_frequency = Some(read_sampling_frequency(bits)?.1);
+                }
+            }
+            _ => {}
+        }
+
+        Some(())
+    }
+
+    /// The number of channels, or `None` if they are described by a program
+    /// config element rather than a channel configuration
+    pub fn channel_count(&self) -> Option<u8> {
+        match self.channel_configuration {
+            1..=6 => Some(self.channel_configuration),
+            7 => Some(8),
+            _ => None,
+        }
+    }
+
+    /// The sampling frequency of the decoded output, which is the SBR
+    /// extension's frequency when SBR is present
+    pub fn output_sampling_frequency(&self) -> u32 {
+        match (self.sbr_present, self.extension_sampling_frequency) {
+            (Some(true), Some(frequency)) => frequency,
+            _ => self.sampling_frequency,
+        }
+    }
+}
+
+fn read_audio_object_type(bits: &mut BitReader<'_>) -> Option<u8> {
+    match bits.read(5)? as u8 {
+        31 => Some(32 + bits.read(6)? as u8),
+        audio_object_type => Some(audio_object_type),
+    }
+}
+
+fn read_sampling_frequency(bits: &mut BitReader<'_>) -> Option<(u8, u32)> {
+    let index = bits.read(4)? as u8;
+
+    let frequency = match index {
+        0xf => bits.read(24)?,
+        _ => *SAMPLING_FREQUENCIES.get(index as usize)?,
+    };
+
+    Some((index, frequency))
+}
+
+/// Skips a `GASpecificConfig` along with the error protection config that
+/// follows it. Returns `None` for object types that don't use one
+fn skip_ga_specific_config(
+    bits: &mut BitReader<'_>,
+    audio_object_type: u8,
+    channel_configuration: u8,
+) -> Option<()> {
+    if !matches!(audio_object_type, 1..=4 | 6 | 7 | 17 | 19..=23) {
+        return None;
+    }
+
+    let _frame_length_flag = bits.read(1)?;
+    if bits.read(1)? != 0 {
+        let _core_coder_delay = bits.read(14)?;
+    }
+    let extension_flag = bits.read(1)?;
+
+    if channel_configuration == 0 {
+        return None;
+    }
+
+    if audio_object_type == 6 || audio_object_type == 20 {
+        let _layer_nr = bits.read(3)?;
+    }
+
+    if extension_flag != 0 {
+        if audio_object_type == 22 {
+            let _num_of_sub_frame = bits.read(5)?;
+            let _layer_length = bits.read(11)?;
+        }
+
+        if matches!(audio_object_type, 17 | 19 | 20 | 23) {
+            let _resilience_flags = bits.read(3)?;
+        }
+
+        let _extension_flag_3 = bits.read(1)?;
+    }
+
+    if matches!(audio_object_type, 17 | 19..=23) {
+        // error protection specific configs aren't understood
+        if bits.read(2)? != 0 {
+            return None;
+        }
+    }
+
+    Some(())
+}
+
+/// Reads the tag and size of a descriptor, along with the number of bytes the
+/// size was encoded in
+fn read_descriptor_header<R: Seek + BufRead>(
+    mp4: &mut Mp4<'_, R>,
+    end: u64,
+) -> Mp4Result<(u8, u64, u8)> {
+    let tag = mp4.reader.read_u8()?;
+
+    let mut size = 0;
+    let mut size_len = 0;
+    while size_len < 4 {
+        let byte = mp4.reader.read_u8()?;
+        size = (size << 7) | (byte & 0x7f) as u64;
+        size_len += 1;
+
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    if mp4.position()? + size > end {
+        return invalid(mp4, "descriptor overruns its parent");
+    }
+
+    Ok((tag, size, size_len))
+}
+
+/// Writes a descriptor, encoding its size in `size_len` bytes, or in as few
+/// bytes as the size needs if that is more
+fn write_descriptor(bytes: &mut Vec<u8>, tag: u8, size_len: u8, body: &[u8]) {
+    bytes.push(tag);
+
+    let size = body.len() as u32;
+    let size_len = (1..4)
+        .find(|len| size >> (7 * len) == 0)
+        .unwrap_or(4)
+        .max(size_len.min(4) as u32);
+    for index in (0..size_len).rev() {
+        let continuation = if index > 0 { 0x80 } else { 0 };
+        bytes.push(continuation | ((size >> (7 * index)) & 0x7f) as u8);
+    }
+
+    bytes.extend(body);
+}
+
+fn invalid<R: Seek + BufRead, T>(mp4: &mut Mp4<'_, R>, reason: &'static str) -> Mp4Result<T> {
+    Err(Mp4Error::InvalidDescriptor {
+        offset: mp4.position()?,
+        path: mp4.atom_path(),
+        reason,
+    })
+}
+
+/// Reads big-endian bit fields of up to 32 bits
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() * 8 - self.position
+    }
+
+    fn read(&mut self, bits: usize) -> Option<u32> {
+        if bits > self.remaining() {
+            return None;
+        }
+
+        let mut value = 0;
+        for _ in 0..bits {
+            let byte = self.data[self.position / 8];
+            let bit = (byte >> (7 - self.position % 8)) & 1;
+
+            value = (value << 1) | bit as u32;
+            self.position += 1;
+        }
+
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::AudioSpecificConfig;
+    use crate::{test_util::atom, Mp4, Parse, SampleSoundDescriptionTable};
+
+    #[test]
+    fn decodes_aac_lc() {
+        let config = AudioSpecificConfig::decode(&[0x12, 0x10]).unwrap();
+
+        assert_eq!(config.audio_object_type, 2);
+        assert_eq!(config.sampling_frequency, 44100);
+        assert_eq!(config.channel_count(), Some(2));
+        assert_eq!(config.sbr_present, None);
+        assert_eq!(config.ps_present, None);
+    }
+
+    #[test]
+    fn decodes_hierarchical_sbr_signalling() {
+        let config = AudioSpecificConfig::decode(&[0x2b, 0x11, 0x88, 0x00]).unwrap();
+
+        assert_eq!(config.audio_object_type, 2);
+        assert_eq!(config.sampling_frequency, 24000);
+        assert_eq!(config.extension_audio_object_type, Some(5));
+        assert_eq!(config.sbr_present, Some(true));
+        assert_eq!(config.output_sampling_frequency(), 48000);
+    }
+
+    #[test]
+    fn decodes_backward_compatible_sbr_signalling() {
+        let config = AudioSpecificConfig::decode(&[0x13, 0x90, 0x56, 0xe5, 0xa0]).unwrap();
+
+        assert_eq!(config.audio_object_type, 2);
+        assert_eq!(config.sampling_frequency, 22050);
+        assert_eq!(config.sbr_present, Some(true));
+        assert_eq!(config.output_sampling_frequency(), 44100);
+        assert_eq!(config.ps_present, None);
+    }
+
+    #[test]
+    fn keeps_undecodable_audio_specific_config() {
+        // an explicit sampling frequency cut short
+        let mut decoder_config = vec![0x40, 0x15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        decoder_config.extend([0x05, 2, 0x17, 0x80]);
+        let mut es = vec![0, 1, 0, 0x04, decoder_config.len() as u8];
+        es.extend(decoder_config);
+        let mut esds = vec![0; 4];
+        esds.extend([0x03, es.len() as u8]);
+        esds.extend(es);
+
+        let mut body = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        body.extend([0; 8]);
+        body.extend([0, 2, 0, 16, 0, 0, 0, 0]);
+        body.extend((44100u32 << 16).to_be_bytes());
+        body.extend(atom(b"esds", &esds));
+
+        let mut mp4 = Mp4::new(Cursor::new(atom(b"mp4a", &body)));
+        let sound = SampleSoundDescriptionTable::parse(&mut mp4).unwrap();
+        let decoder_config = sound.esds().unwrap().es_descriptor.decoder_config.as_ref();
+
+        assert!(decoder_config.unwrap().audio_specific_config.is_none());
+        assert_eq!(
+            decoder_config
+                .unwrap()
+                .decoder_specific_info
+                .as_ref()
+                .unwrap()
+                .data,
+            [0x17, 0x80]
+        );
+        assert_eq!(sound.codec_string(), "mp4a.40");
+    }
+}
@@ -0,0 +1,541 @@
+use std::io::{BufRead, Seek, Write};
+
+use atom_macro::{mp4_atom, mp4_container_atom};
+
+use crate::{write_atom, Mp4, Mp4Error, Mp4Result, Parse, Reference, Serialize};
+
+use super::{
+    InternalElement, SampleDependencyFlags, Sbgp, Sdtp, Sgpd, UnparsedAtom, MEHD, TFDT, TFHD, TRUN,
+};
+
+/// Movie extends atom, signalling that the movie may contain movie fragments
+/// and holding the defaults used by the samples of those fragments
+#[mp4_container_atom]
+pub struct Mvex {
+    pub mehd: Option<Reference<Mehd>>,
+    pub trex: Vec<Reference<Trex>>,
+}
+
+/// Movie extends header atom, giving the duration of the whole fragmented
+/// movie
+#[derive(Debug, Clone)]
+pub struct Mehd {
+    pub version: u8,
+    pub flags: [u8; 3],
+    pub fragment_duration: u64,
+}
+
+impl Parse for Mehd {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(MEHD)?;
+
+        mp4.in_atom(offset, len, MEHD, |mp4| {
+            let version = mp4.reader.read_u8()?;
+            let flags = mp4.reader.read_bytes_const::<3>()?;
+            let fragment_duration = match version {
+                1 => mp4.reader.read_u64()?,
+                _ => mp4.reader.read_u32()? as u64,
+            };
+
+            Ok(Self {
+                version,
+                flags,
+                fragment_duration,
+            })
+        })
+    }
+}
+
+impl Serialize for Mehd {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let mut body = vec![self.version];
+        body.extend(self.flags);
+        match self.version {
+            1 => body.extend(self.fragment_duration.to_be_bytes()),
+            _ => body.extend((self.fragment_duration as u32).to_be_bytes()),
+        }
+
+        write_atom(writer, MEHD, &body)
+    }
+}
+
+/// Track extends atom, giving the default values used by the samples of one
+/// track in movie fragments
+#[mp4_atom]
+pub struct Trex {
+    pub version: u8,
+    pub flags: [u8; 3],
+    pub track_id: u32,
+    pub default_sample_description_index: u32,
+    pub default_sample_duration: u32,
+    pub default_sample_size: u32,
+    pub default_sample_flags: SampleFlags,
+}
+
+/// The flags describing a sample in a movie fragment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SampleFlags(pub u32);
+
+impl SampleFlags {
+    /// The dependency information of the sample, with the same meaning as the
+    /// entries of an `sdtp` atom
+    pub fn dependency_flags(&self) -> SampleDependencyFlags {
+        SampleDependencyFlags {
+            is_leading: ((self.0 >> 26) & 0b11) as u8,
+            sample_depends_on: ((self.0 >> 24) & 0b11) as u8,
+            sample_is_depended_on: ((self.0 >> 22) & 0b11) as u8,
+            sample_has_redundancy: ((self.0 >> 20) & 0b11) as u8,
+        }
+    }
+
+    pub fn padding_value(&self) -> u8 {
+        ((self.0 >> 17) & 0b111) as u8
+    }
+
+    /// Whether the sample is not a sync sample
+    pub fn is_non_sync(&self) -> bool {
+        self.0 & 0x1_0000 != 0
+    }
+
+    pub fn degradation_priority(&self) -> u16 {
+        self.0 as u16
+    }
+}
+
+impl Parse for SampleFlags {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(SampleFlags(mp4.reader.read_u32()?))
+    }
+
+    fn peek_len<R: Seek + BufRead>(_mp4: &mut Mp4<'_, R>) -> Mp4Result<u64> {
+        Ok(4)
+    }
+}
+
+impl Serialize for SampleFlags {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        Ok(writer.write_all(&self.0.to_be_bytes())?)
+    }
+}
+
+/// Movie fragment atom, extending the presentation with more samples
+#[mp4_container_atom]
+pub struct Moof {
+    pub mfhd: Reference<Mfhd>,
+    pub traf: Vec<Reference<Traf>>,
+}
+
+impl<R: BufRead + Seek> Mp4<'_, R> {
+    /// Finds every movie fragment at the top level of the file, in file order
+    pub fn movie_fragments(&mut self) -> Mp4Result<Vec<Reference<Moof>>> {
+        Ok(self
+            .top_level_atoms()?
+            .into_iter()
+            .filter(|atom| atom.header == Moof::HEADER)
+            .map(UnparsedAtom::into_ref)
+            .collect())
+    }
+}
+
+#[mp4_atom]
+pub struct Mfhd {
+    pub version: u8,
+    pub flags: [u8; 3],
+    pub sequence_number: u32,
+}
+
+/// Track fragment atom, holding the samples of a single track within a movie
+/// fragment
+#[mp4_container_atom]
+pub struct Traf {
+    pub tfhd: Reference<Tfhd>,
+    pub tfdt: Option<Reference<Tfdt>>,
+    pub trun: Vec<Reference<Trun>>,
+    pub sdtp: Option<Reference<Sdtp>>,
+    pub sbgp: Vec<Reference<Sbgp>>,
+    pub sgpd: Vec<Reference<Sgpd>>,
+}
+
+/// Track fragment header atom. Any value not present here falls back to the
+/// default given by the track's `trex` atom
+#[derive(Debug, Clone)]
+pub struct Tfhd {
+    pub version: u8,
+    pub flags: [u8; 3],
+    pub track_id: u32,
+    pub base_data_offset: Option<u64>,
+    pub sample_description_index: Option<u32>,
+    pub default_sample_duration: Option<u32>,
+    pub default_sample_size: Option<u32>,
+    pub default_sample_flags: Option<SampleFlags>,
+}
+
+impl Tfhd {
+    /// Whether the track fragment covers a duration but contains no samples
+    pub fn duration_is_empty(&self) -> bool {
+        self.flags[0] & 0x01 != 0
+    }
+
+    /// Whether the data offsets of the track runs are relative to the start of
+    /// the enclosing `moof` when no base data offset is given
+    pub fn default_base_is_moof(&self) -> bool {
+        self.flags[0] & 0x02 != 0
+    }
+}
+
+impl Parse for Tfhd {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(TFHD)?;
+
+        mp4.in_atom(offset, len, TFHD, |mp4| {
+            let version = mp4.reader.read_u8()?;
+            let flags = mp4.reader.read_bytes_const::<3>()?;
+            let flag_bits = u32::from_be_bytes([0, flags[0], flags[1], flags[2]]);
+
+            let track_id = mp4.reader.read_u32()?;
+            let base_data_offset = match flag_bits & 0x01 {
+                0 => None,
+                _ => Some(mp4.reader.read_u64()?),
+            };
+            let sample_description_index = match flag_bits & 0x02 {
+                0 => None,
+                _ => Some(mp4.reader.read_u32()?),
+            };
+            let default_sample_duration = match flag_bits & 0x08 {
+                0 => None,
+                _ => Some(mp4.reader.read_u32()?),
+            };
+            let default_sample_size = match flag_bits & 0x10 {
+                0 => None,
+                _ => Some(mp4.reader.read_u32()?),
+            };
+            let default_sample_flags = match flag_bits & 0x20 {
+                0 => None,
+                _ => Some(SampleFlags::parse(mp4)?),
+            };
+
+            Ok(Self {
+                version,
+                flags,
+                track_id,
+                base_data_offset,
+                sample_description_index,
+                default_sample_duration,
+                default_sample_size,
+                default_sample_flags,
+            })
+        })
+    }
+}
+
+impl Serialize for Tfhd {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        // the flags that signal optional fields follow the fields present
+        let flag_bits = u32::from_be_bytes([0, self.flags[0], self.flags[1], self.flags[2]])
+            & !0x3b
+            | self.base_data_offset.map_or(0, |_| 0x01)
+            | self.sample_description_index.map_or(0, |_| 0x02)
+            | self.default_sample_duration.map_or(0, |_| 0x08)
+            | self.default_sample_size.map_or(0, |_| 0x10)
+            | self.default_sample_flags.map_or(0, |_| 0x20);
+
+        let mut body = vec![self.version];
+        body.extend(&flag_bits.to_be_bytes()[1..]);
+        body.extend(self.track_id.to_be_bytes());
+        if let Some(base_data_offset) = self.base_data_offset {
+            body.extend(base_data_offset.to_be_bytes());
+        }
+        if let Some(sample_description_index) = self.sample_description_index {
+            body.extend(sample_description_index.to_be_bytes());
+        }
+        if let Some(default_sample_duration) = self.default_sample_duration {
+            body.extend(default_sample_duration.to_be_bytes());
+        }
+        if let Some(default_sample_size) = self.default_sample_size {
+            body.extend(default_sample_size.to_be_bytes());
+        }
+        if let Some(default_sample_flags) = self.default_sample_flags {
+            body.extend(default_sample_flags.0.to_be_bytes());
+        }
+
+        write_atom(writer, TFHD, &body)
+    }
+}
+
+/// Track fragment decode time atom, giving the decoding time of the first
+/// sample of the track fragment
+#[derive(Debug, Clone)]
+pub struct Tfdt {
+    pub version: u8,
+    pub flags: [u8; 3],
+    pub base_media_decode_time: u64,
+}
+
+impl Parse for Tfdt {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(TFDT)?;
+
+        mp4.in_atom(offset, len, TFDT, |mp4| {
+            let version = mp4.reader.read_u8()?;
+            let flags = mp4.reader.read_bytes_const::<3>()?;
+            let base_media_decode_time = match version {
+                1 => mp4.reader.read_u64()?,
+                _ => mp4.reader.read_u32()? as u64,
+            };
+
+            Ok(Self {
+                version,
+                flags,
+                base_media_decode_time,
+            })
+        })
+    }
+}
+
+impl Serialize for Tfdt {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let mut body = vec![self.version];
+        body.extend(self.flags);
+        match self.version {
+            1 => body.extend(self.base_media_decode_time.to_be_bytes()),
+            _ => body.extend((self.base_media_decode_time as u32).to_be_bytes()),
+        }
+
+        write_atom(writer, TFDT, &body)
+    }
+}
+
+/// Track run atom, describing a contiguous run of samples of a track fragment
+#[derive(Debug, Clone)]
+pub struct Trun {
+    pub version: u8,
+    pub flags: [u8; 3],
+    pub sample_count: u32,
+    /// Offset of the run's sample data from the base data offset of the track
+    /// fragment
+    pub data_offset: Option<i32>,
+    /// Overrides the flags of the first sample only, e.g. to mark a sync
+    /// sample at the start of a run of otherwise non-sync samples
+    pub first_sample_flags: Option<SampleFlags>,
+    pub samples: Vec<TrackRunSample>,
+}
+
+/// A sample of a track run. Any value not present falls back to the defaults of
+/// the track fragment
+#[derive(Debug, Clone, Copy)]
+pub struct TrackRunSample {
+    pub duration: Option<u32>,
+    pub size: Option<u32>,
+    pub flags: Option<SampleFlags>,
+    /// Unsigned in version 0 track runs, signed in version 1
+    pub composition_offset: Option<i64>,
+}
+
+/// The most samples a track run giving no fields per sample may hold. Such a
+/// run takes no more space for more samples, so its count can't be checked
+/// against its size
+const MAX_RUN_WITHOUT_FIELDS: u32 = 1 << 20;
+
+impl Parse for Trun {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(TRUN)?;
+
+        mp4.in_atom(offset, len, TRUN, |mp4| {
+            let version = mp4.reader.read_u8()?;
+            let flags = mp4.reader.read_bytes_const::<3>()?;
+            let flag_bits = u32::from_be_bytes([0, flags[0], flags[1], flags[2]]);
+
+            let sample_count = mp4.reader.read_u32()?;
+            let data_offset = match flag_bits & 0x01 {
+                0 => None,
+                _ => Some(mp4.reader.read_i32()?),
+            };
+            let first_sample_flags = match flag_bits & 0x04 {
+                0 => None,
+                _ => Some(SampleFlags::parse(mp4)?),
+            };
+
+            let sample_len = [0x100, 0x200, 0x400, 0x800]
+                .iter()
+                .filter(|&&bit| flag_bits & bit != 0)
+                .count() as u64
+                * 4;
+            let remaining = (offset + len).saturating_sub(mp4.position()?);
+            let too_many = match sample_len {
+                0 => sample_count > MAX_RUN_WITHOUT_FIELDS,
+                _ => sample_count as u64 * sample_len > remaining,
+            };
+            if too_many {
+                return Err(Mp4Error::InvalidSampleTable {
+                    offset,
+                    path: mp4.atom_path(),
+                    reason: "track run has more samples than it can hold",
+                });
+            }
+
+            let mut samples = Vec::with_capacity(sample_count as usize);
+            for _ in 0..sample_count {
+                let duration = match flag_bits & 0x100 {
+                    0 => None,
+                    _ => Some(mp4.reader.read_u32()?),
+                };
+                let size = match flag_bits & 0x200 {
+                    0 => None,
+                    _ => Some(mp4.reader.read_u32()?),
+                };
+                let flags = match flag_bits & 0x400 {
+                    0 => None,
+                    _ => Some(SampleFlags::parse(mp4)?),
+                };
+                let composition_offset = match (flag_bits & 0x800, version) {
+                    (0, _) => None,
+                    (_, 0) => Some(mp4.reader.read_u32()? as i64),
+                    _ => Some(mp4.reader.read_i32()? as i64),
+                };
+
+                samples.push(TrackRunSample {
+                    duration,
+                    size,
+                    flags,
+                    composition_offset,
+                });
+            }
+
+            Ok(Self {
+                version,
+                flags,
+                sample_count,
+                data_offset,
+                first_sample_flags,
+                samples,
+            })
+        })
+    }
+}
+
+impl Serialize for Trun {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        // the flags that signal optional fields follow the fields present,
+        // which every sample is expected to have in common with the first
+        let first = self.samples.first();
+        let flag_bits = u32::from_be_bytes([0, self.flags[0], self.flags[1], self.flags[2]])
+            & !0xf05
+            | self.data_offset.map_or(0, |_| 0x01)
+            | self.first_sample_flags.map_or(0, |_| 0x04)
+            | first
+                .and_then(|sample| sample.duration)
+                .map_or(0, |_| 0x100)
+            | first.and_then(|sample| sample.size).map_or(0, |_| 0x200)
+            | first.and_then(|sample| sample.flags).map_or(0, |_| 0x400)
+            | first
+                .and_then(|sample| sample.composition_offset)
+                .map_or(0, |_| 0x800);
+
+        let mut body = vec![self.version];
+        body.extend(&flag_bits.to_be_bytes()[1..]);
+        body.extend((self.samples.len() as u32).to_be_bytes());
+        if let Some(data_offset) = self.data_offset {
+            body.extend(data_offset.to_be_bytes());
+        }
+        if let Some(first_sample_flags) = self.first_sample_flags {
+            body.extend(first_sample_flags.0.to_be_bytes());
+        }
+
+        for sample in &self.samples {
+            if flag_bits & 0x100 != 0 {
+                body.extend(sample.duration.unwrap_or_default().to_be_bytes());
+            }
+            if flag_bits & 0x200 != 0 {
+                body.extend(sample.size.unwrap_or_default().to_be_bytes());
+            }
+            if flag_bits & 0x400 != 0 {
+                body.extend(sample.flags.map_or(0, |flags| flags.0).to_be_bytes());
+            }
+            if flag_bits & 0x800 != 0 {
+                let composition_offset = sample.composition_offset.unwrap_or_default();
+                match self.version {
+                    0 => body.extend((composition_offset as u32).to_be_bytes()),
+                    _ => body.extend((composition_offset as i32).to_be_bytes()),
+                }
+            }
+        }
+
+        write_atom(writer, TRUN, &body)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_util::full_atom;
+
+    #[test]
+    fn reads_composition_offsets_by_version() {
+        // sample-composition-time-offsets-present
+        for (version, offset) in [(0, 0x8000_0000), (1, -0x8000_0000)] {
+            let trun = full_atom(b"trun", version << 24 | 0x800, &[1, 0x8000_0000]);
+
+            let mut mp4 = Mp4::new(Cursor::new(trun.clone()));
+            let parsed = Trun::parse(&mut mp4).unwrap();
+            assert_eq!(parsed.samples[0].composition_offset, Some(offset));
+
+            let mut written = Vec::new();
+            parsed.serialize(&mut mp4, &mut written).unwrap();
+            assert_eq!(written, trun);
+        }
+    }
+
+    #[test]
+    fn rejects_runs_with_more_samples_than_they_hold() {
+        for flags in [0, 0x100] {
+            let trun = full_atom(b"trun", flags, &[u32::MAX, 0]);
+
+            let mut mp4 = Mp4::new(Cursor::new(trun));
+            assert!(matches!(
+                Trun::parse(&mut mp4),
+                Err(Mp4Error::InvalidSampleTable { .. })
+            ));
+        }
+    }
+}
@@ -5,6 +5,17 @@ use super::*;
 #[derive(PartialEq, Eq, Clone, Copy)]
 pub struct Header(pub(crate) [u8; 4]);
 
+impl Header {
+    pub const fn new(fourcc: [u8; 4]) -> Self {
+        Self(fourcc)
+    }
+
+    /// The four-character code identifying the atom type
+    pub const fn fourcc(&self) -> [u8; 4] {
+        self.0
+    }
+}
+
 impl fmt::Debug for Header {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match String::from_utf8(self.0.to_vec()) {
@@ -14,6 +25,20 @@ impl fmt::Debug for Header {
     }
 }
 
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &b in &self.0 {
+            if b.is_ascii_graphic() || b == b' ' {
+                write!(f, "{}", b as char)?;
+            } else {
+                write!(f, "\\x{:02x}", b)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
 /// File type compatibility—identifies the file type and differentiates it from
 /// similar file types, such as MPEG-4 files and JPEG-2000 files
 pub(crate) const FTYP: Header = Header(*b"ftyp");
@@ -27,6 +52,23 @@ pub(crate) const MOOV: Header = Header(*b"moov");
 /// samples. Usually this data can be interpreted only by using the movie resource.
 pub(crate) const MDAT: Header = Header(*b"mdat");
 
+/// Unused space—free space that can be ignored, or overwritten when the atoms
+/// around it grow
+pub(crate) const FREE: Header = Header(*b"free");
+pub(crate) const SKIP: Header = Header(*b"skip");
+
+/// Reserved space—space that can be overwritten by an extended size field if
+/// the following atom grows beyond 32 bits
+pub(crate) const WIDE: Header = Header(*b"wide");
+
+/// Progressive download information—pairs of download rates and the initial
+/// playback delay suggested at each rate
+pub(crate) const PDIN: Header = Header(*b"pdin");
+
+/// User extension—an atom whose type is given by a 16-byte UUID following its
+/// header
+pub(crate) const UUID: Header = Header(*b"uuid");
+
 /// The track header atom contains the track characteristics for the track,
 /// including temporal, spatial, and volume information.
 pub(crate) const TKHD: Header = Header(*b"tkhd");
@@ -43,9 +85,45 @@ pub(crate) const STCO: Header = Header(*b"stco");
 /// original chunk offset atom, which can contain only 32-bit offsets
 pub(crate) const CO64: Header = Header(*b"co64");
 
+/// Movie fragment—extends the presentation with further samples, allowing a
+/// movie to be written and played back incrementally
+pub(crate) const MOOF: Header = Header(*b"moof");
+
+/// Movie extends—signals that the movie may contain movie fragments and holds
+/// the defaults used by their samples
+pub(crate) const MVEX: Header = Header(*b"mvex");
+
+pub(crate) const MEHD: Header = Header(*b"mehd");
+pub(crate) const TREX: Header = Header(*b"trex");
+pub(crate) const MFHD: Header = Header(*b"mfhd");
+pub(crate) const TRAF: Header = Header(*b"traf");
+pub(crate) const TFHD: Header = Header(*b"tfhd");
+pub(crate) const TFDT: Header = Header(*b"tfdt");
+pub(crate) const TRUN: Header = Header(*b"trun");
+
+/// Segment type—takes the place of `ftyp` at the start of a media segment
+pub(crate) const STYP: Header = Header(*b"styp");
+
+/// Segment index—maps presentation times to byte ranges of the subsegments
+/// that follow it
+pub(crate) const SIDX: Header = Header(*b"sidx");
+
+/// Movie fragment random access—normally the last atom of a fragmented file,
+/// listing the sync samples of each track along with the fragment holding them
+pub(crate) const MFRA: Header = Header(*b"mfra");
+
+pub(crate) const TFRA: Header = Header(*b"tfra");
+pub(crate) const MFRO: Header = Header(*b"mfro");
 pub(crate) const CLIP: Header = Header(*b"clip");
 pub(crate) const TRAK: Header = Header(*b"trak");
 pub(crate) const UDTA: Header = Header(*b"udta");
+pub(crate) const META: Header = Header(*b"meta");
+pub(crate) const KEYS: Header = Header(*b"keys");
+pub(crate) const ILST: Header = Header(*b"ilst");
+pub(crate) const DATA: Header = Header(*b"data");
+pub(crate) const MEAN: Header = Header(*b"mean");
+pub(crate) const NAME: Header = Header(*b"name");
+pub(crate) const CHPL: Header = Header(*b"chpl");
 pub(crate) const CTAB: Header = Header(*b"ctab");
 pub(crate) const CMOV: Header = Header(*b"cmov");
 pub(crate) const RMRA: Header = Header(*b"rmra");
@@ -106,10 +184,34 @@ pub(crate) const MJQT: Header = Header(*b"mjqt");
 pub(crate) const MJHT: Header = Header(*b"mjht");
 pub(crate) const ESDS: Header = Header(*b"esds");
 pub(crate) const AVCC: Header = Header(*b"avcC");
+pub(crate) const HVCC: Header = Header(*b"hvcC");
+pub(crate) const AV1C: Header = Header(*b"av1C");
+pub(crate) const VPCC: Header = Header(*b"vpcC");
+
+/// Font table—lists the fonts used by a 3GPP timed text track
+pub(crate) const FTAB: Header = Header(*b"ftab");
+
+/// WebVTT configuration—holds the header of the WebVTT file
+pub(crate) const VTTC_CONFIG: Header = Header(*b"vttC");
+pub(crate) const VLAB: Header = Header(*b"vlab");
+pub(crate) const STPP: Header = Header(*b"stpp");
 pub(crate) const PASP: Header = Header(*b"pasp");
 pub(crate) const COLR: Header = Header(*b"colr");
 pub(crate) const CLAP: Header = Header(*b"clap");
 
+/// Bit rate—the buffer size and bit rates of the stream
+pub(crate) const BTRT: Header = Header(*b"btrt");
+
+/// Sound decompression parameters—QuickTime extension of a sound sample
+/// description holding settings specific to its codec
+pub(crate) const WAVE: Header = Header(*b"wave");
+
+/// Original format—gives the data format of the sound before it was wrapped
+pub(crate) const FRMA: Header = Header(*b"frma");
+
+/// Audio channel layout
+pub(crate) const CHAN: Header = Header(*b"chan");
+
 macro_rules! set_header {
     ($s:ty, $header:ident) => {
         impl $s {
@@ -128,6 +230,13 @@ set_header!(Moov, MOOV);
 set_header!(Clip, CLIP);
 set_header!(Trak, TRAK);
 set_header!(Udta, UDTA);
+set_header!(Meta, META);
+set_header!(Keys, KEYS);
+set_header!(Ilst, ILST);
+set_header!(Data, DATA);
+set_header!(Mean, MEAN);
+set_header!(Name, NAME);
+set_header!(Chpl, CHPL);
 set_header!(Ctab, CTAB);
 set_header!(Cmov, CMOV);
 set_header!(Rmra, RMRA);
@@ -188,6 +297,33 @@ set_header!(Mjqt, MJQT);
 set_header!(Mjht, MJHT);
 set_header!(Esds, ESDS);
 set_header!(AvcC, AVCC);
+set_header!(HvcC, HVCC);
+set_header!(Av1C, AV1C);
+set_header!(VpcC, VPCC);
+set_header!(FontTable, FTAB);
+set_header!(WebVttConfig, VTTC_CONFIG);
+set_header!(WebVttSourceLabel, VLAB);
+set_header!(StppSampleEntry, STPP);
 set_header!(Pasp, PASP);
 set_header!(Colr, COLR);
 set_header!(Clap, CLAP);
+set_header!(Btrt, BTRT);
+set_header!(Wave, WAVE);
+set_header!(Frma, FRMA);
+set_header!(Chan, CHAN);
+set_header!(Moof, MOOF);
+set_header!(Mvex, MVEX);
+set_header!(Mehd, MEHD);
+set_header!(Trex, TREX);
+set_header!(Mfhd, MFHD);
+set_header!(Traf, TRAF);
+set_header!(Tfhd, TFHD);
+set_header!(Tfdt, TFDT);
+set_header!(Trun, TRUN);
+set_header!(Styp, STYP);
+set_header!(Sidx, SIDX);
+set_header!(Mfra, MFRA);
+set_header!(Pdin, PDIN);
+set_header!(Uuid, UUID);
+set_header!(Tfra, TFRA);
+set_header!(Mfro, MFRO);
@@ -2,9 +2,9 @@ use std::io;
 
 use atom_macro::{mp4_atom, mp4_media_data_type_atom};
 
-use crate::{Fixed32, Parse};
+use crate::{write_atom, Fixed32, Mp4Error, Mp4Result, Parse, Serialize};
 
-use super::header::*;
+use super::{header::*, Esds, TextSampleDescription};
 
 #[mp4_media_data_type_atom]
 pub struct BaseSampleDescriptionTable {
@@ -18,6 +18,22 @@ pub struct BaseSampleDescriptionTable {
 #[non_exhaustive]
 pub enum SampleDescriptionTable {
     Video(SampleVideoDescriptionTable),
+    Sound(SampleSoundDescriptionTable),
+    Text(TextSampleDescription),
+}
+
+impl Serialize for SampleDescriptionTable {
+    fn serialize<R: io::Seek + io::BufRead, W: io::Write>(
+        &self,
+        mp4: &mut crate::Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        match self {
+            SampleDescriptionTable::Video(video) => video.serialize(mp4, writer),
+            SampleDescriptionTable::Sound(sound) => sound.serialize(mp4, writer),
+            SampleDescriptionTable::Text(text) => text.serialize(mp4, writer),
+        }
+    }
 }
 
 #[mp4_media_data_type_atom]
@@ -54,41 +70,202 @@ impl SampleVideoDescriptionTable {
             })
             .next()
     }
+
+    /// The HEVC configuration of an `hvc1` or `hev1` description
+    pub fn hvcc(&self) -> Option<&HvcC> {
+        self.extensions.iter().find_map(|ext| match ext {
+            VideoSampleExtension::HvcC(hvcc) => Some(hvcc),
+            _ => None,
+        })
+    }
+
+    /// The AV1 configuration of an `av01` description
+    pub fn av1c(&self) -> Option<&Av1C> {
+        self.extensions.iter().find_map(|ext| match ext {
+            VideoSampleExtension::Av1C(av1c) => Some(av1c),
+            _ => None,
+        })
+    }
+
+    /// The VP8 or VP9 configuration of a `vp08` or `vp09` description
+    pub fn vpcc(&self) -> Option<&VpcC> {
+        self.extensions.iter().find_map(|ext| match ext {
+            VideoSampleExtension::VpcC(vpcc) => Some(vpcc),
+            _ => None,
+        })
+    }
+
+    pub fn pasp(&self) -> Option<&Pasp> {
+        self.extensions.iter().find_map(|ext| match ext {
+            VideoSampleExtension::Pasp(pasp) => Some(pasp),
+            _ => None,
+        })
+    }
+
+    pub fn colr(&self) -> Option<&Colr> {
+        self.extensions.iter().find_map(|ext| match ext {
+            VideoSampleExtension::Colr(colr) => Some(colr),
+            _ => None,
+        })
+    }
+
+    /// The `esds` atom of an MPEG-4 visual (`mp4v`) description
+    pub fn esds(&self) -> Option<&Esds> {
+        self.extensions.iter().find_map(|ext| match ext {
+            VideoSampleExtension::Esds(esds) => Some(esds),
+            _ => None,
+        })
+    }
+
+    /// The codec as given to the `codecs` parameter of RFC 6381, e.g.
+    /// `avc1.64001f`, including the profile and level where the decoder
+    /// configuration gives them
+    pub fn codec_string(&self) -> String {
+        let format = String::from_utf8_lossy(&self.data_format).into_owned();
+
+        if let Some(avcc) = self.avcc() {
+            return format!(
+                "{}.{:02x}{:02x}{:02x}",
+                format,
+                avcc.avc_profile_indication,
+                avcc.profile_compatibility,
+                avcc.avc_level_indication
+            );
+        }
+
+        if let Some(hvcc) = self.hvcc() {
+            let profile_space = ["", "A", "B", "C"][hvcc.general_profile_space as usize & 0b11];
+            let tier = match hvcc.general_tier_flag {
+                true => 'H',
+                false => 'L',
+            };
+            let mut codec = format!(
+                "{}.{}{}.{:X}.{}{}",
+                format,
+                profile_space,
+                hvcc.general_profile_idc,
+                hvcc.general_profile_compatibility_flags.reverse_bits(),
+                tier,
+                hvcc.general_level_idc
+            );
+
+            // the six constraint bytes, leaving out trailing zero bytes
+            let constraints = &hvcc.general_constraint_indicator_flags.to_be_bytes()[2..];
+            let len = constraints
+                .iter()
+                .rposition(|&byte| byte != 0)
+                .map_or(0, |i| i + 1);
+            for byte in &constraints[..len] {
+                codec.push_str(&format!(".{:02X}", byte));
+            }
+
+            return codec;
+        }
+
+        if let Some(av1c) = self.av1c() {
+            let tier = match av1c.seq_tier_0 {
+                true => 'H',
+                false => 'M',
+            };
+            return format!(
+                "{}.{}.{:02}{}.{:02}",
+                format,
+                av1c.seq_profile,
+                av1c.seq_level_idx_0,
+                tier,
+                av1c.bit_depth()
+            );
+        }
+
+        if let Some(vpcc) = self.vpcc() {
+            return format!(
+                "{}.{:02}.{:02}.{:02}",
+                format, vpcc.profile, vpcc.level, vpcc.bit_depth
+            );
+        }
+
+        if let Some(decoder_config) = self
+            .esds()
+            .and_then(|esds| esds.es_descriptor.decoder_config.as_ref())
+        {
+            return format!("{}.{:02x}", format, decoder_config.object_type_indication);
+        }
+
+        format
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum VideoSampleExtension {
-    Gama,
-    Fiel,
-    Mjqt,
-    Mjht,
-    Esds,
+    Gama(Gama),
+    Fiel(Fiel),
+    Mjqt(Mjqt),
+    Mjht(Mjht),
+    Esds(Esds),
     AvcC(AvcC),
-    Pasp,
-    Colr,
-    Clap,
+    HvcC(HvcC),
+    Av1C(Av1C),
+    VpcC(VpcC),
+    Pasp(Pasp),
+    Colr(Colr),
+    Clap(Clap),
+    Btrt(Btrt),
+    /// Any extension that isn't understood, with the data following its header
+    Unknown(Header, Vec<u8>),
 }
 
 impl Parse for VideoSampleExtension {
-    fn parse<R: io::Seek + io::BufRead>(mp4: &mut crate::Mp4<'_, R>) -> io::Result<Self>
+    fn parse<R: io::Seek + io::BufRead>(mp4: &mut crate::Mp4<'_, R>) -> Mp4Result<Self>
     where
         Self: Sized,
     {
         Ok(match mp4.peek_header()? {
-            GAMA => todo!(),
-            FIEL => todo!(),
-            MJQT => todo!(),
-            MJHT => todo!(),
-            ESDS => todo!(),
+            GAMA => VideoSampleExtension::Gama(Gama::parse(mp4)?),
+            FIEL => VideoSampleExtension::Fiel(Fiel::parse(mp4)?),
+            MJQT => VideoSampleExtension::Mjqt(Mjqt::parse(mp4)?),
+            MJHT => VideoSampleExtension::Mjht(Mjht::parse(mp4)?),
+            ESDS => VideoSampleExtension::Esds(Esds::parse(mp4)?),
             AVCC => VideoSampleExtension::AvcC(AvcC::parse(mp4)?),
-            PASP => todo!(),
-            COLR => todo!(),
-            CLAP => todo!(),
-            _ => todo!(),
+            HVCC => VideoSampleExtension::HvcC(HvcC::parse(mp4)?),
+            AV1C => VideoSampleExtension::Av1C(Av1C::parse(mp4)?),
+            VPCC => VideoSampleExtension::VpcC(VpcC::parse(mp4)?),
+            PASP => VideoSampleExtension::Pasp(Pasp::parse(mp4)?),
+            COLR => VideoSampleExtension::Colr(Colr::parse(mp4)?),
+            CLAP => VideoSampleExtension::Clap(Clap::parse(mp4)?),
+            BTRT => VideoSampleExtension::Btrt(Btrt::parse(mp4)?),
+            _ => {
+                let (header, data) = parse_unknown_atom(mp4)?;
+                VideoSampleExtension::Unknown(header, data)
+            }
         })
     }
 }
 
+impl Serialize for VideoSampleExtension {
+    fn serialize<R: io::Seek + io::BufRead, W: io::Write>(
+        &self,
+        mp4: &mut crate::Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        match self {
+            VideoSampleExtension::Gama(gama) => gama.serialize(mp4, writer),
+            VideoSampleExtension::Fiel(fiel) => fiel.serialize(mp4, writer),
+            VideoSampleExtension::Mjqt(mjqt) => mjqt.serialize(mp4, writer),
+            VideoSampleExtension::Mjht(mjht) => mjht.serialize(mp4, writer),
+            VideoSampleExtension::Esds(esds) => esds.serialize(mp4, writer),
+            VideoSampleExtension::AvcC(avcc) => avcc.serialize(mp4, writer),
+            VideoSampleExtension::HvcC(hvcc) => hvcc.serialize(mp4, writer),
+            VideoSampleExtension::Av1C(av1c) => av1c.serialize(mp4, writer),
+            VideoSampleExtension::VpcC(vpcc) => vpcc.serialize(mp4, writer),
+            VideoSampleExtension::Pasp(pasp) => pasp.serialize(mp4, writer),
+            VideoSampleExtension::Colr(colr) => colr.serialize(mp4, writer),
+            VideoSampleExtension::Clap(clap) => clap.serialize(mp4, writer),
+            VideoSampleExtension::Btrt(btrt) => btrt.serialize(mp4, writer),
+            VideoSampleExtension::Unknown(header, data) => write_atom(writer, *header, data),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct AvcC {
     pub configuration_version: u8,
@@ -102,54 +279,82 @@ pub struct AvcC {
 }
 
 impl Parse for AvcC {
-    fn parse<R: io::Seek + io::BufRead>(mp4: &mut crate::Mp4<'_, R>) -> io::Result<Self>
+    fn parse<R: io::Seek + io::BufRead>(mp4: &mut crate::Mp4<'_, R>) -> Mp4Result<Self>
     where
         Self: Sized,
     {
         let offset = mp4.reader.buffer.stream_position()?;
-        let len = mp4.reader.read_u32()?;
-        mp4.expect_header(AVCC)?;
-        let configuration_version = mp4.reader.read_u8()?;
-        let avc_profile_indication = mp4.reader.read_u8()?;
-        let profile_compatibility = mp4.reader.read_u8()?;
-        let avc_level_indication = mp4.reader.read_u8()?;
-        let length_size_minus_one = mp4.reader.read_u8()? & 0b0000_0011;
-        let num_of_sequence_parameter_sets = mp4.reader.read_u8()? & 0b0001_1111;
-        let sps = (0..num_of_sequence_parameter_sets)
-            .map(|_| SequenceParameterSet::parse(mp4))
-            .collect::<io::Result<Vec<_>>>()?;
-        let num_of_picture_parameter_sets = mp4.reader.read_u8()?;
-        let pps = (0..num_of_picture_parameter_sets)
-            .map(|_| PictureParameterSet::parse(mp4))
-            .collect::<io::Result<Vec<_>>>()?;
-
-        let extension = if [100, 110, 122, 144].contains(&profile_compatibility) {
-            Some(AvcCExtension::parse(mp4)?)
-        } else {
-            None
-        };
+        let len = mp4.expect_atom(AVCC)?;
 
-        assert_eq!(
-            offset,
-            mp4.reader
-                .buffer
-                .stream_position()?
-                .saturating_sub(len as u64)
-        );
+        mp4.in_atom(offset, len, AVCC, |mp4| {
+            let configuration_version = mp4.reader.read_u8()?;
+            let avc_profile_indication = mp4.reader.read_u8()?;
+            let profile_compatibility = mp4.reader.read_u8()?;
+            let avc_level_indication = mp4.reader.read_u8()?;
+            let length_size_minus_one = mp4.reader.read_u8()? & 0b0000_0011;
+            let num_of_sequence_parameter_sets = mp4.reader.read_u8()? & 0b0001_1111;
+            let sps = (0..num_of_sequence_parameter_sets)
+                .map(|_| SequenceParameterSet::parse(mp4))
+                .collect::<Mp4Result<Vec<_>>>()?;
+            let num_of_picture_parameter_sets = mp4.reader.read_u8()?;
+            let pps = (0..num_of_picture_parameter_sets)
+                .map(|_| PictureParameterSet::parse(mp4))
+                .collect::<Mp4Result<Vec<_>>>()?;
 
-        Ok(Self {
-            configuration_version,
-            avc_profile_indication,
-            profile_compatibility,
-            avc_level_indication,
-            length_size_minus_one,
-            sequence_parameter_sets: sps,
-            picture_parameter_sets: pps,
-            extension,
+            let extension = if [100, 110, 122, 144].contains(&profile_compatibility) {
+                Some(AvcCExtension::parse(mp4)?)
+            } else {
+                None
+            };
+
+            Ok(Self {
+                configuration_version,
+                avc_profile_indication,
+                profile_compatibility,
+                avc_level_indication,
+                length_size_minus_one,
+                sequence_parameter_sets: sps,
+                picture_parameter_sets: pps,
+                extension,
+            })
         })
     }
 }
 
+impl Serialize for AvcC {
+    fn serialize<R: io::Seek + io::BufRead, W: io::Write>(
+        &self,
+        mp4: &mut crate::Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let mut body = vec![
+            self.configuration_version,
+            self.avc_profile_indication,
+            self.profile_compatibility,
+            self.avc_level_indication,
+            0b1111_1100 | self.length_size_minus_one,
+            0b1110_0000 | self.sequence_parameter_sets.len() as u8,
+        ];
+        self.sequence_parameter_sets.serialize(mp4, &mut body)?;
+        body.push(self.picture_parameter_sets.len() as u8);
+        self.picture_parameter_sets.serialize(mp4, &mut body)?;
+
+        if let Some(extension) = &self.extension {
+            body.extend([
+                0b1111_1100 | extension.chroma_format,
+                0b1111_1000 | extension.bit_depth_luma_minus8,
+                0b1111_1000 | extension.bit_depth_chroma_minus8,
+                extension.sequence_parameter_set_extension.len() as u8,
+            ]);
+            extension
+                .sequence_parameter_set_extension
+                .serialize(mp4, &mut body)?;
+        }
+
+        write_atom(writer, AVCC, &body)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct AvcCExtension {
     pub chroma_format: u8,
@@ -159,7 +364,7 @@ pub struct AvcCExtension {
 }
 
 impl Parse for AvcCExtension {
-    fn parse<R: io::Seek + io::BufRead>(mp4: &mut crate::Mp4<'_, R>) -> io::Result<Self>
+    fn parse<R: io::Seek + io::BufRead>(mp4: &mut crate::Mp4<'_, R>) -> Mp4Result<Self>
     where
         Self: Sized,
     {
@@ -169,7 +374,7 @@ impl Parse for AvcCExtension {
         let num_of_sequence_parameter_set_ext = mp4.reader.read_u8()?;
         let sequence_parameter_set_extension = (0..num_of_sequence_parameter_set_ext)
             .map(|_| SequenceParameterSet::parse(mp4))
-            .collect::<io::Result<Vec<_>>>()?;
+            .collect::<Mp4Result<Vec<_>>>()?;
 
         Ok(Self {
             chroma_format,
@@ -180,6 +385,378 @@ impl Parse for AvcCExtension {
     }
 }
 
+/// HEVC decoder configuration record
+#[derive(Debug, Clone)]
+pub struct HvcC {
+    pub configuration_version: u8,
+    pub general_profile_space: u8,
+    pub general_tier_flag: bool,
+    pub general_profile_idc: u8,
+    pub general_profile_compatibility_flags: u32,
+    /// Only the low 48 bits are used
+    pub general_constraint_indicator_flags: u64,
+    pub general_level_idc: u8,
+    pub min_spatial_segmentation_idc: u16,
+    pub parallelism_type: u8,
+    pub chroma_format_idc: u8,
+    pub bit_depth_luma_minus8: u8,
+    pub bit_depth_chroma_minus8: u8,
+    /// In frames per 256 seconds, or `0` if unspecified
+    pub avg_frame_rate: u16,
+    pub constant_frame_rate: u8,
+    pub num_temporal_layers: u8,
+    pub temporal_id_nested: bool,
+    pub length_size_minus_one: u8,
+    pub arrays: Vec<HvcCNalArray>,
+}
+
+/// The NAL units of a single type in an HEVC decoder configuration record
+#[derive(Debug, Clone)]
+pub struct HvcCNalArray {
+    /// Whether every NAL unit of this type is in the array, rather than some
+    /// also being in the stream
+    pub array_completeness: bool,
+    pub nal_unit_type: u8,
+    pub nal_units: Vec<Vec<u8>>,
+}
+
+impl HvcC {
+    pub const VPS_NUT: u8 = 32;
+    pub const SPS_NUT: u8 = 33;
+    pub const PPS_NUT: u8 = 34;
+    pub const PREFIX_SEI_NUT: u8 = 39;
+    pub const SUFFIX_SEI_NUT: u8 = 40;
+
+    /// Every NAL unit of the given type in the record
+    pub fn nal_units(&self, nal_unit_type: u8) -> impl Iterator<Item = &[u8]> {
+        self.arrays
+            .iter()
+            .filter(move |array| array.nal_unit_type == nal_unit_type)
+            .flat_map(|array| array.nal_units.iter().map(Vec::as_slice))
+    }
+
+    pub fn video_parameter_sets(&self) -> impl Iterator<Item = &[u8]> {
+        self.nal_units(Self::VPS_NUT)
+    }
+
+    pub fn sequence_parameter_sets(&self) -> impl Iterator<Item = &[u8]> {
+        self.nal_units(Self::SPS_NUT)
+    }
+
+    pub fn picture_parameter_sets(&self) -> impl Iterator<Item = &[u8]> {
+        self.nal_units(Self::PPS_NUT)
+    }
+}
+
+impl Parse for HvcC {
+    fn parse<R: io::Seek + io::BufRead>(mp4: &mut crate::Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(HVCC)?;
+
+        mp4.in_atom(offset, len, HVCC, |mp4| {
+            let configuration_version = mp4.reader.read_u8()?;
+            let profile = mp4.reader.read_u8()?;
+            let general_profile_compatibility_flags = mp4.reader.read_u32()?;
+            let [a, b, c, d, e, f] = mp4.reader.read_bytes_const::<6>()?;
+            let general_constraint_indicator_flags = u64::from_be_bytes([0, 0, a, b, c, d, e, f]);
+            let general_level_idc = mp4.reader.read_u8()?;
+            let min_spatial_segmentation_idc = mp4.reader.read_u16()? & 0x0fff;
+            let parallelism_type = mp4.reader.read_u8()? & 0b11;
+            let chroma_format_idc = mp4.reader.read_u8()? & 0b11;
+            let bit_depth_luma_minus8 = mp4.reader.read_u8()? & 0b111;
+            let bit_depth_chroma_minus8 = mp4.reader.read_u8()? & 0b111;
+            let avg_frame_rate = mp4.reader.read_u16()?;
+            let frame_rate = mp4.reader.read_u8()?;
+
+            let num_of_arrays = mp4.reader.read_u8()?;
+            let mut arrays = Vec::with_capacity(num_of_arrays as usize);
+            for _ in 0..num_of_arrays {
+                let nal_unit_type = mp4.reader.read_u8()?;
+                let num_nalus = mp4.reader.read_u16()?;
+
+                let mut nal_units = Vec::with_capacity(num_nalus as usize);
+                for _ in 0..num_nalus {
+                    let nal_unit_length = mp4.reader.read_u16()?;
+                    nal_units.push(mp4.reader.read_bytes_dyn(nal_unit_length as usize)?);
+                }
+
+                arrays.push(HvcCNalArray {
+                    array_completeness: nal_unit_type & 0x80 != 0,
+                    nal_unit_type: nal_unit_type & 0b0011_1111,
+                    nal_units,
+                });
+            }
+
+            Ok(Self {
+                configuration_version,
+                general_profile_space: profile >> 6,
+                general_tier_flag: profile & 0b0010_0000 != 0,
+                general_profile_idc: profile & 0b0001_1111,
+                general_profile_compatibility_flags,
+                general_constraint_indicator_flags,
+                general_level_idc,
+                min_spatial_segmentation_idc,
+                parallelism_type,
+                chroma_format_idc,
+                bit_depth_luma_minus8,
+                bit_depth_chroma_minus8,
+                avg_frame_rate,
+                constant_frame_rate: frame_rate >> 6,
+                num_temporal_layers: (frame_rate >> 3) & 0b111,
+                temporal_id_nested: frame_rate & 0b100 != 0,
+                length_size_minus_one: frame_rate & 0b11,
+                arrays,
+            })
+        })
+    }
+}
+
+impl Serialize for HvcC {
+    fn serialize<R: io::Seek + io::BufRead, W: io::Write>(
+        &self,
+        _mp4: &mut crate::Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let mut body = vec![
+            self.configuration_version,
+            (self.general_profile_space << 6)
+                | ((self.general_tier_flag as u8) << 5)
+                | self.general_profile_idc,
+        ];
+        body.extend(self.general_profile_compatibility_flags.to_be_bytes());
+        body.extend(&self.general_constraint_indicator_flags.to_be_bytes()[2..]);
+        body.push(self.general_level_idc);
+        body.extend((0xf000 | self.min_spatial_segmentation_idc).to_be_bytes());
+        body.extend([
+            0b1111_1100 | self.parallelism_type,
+            0b1111_1100 | self.chroma_format_idc,
+            0b1111_1000 | self.bit_depth_luma_minus8,
+            0b1111_1000 | self.bit_depth_chroma_minus8,
+        ]);
+        body.extend(self.avg_frame_rate.to_be_bytes());
+        body.push(
+            (self.constant_frame_rate << 6)
+                | (self.num_temporal_layers << 3)
+                | ((self.temporal_id_nested as u8) << 2)
+                | self.length_size_minus_one,
+        );
+
+        body.push(self.arrays.len() as u8);
+        for array in &self.arrays {
+            body.push(((array.array_completeness as u8) << 7) | array.nal_unit_type);
+            body.extend((array.nal_units.len() as u16).to_be_bytes());
+
+            for nal_unit in &array.nal_units {
+                body.extend((nal_unit.len() as u16).to_be_bytes());
+                body.extend(nal_unit);
+            }
+        }
+
+        write_atom(writer, HVCC, &body)
+    }
+}
+
+/// AV1 codec configuration record
+#[derive(Debug, Clone)]
+pub struct Av1C {
+    pub version: u8,
+    pub seq_profile: u8,
+    pub seq_level_idx_0: u8,
+    pub seq_tier_0: bool,
+    pub high_bitdepth: bool,
+    pub twelve_bit: bool,
+    pub monochrome: bool,
+    pub chroma_subsampling_x: bool,
+    pub chroma_subsampling_y: bool,
+    pub chroma_sample_position: u8,
+    pub initial_presentation_delay_minus_one: Option<u8>,
+    /// The sequence header OBU and any metadata OBUs, in low overhead bitstream
+    /// format
+    pub config_obus: Vec<u8>,
+}
+
+impl Av1C {
+    pub fn bit_depth(&self) -> u8 {
+        match (self.high_bitdepth, self.twelve_bit) {
+            (true, true) => 12,
+            (true, false) => 10,
+            (false, _) => 8,
+        }
+    }
+}
+
+impl Parse for Av1C {
+    fn parse<R: io::Seek + io::BufRead>(mp4: &mut crate::Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(AV1C)?;
+
+        mp4.in_atom(offset, len, AV1C, |mp4| {
+            let version = mp4.reader.read_u8()? & 0b0111_1111;
+            let profile = mp4.reader.read_u8()?;
+            let flags = mp4.reader.read_u8()?;
+            let delay = mp4.reader.read_u8()?;
+
+            let config_obus_len = remaining_len(mp4, offset, len)?;
+            let config_obus = mp4.reader.read_bytes_dyn(config_obus_len as usize)?;
+
+            Ok(Self {
+                version,
+                seq_profile: profile >> 5,
+                seq_level_idx_0: profile & 0b0001_1111,
+                seq_tier_0: flags & 0x80 != 0,
+                high_bitdepth: flags & 0x40 != 0,
+                twelve_bit: flags & 0x20 != 0,
+                monochrome: flags & 0x10 != 0,
+                chroma_subsampling_x: flags & 0x08 != 0,
+                chroma_subsampling_y: flags & 0x04 != 0,
+                chroma_sample_position: flags & 0b11,
+                initial_presentation_delay_minus_one: match delay & 0x10 {
+                    0 => None,
+                    _ => Some(delay & 0x0f),
+                },
+                config_obus,
+            })
+        })
+    }
+}
+
+impl Serialize for Av1C {
+    fn serialize<R: io::Seek + io::BufRead, W: io::Write>(
+        &self,
+        _mp4: &mut crate::Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let mut body = vec![
+            0x80 | self.version,
+            (self.seq_profile << 5) | self.seq_level_idx_0,
+            ((self.seq_tier_0 as u8) << 7)
+                | ((self.high_bitdepth as u8) << 6)
+                | ((self.twelve_bit as u8) << 5)
+                | ((self.monochrome as u8) << 4)
+                | ((self.chroma_subsampling_x as u8) << 3)
+                | ((self.chroma_subsampling_y as u8) << 2)
+                | self.chroma_sample_position,
+            match self.initial_presentation_delay_minus_one {
+                Some(delay) => 0x10 | delay,
+                None => 0,
+            },
+        ];
+        body.extend(&self.config_obus);
+
+        write_atom(writer, AV1C, &body)
+    }
+}
+
+/// VP codec configuration record, used by VP8 and VP9
+#[derive(Debug, Clone)]
+pub struct VpcC {
+    pub version: u8,
+    pub flags: [u8; 3],
+    pub profile: u8,
+    pub level: u8,
+    pub bit_depth: u8,
+    /// `0` and `1` for 4:2:0 with vertical or colocated chroma, `2` for 4:2:2
+    /// and `3` for 4:4:4
+    pub chroma_subsampling: u8,
+    pub video_full_range: bool,
+    /// As defined by ISO/IEC 23091-2. Version 0 records don't carry these, so
+    /// they are `2` (unspecified)
+    pub colour_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coefficients: u8,
+    pub codec_initialization_data: Vec<u8>,
+}
+
+impl Parse for VpcC {
+    fn parse<R: io::Seek + io::BufRead>(mp4: &mut crate::Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(VPCC)?;
+
+        mp4.in_atom(offset, len, VPCC, |mp4| {
+            let version = mp4.reader.read_u8()?;
+            let flags = mp4.reader.read_bytes_const::<3>()?;
+            let profile = mp4.reader.read_u8()?;
+            let level = mp4.reader.read_u8()?;
+
+            let depth = mp4.reader.read_u8()?;
+            let bit_depth = depth >> 4;
+
+            let (chroma_subsampling, video_full_range, colour) = match version {
+                0 => {
+                    let subsampling = mp4.reader.read_u8()?;
+                    (subsampling >> 4, subsampling & 1 != 0, [2, 2, 2])
+                }
+                _ => (
+                    (depth >> 1) & 0b111,
+                    depth & 1 != 0,
+                    mp4.reader.read_bytes_const::<3>()?,
+                ),
+            };
+            let [colour_primaries, transfer_characteristics, matrix_coefficients] = colour;
+
+            let codec_initialization_data_size = mp4.reader.read_u16()?;
+            let codec_initialization_data = mp4
+                .reader
+                .read_bytes_dyn(codec_initialization_data_size as usize)?;
+
+            Ok(Self {
+                version,
+                flags,
+                profile,
+                level,
+                bit_depth,
+                chroma_subsampling,
+                video_full_range,
+                colour_primaries,
+                transfer_characteristics,
+                matrix_coefficients,
+                codec_initialization_data,
+            })
+        })
+    }
+}
+
+impl Serialize for VpcC {
+    fn serialize<R: io::Seek + io::BufRead, W: io::Write>(
+        &self,
+        _mp4: &mut crate::Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let mut body = vec![self.version];
+        body.extend(self.flags);
+        body.extend([self.profile, self.level]);
+
+        match self.version {
+            0 => body.extend([
+                self.bit_depth << 4,
+                (self.chroma_subsampling << 4) | self.video_full_range as u8,
+            ]),
+            _ => body.extend([
+                (self.bit_depth << 4)
+                    | (self.chroma_subsampling << 1)
+                    | self.video_full_range as u8,
+                self.colour_primaries,
+                self.transfer_characteristics,
+                self.matrix_coefficients,
+            ]),
+        }
+
+        body.extend((self.codec_initialization_data.len() as u16).to_be_bytes());
+        body.extend(&self.codec_initialization_data);
+
+        write_atom(writer, VPCC, &body)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SequenceParameterSet {
     pub len: u16,
@@ -187,7 +764,7 @@ pub struct SequenceParameterSet {
 }
 
 impl Parse for SequenceParameterSet {
-    fn parse<R: io::Seek + io::BufRead>(mp4: &mut crate::Mp4<'_, R>) -> io::Result<Self>
+    fn parse<R: io::Seek + io::BufRead>(mp4: &mut crate::Mp4<'_, R>) -> Mp4Result<Self>
     where
         Self: Sized,
     {
@@ -198,6 +775,18 @@ impl Parse for SequenceParameterSet {
     }
 }
 
+impl Serialize for SequenceParameterSet {
+    fn serialize<R: io::Seek + io::BufRead, W: io::Write>(
+        &self,
+        _mp4: &mut crate::Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        writer.write_all(&(self.nal_unit.len() as u16).to_be_bytes())?;
+
+        Ok(writer.write_all(&self.nal_unit)?)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PictureParameterSet {
     pub len: u16,
@@ -205,7 +794,7 @@ pub struct PictureParameterSet {
 }
 
 impl Parse for PictureParameterSet {
-    fn parse<R: io::Seek + io::BufRead>(mp4: &mut crate::Mp4<'_, R>) -> io::Result<Self>
+    fn parse<R: io::Seek + io::BufRead>(mp4: &mut crate::Mp4<'_, R>) -> Mp4Result<Self>
     where
         Self: Sized,
     {
@@ -216,25 +805,244 @@ impl Parse for PictureParameterSet {
     }
 }
 
+impl Serialize for PictureParameterSet {
+    fn serialize<R: io::Seek + io::BufRead, W: io::Write>(
+        &self,
+        _mp4: &mut crate::Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        writer.write_all(&(self.nal_unit.len() as u16).to_be_bytes())?;
+
+        Ok(writer.write_all(&self.nal_unit)?)
+    }
+}
+
+/// Reads an atom of any type, returning its header along with the data
+/// following the header
+pub(super) fn parse_unknown_atom<R: io::Seek + io::BufRead>(
+    mp4: &mut crate::Mp4<'_, R>,
+) -> Mp4Result<(Header, Vec<u8>)> {
+    let offset = mp4.position()?;
+    let (len, header) = mp4.read_atom_header()?;
+
+    mp4.in_atom(offset, len, header, |mp4| {
+        let data_len = offset + len - mp4.position()?;
+
+        Ok((header, mp4.reader.read_bytes_dyn(data_len as usize)?))
+    })
+}
+
+/// The number of bytes of the atom at `offset` that are yet to be read, failing
+/// if its fixed fields alone are longer than the atom
+fn remaining_len<R: io::Seek + io::BufRead>(
+    mp4: &mut crate::Mp4<'_, R>,
+    offset: u64,
+    len: u64,
+) -> Mp4Result<u64> {
+    (offset + len)
+        .checked_sub(mp4.position()?)
+        .ok_or_else(|| Mp4Error::InvalidAtomLength {
+            offset,
+            path: mp4.atom_path(),
+            len,
+        })
+}
+
+/// Gamma level atom
 #[mp4_atom]
-pub struct Gama {}
-#[mp4_atom]
-pub struct Fiel {}
+pub struct Gama {
+    pub gamma: Fixed32,
+}
+
+impl Gama {
+    pub fn gamma(&self) -> f64 {
+        self.gamma as f64 / (1 << 16) as f64
+    }
+}
+
+/// Field handling atom
 #[mp4_atom]
-pub struct Mjqt {}
+pub struct Fiel {
+    /// `1` for progressive scan, `2` for interlaced
+    pub field_count: u8,
+    /// For interlaced video, `1` or `6` if the top field is displayed first,
+    /// `9` or `14` if the bottom field is
+    pub field_ordering: u8,
+}
+
+impl Fiel {
+    pub fn is_interlaced(&self) -> bool {
+        self.field_count == 2
+    }
+}
+
+/// Motion-JPEG quantization table atom
 #[mp4_atom]
-pub struct Mjht {}
+pub struct Mjqt {
+    pub data: Vec<u8>,
+}
+
+/// Motion-JPEG Huffman table atom
 #[mp4_atom]
-pub struct Esds {}
+pub struct Mjht {
+    pub data: Vec<u8>,
+}
+
+/// Pixel aspect ratio atom, giving the ratio of a pixel's width to its height
+/// as `h_spacing:v_spacing`
 #[mp4_atom]
-pub struct Pasp {}
+pub struct Pasp {
+    pub h_spacing: u32,
+    pub v_spacing: u32,
+}
+
+/// Colour information atom
+#[derive(Debug, Clone)]
+pub enum Colr {
+    /// ISO colour parameters, as defined by ISO/IEC 23091-2
+    Nclx {
+        colour_primaries: u16,
+        transfer_characteristics: u16,
+        matrix_coefficients: u16,
+        full_range: bool,
+    },
+    /// QuickTime colour parameters
+    Nclc {
+        primaries: u16,
+        transfer_function: u16,
+        matrix: u16,
+    },
+    /// An ICC profile, either restricted (`rICC`) or unrestricted (`prof`)
+    Icc {
+        colour_type: [u8; 4],
+        profile: Vec<u8>,
+    },
+    Unknown {
+        colour_type: [u8; 4],
+        data: Vec<u8>,
+    },
+}
+
+impl Parse for Colr {
+    fn parse<R: io::Seek + io::BufRead>(mp4: &mut crate::Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(COLR)?;
+
+        mp4.in_atom(offset, len, COLR, |mp4| {
+            let colour_type = mp4.reader.read_bytes_const::<4>()?;
+
+            Ok(match &colour_type {
+                b"nclx" => {
+                    let colour_primaries = mp4.reader.read_u16()?;
+                    let transfer_characteristics = mp4.reader.read_u16()?;
+                    let matrix_coefficients = mp4.reader.read_u16()?;
+                    let full_range = mp4.reader.read_u8()? & 0x80 != 0;
+
+                    Colr::Nclx {
+                        colour_primaries,
+                        transfer_characteristics,
+                        matrix_coefficients,
+                        full_range,
+                    }
+                }
+                b"nclc" => Colr::Nclc {
+                    primaries: mp4.reader.read_u16()?,
+                    transfer_function: mp4.reader.read_u16()?,
+                    matrix: mp4.reader.read_u16()?,
+                },
+                _ => {
+                    let data_len = remaining_len(mp4, offset, len)?;
+                    let data = mp4.reader.read_bytes_dyn(data_len as usize)?;
+
+                    match &colour_type {
+                        b"rICC" | b"prof" => Colr::Icc {
+                            colour_type,
+                            profile: data,
+                        },
+                        _ => Colr::Unknown { colour_type, data },
+                    }
+                }
+            })
+        })
+    }
+}
+
+impl Serialize for Colr {
+    fn serialize<R: io::Seek + io::BufRead, W: io::Write>(
+        &self,
+        _mp4: &mut crate::Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let mut body = Vec::new();
+
+        match self {
+            Colr::Nclx {
+                colour_primaries,
+                transfer_characteristics,
+                matrix_coefficients,
+                full_range,
+            } => {
+                body.extend(b"nclx");
+                body.extend(colour_primaries.to_be_bytes());
+                body.extend(transfer_characteristics.to_be_bytes());
+                body.extend(matrix_coefficients.to_be_bytes());
+                body.push((*full_range as u8) << 7);
+            }
+            Colr::Nclc {
+                primaries,
+                transfer_function,
+                matrix,
+            } => {
+                body.extend(b"nclc");
+                body.extend(primaries.to_be_bytes());
+                body.extend(transfer_function.to_be_bytes());
+                body.extend(matrix.to_be_bytes());
+            }
+            Colr::Icc {
+                colour_type,
+                profile: data,
+            }
+            | Colr::Unknown { colour_type, data } => {
+                body.extend(colour_type);
+                body.extend(data);
+            }
+        }
+
+        write_atom(writer, COLR, &body)
+    }
+}
+
+/// Clean aperture atom, giving the region of the picture intended for
+/// display. Each value is a fraction of a numerator over a denominator, with
+/// the offsets measured from the centre of the picture
 #[mp4_atom]
-pub struct Colr {}
+pub struct Clap {
+    pub clean_aperture_width_n: u32,
+    pub clean_aperture_width_d: u32,
+    pub clean_aperture_height_n: u32,
+    pub clean_aperture_height_d: u32,
+    pub horiz_off_n: i32,
+    pub horiz_off_d: u32,
+    pub vert_off_n: i32,
+    pub vert_off_d: u32,
+}
+
+/// Bit rate atom
 #[mp4_atom]
-pub struct Clap {}
+pub struct Btrt {
+    pub buffer_size_db: u32,
+    pub max_bitrate: u32,
+    pub avg_bitrate: u32,
+}
 
-#[mp4_media_data_type_atom]
-pub struct SampleSoundVersion0DescriptionTable {
+/// A sound sample description, covering QuickTime sound descriptions of
+/// versions 0, 1 and 2 as well as the ISO `AudioSampleEntry`, which shares its
+/// layout with version 0
+#[derive(Debug, Clone)]
+pub struct SampleSoundDescriptionTable {
     pub data_format: [u8; 4],
     pub reserved: [u8; 6],
     pub data_reference_index: u16,
@@ -242,25 +1050,536 @@ pub struct SampleSoundVersion0DescriptionTable {
     pub version: u16,
     pub revision_level: u16,
     pub vendor: u32,
+    /// Always 3 in version 2 descriptions, see
+    /// [`SampleSoundDescriptionTable::channel_count`]
     pub number_of_channels: u16,
     pub sample_size: u16,
-    pub compression_id: u16,
+    pub compression_id: i16,
     pub packet_size: u16,
+    /// Unsigned 16.16 fixed-point, see
+    /// [`SampleSoundDescriptionTable::sample_rate`]
     pub sample_rate: Fixed32,
+
+    pub version_fields: SoundDescriptionVersion,
+    pub extensions: Vec<SoundSampleExtension>,
 }
 
-#[mp4_media_data_type_atom]
-pub struct SampleSoundVersion1DescriptionTable {
+/// The fields a sound sample description adds to version 0 in later versions
+#[derive(Debug, Clone)]
+pub enum SoundDescriptionVersion {
+    V0,
+    V1 {
+        samples_per_packet: u32,
+        bytes_per_packet: u32,
+        bytes_per_frame: u32,
+        bytes_per_sample: u32,
+    },
+    V2 {
+        size_of_struct_only: u32,
+        audio_sample_rate: f64,
+        num_audio_channels: u32,
+        const_bits_per_channel: u32,
+        format_specific_flags: u32,
+        const_bytes_per_audio_packet: u32,
+        const_lpcm_frames_per_audio_packet: u32,
+    },
+}
+
+impl SampleSoundDescriptionTable {
+    pub fn channel_count(&self) -> u32 {
+        match self.version_fields {
+            SoundDescriptionVersion::V2 {
+                num_audio_channels, ..
+            } => num_audio_channels,
+            _ => self.number_of_channels as u32,
+        }
+    }
+
+    /// The number of samples per second
+    pub fn sample_rate(&self) -> f64 {
+        match self.version_fields {
+            SoundDescriptionVersion::V2 {
+                audio_sample_rate, ..
+            } => audio_sample_rate,
+            _ => self.sample_rate as f64 / (1 << 16) as f64,
+        }
+    }
+
+    /// The format the sound is encoded in. QuickTime may wrap a codec in a
+    /// different data format, in which case the original format is given by a
+    /// `frma` atom inside `wave`
+    pub fn codec_type(&self) -> [u8; 4] {
+        self.wave()
+            .and_then(|wave| {
+                wave.extensions.iter().find_map(|ext| match ext {
+                    SoundSampleExtension::Frma(frma) => Some(frma.data_format),
+                    _ => None,
+                })
+            })
+            .unwrap_or(self.data_format)
+    }
+
+    pub fn wave(&self) -> Option<&Wave> {
+        self.extensions.iter().find_map(|ext| match ext {
+            SoundSampleExtension::Wave(wave) => Some(wave),
+            _ => None,
+        })
+    }
+
+    pub fn chan(&self) -> Option<&Chan> {
+        self.extensions.iter().find_map(|ext| match ext {
+            SoundSampleExtension::Chan(chan) => Some(chan),
+            _ => None,
+        })
+    }
+
+    /// The `esds` atom of the description, which QuickTime files keep inside
+    /// `wave`
+    pub fn esds(&self) -> Option<&Esds> {
+        self.extensions
+            .iter()
+            .chain(self.wave().into_iter().flat_map(|wave| &wave.extensions))
+            .find_map(|ext| match ext {
+                SoundSampleExtension::Esds(esds) => Some(esds),
+                _ => None,
+            })
+    }
+
+    /// The codec as given to the `codecs` parameter of RFC 6381, e.g.
+    /// `mp4a.40.2` for AAC LC
+    pub fn codec_string(&self) -> String {
+        let codec_type = self.codec_type();
+
+        match &codec_type {
+            b"mp4a" => {
+                let decoder_config = match self
+                    .esds()
+                    .and_then(|esds| esds.es_descriptor.decoder_config.as_ref())
+                {
+                    Some(decoder_config) => decoder_config,
+                    None => return "mp4a".to_string(),
+                };
+
+                match &decoder_config.audio_specific_config {
+                    // HE-AAC is signalled by its extension rather than the
+                    // core AAC LC object type
+                    Some(config) => {
+                        let object_type = match (config.sbr_present, config.ps_present) {
+                            (_, Some(true)) => 29,
+                            (Some(true), _) => 5,
+                            _ => match config.extension_audio_object_type {
+                                Some(5) => 5,
+                                _ => config.audio_object_type,
+                            },
+                        };
+                        format!(
+                            "mp4a.{:02x}.{}",
+                            decoder_config.object_type_indication, object_type
+                        )
+                    }
+                    None => format!("mp4a.{:02x}", decoder_config.object_type_indication),
+                }
+            }
+            b"Opus" => "opus".to_string(),
+            b"fLaC" => "flac".to_string(),
+            _ => String::from_utf8_lossy(&codec_type).into_owned(),
+        }
+    }
+}
+
+impl Parse for SampleSoundDescriptionTable {
+    fn parse<R: io::Seek + io::BufRead>(mp4: &mut crate::Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let (len, header) = mp4.read_atom_header()?;
+
+        mp4.in_atom(offset, len, header, |mp4| {
+            let reserved = mp4.reader.read_bytes_const::<6>()?;
+            let data_reference_index = mp4.reader.read_u16()?;
+
+            let version = mp4.reader.read_u16()?;
+            let revision_level = mp4.reader.read_u16()?;
+            let vendor = mp4.reader.read_u32()?;
+            let number_of_channels = mp4.reader.read_u16()?;
+            let sample_size = mp4.reader.read_u16()?;
+            let compression_id = mp4.reader.read_u16()? as i16;
+            let packet_size = mp4.reader.read_u16()?;
+            let sample_rate = mp4.reader.read_u32()?;
+
+            let version_fields = match version {
+                0 => SoundDescriptionVersion::V0,
+                1 => SoundDescriptionVersion::V1 {
+                    samples_per_packet: mp4.reader.read_u32()?,
+                    bytes_per_packet: mp4.reader.read_u32()?,
+                    bytes_per_frame: mp4.reader.read_u32()?,
+                    bytes_per_sample: mp4.reader.read_u32()?,
+                },
+                2 => {
+                    let size_of_struct_only = mp4.reader.read_u32()?;
+                    let audio_sample_rate = f64::from_bits(mp4.reader.read_u64()?);
+                    let num_audio_channels = mp4.reader.read_u32()?;
+                    let _always_7f000000 = mp4.reader.read_u32()?;
+
+                    SoundDescriptionVersion::V2 {
+                        size_of_struct_only,
+                        audio_sample_rate,
+                        num_audio_channels,
+                        const_bits_per_channel: mp4.reader.read_u32()?,
+                        format_specific_flags: mp4.reader.read_u32()?,
+                        const_bytes_per_audio_packet: mp4.reader.read_u32()?,
+                        const_lpcm_frames_per_audio_packet: mp4.reader.read_u32()?,
+                    }
+                }
+                _ => {
+                    return Err(Mp4Error::UnsupportedSampleEntry {
+                        offset,
+                        path: mp4.atom_path(),
+                        handler: *b"soun",
+                    })
+                }
+            };
+
+            let mut extensions = Vec::new();
+            while mp4.position()? < offset + len {
+                extensions.push(SoundSampleExtension::parse(mp4)?);
+            }
+
+            Ok(Self {
+                data_format: header.0,
+                reserved,
+                data_reference_index,
+                version,
+                revision_level,
+                vendor,
+                number_of_channels,
+                sample_size,
+                compression_id,
+                packet_size,
+                sample_rate,
+                version_fields,
+                extensions,
+            })
+        })
+    }
+}
+
+impl Serialize for SampleSoundDescriptionTable {
+    fn serialize<R: io::Seek + io::BufRead, W: io::Write>(
+        &self,
+        mp4: &mut crate::Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let mut body = self.reserved.to_vec();
+        body.extend(self.data_reference_index.to_be_bytes());
+        body.extend(self.version.to_be_bytes());
+        body.extend(self.revision_level.to_be_bytes());
+        body.extend(self.vendor.to_be_bytes());
+        body.extend(self.number_of_channels.to_be_bytes());
+        body.extend(self.sample_size.to_be_bytes());
+        body.extend(self.compression_id.to_be_bytes());
+        body.extend(self.packet_size.to_be_bytes());
+        body.extend(self.sample_rate.to_be_bytes());
+
+        match self.version_fields {
+            SoundDescriptionVersion::V0 => {}
+            SoundDescriptionVersion::V1 {
+                samples_per_packet,
+                bytes_per_packet,
+                bytes_per_frame,
+                bytes_per_sample,
+            } => {
+                for field in [
+                    samples_per_packet,
+                    bytes_per_packet,
+                    bytes_per_frame,
+                    bytes_per_sample,
+                ] {
+                    body.extend(field.to_be_bytes());
+                }
+            }
+            SoundDescriptionVersion::V2 {
+                size_of_struct_only,
+                audio_sample_rate,
+                num_audio_channels,
+                const_bits_per_channel,
+                format_specific_flags,
+                const_bytes_per_audio_packet,
+                const_lpcm_frames_per_audio_packet,
+            } => {
+                body.extend(size_of_struct_only.to_be_bytes());
+                body.extend(audio_sample_rate.to_bits().to_be_bytes());
+                body.extend(num_audio_channels.to_be_bytes());
+                body.extend(0x7f00_0000u32.to_be_bytes());
+                for field in [
+                    const_bits_per_channel,
+                    format_specific_flags,
+                    const_bytes_per_audio_packet,
+                    const_lpcm_frames_per_audio_packet,
+                ] {
+                    body.extend(field.to_be_bytes());
+                }
+            }
+        }
+
+        self.extensions.serialize(mp4, &mut body)?;
+
+        write_atom(writer, Header(self.data_format), &body)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SoundSampleExtension {
+    Wave(Wave),
+    Frma(Frma),
+    Chan(Chan),
+    Esds(Esds),
+    /// Any other atom, such as the codec specific atoms inside `wave` or the
+    /// terminator atom ending it
+    Unknown(Header, Vec<u8>),
+}
+
+impl Parse for SoundSampleExtension {
+    fn parse<R: io::Seek + io::BufRead>(mp4: &mut crate::Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(match mp4.peek_header()? {
+            WAVE => SoundSampleExtension::Wave(Wave::parse(mp4)?),
+            FRMA => SoundSampleExtension::Frma(Frma::parse(mp4)?),
+            CHAN => SoundSampleExtension::Chan(Chan::parse(mp4)?),
+            ESDS => SoundSampleExtension::Esds(Esds::parse(mp4)?),
+            _ => {
+                let (header, data) = parse_unknown_atom(mp4)?;
+                SoundSampleExtension::Unknown(header, data)
+            }
+        })
+    }
+}
+
+impl Serialize for SoundSampleExtension {
+    fn serialize<R: io::Seek + io::BufRead, W: io::Write>(
+        &self,
+        mp4: &mut crate::Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        match self {
+            SoundSampleExtension::Wave(wave) => wave.serialize(mp4, writer),
+            SoundSampleExtension::Frma(frma) => frma.serialize(mp4, writer),
+            SoundSampleExtension::Chan(chan) => chan.serialize(mp4, writer),
+            SoundSampleExtension::Esds(esds) => esds.serialize(mp4, writer),
+            SoundSampleExtension::Unknown(header, data) => write_atom(writer, *header, data),
+        }
+    }
+}
+
+#[mp4_atom]
+pub struct Wave {
+    pub extensions: Vec<SoundSampleExtension>,
+}
+
+#[mp4_atom]
+pub struct Frma {
     pub data_format: [u8; 4],
-    pub reserved: [u8; 6],
-    pub data_reference_index: u16,
+}
 
-    pub version: u16,
-    pub revision_level: u16,
-    pub vendor: u32,
-    pub number_of_channels: u16,
-    pub sample_size: u16,
-    pub compression_id: u16,
-    pub packet_size: u16,
-    pub sample_rate: Fixed32,
+/// Audio channel layout atom, describing the role of each channel
+#[mp4_atom]
+pub struct Chan {
+    pub version: u8,
+    pub flags: [u8; 3],
+    pub channel_layout_tag: u32,
+    pub channel_bitmap: u32,
+    pub number_channel_descriptions: u32,
+    pub channel_descriptions: Vec<ChannelDescription>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ChannelDescription {
+    pub channel_label: u32,
+    pub channel_flags: u32,
+    pub coordinates: [f32; 3],
+}
+
+impl Parse for ChannelDescription {
+    fn parse<R: io::Seek + io::BufRead>(mp4: &mut crate::Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let channel_label = mp4.reader.read_u32()?;
+        let channel_flags = mp4.reader.read_u32()?;
+        let coordinates = [
+            f32::from_bits(mp4.reader.read_u32()?),
+            f32::from_bits(mp4.reader.read_u32()?),
+            f32::from_bits(mp4.reader.read_u32()?),
+        ];
+
+        Ok(Self {
+            channel_label,
+            channel_flags,
+            coordinates,
+        })
+    }
+
+    fn peek_len<R: io::Seek + io::BufRead>(_mp4: &mut crate::Mp4<'_, R>) -> Mp4Result<u64> {
+        Ok(20)
+    }
+}
+
+impl Serialize for ChannelDescription {
+    fn serialize<R: io::Seek + io::BufRead, W: io::Write>(
+        &self,
+        _mp4: &mut crate::Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        writer.write_all(&self.channel_label.to_be_bytes())?;
+        writer.write_all(&self.channel_flags.to_be_bytes())?;
+        for coordinate in self.coordinates {
+            writer.write_all(&coordinate.to_bits().to_be_bytes())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{test_util::atom, Mp4};
+
+    #[test]
+    fn rejects_colr_shorter_than_its_colour_type() {
+        let mut mp4 = Mp4::new(Cursor::new(atom(b"colr", b"rICC")));
+        assert!(matches!(
+            Colr::parse(&mut mp4),
+            Ok(Colr::Icc { profile, .. }) if profile.is_empty()
+        ));
+
+        // the colour type is read from the atom that follows
+        let file = [atom(b"colr", &[]), atom(b"free", b"rICC")].concat();
+        let mut mp4 = Mp4::new(Cursor::new(file));
+        assert!(matches!(
+            Colr::parse(&mut mp4),
+            Err(Mp4Error::InvalidAtomLength { offset: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn reads_hvcc_and_its_codec_string() {
+        // the record of an x265 Main profile, level 3.1 stream, with one VPS,
+        // SPS and PPS
+        let vps = [
+            0x40, 0x01, 0x0c, 0x01, 0xff, 0xff, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0xb0, 0x00,
+            0x00, 0x03, 0x00, 0x00, 0x03, 0x00, 0x5d, 0x95, 0x98, 0x09,
+        ];
+        let sps = [
+            0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0xb0, 0x00, 0x00, 0x03, 0x00,
+            0x00, 0x03, 0x00, 0x5d, 0xa0, 0x02, 0x80, 0x80, 0x2d, 0x16, 0x59, 0x59, 0xa4, 0x93,
+            0x2b, 0xc0, 0x5a, 0x70, 0x80, 0x00, 0x01, 0xf4, 0x80, 0x00, 0x3a, 0x98, 0x04,
+        ];
+        let pps = [0x44, 0x01, 0xc1, 0x72, 0xb4, 0x62, 0x40];
+
+        let mut hvcc = vec![
+            0x01, 0x01, 0x60, 0x00, 0x00, 0x00, 0xb0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x5d, 0xf0,
+            0x00, 0xfc, 0xfd, 0xf8, 0xf8, 0x00, 0x00, 0x0f, 0x03,
+        ];
+        for (nal_unit_type, nal_unit) in [(0xa0, &vps[..]), (0xa1, &sps), (0xa2, &pps)] {
+            hvcc.extend([nal_unit_type, 0x00, 0x01]);
+            hvcc.extend((nal_unit.len() as u16).to_be_bytes());
+            hvcc.extend(nal_unit);
+        }
+
+        let mut hvc1 = vec![0; 6];
+        hvc1.extend(1u16.to_be_bytes());
+        hvc1.extend([0; 16]);
+        hvc1.extend(1280u16.to_be_bytes());
+        hvc1.extend(720u16.to_be_bytes());
+        hvc1.extend([0x00, 0x48, 0x00, 0x00, 0x00, 0x48, 0x00, 0x00]);
+        hvc1.extend([0; 4]);
+        hvc1.extend(1u16.to_be_bytes());
+        hvc1.extend([0; 32]);
+        hvc1.extend(0x18u16.to_be_bytes());
+        hvc1.extend((-1i16).to_be_bytes());
+        hvc1.extend(atom(b"hvcC", &hvcc));
+
+        let mut mp4 = Mp4::new(Cursor::new(atom(b"hvc1", &hvc1)));
+        let description = SampleVideoDescriptionTable::parse(&mut mp4).unwrap();
+        assert_eq!(description.codec_string(), "hvc1.1.6.L93.B0");
+
+        let hvcc = description.hvcc().unwrap();
+        assert_eq!(hvcc.chroma_format_idc, 1);
+        assert_eq!(hvcc.num_temporal_layers, 1);
+        assert!(hvcc.temporal_id_nested);
+        assert_eq!(hvcc.length_size_minus_one, 3);
+        assert!(hvcc.arrays.iter().all(|array| array.array_completeness));
+        assert_eq!(hvcc.video_parameter_sets().collect::<Vec<_>>(), [&vps[..]]);
+        assert_eq!(
+            hvcc.sequence_parameter_sets().collect::<Vec<_>>(),
+            [&sps[..]]
+        );
+        assert_eq!(
+            hvcc.picture_parameter_sets().collect::<Vec<_>>(),
+            [&pps[..]]
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_av1c() {
+        // version, profile and level, then flags for 10-bit 4:2:0
+        let file = [atom(b"av1C", &[0x81, 0x08]), atom(b"free", &[0x4c, 0])].concat();
+        let mut mp4 = Mp4::new(Cursor::new(file));
+        assert!(matches!(
+            Av1C::parse(&mut mp4),
+            Err(Mp4Error::InvalidAtomLength { offset: 0, .. })
+        ));
+
+        let mut mp4 = Mp4::new(Cursor::new(atom(b"av1C", &[0x81, 0x08, 0x4c, 0])));
+        let av1c = Av1C::parse(&mut mp4).unwrap();
+        assert_eq!(av1c.bit_depth(), 10);
+        assert!(av1c.config_obus.is_empty());
+    }
+
+    #[test]
+    fn round_trips_sound_descriptions() {
+        let mut decoder_config = vec![0x40, 0x15, 0, 0, 0, 0, 1, 0xf4, 0, 0, 1, 0xf4, 0];
+        decoder_config.extend([0x05, 2, 0x12, 0x10]);
+        let mut es = vec![0, 1, 0, 0x04, decoder_config.len() as u8];
+        es.extend(decoder_config);
+        es.extend([0x06, 1, 0x02]);
+        let mut esds = vec![0; 4];
+        esds.extend([0x03, es.len() as u8]);
+        esds.extend(es);
+
+        // a QuickTime version 1 description keeping its esds inside wave
+        let wave = [
+            atom(b"frma", b"mp4a"),
+            atom(b"esds", &esds),
+            atom(&[0; 4], &[]),
+        ]
+        .concat();
+
+        let mut body = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        body.extend([0, 1, 0, 0, 0, 0, 0, 0]);
+        body.extend([0, 2, 0, 16, 0xff, 0xfe, 0, 0]);
+        body.extend((44100u32 << 16).to_be_bytes());
+        body.extend(
+            [1024u32, 0, 4, 2]
+                .iter()
+                .flat_map(|field| field.to_be_bytes()),
+        );
+        body.extend(atom(b"wave", &wave));
+        let mp4a = atom(b"mp4a", &body);
+
+        let mut mp4 = Mp4::new(Cursor::new(mp4a.clone()));
+        let sound = SampleSoundDescriptionTable::parse(&mut mp4).unwrap();
+        assert_eq!(sound.codec_string(), "mp4a.40.2");
+        assert_eq!(sound.compression_id, -2);
+
+        let mut written = Vec::new();
+        sound.serialize(&mut mp4, &mut written).unwrap();
+        assert_eq!(written, mp4a);
+    }
 }
@@ -0,0 +1,739 @@
+use std::{
+    borrow::Cow,
+    io::{BufRead, Seek, Write},
+};
+
+use atom_macro::{mp4_atom, mp4_container_atom};
+
+use crate::{write_atom, Mp4, Mp4Result, Parse, Reference, Serialize};
+
+use super::{
+    parse_unknown_atom, Chpl, Hdlr, Header, InternalElement, Moov, Trak, UnparsedAtom, DATA, HDLR,
+    ILST, KEYS, MEAN, META, NAME,
+};
+
+/// User data atom
+#[mp4_container_atom]
+pub struct Udta {
+    pub meta: Option<Reference<Meta>>,
+    pub chpl: Option<Reference<Chpl>>,
+}
+
+/// Metadata atom. QuickTime writes it as a plain container, while ISO files
+/// and iTunes write it as a full atom with a version and flags
+#[derive(Debug, Clone)]
+pub struct Meta {
+    /// The version and flags of an ISO metadata atom, or `None` for QuickTime
+    pub version_flags: Option<(u8, [u8; 3])>,
+    pub hdlr: Option<Reference<Hdlr>>,
+    pub keys: Option<Reference<Keys>>,
+    pub ilst: Option<Reference<Ilst>>,
+    /// Any other child atoms, such as `free` padding, in file order. They are
+    /// written after `hdlr`, `keys` and `ilst`
+    pub other_atoms: Vec<UnparsedAtom>,
+}
+
+impl Parse for Meta {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(META)?;
+
+        mp4.in_atom(offset, len, META, |mp4| {
+            // a QuickTime metadata atom starts directly with its handler atom,
+            // while an ISO one starts with its version and flags
+            let start = mp4.position()?;
+            let peek = mp4.reader.read_bytes_const::<8>()?;
+            mp4.jump_to(start)?;
+
+            let version_flags = match peek[4..] == HDLR.0 {
+                true => None,
+                false => {
+                    let version = mp4.reader.read_u8()?;
+                    let flags = mp4.reader.read_bytes_const::<3>()?;
+                    Some((version, flags))
+                }
+            };
+
+            let mut meta = Self {
+                version_flags,
+                hdlr: None,
+                keys: None,
+                ilst: None,
+                other_atoms: Vec::new(),
+            };
+
+            while mp4.position()? < offset + len {
+                let atom = UnparsedAtom::parse(mp4)?;
+
+                match atom.header {
+                    HDLR => meta.hdlr = Some(atom.into_ref()),
+                    KEYS => meta.keys = Some(atom.into_ref()),
+                    ILST => meta.ilst = Some(atom.into_ref()),
+                    _ => meta.other_atoms.push(atom),
+                }
+            }
+
+            Ok(meta)
+        })
+    }
+}
+
+impl Serialize for Meta {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let mut body = Vec::new();
+        if let Some((version, flags)) = self.version_flags {
+            body.push(version);
+            body.extend(flags);
+        }
+
+        if let Some(hdlr) = self.hdlr {
+            hdlr.serialize(mp4, &mut body)?;
+        }
+        if let Some(keys) = self.keys {
+            keys.serialize(mp4, &mut body)?;
+        }
+        if let Some(ilst) = self.ilst {
+            ilst.serialize(mp4, &mut body)?;
+        }
+        self.other_atoms.serialize(mp4, &mut body)?;
+
+        write_atom(writer, META, &body)
+    }
+}
+
+/// Metadata item keys atom, naming the items of a QuickTime `mdta` metadata
+/// atom. Items in its `ilst` are identified by one-based indices into `keys`
+#[mp4_atom]
+pub struct Keys {
+    pub version: u8,
+    pub flags: [u8; 3],
+    pub entry_count: u32,
+    pub entries: Vec<MetadataKeyEntry>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MetadataKeyEntry {
+    /// Usually `mdta`
+    pub namespace: [u8; 4],
+    /// E.g. `com.apple.quicktime.make`
+    pub value: String,
+}
+
+impl Parse for MetadataKeyEntry {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let key_size = mp4.reader.read_u32()?;
+        let namespace = mp4.reader.read_bytes_const::<4>()?;
+        let value = mp4
+            .reader
+            .read_bytes_dyn(key_size.saturating_sub(8) as usize)?;
+
+        Ok(Self {
+            namespace,
+            value: String::from_utf8_lossy(&value).into_owned(),
+        })
+    }
+}
+
+impl Serialize for MetadataKeyEntry {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        writer.write_all(&(self.value.len() as u32 + 8).to_be_bytes())?;
+        writer.write_all(&self.namespace)?;
+        writer.write_all(self.value.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+/// Metadata item list atom
+#[derive(Debug, Clone)]
+pub struct Ilst {
+    pub items: Vec<IlstItem>,
+}
+
+/// An item of a metadata item list. Its header is either the item's type, e.g.
+/// `©nam`, or a big-endian one-based index into the `keys` atom
+#[derive(Debug, Clone)]
+pub struct IlstItem {
+    pub header: Header,
+    /// The reverse DNS domain of a freeform (`----`) item
+    pub mean: Option<Mean>,
+    /// The name of a freeform (`----`) item
+    pub name: Option<Name>,
+    pub data: Vec<Data>,
+    /// Any other child atoms, in file order. They are written after the
+    /// values
+    pub other_atoms: Vec<(Header, Vec<u8>)>,
+}
+
+impl Parse for Ilst {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(ILST)?;
+
+        mp4.in_atom(offset, len, ILST, |mp4| {
+            let mut items = Vec::new();
+
+            while mp4.position()? < offset + len {
+                items.push(IlstItem::parse(mp4)?);
+            }
+
+            Ok(Self { items })
+        })
+    }
+}
+
+impl Serialize for Ilst {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let mut body = Vec::new();
+        self.items.serialize(mp4, &mut body)?;
+
+        write_atom(writer, ILST, &body)
+    }
+}
+
+impl Parse for IlstItem {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let (len, header) = mp4.read_atom_header()?;
+
+        mp4.in_atom(offset, len, header, |mp4| {
+            let mut item = Self {
+                header,
+                mean: None,
+                name: None,
+                data: Vec::new(),
+                other_atoms: Vec::new(),
+            };
+
+            while mp4.position()? < offset + len {
+                match mp4.peek_header()? {
+                    DATA => item.data.push(Data::parse(mp4)?),
+                    MEAN => item.mean = Some(Mean::parse(mp4)?),
+                    NAME => item.name = Some(Name::parse(mp4)?),
+                    _ => item.other_atoms.push(parse_unknown_atom(mp4)?),
+                }
+            }
+
+            Ok(item)
+        })
+    }
+}
+
+impl Serialize for IlstItem {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let mut body = Vec::new();
+        if let Some(mean) = &self.mean {
+            mean.serialize(mp4, &mut body)?;
+        }
+        if let Some(name) = &self.name {
+            name.serialize(mp4, &mut body)?;
+        }
+        self.data.serialize(mp4, &mut body)?;
+        for (header, data) in &self.other_atoms {
+            write_atom(&mut body, *header, data)?;
+        }
+
+        write_atom(writer, self.header, &body)
+    }
+}
+
+/// A value of a metadata item
+#[mp4_atom]
+pub struct Data {
+    /// The well-known type of the value, e.g. `1` for UTF-8 text. The high
+    /// byte is the type set, which is always `0`
+    pub type_indicator: u32,
+    pub locale: u32,
+    pub value: Vec<u8>,
+}
+
+#[mp4_atom]
+pub struct Mean {
+    pub version: u8,
+    pub flags: [u8; 3],
+    pub value: String,
+}
+
+#[mp4_atom]
+pub struct Name {
+    pub version: u8,
+    pub flags: [u8; 3],
+    pub value: String,
+}
+
+/// Identifies an item of [`Metadata`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MetadataKey {
+    /// An iTunes item type, e.g. `©nam` for the title
+    Atom([u8; 4]),
+    /// A freeform (`----`) item, identified by a reverse DNS domain and a name
+    Freeform { mean: String, name: String },
+    /// An item named by a `keys` atom, e.g. `com.apple.quicktime.make`
+    Key(String),
+}
+
+impl MetadataKey {
+    pub const TITLE: MetadataKey = MetadataKey::Atom(*b"\xa9nam");
+    pub const ARTIST: MetadataKey = MetadataKey::Atom(*b"\xa9ART");
+    pub const ALBUM_ARTIST: MetadataKey = MetadataKey::Atom(*b"aART");
+    pub const ALBUM: MetadataKey = MetadataKey::Atom(*b"\xa9alb");
+    pub const GENRE: MetadataKey = MetadataKey::Atom(*b"\xa9gen");
+    /// A genre given as an ID3v1 genre number plus one
+    pub const GENRE_ID: MetadataKey = MetadataKey::Atom(*b"gnre");
+    pub const YEAR: MetadataKey = MetadataKey::Atom(*b"\xa9day");
+    pub const COMMENT: MetadataKey = MetadataKey::Atom(*b"\xa9cmt");
+    pub const ENCODER: MetadataKey = MetadataKey::Atom(*b"\xa9too");
+    pub const TRACK_NUMBER: MetadataKey = MetadataKey::Atom(*b"trkn");
+    pub const DISK_NUMBER: MetadataKey = MetadataKey::Atom(*b"disk");
+    pub const COVER_ART: MetadataKey = MetadataKey::Atom(*b"covr");
+}
+
+/// A decoded value of a metadata item
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetadataValue {
+    Text(String),
+    Integer(i64),
+    Float(f64),
+    Jpeg(Vec<u8>),
+    Png(Vec<u8>),
+    Bmp(Vec<u8>),
+    /// The value of a `trkn` or `disk` item. A total of `0` means unknown
+    Position {
+        number: u16,
+        total: u16,
+    },
+    /// Any value of a type that isn't decoded
+    Binary {
+        type_indicator: u32,
+        data: Vec<u8>,
+    },
+}
+
+impl MetadataValue {
+    fn decode(key: &MetadataKey, data: Data) -> Self {
+        let value = data.value;
+
+        let integer = |value: &[u8], signed: bool| -> i64 {
+            let mut bytes = [0; 8];
+            bytes[8 - value.len()..].copy_from_slice(value);
+            let unsigned = u64::from_be_bytes(bytes);
+
+            let bits = value.len() as u32 * 8;
+            match signed && bits < 64 {
+                true => ((unsigned << (64 - bits)) as i64) >> (64 - bits),
+                false => unsigned as i64,
+            }
+        };
+
+        let decoded = match data.type_indicator & 0x00ff_ffff {
+            1 => Some(MetadataValue::Text(
+                String::from_utf8_lossy(&value).into_owned(),
+            )),
+            2 => Some(MetadataValue::Text(String::from_utf16_lossy(
+                &value
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                    .collect::<Vec<_>>(),
+            ))),
+            13 => Some(MetadataValue::Jpeg(value.clone())),
+            14 => Some(MetadataValue::Png(value.clone())),
+            27 => Some(MetadataValue::Bmp(value.clone())),
+            21 if matches!(value.len(), 1..=4 | 8) => {
+                Some(MetadataValue::Integer(integer(&value, true)))
+            }
+            22 if matches!(value.len(), 1..=4 | 8) => {
+                Some(MetadataValue::Integer(integer(&value, false)))
+            }
+            23 if value.len() == 4 => Some(MetadataValue::Float(f32::from_be_bytes(
+                value[..].try_into().unwrap(),
+            ) as f64)),
+            24 if value.len() == 8 => Some(MetadataValue::Float(f64::from_be_bytes(
+                value[..].try_into().unwrap(),
+            ))),
+            0 if (*key == MetadataKey::TRACK_NUMBER || *key == MetadataKey::DISK_NUMBER)
+                && value.len() >= 6 =>
+            {
+                Some(MetadataValue::Position {
+                    number: u16::from_be_bytes([value[2], value[3]]),
+                    total: u16::from_be_bytes([value[4], value[5]]),
+                })
+            }
+            0 if *key == MetadataKey::GENRE_ID && value.len() == 2 => Some(MetadataValue::Integer(
+                u16::from_be_bytes([value[0], value[1]]) as i64,
+            )),
+            _ => None,
+        };
+
+        decoded.unwrap_or(MetadataValue::Binary {
+            type_indicator: data.type_indicator,
+            data: value,
+        })
+    }
+
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            MetadataValue::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+/// The metadata items of a movie or track, in the order they appear in the
+/// file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    pub items: Vec<(MetadataKey, Vec<MetadataValue>)>,
+}
+
+impl Metadata {
+    /// Reads the item list of a metadata atom, naming its items with the
+    /// atom's `keys` if it has them
+    pub fn from_meta<R: BufRead + Seek>(meta: &Meta, mp4: &mut Mp4<'_, R>) -> Mp4Result<Self> {
+        let keys = match meta.keys {
+            Some(keys) => keys.parse(mp4)?.entries,
+            None => Vec::new(),
+        };
+
+        let ilst = match meta.ilst {
+            Some(ilst) => ilst.parse(mp4)?,
+            None => return Ok(Self::default()),
+        };
+
+        let items = ilst
+            .items
+            .into_iter()
+            .map(|item| {
+                let index = u32::from_be_bytes(item.header.0) as usize;
+
+                let key = match (&item.header.0, &item.mean, &item.name) {
+                    (b"----", Some(mean), Some(name)) => MetadataKey::Freeform {
+                        mean: mean.value.clone(),
+                        name: name.value.clone(),
+                    },
+                    _ if !keys.is_empty() && (1..=keys.len()).contains(&index) => {
+                        MetadataKey::Key(keys[index - 1].value.clone())
+                    }
+                    (header, ..) => MetadataKey::Atom(*header),
+                };
+
+                let values = item
+                    .data
+                    .into_iter()
+                    .map(|data| MetadataValue::decode(&key, data))
+                    .collect();
+
+                (key, values)
+            })
+            .collect();
+
+        Ok(Self { items })
+    }
+
+    /// Every value of the item with the given key
+    pub fn get(&self, key: &MetadataKey) -> &[MetadataValue] {
+        self.items
+            .iter()
+            .find(|(item_key, _)| item_key == key)
+            .map(|(_, values)| values.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn text(&self, key: &MetadataKey) -> Option<&str> {
+        self.get(key).iter().find_map(MetadataValue::as_text)
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.text(&MetadataKey::TITLE)
+    }
+
+    pub fn artist(&self) -> Option<&str> {
+        self.text(&MetadataKey::ARTIST)
+    }
+
+    pub fn album(&self) -> Option<&str> {
+        self.text(&MetadataKey::ALBUM)
+    }
+
+    /// The genre, given either as text or as an ID3v1 genre number
+    pub fn genre(&self) -> Option<Cow<'_, str>> {
+        if let Some(genre) = self.text(&MetadataKey::GENRE) {
+            return Some(Cow::Borrowed(genre));
+        }
+
+        match self.get(&MetadataKey::GENRE_ID).first()? {
+            MetadataValue::Integer(id) => ID3_GENRES
+                .get((*id as usize).checked_sub(1)?)
+                .map(|genre| Cow::Borrowed(*genre)),
+            _ => None,
+        }
+    }
+
+    /// The track number and total number of tracks
+    pub fn track_number(&self) -> Option<(u16, u16)> {
+        self.get(&MetadataKey::TRACK_NUMBER)
+            .iter()
+            .find_map(|value| match value {
+                MetadataValue::Position { number, total } => Some((*number, *total)),
+                _ => None,
+            })
+    }
+
+    /// The first cover art image
+    pub fn cover_art(&self) -> Option<&MetadataValue> {
+        self.get(&MetadataKey::COVER_ART).iter().find(|value| {
+            matches!(
+                value,
+                MetadataValue::Jpeg(..) | MetadataValue::Png(..) | MetadataValue::Bmp(..)
+            )
+        })
+    }
+
+    fn extend(&mut self, other: Metadata) {
+        self.items.extend(other.items);
+    }
+}
+
+impl Moov {
+    /// The metadata of the movie, from both `udta/meta` as written by iTunes
+    /// and `meta` as written by QuickTime
+    pub fn metadata<R: BufRead + Seek>(&mut self, mp4: &mut Mp4<'_, R>) -> Mp4Result<Metadata> {
+        let udta = *self.udta(mp4);
+        let meta = *self.meta(mp4);
+
+        read_metadata(udta, meta, mp4)
+    }
+}
+
+impl Trak {
+    /// The metadata of the track, from both `udta/meta` and `meta`
+    pub fn metadata<R: BufRead + Seek>(&mut self, mp4: &mut Mp4<'_, R>) -> Mp4Result<Metadata> {
+        let udta = *self.udta(mp4);
+        let meta = *self.meta(mp4);
+
+        read_metadata(udta, meta, mp4)
+    }
+}
+
+fn read_metadata<R: BufRead + Seek>(
+    udta: Option<Reference<Udta>>,
+    meta: Option<Reference<Meta>>,
+    mp4: &mut Mp4<'_, R>,
+) -> Mp4Result<Metadata> {
+    let mut metadata = Metadata::default();
+
+    if let Some(udta) = udta {
+        if let Some(meta) = *udta.parse(mp4)?.meta(mp4) {
+            metadata.extend(Metadata::from_meta(&meta.parse(mp4)?, mp4)?);
+        }
+    }
+
+    if let Some(meta) = meta {
+        metadata.extend(Metadata::from_meta(&meta.parse(mp4)?, mp4)?);
+    }
+
+    Ok(metadata)
+}
+
+const ID3_GENRES: [&str; 80] = [
+    "Blues",
+    "Classic Rock",
+    "Country",
+    "Dance",
+    "Disco",
+    "Funk",
+    "Grunge",
+    "Hip-Hop",
+    "Jazz",
+    "Metal",
+    "New Age",
+    "Oldies",
+    "Other",
+    "Pop",
+    "R&B",
+    "Rap",
+    "Reggae",
+    "Rock",
+    "Techno",
+    "Industrial",
+    "Alternative",
+    "Ska",
+    "Death Metal",
+    "Pranks",
+    "Soundtrack",
+    "Euro-Techno",
+    "Ambient",
+    "Trip-Hop",
+    "Vocal",
+    "Jazz+Funk",
+    "Fusion",
+    "Trance",
+    "Classical",
+    "Instrumental",
+    "Acid",
+    "House",
+    "Game",
+    "Sound Clip",
+    "Gospel",
+    "Noise",
+    "AlternRock",
+    "Bass",
+    "Soul",
+    "Punk",
+    "Space",
+    "Meditative",
+    "Instrumental Pop",
+    "Instrumental Rock",
+    "Ethnic",
+    "Gothic",
+    "Darkwave",
+    "Techno-Industrial",
+    "Electronic",
+    "Pop-Folk",
+    "Eurodance",
+    "Dream",
+    "Southern Rock",
+    "Comedy",
+    "Cult",
+    "Gangsta",
+    "Top 40",
+    "Christian Rap",
+    "Pop/Funk",
+    "Jungle",
+    "Native American",
+    "Cabaret",
+    "New Wave",
+    "Psychadelic",
+    "Rave",
+    "Showtunes",
+    "Trailer",
+    "Lo-Fi",
+    "Tribal",
+    "Acid Punk",
+    "Acid Jazz",
+    "Polka",
+    "Retro",
+    "Musical",
+    "Rock & Roll",
+    "Hard Rock",
+];
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_util::atom;
+
+    fn data(type_indicator: u32, value: &[u8]) -> Data {
+        Data {
+            type_indicator,
+            locale: 0,
+            value: value.to_vec(),
+        }
+    }
+
+    #[test]
+    fn decodes_well_known_types() {
+        let key = MetadataKey::Atom(*b"tmpo");
+
+        assert_eq!(
+            MetadataValue::decode(&key, data(21, &[0xff, 0xfe])),
+            MetadataValue::Integer(-2)
+        );
+        assert_eq!(
+            MetadataValue::decode(&key, data(22, &[0xff, 0xfe])),
+            MetadataValue::Integer(0xfffe)
+        );
+        assert_eq!(
+            MetadataValue::decode(&key, data(2, &[0, b'h', 0, b'i'])),
+            MetadataValue::Text("hi".into())
+        );
+        assert_eq!(
+            MetadataValue::decode(
+                &MetadataKey::TRACK_NUMBER,
+                data(0, &[0, 0, 0, 3, 0, 12, 0, 0])
+            ),
+            MetadataValue::Position {
+                number: 3,
+                total: 12
+            }
+        );
+        assert_eq!(
+            MetadataValue::decode(&key, data(0, &[1])),
+            MetadataValue::Binary {
+                type_indicator: 0,
+                data: vec![1]
+            }
+        );
+    }
+
+    #[test]
+    fn round_trips_item_lists() {
+        let full_atom = |header: &[u8; 4], body: &[u8]| atom(header, &[&[0; 4], body].concat());
+
+        let title = atom(b"\xa9nam", &full_atom(b"data", b"\0\0\0\0Title"));
+        let freeform = [
+            full_atom(b"mean", b"com.apple.iTunes"),
+            full_atom(b"name", b"iTunSMPB"),
+            full_atom(b"data", b"\0\0\0\0 00000000"),
+            // an atom that isn't understood, kept after the values
+            atom(b"itif", &[1, 2]),
+        ]
+        .concat();
+        let ilst = atom(b"ilst", &[title, atom(b"----", &freeform)].concat());
+
+        let meta = full_atom(
+            b"meta",
+            &[
+                full_atom(b"hdlr", b"\0\0\0\0mdirappl\0\0\0\0\0\0\0\0\0"),
+                ilst.clone(),
+                atom(b"free", &[0; 6]),
+            ]
+            .concat(),
+        );
+
+        let mut mp4 = Mp4::new(Cursor::new(meta.clone()));
+        let parsed = Meta::parse(&mut mp4).unwrap();
+        assert_eq!(parsed.other_atoms.len(), 1);
+
+        let mut written = Vec::new();
+        parsed.serialize(&mut mp4, &mut written).unwrap();
+        assert_eq!(written, meta);
+
+        let items = parsed.ilst.unwrap().parse(&mut mp4).unwrap();
+        assert_eq!(items.items[1].name.as_ref().unwrap().value, "iTunSMPB");
+        assert_eq!(items.items[1].other_atoms.len(), 1);
+
+        let mut written = Vec::new();
+        items.serialize(&mut mp4, &mut written).unwrap();
+        assert_eq!(written, ilst);
+    }
+}
@@ -1,41 +1,59 @@
 use std::{
-    io::{self, BufRead, Seek, SeekFrom},
-    time::Duration,
+    io::{BufRead, Seek, Write},
+    ops::Range,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use atom_macro::{mp4_atom, mp4_container_atom};
 
-use crate::{data_structures::Matrix, Fixed16, Fixed32, Mp4, Parse, Reference};
+use crate::{
+    data_structures::Matrix, write_atom, write_atom_header, Fixed16, Fixed32, Mp4, Mp4Error,
+    Mp4Result, Parse, Reference, Serialize,
+};
 
+pub use chapter::*;
+pub use edit_list::*;
+pub use esds::*;
+pub use fragment::*;
 pub use header::*;
 pub use media_data_type::*;
+pub use metadata::*;
+pub use sample_table::*;
+pub use segment_index::*;
+pub use text::*;
 pub use track::*;
 
+mod chapter;
+mod edit_list;
+mod esds;
+mod fragment;
 mod header;
 mod media_data_type;
+mod metadata;
+mod sample_table;
+mod segment_index;
+mod text;
 mod track;
 
+/// An atom known only by its type and location
 #[derive(Debug, Clone)]
-struct UnparsedAtom {
-    offset: u64,
-    len: u64,
-    #[allow(dead_code)]
-    header: Header,
+pub struct UnparsedAtom {
+    pub offset: u64,
+    /// The total size of the atom, including its header
+    pub len: u64,
+    pub header: Header,
 }
 
 impl Parse for UnparsedAtom {
-    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> io::Result<Self>
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
     where
         Self: Sized,
     {
         let offset = mp4.reader.buffer.stream_position()?;
-        let len = mp4.read_atom_len()?;
-        let header = Header(mp4.reader.read_bytes_const::<4>()?);
+        let (len, header) = mp4.read_atom_header()?;
 
-        let current_pos = mp4.reader.buffer.stream_position()?;
-        mp4.reader
-            .buffer
-            .seek(SeekFrom::Current((len + offset - current_pos) as i64))?;
+        mp4.remember_parents(offset);
+        mp4.jump_to(offset + len)?;
 
         Ok(Self {
             offset,
@@ -54,9 +72,6 @@ impl UnparsedAtom {
 #[mp4_atom]
 pub struct Clip {}
 
-#[mp4_atom]
-pub struct Udta {}
-
 #[mp4_atom]
 pub struct Ctab {
     pub seed: u32,
@@ -76,22 +91,113 @@ pub struct Rmra {
 
 #[mp4_atom]
 pub struct Prfl {}
-#[mp4_atom]
+/// Media header atom. Version 1 headers have 64-bit times and duration
+#[derive(Debug, Clone)]
 pub struct Mdhd {
+    /// Version 1 headers have 64-bit times and duration. A version 0 header
+    /// is written as version 1 if they don't fit in 32 bits
     pub version: u8,
     pub flags: [u8; 3],
-    pub creation_time: u32,
-    pub modification_time: u32,
+    /// Seconds since midnight, January 1, 1904 UTC
+    pub creation_time: u64,
+    /// Seconds since midnight, January 1, 1904 UTC
+    pub modification_time: u64,
     pub time_scale: u32,
-    pub duration: u32,
+    /// Duration of the media, in the media's time scale
+    pub duration: u64,
     pub language: u16,
     pub quality: u16,
 }
 
+impl Parse for Mdhd {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(MDHD)?;
+
+        mp4.in_atom(offset, len, MDHD, |mp4| {
+            let version = mp4.reader.read_u8()?;
+            let flags = mp4.reader.read_bytes_const::<3>()?;
+            let creation_time = read_time(mp4, version)?;
+            let modification_time = read_time(mp4, version)?;
+            let time_scale = mp4.reader.read_u32()?;
+            let duration = read_time(mp4, version)?;
+            let language = mp4.reader.read_u16()?;
+            let quality = mp4.reader.read_u16()?;
+
+            Ok(Self {
+                version,
+                flags,
+                creation_time,
+                modification_time,
+                time_scale,
+                duration,
+                language,
+                quality,
+            })
+        })
+    }
+}
+
+impl Serialize for Mdhd {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let version = time_version(
+            self.version,
+            [self.creation_time, self.modification_time, self.duration],
+        );
+
+        let mut body = vec![version];
+        body.extend(self.flags);
+        write_time(&mut body, version, self.creation_time);
+        write_time(&mut body, version, self.modification_time);
+        body.extend(self.time_scale.to_be_bytes());
+        write_time(&mut body, version, self.duration);
+        body.extend(self.language.to_be_bytes());
+        body.extend(self.quality.to_be_bytes());
+
+        write_atom(writer, MDHD, &body)
+    }
+}
+
 impl Mdhd {
+    /// The creation time, or `None` if it is out of the range of
+    /// [`SystemTime`]
+    pub fn created(&self) -> Option<SystemTime> {
+        date_from_1904(self.creation_time)
+    }
+
+    /// The modification time, or `None` if it is out of the range of
+    /// [`SystemTime`]
+    pub fn modified(&self) -> Option<SystemTime> {
+        date_from_1904(self.modification_time)
+    }
+
+    pub fn media_duration(&self) -> Duration {
+        from_timescale(self.duration, self.time_scale)
+    }
+
     pub fn convert_to_media_time(&self, time: Duration) -> u64 {
         time.as_secs() * self.time_scale as u64
     }
+
+    /// The ISO 639-2/T code of the media's language, e.g. `eng`. QuickTime
+    /// files may instead give a Macintosh language code, for which this is
+    /// `None`
+    pub fn language_code(&self) -> Option<String> {
+        if self.language < 0x400 || self.language == 0x7fff {
+            return None;
+        }
+
+        let letters = [10, 5, 0].map(|shift| ((self.language >> shift) as u8 & 0x1f) + 0x60);
+
+        Some(letters.iter().map(|&letter| letter as char).collect())
+    }
 }
 
 #[mp4_atom]
@@ -144,8 +250,13 @@ pub struct Smhd {
     pub reserved: u16,
 }
 
-#[mp4_atom]
-pub struct Gmhd {}
+/// Base media information header atom, used by media types other than video
+/// and sound
+#[mp4_container_atom]
+pub struct Gmhd {
+    pub gmin: Option<Reference<Gmin>>,
+    pub text: Option<Reference<Text>>,
+}
 
 #[mp4_container_atom]
 pub struct Dinf {
@@ -163,16 +274,28 @@ pub struct Stbl {
     sample_to_chunk: Option<Reference<Stsc>>,
     sample_size: Option<Reference<Stsz>>,
     chunk_offset: Option<Reference<Stco>>,
+    chunk_offset_64: Option<Reference<Co64>>,
     shadow_sync: Option<Reference<Stsh>>,
-    sgpd: Option<Reference<Sgpd>>,
-    sbgp: Option<Reference<Sbgp>>,
+    sgpd: Vec<Reference<Sgpd>>,
+    sbgp: Vec<Reference<Sbgp>>,
     sdtp: Option<Reference<Sdtp>>,
 }
 
 #[mp4_atom]
-pub struct Gmin {}
+pub struct Gmin {
+    pub version: u8,
+    pub flags: [u8; 3],
+    pub graphics_mode: u16,
+    pub op_color: [u8; 6],
+    pub balance: i16,
+    pub reserved: u16,
+}
+
+/// Text media information atom of a QuickTime text track
 #[mp4_atom]
-pub struct Text {}
+pub struct Text {
+    pub matrix: Matrix,
+}
 
 #[mp4_atom]
 pub struct Dref {
@@ -190,21 +313,42 @@ pub enum DataRef {
 }
 
 impl Parse for DataRef {
-    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> io::Result<Self>
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
     where
         Self: Sized,
     {
+        let offset = mp4.position()?;
         let header = mp4.peek_header()?;
 
         Ok(match header {
             Alis::HEADER => DataRef::Alis(<Reference<Alis> as Parse>::parse(mp4)?),
             Rsrc::HEADER => DataRef::Rsrc(<Reference<Rsrc> as Parse>::parse(mp4)?),
             Url::HEADER => DataRef::Url(<Reference<Url> as Parse>::parse(mp4)?),
-            _ => panic!(),
+            _ => {
+                return Err(Mp4Error::UnsupportedAtom {
+                    offset,
+                    path: mp4.atom_path(),
+                    header,
+                })
+            }
         })
     }
 }
 
+impl Serialize for DataRef {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        match self {
+            DataRef::Alis(alis) => alis.serialize(mp4, writer),
+            DataRef::Rsrc(rsrc) => rsrc.serialize(mp4, writer),
+            DataRef::Url(url) => url.serialize(mp4, writer),
+        }
+    }
+}
+
 #[mp4_atom]
 pub struct Alis {
     pub version: u8,
@@ -247,7 +391,7 @@ pub struct TimeToSampleEntry {
 }
 
 impl Parse for TimeToSampleEntry {
-    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> io::Result<Self>
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
     where
         Self: Sized,
     {
@@ -261,27 +405,16 @@ impl Parse for TimeToSampleEntry {
     }
 }
 
-impl Stts {
-    pub fn lookup_time(&self, time: u32) -> u32 {
-        let mut n = 0;
-        let mut sample_number = 0;
+impl Serialize for TimeToSampleEntry {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        writer.write_all(&self.sample_count.to_be_bytes())?;
+        writer.write_all(&self.sample_duration.to_be_bytes())?;
 
-        if time == 0 {
-            return 1;
-        }
-
-        for entry in &self.time_to_sample_table {
-            for _ in 0..entry.sample_count {
-                n += entry.sample_duration;
-                sample_number += 1;
-
-                if n as u32 > time {
-                    return sample_number - 1;
-                }
-            }
-        }
-
-        sample_number
+        Ok(())
     }
 }
 
@@ -300,7 +433,7 @@ pub struct CompositionOffsetEntry {
 }
 
 impl Parse for CompositionOffsetEntry {
-    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> io::Result<Self>
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
     where
         Self: Sized,
     {
@@ -314,6 +447,19 @@ impl Parse for CompositionOffsetEntry {
     }
 }
 
+impl Serialize for CompositionOffsetEntry {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        writer.write_all(&self.sample_count.to_be_bytes())?;
+        writer.write_all(&self.composition_offset.to_be_bytes())?;
+
+        Ok(())
+    }
+}
+
 #[mp4_atom]
 pub struct Cslg {
     pub version: u8,
@@ -330,7 +476,9 @@ pub struct Stss {
     pub version: u8,
     pub flags: [u8; 3],
     pub number_of_entries: u32,
-    pub sync_sample_table: Vec<u8>,
+    /// One-based numbers of the samples that are sync samples, in strictly
+    /// increasing order
+    pub sync_sample_table: Vec<u32>,
 }
 
 #[mp4_atom]
@@ -338,7 +486,9 @@ pub struct Stps {
     pub version: u8,
     pub flags: [u8; 3],
     pub number_of_entries: u32,
-    pub partial_sync_sample_table: Vec<u8>,
+    /// One-based numbers of the samples that are partial sync samples, in
+    /// strictly increasing order
+    pub partial_sync_sample_table: Vec<u32>,
 }
 #[mp4_atom]
 pub struct Stsc {
@@ -356,7 +506,7 @@ pub struct SampleToChunkEntry {
 }
 
 impl Parse for SampleToChunkEntry {
-    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> io::Result<Self>
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
     where
         Self: Sized,
     {
@@ -372,30 +522,17 @@ impl Parse for SampleToChunkEntry {
     }
 }
 
-impl Stsc {
-    pub fn lookup_chunk(&self, sample_number: u32) -> u32 {
-        let mut chunk_number = 0;
-        let mut sample_count = 0;
-
-        if sample_number == 0 {
-            return 0;
-        }
-
-        for i in 0..self.sample_to_chunk_table.len() {
-            let entry = self.sample_to_chunk_table[i];
-            let next_entry = self.sample_to_chunk_table[i + 1];
-            while chunk_number < next_entry.first_chunk {
-                sample_count += entry.samples_per_chunk;
-
-                if sample_count >= sample_number {
-                    return chunk_number;
-                }
-
-                chunk_number += 1;
-            }
-        }
-
-        chunk_number
+impl Serialize for SampleToChunkEntry {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        writer.write_all(&self.first_chunk.to_be_bytes())?;
+        writer.write_all(&self.samples_per_chunk.to_be_bytes())?;
+        writer.write_all(&self.sample_description_id.to_be_bytes())?;
+
+        Ok(())
     }
 }
 
@@ -419,15 +556,295 @@ impl Stsz {
 
 #[mp4_atom]
 pub struct Stsh {}
-#[mp4_atom]
-pub struct Sgpd {}
-#[mp4_atom]
-pub struct Sbgp {}
+
+/// Sample group description atom, describing the properties shared by each
+/// group of samples of a particular grouping type
+#[derive(Debug, Clone)]
+pub struct Sgpd {
+    pub version: u8,
+    pub flags: [u8; 3],
+    pub grouping_type: [u8; 4],
+    /// The length of every entry, or 0 if each entry is preceded by its own
+    /// length. Only present in version 1
+    pub default_length: Option<u32>,
+    /// Only present in version 2 and later
+    pub default_sample_description_index: Option<u32>,
+    pub entry_count: u32,
+    pub entries: Vec<SampleGroupEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SampleGroupEntry {
+    /// `roll`: the number of samples that must be decoded before (if negative)
+    /// or after (if positive) a sample for decoding to be correct at it
+    RollRecovery(i16),
+
+    /// `prol`: the number of samples that must be decoded before a sample for
+    /// its decoded output to be correct
+    AudioPreRoll(i16),
+
+    /// An entry of a grouping type that is not understood. If the length of
+    /// each entry cannot be determined, this contains the data for every
+    /// remaining entry
+    Unknown(Vec<u8>),
+}
+
+impl Parse for Sgpd {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(SGPD)?;
+
+        mp4.in_atom(offset, len, SGPD, |mp4| {
+            let version = mp4.reader.read_u8()?;
+            let flags = mp4.reader.read_bytes_const::<3>()?;
+            let grouping_type = mp4.reader.read_bytes_const::<4>()?;
+            let default_length = match version {
+                1 => Some(mp4.reader.read_u32()?),
+                _ => None,
+            };
+            let default_sample_description_index = match version {
+                2.. => Some(mp4.reader.read_u32()?),
+                _ => None,
+            };
+            let entry_count = mp4.reader.read_u32()?;
+
+            let mut entries = Vec::new();
+            for _ in 0..entry_count {
+                let entry_len = match default_length {
+                    Some(0) => Some(mp4.reader.read_u32()?),
+                    Some(len) => Some(len),
+                    None => None,
+                };
+
+                let entry = match (&grouping_type, entry_len) {
+                    (b"roll", None | Some(2)) => SampleGroupEntry::RollRecovery(i16::parse(mp4)?),
+                    (b"prol", None | Some(2)) => SampleGroupEntry::AudioPreRoll(i16::parse(mp4)?),
+                    (_, Some(len)) => {
+                        SampleGroupEntry::Unknown(mp4.reader.read_bytes_dyn(len as usize)?)
+                    }
+                    (_, None) => {
+                        let current_pos = mp4.position()?;
+                        entries.push(SampleGroupEntry::Unknown(mp4.reader.read_bytes_dyn(
+                            (offset + len).saturating_sub(current_pos) as usize,
+                        )?));
+                        break;
+                    }
+                };
+
+                entries.push(entry);
+            }
+
+            Ok(Self {
+                version,
+                flags,
+                grouping_type,
+                default_length,
+                default_sample_description_index,
+                entry_count,
+                entries,
+            })
+        })
+    }
+}
+
+impl Serialize for Sgpd {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let mut body = vec![self.version];
+        body.extend(self.flags);
+        body.extend(self.grouping_type);
+        if let Some(default_length) = self.default_length {
+            body.extend(default_length.to_be_bytes());
+        }
+        if let Some(index) = self.default_sample_description_index {
+            body.extend(index.to_be_bytes());
+        }
+        body.extend(self.entry_count.to_be_bytes());
+
+        for entry in &self.entries {
+            let data = match entry {
+                SampleGroupEntry::RollRecovery(distance)
+                | SampleGroupEntry::AudioPreRoll(distance) => distance.to_be_bytes().to_vec(),
+                SampleGroupEntry::Unknown(data) => data.clone(),
+            };
+
+            if self.default_length == Some(0) {
+                body.extend((data.len() as u32).to_be_bytes());
+            }
+            body.extend(data);
+        }
+
+        write_atom(writer, SGPD, &body)
+    }
+}
+
+/// Sample-to-group atom, assigning runs of samples to one of the groups
+/// described by the `sgpd` atom of the same grouping type
+#[derive(Debug, Clone)]
+pub struct Sbgp {
+    pub version: u8,
+    pub flags: [u8; 3],
+    pub grouping_type: [u8; 4],
+    /// Only present in version 1
+    pub grouping_type_parameter: Option<u32>,
+    pub entry_count: u32,
+    pub entries: Vec<SampleToGroupEntry>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SampleToGroupEntry {
+    pub sample_count: u32,
+    /// One-based index of the group entry in the matching `sgpd` atom, or 0
+    /// if the samples are not a member of any group of this type
+    pub group_description_index: u32,
+}
+
+impl Parse for SampleToGroupEntry {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let sample_count = mp4.reader.read_u32()?;
+        let group_description_index = mp4.reader.read_u32()?;
+
+        Ok(Self {
+            sample_count,
+            group_description_index,
+        })
+    }
+}
+
+impl Parse for Sbgp {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(SBGP)?;
+
+        mp4.in_atom(offset, len, SBGP, |mp4| {
+            let version = mp4.reader.read_u8()?;
+            let flags = mp4.reader.read_bytes_const::<3>()?;
+            let grouping_type = mp4.reader.read_bytes_const::<4>()?;
+            let grouping_type_parameter = match version {
+                1 => Some(mp4.reader.read_u32()?),
+                _ => None,
+            };
+            let entry_count = mp4.reader.read_u32()?;
+            let entries = (0..entry_count)
+                .map(|_| SampleToGroupEntry::parse(mp4))
+                .collect::<Mp4Result<Vec<_>>>()?;
+
+            Ok(Self {
+                version,
+                flags,
+                grouping_type,
+                grouping_type_parameter,
+                entry_count,
+                entries,
+            })
+        })
+    }
+}
+
+impl Serialize for Sbgp {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let mut body = vec![self.version];
+        body.extend(self.flags);
+        body.extend(self.grouping_type);
+        if let Some(parameter) = self.grouping_type_parameter {
+            body.extend(parameter.to_be_bytes());
+        }
+        body.extend(self.entry_count.to_be_bytes());
+
+        for entry in &self.entries {
+            body.extend(entry.sample_count.to_be_bytes());
+            body.extend(entry.group_description_index.to_be_bytes());
+        }
+
+        write_atom(writer, SBGP, &body)
+    }
+}
+
 #[mp4_atom]
 pub struct Sdtp {
     pub version: u8,
     pub flags: [u8; 3],
-    pub sample_dependency_flags_table: Vec<u8>,
+    pub sample_dependency_flags_table: Vec<SampleDependencyFlags>,
+}
+
+/// The dependency information of a single sample. Each field is a 2-bit value
+/// where 0 means the information is unknown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleDependencyFlags {
+    /// 1: this is a leading sample that depends on samples before the
+    /// preceding sync sample, 2: this is not a leading sample, 3: this is a
+    /// leading sample that can be decoded from the preceding sync sample
+    pub is_leading: u8,
+
+    /// 1: this sample depends on others, 2: this sample is independent of
+    /// others, e.g. an I-picture
+    pub sample_depends_on: u8,
+
+    /// 1: other samples depend on this one, 2: no other sample depends on this
+    /// one, so it is disposable
+    pub sample_is_depended_on: u8,
+
+    /// 1: this sample contains redundant coding, 2: it does not
+    pub sample_has_redundancy: u8,
+}
+
+impl SampleDependencyFlags {
+    /// Whether no other sample depends on this one, so it need not be decoded
+    /// unless it is displayed
+    pub fn is_disposable(&self) -> bool {
+        self.sample_is_depended_on == 2
+    }
+
+    pub fn is_independent(&self) -> bool {
+        self.sample_depends_on == 2
+    }
+}
+
+impl Parse for SampleDependencyFlags {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let flags = mp4.reader.read_u8()?;
+
+        Ok(Self {
+            is_leading: flags >> 6,
+            sample_depends_on: (flags >> 4) & 0b11,
+            sample_is_depended_on: (flags >> 2) & 0b11,
+            sample_has_redundancy: flags & 0b11,
+        })
+    }
+}
+
+impl Serialize for SampleDependencyFlags {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let flags = (self.is_leading << 6)
+            | (self.sample_depends_on << 4)
+            | (self.sample_is_depended_on << 2)
+            | self.sample_has_redundancy;
+
+        Ok(writer.write_all(&[flags])?)
+    }
 }
 #[mp4_container_atom]
 pub struct Rmda {
@@ -476,7 +893,9 @@ pub struct Moov {
     pub movie_header: Reference<Mvhd>,
     pub clip: Option<Reference<Clip>>,
     pub trak: Vec<Reference<Trak>>,
-    pub udta: Reference<Udta>,
+    pub mvex: Option<Reference<Mvex>>,
+    pub udta: Option<Reference<Udta>>,
+    pub meta: Option<Reference<Meta>>,
     pub ctab: Reference<Udta>,
     pub cmov: Reference<Cmov>,
     pub rmra: Reference<Rmra>,
@@ -486,17 +905,72 @@ pub struct Moov {
 pub struct Ftyp {
     pub major_brand: [u8; 4],
     pub major_brand_version: [u8; 4],
-    pub compatible_brands: Vec<u8>,
+    pub compatible_brands: Vec<[u8; 4]>,
 }
 
 #[mp4_atom]
+pub struct Pdin {
+    pub version: u8,
+    pub flags: [u8; 3],
+    pub entries: Vec<ProgressiveDownloadEntry>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProgressiveDownloadEntry {
+    /// Download rate in bytes per second
+    pub rate: u32,
+    /// Suggested delay before starting playback at `rate`, in milliseconds
+    pub initial_delay: u32,
+}
+
+impl Parse for ProgressiveDownloadEntry {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let rate = mp4.reader.read_u32()?;
+        let initial_delay = mp4.reader.read_u32()?;
+
+        Ok(Self {
+            rate,
+            initial_delay,
+        })
+    }
+}
+
+impl Serialize for ProgressiveDownloadEntry {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        writer.write_all(&self.rate.to_be_bytes())?;
+        writer.write_all(&self.initial_delay.to_be_bytes())?;
+
+        Ok(())
+    }
+}
+
+#[mp4_atom]
+pub struct Uuid {
+    pub user_type: [u8; 16],
+    pub data: Vec<u8>,
+}
+
+/// Movie header atom. Version 1 headers have 64-bit times and duration
+#[derive(Debug, Clone)]
 pub struct Mvhd {
+    /// Version 1 headers have 64-bit times and duration. A version 0 header
+    /// is written as version 1 if they don't fit in 32 bits
     pub version: u8,
     pub flags: [u8; 3],
-    pub creation_time: u32,
-    pub modification_time: u32,
+    /// Seconds since midnight, January 1, 1904 UTC
+    pub creation_time: u64,
+    /// Seconds since midnight, January 1, 1904 UTC
+    pub modification_time: u64,
     pub time_scale: u32,
-    pub duration: u32,
+    /// Duration of the longest track, in the movie's time scale
+    pub duration: u64,
     pub preferred_rate: Fixed32,
     pub preferred_volume: Fixed16,
     pub reserved: [u8; 10],
@@ -510,6 +984,159 @@ pub struct Mvhd {
     pub next_track_id: u32,
 }
 
+impl Parse for Mvhd {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(MVHD)?;
+
+        mp4.in_atom(offset, len, MVHD, |mp4| {
+            let version = mp4.reader.read_u8()?;
+            let flags = mp4.reader.read_bytes_const::<3>()?;
+            let creation_time = read_time(mp4, version)?;
+            let modification_time = read_time(mp4, version)?;
+            let time_scale = mp4.reader.read_u32()?;
+            let duration = read_time(mp4, version)?;
+
+            Ok(Self {
+                version,
+                flags,
+                creation_time,
+                modification_time,
+                time_scale,
+                duration,
+                preferred_rate: mp4.reader.read_u32()?,
+                preferred_volume: mp4.reader.read_u16()?,
+                reserved: mp4.reader.read_bytes_const()?,
+                matrix: mp4.read_matrix()?,
+                preview_time: mp4.reader.read_u32()?,
+                preview_duration: mp4.reader.read_u32()?,
+                poster_time: mp4.reader.read_u32()?,
+                selection_time: mp4.reader.read_u32()?,
+                selection_duration: mp4.reader.read_u32()?,
+                current_time: mp4.reader.read_u32()?,
+                next_track_id: mp4.reader.read_u32()?,
+            })
+        })
+    }
+}
+
+impl Serialize for Mvhd {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let version = time_version(
+            self.version,
+            [self.creation_time, self.modification_time, self.duration],
+        );
+
+        let mut body = vec![version];
+        body.extend(self.flags);
+        write_time(&mut body, version, self.creation_time);
+        write_time(&mut body, version, self.modification_time);
+        body.extend(self.time_scale.to_be_bytes());
+        write_time(&mut body, version, self.duration);
+        body.extend(self.preferred_rate.to_be_bytes());
+        body.extend(self.preferred_volume.to_be_bytes());
+        body.extend(self.reserved);
+        self.matrix.serialize(mp4, &mut body)?;
+        for value in [
+            self.preview_time,
+            self.preview_duration,
+            self.poster_time,
+            self.selection_time,
+            self.selection_duration,
+            self.current_time,
+            self.next_track_id,
+        ] {
+            body.extend(value.to_be_bytes());
+        }
+
+        write_atom(writer, MVHD, &body)
+    }
+}
+
+impl Mvhd {
+    /// The creation time, or `None` if it is out of the range of
+    /// [`SystemTime`]
+    pub fn created(&self) -> Option<SystemTime> {
+        date_from_1904(self.creation_time)
+    }
+
+    /// The modification time, or `None` if it is out of the range of
+    /// [`SystemTime`]
+    pub fn modified(&self) -> Option<SystemTime> {
+        date_from_1904(self.modification_time)
+    }
+
+    pub fn movie_duration(&self) -> Duration {
+        from_timescale(self.duration, self.time_scale)
+    }
+}
+
+/// Seconds from midnight, January 1, 1904, the epoch of the times in movie,
+/// track and media headers, to the Unix epoch
+const SECONDS_FROM_1904_TO_1970: u64 = 2_082_844_800;
+
+/// Converts a time in seconds since midnight, January 1, 1904 UTC, or returns
+/// `None` if the time is out of the range of [`SystemTime`]
+fn date_from_1904(seconds: u64) -> Option<SystemTime> {
+    match seconds.checked_sub(SECONDS_FROM_1904_TO_1970) {
+        Some(since_1970) => UNIX_EPOCH.checked_add(Duration::from_secs(since_1970)),
+        None => UNIX_EPOCH.checked_sub(Duration::from_secs(SECONDS_FROM_1904_TO_1970 - seconds)),
+    }
+}
+
+/// The longest prefix of `text` that fits in a string with a one-byte length,
+/// cut at a character boundary
+fn short_string(text: &str) -> &str {
+    let mut len = text.len().min(u8::MAX as usize);
+    while !text.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    &text[..len]
+}
+
+/// Converts a number of units of a time scale to a duration
+fn from_timescale(units: u64, timescale: u32) -> Duration {
+    let timescale = timescale.max(1) as u64;
+    let nanos = (units % timescale) as u128 * 1_000_000_000 / timescale as u128;
+
+    Duration::from_secs(units / timescale) + Duration::from_nanos(nanos as u64)
+}
+
+/// Reads a time or duration of a movie, track or media header, which is 64
+/// bits wide in version 1 headers and 32 bits wide otherwise
+fn read_time<R: BufRead + Seek>(mp4: &mut Mp4<'_, R>, version: u8) -> Mp4Result<u64> {
+    Ok(match version {
+        1 => mp4.reader.read_u64()?,
+        _ => mp4.reader.read_u32()? as u64,
+    })
+}
+
+/// The version to write a movie, track or media header with, which is 1 when
+/// any of its times or durations no longer fits in 32 bits
+fn time_version(version: u8, times: [u64; 3]) -> u8 {
+    match times.iter().any(|&time| time > u32::MAX as u64) {
+        true => 1,
+        false => version,
+    }
+}
+
+/// Writes a time or duration of a header of the given version, which must be
+/// one returned by [`time_version`] so that the time isn't truncated
+fn write_time(body: &mut Vec<u8>, version: u8, time: u64) {
+    match version {
+        1 => body.extend(time.to_be_bytes()),
+        _ => body.extend((time as u32).to_be_bytes()),
+    }
+}
+
 #[mp4_atom]
 pub struct Stco {
     pub version: u8,
@@ -532,7 +1159,61 @@ pub struct Co64 {
     pub chunk_offset_table: Vec<u64>,
 }
 
-#[mp4_atom]
+/// Media data atom. Parsing it only records where its body lies, as it
+/// usually holds nearly all of the file
+#[derive(Debug, Clone)]
 pub struct Mdat {
-    pub bytes: Vec<u8>,
+    /// Offset of the atom within the file
+    pub offset: u64,
+    /// The total size of the atom, including its header
+    pub len: u64,
+    /// Offset of the atom's body, following its header
+    pub data_offset: u64,
+}
+
+impl Mdat {
+    /// The range of the file holding the atom's body
+    pub fn data_range(&self) -> Range<u64> {
+        self.data_offset..self.offset + self.len
+    }
+}
+
+impl Parse for Mdat {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(MDAT)?;
+        let data_offset = mp4.position()?;
+
+        if offset + len > mp4.reader.buffer.stream_len()? {
+            return Err(Mp4Error::TruncatedAtom {
+                offset,
+                path: mp4.atom_path(),
+                header: MDAT,
+            });
+        }
+
+        mp4.jump_to(offset + len)?;
+
+        Ok(Self {
+            offset,
+            len,
+            data_offset,
+        })
+    }
+}
+
+impl Serialize for Mdat {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let data_len = self.len - (self.data_offset - self.offset);
+
+        write_atom_header(writer, MDAT, data_len, self.data_offset - self.offset > 8)?;
+        mp4.copy_range(self.data_offset, data_len, writer)
+    }
 }
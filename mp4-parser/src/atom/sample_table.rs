@@ -0,0 +1,1307 @@
+use std::io::{BufRead, Seek};
+
+use crate::{Mp4, Mp4Error, Mp4Result};
+
+use super::{
+    Ctts, Header, Moof, Moov, SampleDependencyFlags, SampleFlags, SampleGroupEntry, Sbgp, Sdtp,
+    Sgpd, Stbl, Stps, Stsc, Stss, Stsz, Stts, Trak, Trex, MINF, STBL, STCO, STSC, STSZ, STTS,
+};
+
+/// The location, timing and properties of a single sample of a track
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleInfo {
+    /// Zero-based index of this sample within its track
+    pub index: u32,
+
+    /// Absolute byte offset of the sample data within the file
+    pub offset: u64,
+
+    /// Size of the sample data in bytes
+    pub size: u32,
+
+    /// Decoding time stamp, in the media's time scale
+    pub decode_time: u64,
+
+    /// Presentation time stamp, in the media's time scale. This is the decoding
+    /// time adjusted by the sample's composition offset, and so may be negative
+    /// for version 1 `ctts` atoms
+    pub presentation_time: i64,
+
+    /// Duration of the sample, in the media's time scale
+    pub duration: u32,
+
+    /// One-based index of the entry in the `stsd` atom describing this sample
+    pub sample_description_index: u32,
+
+    /// Whether decoding may begin at this sample without reference to any
+    /// earlier sample
+    pub is_sync: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TimeRun {
+    first_sample: u32,
+    first_time: u64,
+    sample_count: u32,
+    duration: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CompositionRun {
+    first_sample: u32,
+    sample_count: u32,
+    offset: i32,
+}
+
+/// A run of samples that are roll recovery points with the same roll distance
+#[derive(Debug, Clone, Copy)]
+struct RollRun {
+    first_sample: u32,
+    sample_count: u32,
+    /// If positive, decoding from a sample in this run produces correct output
+    /// this many samples later. If negative, decoding must begin this many
+    /// samples earlier for the output to be correct at a sample in this run
+    distance: i32,
+}
+
+impl RollRun {
+    /// The latest sample decoding can start from so that the output is correct
+    /// at `target`
+    fn recovery_start(&self, target: u32) -> Option<u32> {
+        let last_sample = self.first_sample + (self.sample_count - 1);
+
+        if self.distance >= 0 {
+            let latest = target.checked_sub(self.distance as u32)?;
+
+            if latest < self.first_sample {
+                return None;
+            }
+
+            Some(latest.min(last_sample))
+        } else {
+            if target < self.first_sample {
+                return None;
+            }
+
+            Some(
+                target
+                    .min(last_sample)
+                    .saturating_sub(self.distance.unsigned_abs()),
+            )
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ChunkRun {
+    /// Zero-based index of the first chunk in this run
+    first_chunk: u32,
+    first_sample: u32,
+    samples_per_chunk: u32,
+    sample_description_index: u32,
+}
+
+/// A sample described by a track run of a movie fragment, with every default
+/// already resolved
+#[derive(Debug, Clone, Copy)]
+struct FragmentSample {
+    offset: u64,
+    size: u32,
+    decode_time: u64,
+    duration: u32,
+    composition_offset: i64,
+    sample_description_index: u32,
+    flags: SampleFlags,
+}
+
+#[derive(Debug, Clone)]
+enum SampleSizes {
+    Constant(u32),
+    /// The size of every sample along with the total size of all samples
+    /// preceding it, so that a sample's position in its chunk can be found
+    /// without summing every sample before it
+    Variable {
+        sizes: Vec<u32>,
+        preceding: Vec<u64>,
+    },
+}
+
+impl SampleSizes {
+    fn size(&self, sample: u32) -> u32 {
+        match self {
+            SampleSizes::Constant(size) => *size,
+            SampleSizes::Variable { sizes, .. } => sizes[sample as usize],
+        }
+    }
+
+    /// The total size of the samples in the range `start..end`
+    fn size_between(&self, start: u32, end: u32) -> u64 {
+        match self {
+            SampleSizes::Constant(size) => (end - start) as u64 * *size as u64,
+            SampleSizes::Variable { preceding, .. } => {
+                preceding[end as usize] - preceding[start as usize]
+            }
+        }
+    }
+}
+
+/// Resolves samples of a track using the tables of its `stbl` atom, followed
+/// by the samples of any movie fragments
+///
+/// The individual tables are stored run-length encoded, so every lookup is a
+/// binary search over the runs rather than a scan of every preceding sample.
+#[derive(Debug, Clone)]
+pub struct SampleTable {
+    /// The number of samples described by the `stbl` atom. Any further samples
+    /// come from movie fragments
+    sample_count: u32,
+    time_runs: Vec<TimeRun>,
+    composition_runs: Vec<CompositionRun>,
+    chunk_runs: Vec<ChunkRun>,
+    chunk_offsets: Vec<u64>,
+    sample_sizes: SampleSizes,
+    /// Zero-based indices of the sync samples, or `None` if there is no `stss`
+    /// atom, in which case every sample is a sync sample
+    sync_samples: Option<Vec<u32>>,
+    /// Zero-based indices of the partial sync samples
+    partial_sync_samples: Vec<u32>,
+    roll_runs: Vec<RollRun>,
+    /// Empty if there is no `sdtp` atom
+    dependency_flags: Vec<SampleDependencyFlags>,
+    /// Sample indices sorted by presentation time. Empty if there are no
+    /// composition offsets, in which case presentation order is decode order
+    presentation_order: Vec<u32>,
+    fragment_samples: Vec<FragmentSample>,
+    /// Zero-based indices of the sync samples that come from movie fragments
+    fragment_sync_samples: Vec<u32>,
+}
+
+/// How a [`SeekPoint`] allows decoding to begin part way through a track
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekPointKind {
+    /// Decoding starts at a sync sample (`stss`)
+    Sync,
+
+    /// Decoding starts at a partial sync sample (`stps`), such as an open-GOP
+    /// I-frame, whose leading samples may not decode correctly
+    PartialSync,
+
+    /// Decoding starts at or before a roll recovery point described by a
+    /// `roll` or `prol` sample group
+    RollRecovery,
+}
+
+/// Where to begin decoding in order to present the sample at a given time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeekPoint {
+    /// Zero-based index of the sample decoding must start from
+    pub start: u32,
+
+    /// Zero-based index of the sample presented at the requested time
+    pub target: u32,
+
+    /// The number of samples from `start` up to, but not including, `target`
+    /// that must be decoded and then discarded. Samples that no other sample
+    /// depends on, according to `sdtp`, are not counted as they can be skipped
+    pub samples_to_discard: u32,
+
+    pub kind: SeekPointKind,
+}
+
+impl SampleTable {
+    /// Builds the sample table of a track, including the samples of every
+    /// movie fragment in the file if the movie is fragmented
+    pub fn for_track<R: BufRead + Seek>(
+        moov: &mut Moov,
+        trak: &mut Trak,
+        mp4: &mut Mp4<'_, R>,
+    ) -> Mp4Result<Self> {
+        let track_id = trak.track_header(mp4)?.parse(mp4)?.track_id;
+        let mut mdia = trak.mdia(mp4)?.parse(mp4)?;
+        let mut minf = match *mdia.minf(mp4) {
+            Some(minf) => minf.parse(mp4)?,
+            None => {
+                return Err(Mp4Error::MissingAtom {
+                    offset: mdia.offset,
+                    path: mdia.path.clone(),
+                    header: MINF,
+                })
+            }
+        };
+        let mut stbl = match *minf.stbl(mp4) {
+            Some(stbl) => stbl.parse(mp4)?,
+            None => {
+                return Err(Mp4Error::MissingAtom {
+                    offset: minf.offset,
+                    path: minf.path.clone(),
+                    header: STBL,
+                })
+            }
+        };
+
+        let mut table = Self::new(&mut stbl, mp4)?;
+
+        let mut mvex = match *moov.mvex(mp4) {
+            Some(mvex) => mvex.parse(mp4)?,
+            None => return Ok(table),
+        };
+
+        let mut trex = None;
+        for candidate in mvex.trex(mp4).clone() {
+            let candidate = candidate.parse(mp4)?;
+
+            if candidate.track_id == track_id {
+                trex = Some(candidate);
+                break;
+            }
+        }
+
+        for moof in mp4.movie_fragments()? {
+            table.add_fragment(track_id, &mut moof.parse(mp4)?, trex.as_ref(), mp4)?;
+        }
+
+        Ok(table)
+    }
+
+    pub fn new<R: BufRead + Seek>(stbl: &mut Stbl, mp4: &mut Mp4<'_, R>) -> Mp4Result<Self> {
+        fn missing(stbl: &Stbl, header: Header) -> Mp4Error {
+            Mp4Error::MissingAtom {
+                offset: stbl.offset,
+                path: stbl.path.clone(),
+                header,
+            }
+        }
+
+        let stts = match *stbl.time_to_sample(mp4) {
+            Some(stts) => stts.parse(mp4)?,
+            None => return Err(missing(stbl, STTS)),
+        };
+        let stsc = match *stbl.sample_to_chunk(mp4) {
+            Some(stsc) => stsc.parse(mp4)?,
+            None => return Err(missing(stbl, STSC)),
+        };
+        let stsz = match *stbl.sample_size(mp4) {
+            Some(stsz) => stsz.parse(mp4)?,
+            None => return Err(missing(stbl, STSZ)),
+        };
+
+        // when present, the 64-bit chunk offset atom is used in place of `stco`
+        let chunk_offsets = match (*stbl.chunk_offset_64(mp4), *stbl.chunk_offset(mp4)) {
+            (Some(co64), _) => co64.parse(mp4)?.chunk_offset_table,
+            (None, Some(stco)) => stco
+                .parse(mp4)?
+                .chunk_offset_table
+                .into_iter()
+                .map(u64::from)
+                .collect(),
+            (None, None) => return Err(missing(stbl, STCO)),
+        };
+
+        let ctts = match *stbl.composition_offset(mp4) {
+            Some(ctts) => Some(ctts.parse(mp4)?),
+            None => None,
+        };
+        let stss = match *stbl.sync_sample(mp4) {
+            Some(stss) => Some(stss.parse(mp4)?),
+            None => None,
+        };
+        let stps = match *stbl.stps(mp4) {
+            Some(stps) => Some(stps.parse(mp4)?),
+            None => None,
+        };
+        let sdtp = match *stbl.sdtp(mp4) {
+            Some(sdtp) => Some(sdtp.parse(mp4)?),
+            None => None,
+        };
+        let sgpd = stbl
+            .sgpd(mp4)
+            .clone()
+            .into_iter()
+            .map(|sgpd| sgpd.parse(mp4))
+            .collect::<Mp4Result<Vec<_>>>()?;
+        let sbgp = stbl
+            .sbgp(mp4)
+            .clone()
+            .into_iter()
+            .map(|sbgp| sbgp.parse(mp4))
+            .collect::<Mp4Result<Vec<_>>>()?;
+
+        Self::from_tables(
+            &stts,
+            ctts.as_ref(),
+            &stsc,
+            &stsz,
+            chunk_offsets,
+            stss.as_ref(),
+        )
+        .and_then(|table| table.with_partial_sync_samples(stps.as_ref()))
+        .map(|table| table.with_dependency_flags(sdtp))
+        .map(|table| table.with_sample_groups(&sgpd, &sbgp))
+        .map_err(|reason| Mp4Error::InvalidSampleTable {
+            offset: stbl.offset,
+            path: stbl.path.clone(),
+            reason,
+        })
+    }
+
+    fn from_tables(
+        stts: &Stts,
+        ctts: Option<&Ctts>,
+        stsc: &Stsc,
+        stsz: &Stsz,
+        chunk_offsets: Vec<u64>,
+        stss: Option<&Stss>,
+    ) -> Result<Self, &'static str> {
+        let (sample_count, sample_sizes) = match stsz.sample_size {
+            0 => {
+                let mut preceding = Vec::with_capacity(stsz.sample_size_table.len() + 1);
+                let mut total = 0;
+                preceding.push(total);
+                for &size in &stsz.sample_size_table {
+                    total += size as u64;
+                    preceding.push(total);
+                }
+
+                (
+                    u32::try_from(stsz.sample_size_table.len()).map_err(|_| "too many samples")?,
+                    SampleSizes::Variable {
+                        sizes: stsz.sample_size_table.clone(),
+                        preceding,
+                    },
+                )
+            }
+            size => (stsz.number_of_entries, SampleSizes::Constant(size)),
+        };
+
+        let mut time_runs = Vec::with_capacity(stts.time_to_sample_table.len());
+        let mut first_sample = 0_u64;
+        let mut first_time = 0_u64;
+        for entry in &stts.time_to_sample_table {
+            if entry.sample_count == 0 {
+                continue;
+            }
+
+            time_runs.push(TimeRun {
+                first_sample: first_sample as u32,
+                first_time,
+                sample_count: entry.sample_count,
+                duration: entry.sample_duration,
+            });
+
+            first_sample += entry.sample_count as u64;
+            first_time += entry.sample_count as u64 * entry.sample_duration as u64;
+
+            if first_sample > u32::MAX as u64 {
+                return Err("too many samples in time-to-sample table");
+            }
+        }
+
+        if first_sample < sample_count as u64 {
+            return Err("time-to-sample table does not cover every sample");
+        }
+
+        let mut composition_runs = Vec::new();
+        let mut first_sample = 0_u64;
+        for entry in ctts.map_or(&[][..], |ctts| &ctts.composition_offset_table) {
+            if entry.sample_count == 0 {
+                continue;
+            }
+
+            composition_runs.push(CompositionRun {
+                first_sample: first_sample as u32,
+                sample_count: entry.sample_count,
+                offset: entry.composition_offset,
+            });
+
+            first_sample += entry.sample_count as u64;
+
+            if first_sample > u32::MAX as u64 {
+                return Err("too many samples in composition offset table");
+            }
+        }
+
+        let chunk_count =
+            u32::try_from(chunk_offsets.len()).map_err(|_| "too many chunk offsets")?;
+        let mut chunk_runs = Vec::with_capacity(stsc.sample_to_chunk_table.len());
+        let mut first_sample = 0_u64;
+        for (idx, entry) in stsc.sample_to_chunk_table.iter().enumerate() {
+            // the last entry applies to every remaining chunk
+            let next_first_chunk = stsc
+                .sample_to_chunk_table
+                .get(idx + 1)
+                .map_or(chunk_count + 1, |next| next.first_chunk);
+
+            if entry.first_chunk == 0 || entry.first_chunk >= next_first_chunk {
+                return Err("sample-to-chunk entries are not in increasing chunk order");
+            }
+
+            if next_first_chunk > chunk_count + 1 {
+                return Err("sample-to-chunk table refers to a chunk without an offset");
+            }
+
+            if entry.samples_per_chunk != 0 {
+                chunk_runs.push(ChunkRun {
+                    first_chunk: entry.first_chunk - 1,
+                    first_sample: first_sample as u32,
+                    samples_per_chunk: entry.samples_per_chunk,
+                    sample_description_index: entry.sample_description_id,
+                });
+            }
+
+            first_sample +=
+                (next_first_chunk - entry.first_chunk) as u64 * entry.samples_per_chunk as u64;
+
+            if first_sample >= sample_count as u64 {
+                break;
+            }
+        }
+
+        if first_sample < sample_count as u64 {
+            return Err("chunks do not hold every sample");
+        }
+
+        let sync_samples = stss
+            .map(|stss| sample_indices(&stss.sync_sample_table))
+            .transpose()?;
+
+        let mut table = Self {
+            sample_count,
+            time_runs,
+            composition_runs,
+            chunk_runs,
+            chunk_offsets,
+            sample_sizes,
+            sync_samples,
+            partial_sync_samples: Vec::new(),
+            roll_runs: Vec::new(),
+            dependency_flags: Vec::new(),
+            presentation_order: Vec::new(),
+            fragment_samples: Vec::new(),
+            fragment_sync_samples: Vec::new(),
+        };
+
+        table.sort_presentation_order();
+
+        Ok(table)
+    }
+
+    /// Appends the samples of the track `track_id` contained in a movie
+    /// fragment, using the defaults in the track's `trex` atom for any values
+    /// the fragment doesn't give
+    pub fn add_fragment<R: BufRead + Seek>(
+        &mut self,
+        track_id: u32,
+        moof: &mut Moof,
+        trex: Option<&Trex>,
+        mp4: &mut Mp4<'_, R>,
+    ) -> Mp4Result<()> {
+        fn invalid(moof: &Moof, reason: &'static str) -> Mp4Error {
+            Mp4Error::InvalidSampleTable {
+                offset: moof.offset,
+                path: moof.path.clone(),
+                reason,
+            }
+        }
+
+        let mut decode_time = self.end_decode_time();
+
+        // the end of the data of the previous track fragment, which is the base
+        // data offset of a track fragment that doesn't give one explicitly
+        let mut previous_data_end = moof.offset;
+
+        for (idx, traf) in moof.traf(mp4).clone().into_iter().enumerate() {
+            let mut traf = traf.parse(mp4)?;
+            let tfhd = traf.tfhd(mp4)?.parse(mp4)?;
+
+            let base_data_offset = match tfhd.base_data_offset {
+                Some(base_data_offset) => base_data_offset,
+                None if idx == 0 || tfhd.default_base_is_moof() => moof.offset,
+                None => previous_data_end,
+            };
+
+            let is_track = tfhd.track_id == track_id;
+
+            if is_track {
+                if let Some(tfdt) = *traf.tfdt(mp4) {
+                    decode_time = tfdt.parse(mp4)?.base_media_decode_time;
+                }
+            }
+
+            let default_duration = tfhd
+                .default_sample_duration
+                .or(trex.map(|trex| trex.default_sample_duration))
+                .unwrap_or(0);
+            let default_size = tfhd
+                .default_sample_size
+                .or(trex.map(|trex| trex.default_sample_size))
+                .unwrap_or(0);
+            let default_flags = tfhd
+                .default_sample_flags
+                .or(trex.map(|trex| trex.default_sample_flags))
+                .unwrap_or_default();
+            let sample_description_index = tfhd
+                .sample_description_index
+                .or(trex.map(|trex| trex.default_sample_description_index))
+                .unwrap_or(1);
+
+            let mut data_offset = base_data_offset;
+
+            for trun in traf.trun(mp4).clone() {
+                let trun = trun.parse(mp4)?;
+
+                if let Some(run_offset) = trun.data_offset {
+                    data_offset = base_data_offset
+                        .checked_add_signed(run_offset as i64)
+                        .ok_or_else(|| invalid(moof, "track run data offset is out of range"))?;
+                }
+
+                for (sample_idx, sample) in trun.samples.iter().enumerate() {
+                    let size = sample.size.unwrap_or(default_size);
+                    let duration = sample.duration.unwrap_or(default_duration);
+                    let flags = match (sample.flags, trun.first_sample_flags) {
+                        (Some(flags), _) => flags,
+                        (None, Some(first_sample_flags)) if sample_idx == 0 => first_sample_flags,
+                        (None, _) => default_flags,
+                    };
+
+                    if is_track {
+                        let index = self.len();
+
+                        if index == u32::MAX {
+                            return Err(invalid(moof, "track has too many samples"));
+                        }
+
+                        if !flags.is_non_sync() {
+                            self.fragment_sync_samples.push(index);
+                        }
+
+                        self.fragment_samples.push(FragmentSample {
+                            offset: data_offset,
+                            size,
+                            decode_time,
+                            duration,
+                            composition_offset: sample.composition_offset.unwrap_or(0),
+                            sample_description_index,
+                            flags,
+                        });
+
+                        // keeps the end of every sample within the range of
+                        // presentation times
+                        decode_time = decode_time
+                            .checked_add(duration as u64)
+                            .filter(|&end| end <= i64::MAX as u64)
+                            .ok_or_else(|| {
+                                invalid(moof, "track fragment decode time is out of range")
+                            })?;
+                    }
+
+                    data_offset = data_offset
+                        .checked_add(size as u64)
+                        .ok_or_else(|| invalid(moof, "track run data offset is out of range"))?;
+                }
+            }
+
+            previous_data_end = data_offset;
+        }
+
+        self.sort_presentation_order();
+
+        Ok(())
+    }
+
+    fn sort_presentation_order(&mut self) {
+        let has_composition_offsets = !self.composition_runs.is_empty()
+            || self
+                .fragment_samples
+                .iter()
+                .any(|sample| sample.composition_offset != 0);
+
+        if !has_composition_offsets {
+            self.presentation_order = Vec::new();
+            return;
+        }
+
+        let mut presentation_order = (0..self.len()).collect::<Vec<_>>();
+        presentation_order.sort_by_key(|&index| self.presentation_time(index));
+        self.presentation_order = presentation_order;
+    }
+
+    /// The decoding time at which a sample following every existing sample
+    /// would begin
+    pub(crate) fn end_decode_time(&self) -> u64 {
+        match (self.fragment_samples.last(), self.time_runs.last()) {
+            (Some(sample), _) => sample.decode_time.saturating_add(sample.duration as u64),
+            (None, Some(run)) => run.first_time + run.sample_count as u64 * run.duration as u64,
+            (None, None) => 0,
+        }
+    }
+
+    fn fragment_sample(&self, index: u32) -> Option<&FragmentSample> {
+        index
+            .checked_sub(self.sample_count)
+            .and_then(|idx| self.fragment_samples.get(idx as usize))
+    }
+
+    fn with_partial_sync_samples(mut self, stps: Option<&Stps>) -> Result<Self, &'static str> {
+        if let Some(stps) = stps {
+            self.partial_sync_samples = sample_indices(&stps.partial_sync_sample_table)?;
+        }
+
+        Ok(self)
+    }
+
+    fn with_dependency_flags(mut self, sdtp: Option<Sdtp>) -> Self {
+        if let Some(sdtp) = sdtp {
+            self.dependency_flags = sdtp.sample_dependency_flags_table;
+        }
+
+        self
+    }
+
+    fn with_sample_groups(mut self, sgpd: &[Sgpd], sbgp: &[Sbgp]) -> Self {
+        for sample_to_group in sbgp {
+            let description = match sgpd
+                .iter()
+                .find(|sgpd| sgpd.grouping_type == sample_to_group.grouping_type)
+            {
+                Some(description) => description,
+                None => continue,
+            };
+
+            let mut first_sample = 0_u32;
+            for entry in &sample_to_group.entries {
+                // runs are cut short at the last sample, and dropped past it
+                let sample_count = entry
+                    .sample_count
+                    .min(self.sample_count.saturating_sub(first_sample));
+                let distance = match entry
+                    .group_description_index
+                    .checked_sub(1)
+                    .and_then(|idx| description.entries.get(idx as usize))
+                {
+                    Some(SampleGroupEntry::RollRecovery(distance)) => Some(*distance as i32),
+                    Some(SampleGroupEntry::AudioPreRoll(distance)) => Some(-(*distance as i32)),
+                    _ => None,
+                };
+
+                if let Some(distance) = distance.filter(|_| sample_count != 0) {
+                    self.roll_runs.push(RollRun {
+                        first_sample,
+                        sample_count,
+                        distance,
+                    });
+                }
+
+                first_sample = first_sample.saturating_add(entry.sample_count);
+            }
+        }
+
+        self
+    }
+
+    /// The number of samples in the track
+    pub fn len(&self) -> u32 {
+        self.sample_count + self.fragment_samples.len() as u32
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Resolves the sample with the given zero-based index
+    pub fn sample(&self, index: u32) -> Option<SampleInfo> {
+        if index >= self.sample_count {
+            return self.fragment_sample(index).map(|sample| SampleInfo {
+                index,
+                offset: sample.offset,
+                size: sample.size,
+                decode_time: sample.decode_time,
+                presentation_time: sample.decode_time as i64 + sample.composition_offset,
+                duration: sample.duration,
+                sample_description_index: sample.sample_description_index,
+                is_sync: !sample.flags.is_non_sync(),
+            });
+        }
+
+        let time_run = self.time_run(index);
+        let decode_time = self.decode_time(index);
+
+        let chunk_run = self.chunk_runs[self
+            .chunk_runs
+            .partition_point(|run| run.first_sample <= index)
+            - 1];
+        let chunk_in_run = (index - chunk_run.first_sample) / chunk_run.samples_per_chunk;
+        let chunk = chunk_run.first_chunk + chunk_in_run;
+        let first_sample_in_chunk =
+            chunk_run.first_sample + chunk_in_run * chunk_run.samples_per_chunk;
+
+        let offset = self.chunk_offsets[chunk as usize]
+            + self.sample_sizes.size_between(first_sample_in_chunk, index);
+
+        Some(SampleInfo {
+            index,
+            offset,
+            size: self.sample_sizes.size(index),
+            decode_time,
+            presentation_time: self.presentation_time(index),
+            duration: time_run.duration,
+            sample_description_index: chunk_run.sample_description_index,
+            is_sync: self.is_sync(index),
+        })
+    }
+
+    fn time_run(&self, index: u32) -> TimeRun {
+        self.time_runs[self
+            .time_runs
+            .partition_point(|run| run.first_sample <= index)
+            - 1]
+    }
+
+    fn decode_time(&self, index: u32) -> u64 {
+        if let Some(sample) = self.fragment_sample(index) {
+            return sample.decode_time;
+        }
+
+        let run = self.time_run(index);
+
+        run.first_time + (index - run.first_sample) as u64 * run.duration as u64
+    }
+
+    fn presentation_time(&self, index: u32) -> i64 {
+        if let Some(sample) = self.fragment_sample(index) {
+            return sample.decode_time as i64 + sample.composition_offset;
+        }
+
+        let composition_offset = match self
+            .composition_runs
+            .partition_point(|run| run.first_sample <= index)
+        {
+            0 => 0,
+            idx => {
+                let run = self.composition_runs[idx - 1];
+
+                if index - run.first_sample < run.sample_count {
+                    run.offset
+                } else {
+                    0
+                }
+            }
+        };
+
+        self.decode_time(index) as i64 + composition_offset as i64
+    }
+
+    /// Resolves the sample being decoded at `decode_time`, given in the media's
+    /// time scale
+    pub fn sample_at_decode_time(&self, decode_time: u64) -> Option<SampleInfo> {
+        let position = self
+            .fragment_samples
+            .partition_point(|sample| sample.decode_time <= decode_time);
+
+        if position != 0 {
+            let sample = self.fragment_samples[position - 1];
+
+            if position == self.fragment_samples.len()
+                && decode_time >= sample.decode_time.saturating_add(sample.duration as u64)
+            {
+                return None;
+            }
+
+            return self.sample(self.sample_count + (position - 1) as u32);
+        }
+
+        let run = match self
+            .time_runs
+            .partition_point(|run| run.first_time <= decode_time)
+        {
+            0 => return None,
+            idx => self.time_runs[idx - 1],
+        };
+
+        let offset_in_run = match run.duration {
+            0 => 0,
+            duration => (decode_time - run.first_time) / duration as u64,
+        };
+
+        if offset_in_run >= run.sample_count as u64 {
+            return None;
+        }
+
+        self.sample(run.first_sample + offset_in_run as u32)
+    }
+
+    /// Whether the sample with the given zero-based index is a sync sample
+    pub fn is_sync(&self, index: u32) -> bool {
+        if let Some(sample) = self.fragment_sample(index) {
+            return !sample.flags.is_non_sync();
+        }
+
+        match &self.sync_samples {
+            Some(sync_samples) => sync_samples.binary_search(&index).is_ok(),
+            None => true,
+        }
+    }
+
+    /// Resolves the sample being presented at `presentation_time`, given in the
+    /// media's time scale. Times before the first sample resolve to the first
+    /// sample to be presented
+    pub fn sample_at_presentation_time(&self, presentation_time: i64) -> Option<SampleInfo> {
+        if self.presentation_order.is_empty() {
+            return self.sample_at_decode_time(presentation_time.max(0) as u64);
+        }
+
+        let position = self
+            .presentation_order
+            .partition_point(|&index| self.presentation_time(index) <= presentation_time);
+
+        let sample = self.sample(self.presentation_order[position.saturating_sub(1)])?;
+
+        if position == self.presentation_order.len()
+            && presentation_time
+                >= sample
+                    .presentation_time
+                    .saturating_add(sample.duration as i64)
+        {
+            return None;
+        }
+
+        Some(sample)
+    }
+
+    /// Finds the closest point before `presentation_time` that decoding can
+    /// start from in order to present the sample at that time, considering
+    /// sync samples, partial sync samples and roll recovery points
+    pub fn seek(&self, presentation_time: i64) -> Option<SeekPoint> {
+        let target = self.sample_at_presentation_time(presentation_time)?.index;
+
+        let sync = preceding(&self.fragment_sync_samples, target).or_else(|| {
+            let target = target.min(self.sample_count.checked_sub(1)?);
+
+            match &self.sync_samples {
+                Some(sync_samples) => preceding(sync_samples, target),
+                None => Some(target),
+            }
+        });
+        let partial_sync = preceding(&self.partial_sync_samples, target);
+        let roll_recovery = self
+            .roll_runs
+            .iter()
+            .filter_map(|run| run.recovery_start(target))
+            .max();
+
+        // prefer whichever requires the fewest samples to be decoded, and on a
+        // tie, a full sync sample
+        let (start, kind) = [
+            sync.map(|start| (start, SeekPointKind::Sync)),
+            partial_sync.map(|start| (start, SeekPointKind::PartialSync)),
+            roll_recovery.map(|start| (start, SeekPointKind::RollRecovery)),
+        ]
+        .into_iter()
+        .flatten()
+        .reduce(|best, candidate| {
+            if candidate.0 > best.0 {
+                candidate
+            } else {
+                best
+            }
+        })?;
+
+        let samples_to_discard = (start..target)
+            .filter(|&index| !self.is_disposable(index))
+            .count() as u32;
+
+        Some(SeekPoint {
+            start,
+            target,
+            samples_to_discard,
+            kind,
+        })
+    }
+
+    /// Whether the sample with the given zero-based index is a partial sync
+    /// sample
+    pub fn is_partial_sync(&self, index: u32) -> bool {
+        self.partial_sync_samples.binary_search(&index).is_ok()
+    }
+
+    /// The dependency information of the sample with the given zero-based
+    /// index, if the track has an `sdtp` atom
+    pub fn dependency_flags(&self, index: u32) -> Option<SampleDependencyFlags> {
+        if let Some(sample) = self.fragment_sample(index) {
+            return Some(sample.flags.dependency_flags());
+        }
+
+        self.dependency_flags.get(index as usize).copied()
+    }
+
+    fn is_disposable(&self, index: u32) -> bool {
+        self.dependency_flags(index)
+            .is_some_and(|flags| flags.is_disposable())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = SampleInfo> + '_ {
+        (0..self.len()).filter_map(|index| self.sample(index))
+    }
+}
+
+/// Converts a table of one-based sample numbers to sorted zero-based indices,
+/// as not every file lists them in increasing order
+fn sample_indices(sample_numbers: &[u32]) -> Result<Vec<u32>, &'static str> {
+    let mut indices = sample_numbers
+        .iter()
+        .map(|&sample_number| {
+            sample_number
+                .checked_sub(1)
+                .ok_or("sample numbers start at 1")
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    indices.sort_unstable();
+    indices.dedup();
+
+    Ok(indices)
+}
+
+/// The last of the sorted `indices` that is not after `target`
+fn preceding(indices: &[u32], target: u32) -> Option<u32> {
+    indices[..indices.partition_point(|&index| index <= target)]
+        .last()
+        .copied()
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        test_util::{atom, full_atom},
+        CompositionOffsetEntry, Parse, SampleFlags, SampleToChunkEntry, SampleToGroupEntry,
+        TimeToSampleEntry,
+    };
+
+    fn stts(entries: &[(u32, u32)]) -> Stts {
+        Stts {
+            version: 0,
+            flags: [0; 3],
+            number_of_entries: entries.len() as u32,
+            time_to_sample_table: entries
+                .iter()
+                .map(|&(sample_count, sample_duration)| TimeToSampleEntry {
+                    sample_count,
+                    sample_duration,
+                })
+                .collect(),
+        }
+    }
+
+    fn stsc(entries: &[(u32, u32, u32)]) -> Stsc {
+        Stsc {
+            version: 0,
+            flags: [0; 3],
+            number_of_entries: entries.len() as u32,
+            sample_to_chunk_table: entries
+                .iter()
+                .map(|&(first_chunk, samples_per_chunk, sample_description_id)| {
+                    SampleToChunkEntry {
+                        first_chunk,
+                        samples_per_chunk,
+                        sample_description_id,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    fn stsz(sizes: &[u32]) -> Stsz {
+        Stsz {
+            version: 0,
+            flags: [0; 3],
+            sample_size: 0,
+            number_of_entries: sizes.len() as u32,
+            sample_size_table: sizes.to_vec(),
+        }
+    }
+
+    fn stss(sample_numbers: &[u32]) -> Stss {
+        Stss {
+            version: 0,
+            flags: [0; 3],
+            number_of_entries: sample_numbers.len() as u32,
+            sync_sample_table: sample_numbers.to_vec(),
+        }
+    }
+
+    /// Ten samples lasting 10 each, in a single chunk
+    fn ten_samples(sync_samples: &[u32]) -> SampleTable {
+        SampleTable::from_tables(
+            &stts(&[(10, 10)]),
+            None,
+            &stsc(&[(1, 10, 1)]),
+            &stsz(&[1; 10]),
+            vec![0],
+            Some(&stss(sync_samples)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn resolves_samples_within_chunks() {
+        // chunks 1 and 2 hold 2 samples each, chunk 3 holds 1
+        let table = SampleTable::from_tables(
+            &stts(&[(3, 10), (2, 20)]),
+            Some(&Ctts {
+                version: 0,
+                flags: [0; 3],
+                number_of_entries: 2,
+                composition_offset_table: vec![
+                    CompositionOffsetEntry {
+                        sample_count: 1,
+                        composition_offset: 20,
+                    },
+                    CompositionOffsetEntry {
+                        sample_count: 4,
+                        composition_offset: -10,
+                    },
+                ],
+            }),
+            &stsc(&[(1, 2, 1), (3, 1, 2)]),
+            &stsz(&[5, 6, 7, 8, 9]),
+            vec![100, 200, 300],
+            Some(&Stss {
+                version: 0,
+                flags: [0; 3],
+                number_of_entries: 2,
+                sync_sample_table: vec![1, 4],
+            }),
+        )
+        .unwrap();
+
+        assert_eq!(table.len(), 5);
+
+        let offsets = table.iter().map(|s| s.offset).collect::<Vec<_>>();
+        assert_eq!(offsets, vec![100, 105, 200, 207, 300]);
+
+        let sample = table.sample(4).unwrap();
+        assert_eq!(sample.decode_time, 50);
+        assert_eq!(sample.presentation_time, 40);
+        assert_eq!(sample.duration, 20);
+        assert_eq!(sample.sample_description_index, 2);
+        assert!(!sample.is_sync);
+        assert!(table.sample(3).unwrap().is_sync);
+        assert_eq!(table.sample(0).unwrap().presentation_time, 20);
+        assert_eq!(table.sample(5), None);
+
+        assert_eq!(table.sample_at_decode_time(0).unwrap().index, 0);
+        assert_eq!(table.sample_at_decode_time(29).unwrap().index, 2);
+        assert_eq!(table.sample_at_decode_time(30).unwrap().index, 3);
+        assert_eq!(table.sample_at_decode_time(69).unwrap().index, 4);
+        assert_eq!(table.sample_at_decode_time(70), None);
+
+        assert_eq!(table.sample_at_presentation_time(5).unwrap().index, 1);
+        assert_eq!(
+            table.seek(5),
+            Some(SeekPoint {
+                start: 0,
+                target: 1,
+                samples_to_discard: 1,
+                kind: SeekPointKind::Sync,
+            })
+        );
+        assert_eq!(
+            table.seek(45),
+            Some(SeekPoint {
+                start: 3,
+                target: 4,
+                samples_to_discard: 1,
+                kind: SeekPointKind::Sync,
+            })
+        );
+        assert_eq!(table.seek(60), None);
+    }
+
+    #[test]
+    fn resolves_fragment_samples_from_their_defaults() {
+        // the first run takes its durations and sample description from the
+        // tfhd and the flags of its second sample from the trex, the other
+        // track's run follows on from it and the last run uses the trex alone
+        let trafs = [
+            [
+                full_atom(b"tfhd", 0x00000a, &[1, 2, 5]),
+                full_atom(b"trun", 0x000205, &[2, 100, 0, 3, 4]),
+            ],
+            [
+                full_atom(b"tfhd", 0, &[2]),
+                full_atom(b"trun", 0x000200, &[1, 6]),
+            ],
+            [full_atom(b"tfhd", 0, &[1]), full_atom(b"trun", 0, &[1])],
+        ];
+        let mut moof = full_atom(b"mfhd", 0, &[1]);
+        for traf in trafs {
+            moof.extend(atom(b"traf", &traf.concat()));
+        }
+
+        let mut mp4 = Mp4::new(Cursor::new(atom(b"moof", &moof)));
+        let mut moof = Moof::parse(&mut mp4).unwrap();
+        let trex = Trex {
+            version: 0,
+            flags: [0; 3],
+            track_id: 1,
+            default_sample_description_index: 1,
+            default_sample_duration: 10,
+            default_sample_size: 7,
+            default_sample_flags: SampleFlags(0x1_0000),
+        };
+
+        let mut table =
+            SampleTable::from_tables(&stts(&[]), None, &stsc(&[]), &stsz(&[]), Vec::new(), None)
+                .unwrap();
+        table
+            .add_fragment(1, &mut moof, Some(&trex), &mut mp4)
+            .unwrap();
+
+        let samples = table
+            .iter()
+            .map(|sample| {
+                (
+                    sample.offset,
+                    sample.size,
+                    sample.decode_time,
+                    sample.duration,
+                    sample.sample_description_index,
+                    sample.is_sync,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            samples,
+            [
+                (100, 3, 0, 5, 2, true),
+                (103, 4, 5, 5, 2, false),
+                (113, 7, 10, 10, 1, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn seeks_to_roll_recovery_points() {
+        let sgpd = Sgpd {
+            version: 1,
+            flags: [0; 3],
+            grouping_type: *b"roll",
+            default_length: Some(2),
+            default_sample_description_index: None,
+            entry_count: 1,
+            entries: vec![SampleGroupEntry::RollRecovery(2)],
+        };
+        // the second run is cut short at the last sample, and the runs after
+        // it are dropped
+        let entries = [(4, 0), (2, 1), (10, 1), (u32::MAX, 0), (5, 1)]
+            .into_iter()
+            .map(
+                |(sample_count, group_description_index)| SampleToGroupEntry {
+                    sample_count,
+                    group_description_index,
+                },
+            )
+            .collect::<Vec<_>>();
+        let sbgp = Sbgp {
+            version: 0,
+            flags: [0; 3],
+            grouping_type: *b"roll",
+            grouping_type_parameter: None,
+            entry_count: entries.len() as u32,
+            entries,
+        };
+
+        let table = ten_samples(&[1]).with_sample_groups(&[sgpd], &[sbgp]);
+
+        let runs = table
+            .roll_runs
+            .iter()
+            .map(|run| (run.first_sample, run.sample_count))
+            .collect::<Vec<_>>();
+        assert_eq!(runs, [(4, 2), (6, 4)]);
+
+        assert_eq!(
+            table.seek(70),
+            Some(SeekPoint {
+                start: 5,
+                target: 7,
+                samples_to_discard: 2,
+                kind: SeekPointKind::RollRecovery,
+            })
+        );
+        assert_eq!(table.seek(50).unwrap().kind, SeekPointKind::Sync);
+        assert_eq!(table.seek(99).unwrap().start, 7);
+    }
+
+    #[test]
+    fn seeks_to_partial_sync_samples() {
+        // neither table is in increasing order
+        let table = ten_samples(&[6, 1])
+            .with_partial_sync_samples(Some(&Stps {
+                version: 0,
+                flags: [0; 3],
+                number_of_entries: 2,
+                partial_sync_sample_table: vec![9, 4],
+            }))
+            .unwrap();
+
+        assert!(table.is_sync(5));
+        assert!(table.is_partial_sync(3));
+        assert!(!table.is_partial_sync(5));
+
+        assert_eq!(
+            table.seek(95),
+            Some(SeekPoint {
+                start: 8,
+                target: 9,
+                samples_to_discard: 1,
+                kind: SeekPointKind::PartialSync,
+            })
+        );
+        assert_eq!(table.seek(45).unwrap().start, 3);
+        assert_eq!(table.seek(75).unwrap().kind, SeekPointKind::Sync);
+    }
+
+    #[test]
+    fn skips_disposable_samples_when_seeking() {
+        // samples 1 to 3 are depended on by no other sample
+        let table = ten_samples(&[1]).with_dependency_flags(Some(Sdtp {
+            version: 0,
+            flags: [0; 3],
+            sample_dependency_flags_table: (0..10)
+                .map(|index| SampleDependencyFlags {
+                    is_leading: 0,
+                    sample_depends_on: 0,
+                    sample_is_depended_on: if (1..4).contains(&index) { 2 } else { 1 },
+                    sample_has_redundancy: 0,
+                })
+                .collect(),
+        }));
+
+        assert_eq!(
+            table.seek(45),
+            Some(SeekPoint {
+                start: 0,
+                target: 4,
+                samples_to_discard: 1,
+                kind: SeekPointKind::Sync,
+            })
+        );
+        assert_eq!(table.seek(65).unwrap().samples_to_discard, 3);
+    }
+
+    #[test]
+    fn rejects_chunks_that_do_not_hold_every_sample() {
+        assert!(SampleTable::from_tables(
+            &stts(&[(3, 10)]),
+            None,
+            &stsc(&[(1, 1, 1)]),
+            &stsz(&[1, 2, 3]),
+            vec![0, 10],
+            None,
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,633 @@
+use std::io::{BufRead, Seek, Write};
+
+use atom_macro::{mp4_atom, mp4_container_atom};
+
+use crate::{write_atom, Mp4, Mp4Error, Mp4Result, Parse, Reference, Serialize};
+
+use super::{Header, InternalElement, Moof, UnparsedAtom, MFRA, MFRO, MOOF, SIDX, STYP, TFRA};
+
+/// Segment type atom, identifying the specifications a media segment conforms
+/// to
+#[mp4_atom]
+pub struct Styp {
+    pub major_brand: [u8; 4],
+    pub major_brand_version: [u8; 4],
+    pub compatible_brands: Vec<[u8; 4]>,
+}
+
+/// Segment index atom, describing the subsegments that follow it
+#[derive(Debug, Clone)]
+pub struct Sidx {
+    pub version: u8,
+    pub flags: [u8; 3],
+    /// The track the index is for
+    pub reference_id: u32,
+    pub timescale: u32,
+    pub earliest_presentation_time: u64,
+    /// Distance from the end of this atom to the first byte of the first
+    /// referenced subsegment
+    pub first_offset: u64,
+    pub references: Vec<SidxReference>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SidxReference {
+    /// Whether the reference points to another `sidx` atom rather than to
+    /// media, as in a hierarchical or daisy-chained index
+    pub references_index: bool,
+    /// Size in bytes of the referenced material
+    pub referenced_size: u32,
+    /// Duration of the referenced material, in the index's time scale
+    pub subsegment_duration: u32,
+    pub starts_with_sap: bool,
+    pub sap_type: u8,
+    pub sap_delta_time: u32,
+}
+
+impl Parse for Sidx {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(SIDX)?;
+
+        mp4.in_atom(offset, len, SIDX, |mp4| {
+            let version = mp4.reader.read_u8()?;
+            let flags = mp4.reader.read_bytes_const::<3>()?;
+            let reference_id = mp4.reader.read_u32()?;
+            let timescale = mp4.reader.read_u32()?;
+            let (earliest_presentation_time, first_offset) = match version {
+                0 => (mp4.reader.read_u32()? as u64, mp4.reader.read_u32()? as u64),
+                _ => (mp4.reader.read_u64()?, mp4.reader.read_u64()?),
+            };
+            let _reserved = mp4.reader.read_u16()?;
+            let reference_count = mp4.reader.read_u16()?;
+
+            let mut references = Vec::new();
+            for _ in 0..reference_count {
+                let size = mp4.reader.read_u32()?;
+                let subsegment_duration = mp4.reader.read_u32()?;
+                let sap = mp4.reader.read_u32()?;
+
+                references.push(SidxReference {
+                    references_index: size >> 31 != 0,
+                    referenced_size: size & 0x7fff_ffff,
+                    subsegment_duration,
+                    starts_with_sap: sap >> 31 != 0,
+                    sap_type: ((sap >> 28) & 0b111) as u8,
+                    sap_delta_time: sap & 0x0fff_ffff,
+                });
+            }
+
+            Ok(Self {
+                version,
+                flags,
+                reference_id,
+                timescale,
+                earliest_presentation_time,
+                first_offset,
+                references,
+            })
+        })
+    }
+}
+
+impl Serialize for Sidx {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let mut body = vec![self.version];
+        body.extend(self.flags);
+        body.extend(self.reference_id.to_be_bytes());
+        body.extend(self.timescale.to_be_bytes());
+        match self.version {
+            0 => {
+                body.extend((self.earliest_presentation_time as u32).to_be_bytes());
+                body.extend((self.first_offset as u32).to_be_bytes());
+            }
+            _ => {
+                body.extend(self.earliest_presentation_time.to_be_bytes());
+                body.extend(self.first_offset.to_be_bytes());
+            }
+        }
+        body.extend(0u16.to_be_bytes());
+        body.extend((self.references.len() as u16).to_be_bytes());
+
+        for reference in &self.references {
+            let size = (reference.references_index as u32) << 31 | reference.referenced_size;
+            let sap = (reference.starts_with_sap as u32) << 31
+                | ((reference.sap_type as u32 & 0b111) << 28)
+                | reference.sap_delta_time & 0x0fff_ffff;
+
+            body.extend(size.to_be_bytes());
+            body.extend(reference.subsegment_duration.to_be_bytes());
+            body.extend(sap.to_be_bytes());
+        }
+
+        write_atom(writer, SIDX, &body)
+    }
+}
+
+/// Movie fragment random access atom
+#[mp4_container_atom]
+pub struct Mfra {
+    pub tfra: Vec<Reference<Tfra>>,
+    pub mfro: Option<Reference<Mfro>>,
+}
+
+/// Track fragment random access atom, listing the sync samples of one track
+/// along with the movie fragment each one is found in
+#[derive(Debug, Clone)]
+pub struct Tfra {
+    pub version: u8,
+    pub flags: [u8; 3],
+    pub track_id: u32,
+    /// The reserved bits followed by the byte widths, less one, of the
+    /// `traf_number`, `trun_number` and `sample_number` of each entry, two
+    /// bits each
+    pub length_sizes: u32,
+    pub entries: Vec<TfraEntry>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TfraEntry {
+    /// Presentation time of the sync sample, in the track's media time scale
+    pub time: u64,
+    /// Absolute offset of the `moof` atom holding the sync sample
+    pub moof_offset: u64,
+    /// One-based index of the `traf` atom within the `moof`
+    pub traf_number: u32,
+    /// One-based index of the `trun` atom within the `traf`
+    pub trun_number: u32,
+    /// One-based index of the sample within the `trun`
+    pub sample_number: u32,
+}
+
+impl Parse for Tfra {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        fn read_sized<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>, size: u32) -> Mp4Result<u32> {
+            let mut value = 0;
+            for _ in 0..=size {
+                value = (value << 8) | mp4.reader.read_u8()? as u32;
+            }
+
+            Ok(value)
+        }
+
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(TFRA)?;
+
+        mp4.in_atom(offset, len, TFRA, |mp4| {
+            let version = mp4.reader.read_u8()?;
+            let flags = mp4.reader.read_bytes_const::<3>()?;
+            let track_id = mp4.reader.read_u32()?;
+            let length_sizes = mp4.reader.read_u32()?;
+            let entry_count = mp4.reader.read_u32()?;
+
+            let mut entries = Vec::new();
+            for _ in 0..entry_count {
+                let (time, moof_offset) = match version {
+                    1 => (mp4.reader.read_u64()?, mp4.reader.read_u64()?),
+                    _ => (mp4.reader.read_u32()? as u64, mp4.reader.read_u32()? as u64),
+                };
+
+                entries.push(TfraEntry {
+                    time,
+                    moof_offset,
+                    traf_number: read_sized(mp4, (length_sizes >> 4) & 0b11)?,
+                    trun_number: read_sized(mp4, (length_sizes >> 2) & 0b11)?,
+                    sample_number: read_sized(mp4, length_sizes & 0b11)?,
+                });
+            }
+
+            Ok(Self {
+                version,
+                flags,
+                track_id,
+                length_sizes,
+                entries,
+            })
+        })
+    }
+}
+
+impl Serialize for Tfra {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        // widen the header and fields when an entry no longer fits in them
+        let version = match self
+            .entries
+            .iter()
+            .any(|entry| entry.time > u32::MAX as u64 || entry.moof_offset > u32::MAX as u64)
+        {
+            true => 1,
+            false => self.version,
+        };
+        let size = |shift: u32, number: fn(&TfraEntry) -> u32| {
+            let max = self.entries.iter().map(number).max().unwrap_or(0);
+            let needed = (4 - max.leading_zeros() / 8).max(1) - 1;
+
+            needed.max((self.length_sizes >> shift) & 0b11)
+        };
+        let sizes = [
+            size(4, |entry| entry.traf_number),
+            size(2, |entry| entry.trun_number),
+            size(0, |entry| entry.sample_number),
+        ];
+
+        let mut body = vec![version];
+        body.extend(self.flags);
+        body.extend(self.track_id.to_be_bytes());
+        body.extend(
+            (self.length_sizes & !0b11_1111 | sizes[0] << 4 | sizes[1] << 2 | sizes[2])
+                .to_be_bytes(),
+        );
+        body.extend((self.entries.len() as u32).to_be_bytes());
+
+        for entry in &self.entries {
+            match version {
+                1 => {
+                    body.extend(entry.time.to_be_bytes());
+                    body.extend(entry.moof_offset.to_be_bytes());
+                }
+                _ => {
+                    body.extend((entry.time as u32).to_be_bytes());
+                    body.extend((entry.moof_offset as u32).to_be_bytes());
+                }
+            }
+
+            let numbers = [entry.traf_number, entry.trun_number, entry.sample_number];
+            for (number, size) in numbers.into_iter().zip(sizes) {
+                body.extend(&number.to_be_bytes()[3 - size as usize..]);
+            }
+        }
+
+        write_atom(writer, TFRA, &body)
+    }
+}
+
+/// Movie fragment random access offset atom, the last atom of `mfra`, so that
+/// `mfra` can be found by reading the end of the file
+#[mp4_atom]
+pub struct Mfro {
+    pub version: u8,
+    pub flags: [u8; 3],
+    /// Size of the enclosing `mfra` atom
+    pub size: u32,
+}
+
+/// A position in a fragmented file that playback of a track can begin from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RandomAccessPoint {
+    pub track_id: u32,
+    /// Presentation time of the first sample in the byte range, or of the sync
+    /// sample when the point comes from a `tfra` atom
+    pub time: u64,
+    /// Time scale of `time`, or `None` if it is the track's media time scale
+    pub timescale: Option<u32>,
+    /// Absolute offset of the byte range, which starts with the movie fragment
+    /// holding the point, possibly preceded by `styp` or `sidx` atoms
+    pub offset: u64,
+    pub len: u64,
+    /// Whether the first sample of the track in the byte range is a stream
+    /// access point, e.g. a sync sample
+    pub starts_with_sap: bool,
+}
+
+impl RandomAccessPoint {
+    /// Finds the first movie fragment within the byte range
+    pub fn fragment<R: BufRead + Seek>(
+        &self,
+        mp4: &mut Mp4<'_, R>,
+    ) -> Mp4Result<Option<Reference<Moof>>> {
+        let end =
+            self.offset
+                .checked_add(self.len)
+                .ok_or_else(|| Mp4Error::InvalidSampleTable {
+                    offset: self.offset,
+                    path: mp4.atom_path(),
+                    reason: "random access point ends past the largest offset",
+                })?;
+
+        mp4.jump_to(self.offset)?;
+
+        while mp4.position()? < end {
+            let atom = UnparsedAtom::parse(mp4)?;
+
+            if atom.header == MOOF {
+                return Ok(Some(atom.into_ref()));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl Reference<Sidx> {
+    /// Resolves the subsegments described by the segment index, following any
+    /// references to further `sidx` atoms
+    pub fn subsegments<R: BufRead + Seek>(
+        self,
+        mp4: &mut Mp4<'_, R>,
+    ) -> Mp4Result<Vec<RandomAccessPoint>> {
+        let mut points = Vec::new();
+        self.collect_subsegments(mp4, &mut Vec::new(), &mut points)?;
+
+        Ok(points)
+    }
+
+    fn collect_subsegments<R: BufRead + Seek>(
+        self,
+        mp4: &mut Mp4<'_, R>,
+        visited: &mut Vec<u64>,
+        points: &mut Vec<RandomAccessPoint>,
+    ) -> Mp4Result<()> {
+        // a malformed index could otherwise reference itself
+        if visited.contains(&self.offset) {
+            return Ok(());
+        }
+        visited.push(self.offset);
+
+        let sidx = self.parse(mp4)?;
+
+        let out_of_range = |mp4: &Mp4<'_, R>| Mp4Error::InvalidSampleTable {
+            offset: self.offset,
+            path: mp4.atom_path(),
+            reason: "segment index reaches past the largest time or offset",
+        };
+
+        let mut time = sidx.earliest_presentation_time;
+        let mut offset = (self.offset + self.len)
+            .checked_add(sidx.first_offset)
+            .ok_or_else(|| out_of_range(mp4))?;
+
+        for reference in sidx.references {
+            if reference.references_index {
+                mp4.jump_to(offset)?;
+
+                let child = mp4.expect_atom(SIDX)?;
+
+                Reference::<Sidx>::new(offset, child).collect_subsegments(mp4, visited, points)?;
+            } else {
+                points.push(RandomAccessPoint {
+                    track_id: sidx.reference_id,
+                    time,
+                    timescale: Some(sidx.timescale),
+                    offset,
+                    len: reference.referenced_size as u64,
+                    starts_with_sap: reference.starts_with_sap,
+                });
+            }
+
+            time = time
+                .checked_add(reference.subsegment_duration as u64)
+                .ok_or_else(|| out_of_range(mp4))?;
+            offset = offset
+                .checked_add(reference.referenced_size as u64)
+                .ok_or_else(|| out_of_range(mp4))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: BufRead + Seek> Mp4<'_, R> {
+    /// Finds every segment index at the top level of the file, in file order
+    pub fn segment_indexes(&mut self) -> Mp4Result<Vec<Reference<Sidx>>> {
+        Ok(self
+            .top_level_atoms()?
+            .into_iter()
+            .filter(|atom| atom.header == SIDX)
+            .map(UnparsedAtom::into_ref)
+            .collect())
+    }
+
+    /// Finds the movie fragment random access atom using the `mfro` atom at
+    /// the end of the file, without scanning the rest of the file
+    pub fn movie_fragment_random_access(&mut self) -> Mp4Result<Option<Reference<Mfra>>> {
+        let end = self.reader.buffer.stream_len()?;
+
+        let mfro_offset = match end.checked_sub(16) {
+            Some(mfro_offset) => mfro_offset,
+            None => return Ok(None),
+        };
+
+        self.jump_to(mfro_offset)?;
+        if self.peek_header()? != MFRO {
+            return Ok(None);
+        }
+
+        let mfro = Mfro::parse(self)?;
+
+        let mfra_offset = match end.checked_sub(mfro.size as u64) {
+            Some(mfra_offset) => mfra_offset,
+            None => return Ok(None),
+        };
+
+        self.jump_to(mfra_offset)?;
+        if self.peek_header()? != MFRA {
+            return Ok(None);
+        }
+
+        Ok(Some(Reference::new(mfra_offset, mfro.size as u64)))
+    }
+
+    /// Lists the points that playback of a track can begin from, using the
+    /// segment indexes of the file if it has any for the track, and otherwise
+    /// its movie fragment random access atom
+    pub fn random_access_points(&mut self, track_id: u32) -> Mp4Result<Vec<RandomAccessPoint>> {
+        let mut points = Vec::new();
+        let mut visited = Vec::new();
+
+        for sidx in self.segment_indexes()? {
+            if sidx.parse(self)?.reference_id == track_id {
+                sidx.collect_subsegments(self, &mut visited, &mut points)?;
+            }
+        }
+
+        if !points.is_empty() {
+            return Ok(points);
+        }
+
+        let mut mfra = match self.movie_fragment_random_access()? {
+            Some(mfra) => mfra.parse(self)?,
+            None => return Ok(points),
+        };
+
+        for tfra in mfra.tfra(self).clone() {
+            let tfra = tfra.parse(self)?;
+
+            if tfra.track_id != track_id {
+                continue;
+            }
+
+            for entry in tfra.entries {
+                points.push(RandomAccessPoint {
+                    track_id,
+                    time: entry.time,
+                    timescale: None,
+                    offset: entry.moof_offset,
+                    len: self.fragment_len(entry.moof_offset)?,
+                    starts_with_sap: true,
+                });
+            }
+        }
+
+        Ok(points)
+    }
+
+    /// The length of the movie fragment at `offset` together with the atoms
+    /// following it, such as `mdat`, up to the start of the next fragment or
+    /// segment
+    fn fragment_len(&mut self, offset: u64) -> Mp4Result<u64> {
+        const BOUNDARIES: [Header; 4] = [MOOF, STYP, SIDX, MFRA];
+
+        let end = self.reader.buffer.stream_len()?;
+
+        self.jump_to(offset)?;
+        self.expect_atom(MOOF)?;
+        self.jump_to(offset)?;
+        self.skip_chunk()?;
+
+        while self.position()? < end && !BOUNDARIES.contains(&self.peek_header()?) {
+            self.skip_chunk()?;
+        }
+
+        Ok(self.position()?.min(end) - offset)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_util::{atom, full_atom};
+
+    fn point(time: u64, offset: u64, len: u64, starts_with_sap: bool) -> RandomAccessPoint {
+        RandomAccessPoint {
+            track_id: 1,
+            time,
+            timescale: Some(1000),
+            offset,
+            len,
+            starts_with_sap,
+        }
+    }
+
+    #[test]
+    fn resolves_subsegments_of_hierarchical_indexes() {
+        // the first index references the second along with its two
+        // subsegments, then a subsegment of its own
+        let mut file = full_atom(
+            b"sidx",
+            0,
+            &[
+                1,
+                1000,
+                0,
+                0,
+                2,
+                0x8000_0000 | 126,
+                2000,
+                0,
+                50,
+                1000,
+                0x9000_0000,
+            ],
+        );
+        file.extend(full_atom(
+            b"sidx",
+            0,
+            &[1, 1000, 0, 0, 2, 30, 1000, 0x9000_0000, 40, 1000, 0],
+        ));
+
+        let mut mp4 = Mp4::new(Cursor::new(file));
+        let sidx = Reference::<Sidx>::new(56, 56).parse(&mut mp4).unwrap();
+        assert_eq!(sidx.references.len(), 2);
+        assert!(!sidx.references[0].references_index);
+        assert_eq!(sidx.references[0].referenced_size, 30);
+        assert!(sidx.references[0].starts_with_sap);
+        assert_eq!(sidx.references[0].sap_type, 1);
+
+        assert_eq!(
+            Reference::<Sidx>::new(56, 56)
+                .subsegments(&mut mp4)
+                .unwrap(),
+            [point(0, 112, 30, true), point(1000, 142, 40, false)]
+        );
+        assert_eq!(
+            mp4.random_access_points(1).unwrap(),
+            [
+                point(0, 112, 30, true),
+                point(1000, 142, 40, false),
+                point(2000, 182, 50, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_subsegments_past_the_largest_offset() {
+        let sidx = full_atom(
+            b"sidx",
+            0x01000000,
+            &[1, 1000, 0, 0, u32::MAX, u32::MAX, 1, 10, 10, 0],
+        );
+
+        let mut mp4 = Mp4::new(Cursor::new(sidx));
+        assert!(matches!(
+            Reference::<Sidx>::new(0, 52).subsegments(&mut mp4),
+            Err(Mp4Error::InvalidSampleTable { offset: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn reads_entry_numbers_of_each_size() {
+        // one-byte traf numbers, two-byte trun numbers and four-byte sample
+        // numbers
+        let mut body = vec![0; 4];
+        body.extend(1u32.to_be_bytes());
+        body.extend(0b00_01_11u32.to_be_bytes());
+        body.extend(2u32.to_be_bytes());
+        for (time, moof_offset, traf_number, trun_number, sample_number) in [
+            (100u32, 0u32, 1u8, 0x0102u16, 0x0102_0304u32),
+            (200, 50, 2, 3, 4),
+        ] {
+            body.extend(time.to_be_bytes());
+            body.extend(moof_offset.to_be_bytes());
+            body.push(traf_number);
+            body.extend(trun_number.to_be_bytes());
+            body.extend(sample_number.to_be_bytes());
+        }
+
+        let mut mp4 = Mp4::new(Cursor::new(atom(b"tfra", &body)));
+        let tfra = Tfra::parse(&mut mp4).unwrap();
+
+        let entries = tfra
+            .entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.time,
+                    entry.moof_offset,
+                    entry.traf_number,
+                    entry.trun_number,
+                    entry.sample_number,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            [(100, 0, 1, 0x0102, 0x0102_0304), (200, 50, 2, 3, 4)]
+        );
+    }
+}
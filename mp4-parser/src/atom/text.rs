@@ -0,0 +1,790 @@
+use std::{
+    fmt::Write,
+    io::{self, BufRead, Seek},
+};
+
+use atom_macro::mp4_atom;
+
+use crate::{data_structures::CString, write_atom, Mp4, Mp4Result, Parse, Serialize};
+
+use super::{
+    parse_unknown_atom, short_string, BaseSampleDescriptionTable, Header, SampleTable, FTAB, TEXT,
+    VLAB, VTTC_CONFIG,
+};
+
+/// A sample description of a subtitle, caption or text track
+#[derive(Debug, Clone)]
+pub enum TextSampleDescription {
+    Tx3g(Tx3gSampleEntry),
+    Wvtt(WvttSampleEntry),
+    Stpp(StppSampleEntry),
+    QuickTimeText(QuickTimeTextSampleEntry),
+    /// A format whose samples can't be read as cues, such as CEA-608 closed
+    /// captions (`c608`)
+    Other(BaseSampleDescriptionTable),
+}
+
+impl Parse for TextSampleDescription {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(match &mp4.peek_header()?.0 {
+            b"tx3g" => TextSampleDescription::Tx3g(Tx3gSampleEntry::parse(mp4)?),
+            b"wvtt" => TextSampleDescription::Wvtt(WvttSampleEntry::parse(mp4)?),
+            b"stpp" => TextSampleDescription::Stpp(StppSampleEntry::parse(mp4)?),
+            b"text" => TextSampleDescription::QuickTimeText(QuickTimeTextSampleEntry::parse(mp4)?),
+            _ => TextSampleDescription::Other(BaseSampleDescriptionTable::parse(mp4)?),
+        })
+    }
+}
+
+impl Serialize for TextSampleDescription {
+    fn serialize<R: Seek + BufRead, W: io::Write>(
+        &self,
+        mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        match self {
+            TextSampleDescription::Tx3g(tx3g) => tx3g.serialize(mp4, writer),
+            TextSampleDescription::Wvtt(wvtt) => wvtt.serialize(mp4, writer),
+            TextSampleDescription::Stpp(stpp) => stpp.serialize(mp4, writer),
+            TextSampleDescription::QuickTimeText(text) => text.serialize(mp4, writer),
+            TextSampleDescription::Other(other) => other.serialize(mp4, writer),
+        }
+    }
+}
+
+/// 3GPP timed text sample description
+#[derive(Debug, Clone)]
+pub struct Tx3gSampleEntry {
+    pub reserved: [u8; 6],
+    pub data_reference_index: u16,
+
+    pub display_flags: u32,
+    pub horizontal_justification: i8,
+    pub vertical_justification: i8,
+    /// RGBA
+    pub background_color: [u8; 4],
+    pub default_text_box: BoxRecord,
+    pub default_style: StyleRecord,
+    pub font_table: Option<FontTable>,
+    /// Any other child atoms, such as `btrt`, in file order
+    pub other_atoms: Vec<(Header, Vec<u8>)>,
+}
+
+impl Parse for Tx3gSampleEntry {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let (len, header) = mp4.read_atom_header()?;
+
+        mp4.in_atom(offset, len, header, |mp4| {
+            let reserved = mp4.reader.read_bytes_const::<6>()?;
+            let data_reference_index = mp4.reader.read_u16()?;
+            let display_flags = mp4.reader.read_u32()?;
+            let horizontal_justification = mp4.reader.read_u8()? as i8;
+            let vertical_justification = mp4.reader.read_u8()? as i8;
+            let background_color = mp4.reader.read_bytes_const::<4>()?;
+            let default_text_box = BoxRecord::parse(mp4)?;
+            let default_style = StyleRecord::parse(mp4)?;
+
+            let mut font_table = None;
+            let mut other_atoms = Vec::new();
+            while mp4.position()? < offset + len {
+                if mp4.peek_header()? == FTAB {
+                    font_table = Some(FontTable::parse(mp4)?);
+                } else {
+                    other_atoms.push(parse_unknown_atom(mp4)?);
+                }
+            }
+
+            Ok(Self {
+                reserved,
+                data_reference_index,
+                display_flags,
+                horizontal_justification,
+                vertical_justification,
+                background_color,
+                default_text_box,
+                default_style,
+                font_table,
+                other_atoms,
+            })
+        })
+    }
+}
+
+impl Serialize for Tx3gSampleEntry {
+    fn serialize<R: Seek + BufRead, W: io::Write>(
+        &self,
+        mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let mut body = self.reserved.to_vec();
+        body.extend(self.data_reference_index.to_be_bytes());
+        body.extend(self.display_flags.to_be_bytes());
+        body.push(self.horizontal_justification as u8);
+        body.push(self.vertical_justification as u8);
+        body.extend(self.background_color);
+        self.default_text_box.serialize(mp4, &mut body)?;
+        self.default_style.serialize(mp4, &mut body)?;
+
+        if let Some(font_table) = &self.font_table {
+            font_table.serialize(mp4, &mut body)?;
+        }
+        for (header, data) in &self.other_atoms {
+            write_atom(&mut body, *header, data)?;
+        }
+
+        write_atom(writer, Header(*b"tx3g"), &body)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BoxRecord {
+    pub top: i16,
+    pub left: i16,
+    pub bottom: i16,
+    pub right: i16,
+}
+
+impl Parse for BoxRecord {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(Self {
+            top: mp4.reader.read_u16()? as i16,
+            left: mp4.reader.read_u16()? as i16,
+            bottom: mp4.reader.read_u16()? as i16,
+            right: mp4.reader.read_u16()? as i16,
+        })
+    }
+
+    fn peek_len<R: Seek + BufRead>(_mp4: &mut Mp4<'_, R>) -> Mp4Result<u64> {
+        Ok(8)
+    }
+}
+
+impl Serialize for BoxRecord {
+    fn serialize<R: Seek + BufRead, W: io::Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        for field in [self.top, self.left, self.bottom, self.right] {
+            writer.write_all(&field.to_be_bytes())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct StyleRecord {
+    pub start_char: u16,
+    pub end_char: u16,
+    pub font_id: u16,
+    /// Bold (`0x01`), italic (`0x02`) and underline (`0x04`) flags
+    pub face_style_flags: u8,
+    pub font_size: u8,
+    /// RGBA
+    pub text_color: [u8; 4],
+}
+
+impl Parse for StyleRecord {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(Self {
+            start_char: mp4.reader.read_u16()?,
+            end_char: mp4.reader.read_u16()?,
+            font_id: mp4.reader.read_u16()?,
+            face_style_flags: mp4.reader.read_u8()?,
+            font_size: mp4.reader.read_u8()?,
+            text_color: mp4.reader.read_bytes_const::<4>()?,
+        })
+    }
+
+    fn peek_len<R: Seek + BufRead>(_mp4: &mut Mp4<'_, R>) -> Mp4Result<u64> {
+        Ok(12)
+    }
+}
+
+impl Serialize for StyleRecord {
+    fn serialize<R: Seek + BufRead, W: io::Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        writer.write_all(&self.start_char.to_be_bytes())?;
+        writer.write_all(&self.end_char.to_be_bytes())?;
+        writer.write_all(&self.font_id.to_be_bytes())?;
+        writer.write_all(&[self.face_style_flags, self.font_size])?;
+
+        Ok(writer.write_all(&self.text_color)?)
+    }
+}
+
+/// Font table atom of a 3GPP timed text sample description
+#[derive(Debug, Clone)]
+pub struct FontTable {
+    pub entries: Vec<FontRecord>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FontRecord {
+    pub font_id: u16,
+    /// Written cut to 255 bytes
+    pub font_name: String,
+}
+
+impl Parse for FontTable {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(FTAB)?;
+
+        mp4.in_atom(offset, len, FTAB, |mp4| {
+            let entry_count = mp4.reader.read_u16()?;
+
+            let mut entries = Vec::with_capacity(entry_count as usize);
+            for _ in 0..entry_count {
+                let font_id = mp4.reader.read_u16()?;
+                let font_name_length = mp4.reader.read_u8()?;
+                let font_name = mp4.reader.read_bytes_dyn(font_name_length as usize)?;
+
+                entries.push(FontRecord {
+                    font_id,
+                    font_name: String::from_utf8_lossy(&font_name).into_owned(),
+                });
+            }
+
+            Ok(Self { entries })
+        })
+    }
+}
+
+impl Serialize for FontTable {
+    fn serialize<R: Seek + BufRead, W: io::Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let mut body = (self.entries.len() as u16).to_be_bytes().to_vec();
+        for entry in &self.entries {
+            body.extend(entry.font_id.to_be_bytes());
+            let font_name = short_string(&entry.font_name);
+            body.push(font_name.len() as u8);
+            body.extend(font_name.as_bytes());
+        }
+
+        write_atom(writer, FTAB, &body)
+    }
+}
+
+/// WebVTT sample description
+#[derive(Debug, Clone)]
+pub struct WvttSampleEntry {
+    pub reserved: [u8; 6],
+    pub data_reference_index: u16,
+
+    /// The WebVTT file header, up to the first cue, from the `vttC` atom
+    pub config: Option<String>,
+    /// The source label from the `vlab` atom
+    pub label: Option<String>,
+    /// Any other child atoms, such as `btrt`, in file order
+    pub other_atoms: Vec<(Header, Vec<u8>)>,
+}
+
+impl Parse for WvttSampleEntry {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let (len, header) = mp4.read_atom_header()?;
+
+        mp4.in_atom(offset, len, header, |mp4| {
+            let reserved = mp4.reader.read_bytes_const::<6>()?;
+            let data_reference_index = mp4.reader.read_u16()?;
+
+            let mut config = None;
+            let mut label = None;
+            let mut other_atoms = Vec::new();
+
+            while mp4.position()? < offset + len {
+                match mp4.peek_header()? {
+                    VTTC_CONFIG => config = Some(WebVttConfig::parse(mp4)?.config),
+                    VLAB => label = Some(WebVttSourceLabel::parse(mp4)?.source_label),
+                    _ => other_atoms.push(parse_unknown_atom(mp4)?),
+                }
+            }
+
+            Ok(Self {
+                reserved,
+                data_reference_index,
+                config,
+                label,
+                other_atoms,
+            })
+        })
+    }
+}
+
+impl Serialize for WvttSampleEntry {
+    fn serialize<R: Seek + BufRead, W: io::Write>(
+        &self,
+        mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let mut body = self.reserved.to_vec();
+        body.extend(self.data_reference_index.to_be_bytes());
+
+        if let Some(config) = &self.config {
+            WebVttConfig {
+                config: config.clone(),
+            }
+            .serialize(mp4, &mut body)?;
+        }
+        if let Some(source_label) = &self.label {
+            WebVttSourceLabel {
+                source_label: source_label.clone(),
+            }
+            .serialize(mp4, &mut body)?;
+        }
+        for (header, data) in &self.other_atoms {
+            write_atom(&mut body, *header, data)?;
+        }
+
+        write_atom(writer, Header(*b"wvtt"), &body)
+    }
+}
+
+#[mp4_atom]
+pub struct WebVttConfig {
+    pub config: String,
+}
+
+#[mp4_atom]
+pub struct WebVttSourceLabel {
+    pub source_label: String,
+}
+
+/// TTML subtitle sample description. Each sample is a complete TTML document
+#[mp4_atom]
+pub struct StppSampleEntry {
+    pub reserved: [u8; 6],
+    pub data_reference_index: u16,
+
+    pub namespace: CString,
+    pub schema_location: CString,
+    pub auxiliary_mime_types: CString,
+}
+
+/// QuickTime text sample description
+#[derive(Debug, Clone)]
+pub struct QuickTimeTextSampleEntry {
+    pub reserved: [u8; 6],
+    pub data_reference_index: u16,
+
+    pub display_flags: u32,
+    pub text_justification: i32,
+    /// 16-bit RGB
+    pub background_color: [u16; 3],
+    pub default_text_box: BoxRecord,
+    pub font_number: u16,
+    pub font_face: u16,
+    /// 16-bit RGB
+    pub foreground_color: [u16; 3],
+    /// Written cut to 255 bytes
+    pub text_name: String,
+    /// The extension atoms following the text name, e.g. font tables, left
+    /// unparsed
+    pub extensions: Vec<u8>,
+}
+
+impl Parse for QuickTimeTextSampleEntry {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(TEXT)?;
+
+        mp4.in_atom(offset, len, TEXT, |mp4| {
+            let reserved = mp4.reader.read_bytes_const::<6>()?;
+            let data_reference_index = mp4.reader.read_u16()?;
+            let display_flags = mp4.reader.read_u32()?;
+            let text_justification = mp4.reader.read_i32()?;
+            let background_color = [
+                mp4.reader.read_u16()?,
+                mp4.reader.read_u16()?,
+                mp4.reader.read_u16()?,
+            ];
+            let default_text_box = BoxRecord::parse(mp4)?;
+            let _reserved = mp4.reader.read_u64()?;
+            let font_number = mp4.reader.read_u16()?;
+            let font_face = mp4.reader.read_u16()?;
+            let _reserved = mp4.reader.read_u8()?;
+            let _reserved = mp4.reader.read_u16()?;
+            let foreground_color = [
+                mp4.reader.read_u16()?,
+                mp4.reader.read_u16()?,
+                mp4.reader.read_u16()?,
+            ];
+
+            // a pascal string, which some writers leave out entirely
+            let text_name = match mp4.position()? < offset + len {
+                true => {
+                    let text_name_length = mp4.reader.read_u8()?;
+                    let text_name = mp4.reader.read_bytes_dyn(text_name_length as usize)?;
+                    String::from_utf8_lossy(&text_name).into_owned()
+                }
+                false => String::new(),
+            };
+
+            let extensions_len = (offset + len).saturating_sub(mp4.position()?);
+            let extensions = mp4.reader.read_bytes_dyn(extensions_len as usize)?;
+
+            Ok(Self {
+                reserved,
+                data_reference_index,
+                display_flags,
+                text_justification,
+                background_color,
+                default_text_box,
+                font_number,
+                font_face,
+                foreground_color,
+                text_name,
+                extensions,
+            })
+        })
+    }
+}
+
+impl Serialize for QuickTimeTextSampleEntry {
+    fn serialize<R: Seek + BufRead, W: io::Write>(
+        &self,
+        mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let mut body = self.reserved.to_vec();
+        body.extend(self.data_reference_index.to_be_bytes());
+        body.extend(self.display_flags.to_be_bytes());
+        body.extend(self.text_justification.to_be_bytes());
+        body.extend(self.background_color.iter().flat_map(|c| c.to_be_bytes()));
+        self.default_text_box.serialize(mp4, &mut body)?;
+        body.extend([0; 8]);
+        body.extend(self.font_number.to_be_bytes());
+        body.extend(self.font_face.to_be_bytes());
+        body.extend([0; 3]);
+        body.extend(self.foreground_color.iter().flat_map(|c| c.to_be_bytes()));
+        let text_name = short_string(&self.text_name);
+        body.push(text_name.len() as u8);
+        body.extend(text_name.as_bytes());
+        body.extend(&self.extensions);
+
+        write_atom(writer, TEXT, &body)
+    }
+}
+
+/// A single subtitle or caption, with times in the media's time scale
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cue {
+    pub start: i64,
+    pub end: i64,
+    /// The WebVTT cue identifier
+    pub id: Option<String>,
+    /// The WebVTT cue settings, e.g. `line:0 align:start`
+    pub settings: Option<String>,
+    /// The text of the cue. For TTML this is the whole document of the sample
+    pub text: String,
+}
+
+/// Reads the cues of a text track in decoding order, pairing the text of each
+/// sample with its presentation time. Samples holding no text, which mark the
+/// gaps between cues, are skipped
+#[derive(Debug)]
+pub struct Cues<'a, 'b, R: BufRead + Seek> {
+    mp4: &'a mut Mp4<'b, R>,
+    sample_table: &'a SampleTable,
+    description: &'a TextSampleDescription,
+    index: u32,
+    /// Cues of the current sample not yet returned, as a WebVTT sample may
+    /// hold several
+    pending: Vec<Cue>,
+}
+
+impl<'a, 'b, R: BufRead + Seek> Cues<'a, 'b, R> {
+    pub fn new(
+        mp4: &'a mut Mp4<'b, R>,
+        sample_table: &'a SampleTable,
+        description: &'a TextSampleDescription,
+    ) -> Self {
+        Self {
+            mp4,
+            sample_table,
+            description,
+            index: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    fn read_sample(&mut self) -> Mp4Result<Option<Vec<Cue>>> {
+        let sample = match self.sample_table.sample(self.index) {
+            Some(sample) => sample,
+            None => return Ok(None),
+        };
+        self.index += 1;
+
+        self.mp4.jump_to(sample.offset)?;
+        let data = self.mp4.read_slice(sample.size as usize)?;
+
+        let start = sample.presentation_time;
+        let end = start + sample.duration as i64;
+        let cue = |text: String| Cue {
+            start,
+            end,
+            id: None,
+            settings: None,
+            text,
+        };
+
+        Ok(Some(match self.description {
+            TextSampleDescription::Tx3g(..) | TextSampleDescription::QuickTimeText(..) => {
+                decode_text_sample(&data).into_iter().map(cue).collect()
+            }
+            TextSampleDescription::Wvtt(..) => decode_webvtt_sample(&data)
+                .into_iter()
+                .map(|(id, settings, text)| Cue {
+                    id,
+                    settings,
+                    ..cue(text)
+                })
+                .collect(),
+            TextSampleDescription::Stpp(..) => {
+                vec![cue(String::from_utf8_lossy(&data).into_owned())]
+            }
+            TextSampleDescription::Other(..) => Vec::new(),
+        }))
+    }
+}
+
+impl<R: BufRead + Seek> Iterator for Cues<'_, '_, R> {
+    type Item = Mp4Result<Cue>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            match self.read_sample() {
+                Ok(Some(mut cues)) => {
+                    cues.reverse();
+                    self.pending = cues;
+                }
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        self.pending.pop().map(Ok)
+    }
+}
+
+/// Decodes the text of a 3GPP or QuickTime text sample, which is a 16-bit
+/// length followed by UTF-8 or byte order marked UTF-16 text and then any
+/// modifier atoms
+pub(super) fn decode_text_sample(data: &[u8]) -> Option<String> {
+    let len = u16::from_be_bytes([*data.first()?, *data.get(1)?]) as usize;
+    let text = data.get(2..2 + len)?;
+
+    if text.is_empty() {
+        return None;
+    }
+
+    Some(match text {
+        [0xfe, 0xff, utf16 @ ..] => String::from_utf16_lossy(
+            &utf16
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect::<Vec<_>>(),
+        ),
+        [0xff, 0xfe, utf16 @ ..] => String::from_utf16_lossy(
+            &utf16
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .collect::<Vec<_>>(),
+        ),
+        // QuickTime text uses carriage returns for line breaks
+        _ => String::from_utf8_lossy(text).replace('\r', "\n"),
+    })
+}
+
+/// Decodes the `vttc` cue atoms of a WebVTT sample into their identifier,
+/// settings and payload. A sample of only a `vtte` atom has no cues
+fn decode_webvtt_sample(data: &[u8]) -> Vec<(Option<String>, Option<String>, String)> {
+    let text = |data: &[u8]| String::from_utf8_lossy(data).into_owned();
+
+    atoms(data)
+        .filter(|(header, _)| header.0 == *b"vttc")
+        .map(|(_, cue)| {
+            let mut id = None;
+            let mut settings = None;
+            let mut payload = String::new();
+
+            for (header, data) in atoms(cue) {
+                match &header.0 {
+                    b"iden" => id = Some(text(data)),
+                    b"sttg" => settings = Some(text(data)),
+                    b"payl" => payload = text(data),
+                    _ => {}
+                }
+            }
+
+            (id, settings, payload)
+        })
+        .collect()
+}
+
+/// Iterates over the atoms packed in a sample's data, stopping at the first
+/// that is malformed
+fn atoms(mut data: &[u8]) -> impl Iterator<Item = (Header, &[u8])> {
+    std::iter::from_fn(move || {
+        let len = u32::from_be_bytes(data.get(0..4)?.try_into().unwrap()) as usize;
+        let header = Header(data.get(4..8)?.try_into().unwrap());
+        let body = data.get(8..len.max(8))?;
+
+        data = &data[len.max(8)..];
+
+        Some((header, body))
+    })
+}
+
+/// Formats cues as a SubRip (SRT) file. `timescale` is that of the track's
+/// media
+pub fn to_srt(cues: &[Cue], timescale: u32) -> String {
+    let mut srt = String::new();
+
+    for (idx, cue) in cues.iter().enumerate() {
+        let _ = write!(
+            srt,
+            "{}\n{} --> {}\n{}\n\n",
+            idx + 1,
+            format_timestamp(cue.start, timescale, ','),
+            format_timestamp(cue.end, timescale, ','),
+            cue.text.trim_end()
+        );
+    }
+
+    srt
+}
+
+/// Formats cues as a WebVTT file. `timescale` is that of the track's media
+pub fn to_webvtt(cues: &[Cue], timescale: u32) -> String {
+    let mut vtt = String::from("WEBVTT\n\n");
+
+    for cue in cues {
+        if let Some(id) = &cue.id {
+            let _ = writeln!(vtt, "{}", id);
+        }
+
+        let _ = write!(
+            vtt,
+            "{} --> {}",
+            format_timestamp(cue.start, timescale, '.'),
+            format_timestamp(cue.end, timescale, '.')
+        );
+
+        if let Some(settings) = cue.settings.as_deref().filter(|s| !s.is_empty()) {
+            let _ = write!(vtt, " {}", settings);
+        }
+
+        let _ = write!(vtt, "\n{}\n\n", cue.text.trim_end());
+    }
+
+    vtt
+}
+
+/// Formats a time as `hh:mm:ss` followed by milliseconds, clamping negative
+/// times to zero
+fn format_timestamp(time: i64, timescale: u32, separator: char) -> String {
+    let millis = (time.max(0) as u128 * 1000 / timescale.max(1) as u128) as u64;
+
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        separator,
+        millis % 1000
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::atom;
+
+    #[test]
+    fn decodes_webvtt_cues() {
+        let cue = [
+            atom(b"iden", b"intro"),
+            atom(b"sttg", b"align:start"),
+            atom(b"payl", b"Hello"),
+        ]
+        .concat();
+        let sample = [atom(b"vttc", &cue), atom(b"vttc", &atom(b"payl", b"World"))].concat();
+
+        assert_eq!(
+            decode_webvtt_sample(&sample),
+            vec![
+                (
+                    Some("intro".to_string()),
+                    Some("align:start".to_string()),
+                    "Hello".to_string()
+                ),
+                (None, None, "World".to_string()),
+            ]
+        );
+        assert!(decode_webvtt_sample(&atom(b"vtte", &[])).is_empty());
+    }
+
+    #[test]
+    fn decodes_text_samples() {
+        assert_eq!(decode_text_sample(&[0, 0]), None);
+        assert_eq!(
+            decode_text_sample(&[0, 3, b'a', b'\r', b'b', 0, 0, 0, 8]),
+            Some("a\nb".to_string())
+        );
+        assert_eq!(
+            decode_text_sample(&[0, 4, 0xfe, 0xff, 0, b'c']),
+            Some("c".to_string())
+        );
+    }
+
+    #[test]
+    fn exports_srt_and_webvtt() {
+        let cues = [Cue {
+            start: 90_500,
+            end: 3_723_250,
+            id: Some("1".to_string()),
+            settings: Some("line:0".to_string()),
+            text: "Hi\n".to_string(),
+        }];
+
+        assert_eq!(
+            to_srt(&cues, 1000),
+            "1\n00:01:30,500 --> 01:02:03,250\nHi\n\n"
+        );
+        assert_eq!(
+            to_webvtt(&cues, 1000),
+            "WEBVTT\n\n1\n00:01:30.500 --> 01:02:03.250 line:0\nHi\n\n"
+        );
+    }
+}
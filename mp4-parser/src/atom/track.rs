@@ -1,10 +1,19 @@
-use std::io::{self, BufRead, Seek};
+use std::{
+    io::{BufRead, Seek, Write},
+    time::{Duration, SystemTime},
+};
 
 use atom_macro::{mp4_atom, mp4_container_atom};
 
-use crate::{data_structures::Matrix, Fixed16, Fixed32, Header, Mp4, Parse, Reference};
+use crate::{
+    data_structures::Matrix, write_atom, Fixed16, Fixed32, Mp4, Mp4Result, Parse, Reference,
+    Serialize,
+};
 
-use super::{InternalElement, Mdia, UnparsedAtom};
+use super::{
+    date_from_1904, from_timescale, read_time, time_version, write_time, Elst, Header,
+    InternalElement, Mdia, Meta, Udta, UnparsedAtom, TKHD, TREF,
+};
 
 #[mp4_container_atom]
 pub struct Trak {
@@ -17,11 +26,15 @@ pub struct Trak {
     pub load: Option<Reference<Load>>,
     pub imap: Option<Reference<Imap>>,
     pub mdia: Reference<Mdia>,
+    pub udta: Option<Reference<Udta>>,
+    pub meta: Option<Reference<Meta>>,
 }
 
-#[mp4_atom]
+#[derive(Debug, Clone)]
 pub struct Tkhd {
-    /// A 1-byte specification of the version of this track header.
+    /// A 1-byte specification of the version of this track header. Version 1
+    /// headers have 64-bit creation and modification times and duration. A
+    /// version 0 header is written as version 1 if they don't fit in 32 bits.
     pub version: u8,
 
     /// Three bytes that are reserved for the track header flags. These flags
@@ -43,17 +56,17 @@ pub struct Tkhd {
     ///    0x0008.
     pub flags: [u8; 3],
 
-    /// A 32-bit integer that indicates the calendar date and time (expressed in
+    /// An integer that indicates the calendar date and time (expressed in
     /// seconds since midnight, January 1, 1904) when the track header was created.
     /// It is strongly recommended that this value should be specified using
     /// coordinated universal time (UTC).
-    pub creation_time: u32,
+    pub creation_time: u64,
 
-    /// A 32-bit integer that indicates the calendar date and time (expressed
+    /// An integer that indicates the calendar date and time (expressed
     /// in seconds since midnight, January 1, 1904) when the track header was
     /// changed. It is strongly recommended that this value should be specified
     /// using coordinated universal time (UTC).
-    pub modification_time: u32,
+    pub modification_time: u64,
 
     /// A 32-bit integer that uniquely identifies the track. The value 0 cannot
     /// be used.
@@ -67,7 +80,7 @@ pub struct Tkhd {
     /// track’s edits. The value of this field is equal to the sum of the durations
     /// of all of the track’s edits. If there is no edit list, then the duration
     /// is the sum of the sample durations, converted into the movie timescale.
-    pub duration: u32,
+    pub duration: u64,
 
     /// An 8-byte value that is reserved for use by Apple. Set this field to 0.
     pub reserved_2: u64,
@@ -117,6 +130,94 @@ pub struct Tkhd {
     pub track_height: Fixed32,
 }
 
+impl Parse for Tkhd {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
+    where
+        Self: Sized,
+    {
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(TKHD)?;
+
+        mp4.in_atom(offset, len, TKHD, |mp4| {
+            let version = mp4.reader.read_u8()?;
+            let flags = mp4.reader.read_bytes_const::<3>()?;
+            let creation_time = read_time(mp4, version)?;
+            let modification_time = read_time(mp4, version)?;
+            let track_id = mp4.reader.read_u32()?;
+            let reserved = mp4.reader.read_u32()?;
+            let duration = read_time(mp4, version)?;
+
+            Ok(Self {
+                version,
+                flags,
+                creation_time,
+                modification_time,
+                track_id,
+                reserved,
+                duration,
+                reserved_2: mp4.reader.read_u64()?,
+                layer: mp4.reader.read_u16()?,
+                alternate_group: mp4.reader.read_u16()?,
+                volume: mp4.reader.read_u16()?,
+                reserved_3: mp4.reader.read_u16()?,
+                matrix: mp4.read_matrix()?,
+                track_width: mp4.reader.read_u32()?,
+                track_height: mp4.reader.read_u32()?,
+            })
+        })
+    }
+}
+
+impl Serialize for Tkhd {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let version = time_version(
+            self.version,
+            [self.creation_time, self.modification_time, self.duration],
+        );
+
+        let mut body = vec![version];
+        body.extend(self.flags);
+        write_time(&mut body, version, self.creation_time);
+        write_time(&mut body, version, self.modification_time);
+        body.extend(self.track_id.to_be_bytes());
+        body.extend(self.reserved.to_be_bytes());
+        write_time(&mut body, version, self.duration);
+        body.extend(self.reserved_2.to_be_bytes());
+        body.extend(self.layer.to_be_bytes());
+        body.extend(self.alternate_group.to_be_bytes());
+        body.extend(self.volume.to_be_bytes());
+        body.extend(self.reserved_3.to_be_bytes());
+        self.matrix.serialize(mp4, &mut body)?;
+        body.extend(self.track_width.to_be_bytes());
+        body.extend(self.track_height.to_be_bytes());
+
+        write_atom(writer, TKHD, &body)
+    }
+}
+
+impl Tkhd {
+    /// The creation time, or `None` if it is out of the range of
+    /// [`SystemTime`]
+    pub fn created(&self) -> Option<SystemTime> {
+        date_from_1904(self.creation_time)
+    }
+
+    /// The modification time, or `None` if it is out of the range of
+    /// [`SystemTime`]
+    pub fn modified(&self) -> Option<SystemTime> {
+        date_from_1904(self.modification_time)
+    }
+
+    /// The duration of the track, given the time scale of the movie
+    pub fn track_duration(&self, movie_timescale: u32) -> Duration {
+        from_timescale(self.duration, movie_timescale)
+    }
+}
+
 #[mp4_atom]
 pub struct Tapt {}
 #[mp4_atom]
@@ -125,42 +226,169 @@ pub struct Matt {}
 pub struct Edts {
     pub edit_list: Vec<Elst>,
 }
-#[mp4_atom]
-pub struct Tref {}
-#[mp4_atom]
-pub struct Txas {}
-#[mp4_atom]
-pub struct Load {}
-#[mp4_atom]
-pub struct Imap {}
-#[mp4_atom]
-pub struct Elst {
-    pub version: u8,
-    pub flags: [u8; 3],
-    pub number_of_entries: u32,
-    pub edit_list_table: Vec<EditListEntry>,
+
+/// Track reference atom, relating the track to other tracks of the movie
+#[derive(Debug, Clone)]
+pub struct Tref {
+    pub references: Vec<TrackReference>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TrackReference {
+    /// How the tracks are related, e.g. `chap` for a chapter track or `hint`
+    /// for the track a hint track describes
+    pub reference_type: [u8; 4],
+    pub track_ids: Vec<u32>,
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct EditListEntry {
-    pub track_duration: u32,
-    pub media_time: u32,
-    pub media_rate: Fixed32,
+impl Tref {
+    /// The ids of every track referenced with the given type
+    pub fn track_ids(&self, reference_type: [u8; 4]) -> Vec<u32> {
+        self.references
+            .iter()
+            .filter(|reference| reference.reference_type == reference_type)
+            .flat_map(|reference| reference.track_ids.iter().copied())
+            .collect()
+    }
 }
 
-impl Parse for EditListEntry {
-    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> io::Result<Self>
+impl Parse for Tref {
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
     where
         Self: Sized,
     {
-        let track_duration = mp4.reader.read_u32()?;
-        let media_time = mp4.reader.read_u32()?;
-        let media_rate = mp4.reader.read_u32()?;
-
-        Ok(Self {
-            track_duration,
-            media_time,
-            media_rate,
+        let offset = mp4.position()?;
+        let len = mp4.expect_atom(TREF)?;
+
+        mp4.in_atom(offset, len, TREF, |mp4| {
+            let mut references = Vec::new();
+
+            while mp4.position()? < offset + len {
+                let reference_offset = mp4.position()?;
+                let (reference_len, header) = mp4.read_atom_header()?;
+
+                let track_ids = mp4.in_atom(reference_offset, reference_len, header, |mp4| {
+                    let mut track_ids = Vec::new();
+                    while mp4.position()? + 4 <= reference_offset + reference_len {
+                        track_ids.push(mp4.reader.read_u32()?);
+                    }
+
+                    Ok(track_ids)
+                })?;
+
+                references.push(TrackReference {
+                    reference_type: header.0,
+                    track_ids,
+                });
+            }
+
+            Ok(Self { references })
         })
     }
 }
+
+impl Serialize for Tref {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let mut body = Vec::new();
+        for reference in &self.references {
+            let track_ids: Vec<u8> = reference
+                .track_ids
+                .iter()
+                .flat_map(|track_id| track_id.to_be_bytes())
+                .collect();
+
+            write_atom(&mut body, Header(reference.reference_type), &track_ids)?;
+        }
+
+        write_atom(writer, TREF, &body)
+    }
+}
+
+#[mp4_atom]
+pub struct Txas {}
+#[mp4_atom]
+pub struct Load {}
+#[mp4_atom]
+pub struct Imap {}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_util::atom;
+
+    fn tkhd(version: u8, time: &[u8], duration: &[u8]) -> Vec<u8> {
+        let mut body = vec![version, 0, 0, 3];
+        body.extend(time);
+        body.extend(time);
+        body.extend(1u32.to_be_bytes());
+        body.extend([0; 4]);
+        body.extend(duration);
+        body.extend([0; 16]);
+        body.extend([0; 36]);
+        body.extend([0; 8]);
+
+        atom(b"tkhd", &body)
+    }
+
+    #[test]
+    fn reads_both_header_versions() {
+        // 2001-09-09 01:46:40 UTC, a billion seconds after the Unix epoch
+        let time = 1_000_000_000 + 2_082_844_800u64;
+        let duration = 6_000_000_000u64;
+
+        for bytes in [
+            tkhd(0, &(time as u32).to_be_bytes(), &600u32.to_be_bytes()),
+            tkhd(1, &time.to_be_bytes(), &duration.to_be_bytes()),
+        ] {
+            let mut mp4 = Mp4::new(Cursor::new(bytes.clone()));
+            let tkhd = Tkhd::parse(&mut mp4).unwrap();
+
+            assert_eq!(
+                tkhd.created(),
+                Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000))
+            );
+            assert_eq!(tkhd.track_id, 1);
+
+            let mut written = Vec::new();
+            tkhd.serialize(&mut mp4, &mut written).unwrap();
+            assert_eq!(written, bytes);
+        }
+
+        let mut mp4 = Mp4::new(Cursor::new(tkhd(1, &[0; 8], &duration.to_be_bytes())));
+        let tkhd = Tkhd::parse(&mut mp4).unwrap();
+        assert_eq!(tkhd.track_duration(600), Duration::from_secs(10_000_000));
+        assert_eq!(
+            tkhd.track_duration(7),
+            Duration::new(857_142_857, 142_857_142)
+        );
+        assert!(tkhd.modified().unwrap() < SystemTime::UNIX_EPOCH);
+
+        // a duration too long for a version 0 header promotes it to version 1
+        let mut written = Vec::new();
+        Tkhd {
+            version: 0,
+            ..tkhd.clone()
+        }
+        .serialize(&mut mp4, &mut written)
+        .unwrap();
+        let mut mp4 = Mp4::new(Cursor::new(written));
+        let reparsed = Tkhd::parse(&mut mp4).unwrap();
+        assert_eq!((reparsed.version, reparsed.duration), (1, duration));
+
+        // far past anything a system time can hold
+        assert_eq!(
+            Tkhd {
+                creation_time: u64::MAX,
+                ..tkhd
+            }
+            .created(),
+            None
+        );
+    }
+}
@@ -1,17 +1,41 @@
-use std::io::{self, BufRead, Seek};
+use std::io::{BufRead, Seek, Write};
 
-use crate::{Mp4, Parse};
+use crate::{Mp4, Mp4Error, Mp4Result, Parse, Serialize};
 
 #[derive(Debug, Clone)]
 pub struct CString(String);
 
+impl CString {
+    /// The string without its null terminator
+    pub fn as_str(&self) -> &str {
+        self.0.trim_end_matches('\0')
+    }
+}
+
 impl Parse for CString {
-    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> io::Result<Self>
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
     where
         Self: Sized,
     {
+        let offset = mp4.position()?;
+
         Ok(CString(
-            String::from_utf8(mp4.reader.read_until(b'\0')?).unwrap(),
+            String::from_utf8(mp4.reader.read_until(b'\0')?).map_err(|_| {
+                Mp4Error::InvalidUtf8 {
+                    offset,
+                    path: mp4.atom_path(),
+                }
+            })?,
         ))
     }
 }
+
+impl Serialize for CString {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        Ok(writer.write_all(self.0.as_bytes())?)
+    }
+}
@@ -1,6 +1,6 @@
-use std::io::{self, BufRead, Seek};
+use std::io::{BufRead, Seek, Write};
 
-use crate::{Fixed32, Parse};
+use crate::{Fixed32, Mp4, Mp4Result, Parse, Serialize};
 
 #[derive(Debug, Clone)]
 pub struct Matrix {
@@ -16,14 +16,30 @@ pub struct Matrix {
 }
 
 impl Parse for Matrix {
-    fn parse<R: Seek + BufRead>(mp4: &mut crate::Mp4<'_, R>) -> io::Result<Self>
+    fn parse<R: Seek + BufRead>(mp4: &mut crate::Mp4<'_, R>) -> Mp4Result<Self>
     where
         Self: Sized,
     {
         mp4.read_matrix()
     }
 
-    fn peek_len<R: Seek + BufRead>(_mp4: &mut crate::Mp4<'_, R>) -> io::Result<u64> {
+    fn peek_len<R: Seek + BufRead>(_mp4: &mut crate::Mp4<'_, R>) -> Mp4Result<u64> {
         Ok(36)
     }
 }
+
+impl Serialize for Matrix {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        for value in [
+            self.a, self.b, self.u, self.c, self.d, self.v, self.x, self.y, self.w,
+        ] {
+            writer.write_all(&value.to_be_bytes())?;
+        }
+
+        Ok(())
+    }
+}
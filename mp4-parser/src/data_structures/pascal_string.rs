@@ -1,18 +1,36 @@
-use std::io::{self, BufRead, Seek};
+use std::io::{BufRead, Seek, Write};
 
-use crate::{Mp4, Parse};
+use crate::{Mp4, Mp4Error, Mp4Result, Parse, Serialize};
 
 #[derive(Debug, Clone)]
 pub struct PascalString(String);
 
 impl Parse for PascalString {
-    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> io::Result<Self>
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
     where
         Self: Sized,
     {
+        let offset = mp4.position()?;
         let len = mp4.reader.read_u32()?;
         Ok(PascalString(
-            String::from_utf8(mp4.reader.read_bytes_dyn(len as usize)?).unwrap(),
+            String::from_utf8(mp4.reader.read_bytes_dyn(len as usize)?).map_err(|_| {
+                Mp4Error::InvalidUtf8 {
+                    offset,
+                    path: mp4.atom_path(),
+                }
+            })?,
         ))
     }
 }
+
+impl Serialize for PascalString {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        writer.write_all(&(self.0.len() as u32).to_be_bytes())?;
+
+        Ok(writer.write_all(self.0.as_bytes())?)
+    }
+}
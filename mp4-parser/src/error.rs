@@ -0,0 +1,284 @@
+use std::{error::Error, fmt, io};
+
+use crate::Header;
+
+pub type Mp4Result<T> = Result<T, Mp4Error>;
+
+/// The chain of atoms being parsed when an error occurred, outermost first
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AtomPath(pub Vec<Header>);
+
+impl fmt::Display for AtomPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "<root>");
+        }
+
+        for (idx, header) in self.0.iter().enumerate() {
+            if idx != 0 {
+                write!(f, "/")?;
+            }
+
+            write!(f, "{}", header)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Mp4Error {
+    /// The underlying reader failed for a reason unrelated to the structure of
+    /// the file
+    Io(io::Error),
+
+    /// An atom of a specific type was expected, but the header read from the
+    /// file did not match
+    UnexpectedHeader {
+        offset: u64,
+        path: AtomPath,
+        expected: Header,
+        found: Header,
+    },
+
+    /// The number of bytes consumed while parsing an atom did not match the
+    /// size declared in its header
+    LengthMismatch {
+        offset: u64,
+        path: AtomPath,
+        header: Header,
+        expected: u64,
+        actual: u64,
+    },
+
+    /// An atom declared a size larger than the data available to it, either
+    /// because the file ends early or because it overruns its parent
+    TruncatedAtom {
+        offset: u64,
+        path: AtomPath,
+        header: Header,
+    },
+
+    /// The size field of an atom holds a value that cannot describe an atom,
+    /// e.g. one smaller than the atom header itself or one reaching past the
+    /// end of its parent or of the file
+    InvalidAtomLength {
+        offset: u64,
+        path: AtomPath,
+        len: u64,
+    },
+
+    /// A string field did not contain valid UTF-8
+    InvalidUtf8 { offset: u64, path: AtomPath },
+
+    /// A container is missing a child atom that it is required to have
+    MissingAtom {
+        offset: u64,
+        path: AtomPath,
+        header: Header,
+    },
+
+    /// An atom was found in a position where only a fixed set of atom types
+    /// is understood
+    UnsupportedAtom {
+        offset: u64,
+        path: AtomPath,
+        header: Header,
+    },
+
+    /// A sample description belongs to a media handler type that cannot be
+    /// parsed yet
+    UnsupportedSampleEntry {
+        offset: u64,
+        path: AtomPath,
+        handler: [u8; 4],
+    },
+
+    /// The tables of a sample table atom are inconsistent with one another,
+    /// e.g. the chunks do not hold as many samples as there are sample sizes,
+    /// or the times and offsets of movie fragments or segment indexes are out
+    /// of range
+    InvalidSampleTable {
+        offset: u64,
+        path: AtomPath,
+        reason: &'static str,
+    },
+
+    /// An MPEG-4 descriptor, such as those of an `esds` atom, is malformed
+    InvalidDescriptor {
+        offset: u64,
+        path: AtomPath,
+        reason: &'static str,
+    },
+
+    /// The movie has no track with the requested ID
+    MissingTrack { track_id: u32 },
+
+    /// The fragmented track has no media segment with the requested index
+    MissingSegment { index: usize },
+}
+
+impl Mp4Error {
+    /// The absolute byte offset in the file at which the error occurred
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            Mp4Error::Io(..) | Mp4Error::MissingTrack { .. } | Mp4Error::MissingSegment { .. } => {
+                None
+            }
+            Mp4Error::UnexpectedHeader { offset, .. }
+            | Mp4Error::LengthMismatch { offset, .. }
+            | Mp4Error::TruncatedAtom { offset, .. }
+            | Mp4Error::InvalidAtomLength { offset, .. }
+            | Mp4Error::InvalidUtf8 { offset, .. }
+            | Mp4Error::MissingAtom { offset, .. }
+            | Mp4Error::UnsupportedAtom { offset, .. }
+            | Mp4Error::UnsupportedSampleEntry { offset, .. }
+            | Mp4Error::InvalidSampleTable { offset, .. }
+            | Mp4Error::InvalidDescriptor { offset, .. } => Some(*offset),
+        }
+    }
+
+    pub fn path(&self) -> Option<&AtomPath> {
+        match self {
+            Mp4Error::Io(..) | Mp4Error::MissingTrack { .. } | Mp4Error::MissingSegment { .. } => {
+                None
+            }
+            Mp4Error::UnexpectedHeader { path, .. }
+            | Mp4Error::LengthMismatch { path, .. }
+            | Mp4Error::TruncatedAtom { path, .. }
+            | Mp4Error::InvalidAtomLength { path, .. }
+            | Mp4Error::InvalidUtf8 { path, .. }
+            | Mp4Error::MissingAtom { path, .. }
+            | Mp4Error::UnsupportedAtom { path, .. }
+            | Mp4Error::UnsupportedSampleEntry { path, .. }
+            | Mp4Error::InvalidSampleTable { path, .. }
+            | Mp4Error::InvalidDescriptor { path, .. } => Some(path),
+        }
+    }
+}
+
+impl fmt::Display for Mp4Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mp4Error::Io(e) => write!(f, "{}", e),
+            Mp4Error::UnexpectedHeader {
+                offset,
+                path,
+                expected,
+                found,
+            } => write!(
+                f,
+                "expected atom {} but found {} at offset {} ({})",
+                expected, found, offset, path
+            ),
+            Mp4Error::LengthMismatch {
+                offset,
+                path,
+                header,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "atom {} at offset {} declares {} bytes but {} were parsed ({})",
+                header, offset, expected, actual, path
+            ),
+            Mp4Error::TruncatedAtom {
+                offset,
+                path,
+                header,
+            } => write!(
+                f,
+                "atom {} at offset {} is truncated ({})",
+                header, offset, path
+            ),
+            Mp4Error::InvalidAtomLength { offset, path, len } => {
+                write!(
+                    f,
+                    "invalid atom size {} at offset {} ({})",
+                    len, offset, path
+                )
+            }
+            Mp4Error::InvalidUtf8 { offset, path } => {
+                write!(f, "invalid UTF-8 string at offset {} ({})", offset, path)
+            }
+            Mp4Error::MissingAtom {
+                offset,
+                path,
+                header,
+            } => write!(
+                f,
+                "required atom {} is missing from container at offset {} ({})",
+                header, offset, path
+            ),
+            Mp4Error::UnsupportedAtom {
+                offset,
+                path,
+                header,
+            } => write!(
+                f,
+                "unsupported atom {} at offset {} ({})",
+                header, offset, path
+            ),
+            Mp4Error::UnsupportedSampleEntry {
+                offset,
+                path,
+                handler,
+            } => write!(
+                f,
+                "unsupported sample description for handler {} at offset {} ({})",
+                Header(*handler),
+                offset,
+                path
+            ),
+            Mp4Error::InvalidSampleTable {
+                offset,
+                path,
+                reason,
+            } => write!(
+                f,
+                "invalid sample table at offset {}: {} ({})",
+                offset, reason, path
+            ),
+            Mp4Error::InvalidDescriptor {
+                offset,
+                path,
+                reason,
+            } => write!(
+                f,
+                "invalid descriptor at offset {}: {} ({})",
+                offset, reason, path
+            ),
+            Mp4Error::MissingTrack { track_id } => {
+                write!(f, "the movie has no track with ID {}", track_id)
+            }
+            Mp4Error::MissingSegment { index } => {
+                write!(f, "the track has no media segment with index {}", index)
+            }
+        }
+    }
+}
+
+impl Error for Mp4Error {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Mp4Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Mp4Error {
+    fn from(e: io::Error) -> Self {
+        Mp4Error::Io(e)
+    }
+}
+
+impl From<Mp4Error> for io::Error {
+    fn from(e: Mp4Error) -> Self {
+        match e {
+            Mp4Error::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use std::io::{BufRead, Seek, Write};
+
+use crate::{
+    atom::UnparsedAtom, Co64, Moov, Mp4, Mp4Error, Mp4Result, Reference, Serialize, Stco, FREE,
+    MDAT, MOOV, SKIP, UDTA, WIDE,
+};
+
+/// What a faststart does with the top-level `free`, `skip` and `wide` atoms
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FreeSpace {
+    /// Leave them where they are
+    #[default]
+    Keep,
+    /// Move them ahead of the media data along with `moov`, leaving room for
+    /// `moov` to grow in place
+    Move,
+    /// Leave them out of the output
+    Drop,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FaststartOptions {
+    pub free_space: FreeSpace,
+    /// Whether top-level `udta` atoms are moved ahead of the media data along
+    /// with `moov`
+    pub move_udta: bool,
+}
+
+/// A top-level atom of the output and its offset there
+struct Placement {
+    atom: UnparsedAtom,
+    offset: u64,
+}
+
+impl<R: BufRead + Seek> Mp4<'_, R> {
+    /// Writes the file to `writer` with `moov` ahead of the media data, so that
+    /// playback can begin before the whole file has been downloaded. The chunk
+    /// offsets of every track are rewritten for the new layout, and the media
+    /// data is copied without being loaded into memory
+    pub fn faststart<W: Write>(
+        &mut self,
+        writer: &mut W,
+        options: FaststartOptions,
+    ) -> Mp4Result<()> {
+        let atoms = self.top_level_atoms()?;
+
+        let moov = match atoms.iter().find(|atom| atom.header == MOOV) {
+            Some(moov) => moov.clone().into_ref::<Moov>(),
+            None => {
+                return Err(Mp4Error::MissingAtom {
+                    offset: 0,
+                    path: self.atom_path(),
+                    header: MOOV,
+                })
+            }
+        };
+
+        let is_free = |atom: &UnparsedAtom| [FREE, SKIP, WIDE].contains(&atom.header);
+        let is_moved = |atom: &UnparsedAtom| {
+            atom.header == MOOV
+                || (atom.header == UDTA && options.move_udta)
+                || (is_free(atom) && options.free_space == FreeSpace::Move)
+        };
+        let is_kept = |atom: &UnparsedAtom| !is_free(atom) || options.free_space != FreeSpace::Drop;
+
+        // whatever comes before the media data stays there, followed by the
+        // moved atoms and then the rest of the file
+        let split = atoms
+            .iter()
+            .position(|atom| atom.header == MDAT)
+            .unwrap_or(atoms.len());
+
+        let layout = atoms[..split]
+            .iter()
+            .filter(|atom| !is_moved(atom) && is_kept(atom))
+            .chain(atoms.iter().filter(|atom| is_moved(atom)))
+            .chain(
+                atoms[split..]
+                    .iter()
+                    .filter(|atom| !is_moved(atom) && is_kept(atom)),
+            )
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // promoting a chunk offset atom to 64 bits grows `moov`, which moves
+        // the media data further and may in turn overflow other chunk offsets,
+        // so this repeats until the size of `moov` settles
+        let mut moov_len = moov.len;
+        let (placements, moov_bytes) = loop {
+            let placements = place(&layout, moov_len);
+            let moov_bytes = self.relocate_chunk_offsets(moov, |offset| {
+                placements
+                    .iter()
+                    .find(|placement| {
+                        placement.atom.header != MOOV
+                            && (placement.atom.offset..placement.atom.offset + placement.atom.len)
+                                .contains(&offset)
+                    })
+                    .map(|placement| offset - placement.atom.offset + placement.offset)
+            })?;
+
+            if moov_bytes.len() as u64 == moov_len {
+                break (placements, moov_bytes);
+            }
+            moov_len = moov_bytes.len() as u64;
+        };
+
+        for placement in placements {
+            match placement.atom.header {
+                MOOV => writer.write_all(&moov_bytes)?,
+                _ => self.copy_range(placement.atom.offset, placement.atom.len, writer)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Serializes `moov` with every chunk offset mapped by `relocate`, using a
+    /// `co64` atom in place of any `stco` atom whose offsets no longer fit in
+    /// 32 bits
+    fn relocate_chunk_offsets(
+        &mut self,
+        moov: Reference<Moov>,
+        relocate: impl Fn(u64) -> Option<u64>,
+    ) -> Mp4Result<Vec<u8>> {
+        let mut moov = moov.parse(self)?;
+
+        for trak_ref in moov.trak(self).clone() {
+            let mut trak = trak_ref.parse(self)?;
+            let mdia_ref = *trak.mdia(self)?;
+            let mut mdia = mdia_ref.parse(self)?;
+            let minf_ref = match *mdia.minf(self) {
+                Some(minf) => minf,
+                None => continue,
+            };
+            let mut minf = minf_ref.parse(self)?;
+            let stbl_ref = match *minf.stbl(self) {
+                Some(stbl) => stbl,
+                None => continue,
+            };
+            let mut stbl = stbl_ref.parse(self)?;
+
+            let path = stbl.path.clone();
+            let invalid = |offset| Mp4Error::InvalidSampleTable {
+                offset,
+                path: path.clone(),
+                reason: "chunk offset is outside of the file's media data",
+            };
+
+            if let Some(stco_ref) = *stbl.chunk_offset(self) {
+                let stco = stco_ref.parse(self)?;
+                let offsets = stco
+                    .chunk_offset_table
+                    .iter()
+                    .map(|&offset| relocate(offset as u64).ok_or_else(|| invalid(stco_ref.offset)))
+                    .collect::<Mp4Result<Vec<_>>>()?;
+
+                match offsets.iter().all(|&offset| offset <= u32::MAX as u64) {
+                    true => stbl.replace_child(
+                        stco_ref,
+                        &Stco {
+                            chunk_offset_table: offsets
+                                .iter()
+                                .map(|&offset| offset as u32)
+                                .collect(),
+                            ..stco
+                        },
+                        self,
+                    )?,
+                    false => stbl.replace_child(
+                        stco_ref,
+                        &Co64 {
+                            version: stco.version,
+                            flags: stco.flags,
+                            number_of_entries: stco.number_of_entries,
+                            chunk_offset_table: offsets,
+                        },
+                        self,
+                    )?,
+                };
+            }
+
+            if let Some(co64_ref) = *stbl.chunk_offset_64(self) {
+                let co64 = co64_ref.parse(self)?;
+                let offsets = co64
+                    .chunk_offset_table
+                    .iter()
+                    .map(|&offset| relocate(offset).ok_or_else(|| invalid(co64_ref.offset)))
+                    .collect::<Mp4Result<Vec<_>>>()?;
+
+                stbl.replace_child(
+                    co64_ref,
+                    &Co64 {
+                        chunk_offset_table: offsets,
+                        ..co64
+                    },
+                    self,
+                )?;
+            }
+
+            minf.replace_child(stbl_ref, &stbl, self)?;
+            mdia.replace_child(minf_ref, &minf, self)?;
+            trak.replace_child(mdia_ref, &mdia, self)?;
+            moov.replace_child(trak_ref, &trak, self)?;
+        }
+
+        let mut bytes = Vec::new();
+        moov.serialize(self, &mut bytes)?;
+
+        Ok(bytes)
+    }
+}
+
+/// Lays out the atoms one after another, with `moov` taking `moov_len` bytes
+fn place(layout: &[UnparsedAtom], moov_len: u64) -> Vec<Placement> {
+    let mut offset = 0;
+
+    layout
+        .iter()
+        .map(|atom| {
+            let placement = Placement {
+                atom: atom.clone(),
+                offset,
+            };
+            offset += match atom.header {
+                MOOV => moov_len,
+                _ => atom.len,
+            };
+
+            placement
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_util::atom;
+
+    fn moov(chunk_offset: u32) -> Vec<u8> {
+        let mut stco = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stco.extend(chunk_offset.to_be_bytes());
+
+        let stbl = atom(b"stbl", &atom(b"stco", &stco));
+        let mdia = atom(b"mdia", &atom(b"minf", &stbl));
+        atom(b"moov", &atom(b"trak", &mdia))
+    }
+
+    #[test]
+    fn moves_moov_ahead_of_media_data() {
+        let ftyp = atom(b"ftyp", b"isom\0\0\0\0");
+        let mdat = atom(b"mdat", b"sample");
+        let free = atom(b"free", &[0; 4]);
+
+        let mut file = ftyp.clone();
+        file.extend(&mdat);
+        file.extend(&free);
+        file.extend(moov(ftyp.len() as u32 + 8));
+
+        let mut written = Vec::new();
+        let options = FaststartOptions {
+            free_space: FreeSpace::Drop,
+            move_udta: false,
+        };
+        Mp4::new(Cursor::new(file))
+            .faststart(&mut written, options)
+            .unwrap();
+
+        let moov = moov((ftyp.len() + moov(0).len()) as u32 + 8);
+        let mut expected = ftyp;
+        expected.extend(moov);
+        expected.extend(mdat);
+        assert_eq!(written, expected);
+    }
+}
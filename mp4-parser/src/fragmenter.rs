@@ -0,0 +1,646 @@
+use std::{
+    io::{BufRead, Seek, Write},
+    ops::Range,
+    time::Duration,
+};
+
+use crate::{
+    write_atom, write_atom_header, Ftyp, Mehd, Mfhd, Moov, Mp4, Mp4Error, Mp4Result, Reference,
+    SampleFlags, SampleInfo, SampleTable, Serialize, Sidx, SidxReference, Stbl, Stco, Stsc, Stsz,
+    Stts, Styp, Tfdt, Tfhd, TrackRunSample, Trak, Trex, Trun, MDAT, MINF, MOOF, MOOV, MVEX, STBL,
+    TRAF,
+};
+
+/// Sample flags of a sync sample that depends on no other sample
+const SYNC_SAMPLE_FLAGS: SampleFlags = SampleFlags(0x0200_0000);
+/// Sample flags of a non-sync sample that depends on other samples
+const NON_SYNC_SAMPLE_FLAGS: SampleFlags = SampleFlags(0x0101_0000);
+
+#[derive(Debug, Clone, Copy)]
+pub struct FragmentOptions {
+    /// The duration each media segment should have at least. Segments start
+    /// at the first sync sample after it has elapsed, so they are as long as
+    /// the track's sync samples allow
+    pub segment_duration: Duration,
+    /// Whether each media segment starts with a `sidx` atom indexing it
+    pub segment_index: bool,
+}
+
+impl Default for FragmentOptions {
+    fn default() -> Self {
+        Self {
+            segment_duration: Duration::from_secs(6),
+            segment_index: false,
+        }
+    }
+}
+
+/// A media segment of a fragmented track, holding one movie fragment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaSegment {
+    /// Sequence number of the segment's movie fragment, starting at 1
+    pub sequence_number: u32,
+    /// Zero-based indices of the samples in the segment, in decode order
+    pub samples: Range<u32>,
+    /// Decoding time of the first sample, in the media's time scale
+    pub decode_time: u64,
+    /// In the media's time scale
+    pub duration: u64,
+    /// Size in bytes of the segment's sample data
+    pub data_size: u64,
+}
+
+/// Packages a track of a progressive file as a CMAF track: an init segment
+/// (`ftyp` and a `moov` with `mvex`) and media segments (`styp`, `moof` and
+/// `mdat`) that begin with sync samples. The sample descriptions are copied
+/// from the source file as is, decoder configuration included
+#[derive(Debug, Clone)]
+pub struct Fragmenter {
+    moov: Reference<Moov>,
+    trak: Reference<Trak>,
+    track_id: u32,
+    timescale: u32,
+    table: SampleTable,
+    segments: Vec<MediaSegment>,
+    options: FragmentOptions,
+}
+
+impl Fragmenter {
+    /// Plans the media segments of the track with the given ID. Only the
+    /// samples described by `moov` are considered
+    pub fn new<R: BufRead + Seek>(
+        mp4: &mut Mp4<'_, R>,
+        track_id: u32,
+        options: FragmentOptions,
+    ) -> Mp4Result<Self> {
+        let moov = match mp4
+            .top_level_atoms()?
+            .into_iter()
+            .find(|atom| atom.header == MOOV)
+        {
+            Some(moov) => moov.into_ref::<Moov>(),
+            None => {
+                return Err(Mp4Error::MissingAtom {
+                    offset: 0,
+                    path: mp4.atom_path(),
+                    header: MOOV,
+                })
+            }
+        };
+
+        let mut trak = None;
+        for candidate in moov.parse(mp4)?.trak(mp4).clone() {
+            if candidate
+                .parse(mp4)?
+                .track_header(mp4)?
+                .parse(mp4)?
+                .track_id
+                == track_id
+            {
+                trak = Some(candidate);
+                break;
+            }
+        }
+        let trak = match trak {
+            Some(trak) => trak,
+            None => return Err(Mp4Error::MissingTrack { track_id }),
+        };
+
+        let mut trak_atom = trak.parse(mp4)?;
+        let timescale = trak_atom
+            .mdia(mp4)?
+            .parse(mp4)?
+            .mdhd(mp4)?
+            .parse(mp4)?
+            .time_scale;
+        let mut stbl = sample_table_atom(&mut trak_atom, mp4)?;
+        let table = SampleTable::new(&mut stbl, mp4)?;
+
+        let segment_duration =
+            (options.segment_duration.as_nanos() * timescale as u128 / 1_000_000_000) as u64;
+        let segments = plan_segments(&table, segment_duration);
+
+        Ok(Self {
+            moov,
+            trak,
+            track_id,
+            timescale,
+            table,
+            segments,
+            options,
+        })
+    }
+
+    pub fn track_id(&self) -> u32 {
+        self.track_id
+    }
+
+    /// The time scale of the track's media, which the timing of the segments is
+    /// given in
+    pub fn timescale(&self) -> u32 {
+        self.timescale
+    }
+
+    pub fn segments(&self) -> &[MediaSegment] {
+        &self.segments
+    }
+
+    /// Writes the init segment: an `ftyp` atom and a `moov` atom holding only
+    /// this track, with empty sample tables and an `mvex` atom
+    pub fn write_init_segment<R: BufRead + Seek, W: Write>(
+        &self,
+        mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let ftyp = Ftyp {
+            major_brand: *b"iso6",
+            major_brand_version: [0; 4],
+            compatible_brands: vec![*b"iso6", *b"cmfc"],
+        };
+        ftyp.serialize(mp4, writer)?;
+
+        let mut moov = self.moov.parse(mp4)?;
+        let movie_timescale = moov.movie_header(mp4)?.parse(mp4)?.time_scale;
+
+        for trak_ref in moov.trak(mp4).clone() {
+            if trak_ref.offset != self.trak.offset {
+                moov.remove_child(trak_ref);
+                continue;
+            }
+
+            let mut trak = trak_ref.parse(mp4)?;
+            let tkhd_ref = *trak.track_header(mp4)?;
+            let mut tkhd = tkhd_ref.parse(mp4)?;
+            tkhd.duration = 0;
+            trak.replace_child(tkhd_ref, &tkhd, mp4)?;
+
+            let mdia_ref = *trak.mdia(mp4)?;
+            let mut mdia = mdia_ref.parse(mp4)?;
+            let mdhd_ref = *mdia.mdhd(mp4)?;
+            let mut mdhd = mdhd_ref.parse(mp4)?;
+            mdhd.duration = 0;
+            mdia.replace_child(mdhd_ref, &mdhd, mp4)?;
+
+            // the track has a sample table, or there would be nothing to
+            // fragment
+            let minf_ref = match *mdia.minf(mp4) {
+                Some(minf) => minf,
+                None => continue,
+            };
+            let mut minf = minf_ref.parse(mp4)?;
+            let stbl_ref = match *minf.stbl(mp4) {
+                Some(stbl) => stbl,
+                None => continue,
+            };
+            let mut stbl = stbl_ref.parse(mp4)?;
+            empty_sample_table(&mut stbl, mp4)?;
+
+            minf.replace_child(stbl_ref, &stbl, mp4)?;
+            mdia.replace_child(minf_ref, &minf, mp4)?;
+            trak.replace_child(mdia_ref, &mdia, mp4)?;
+            moov.replace_child(trak_ref, &trak, mp4)?;
+        }
+
+        let mvhd_ref = *moov.movie_header(mp4)?;
+        let mut mvhd = mvhd_ref.parse(mp4)?;
+        mvhd.duration = 0;
+        moov.replace_child(mvhd_ref, &mvhd, mp4)?;
+
+        if let Some(mvex) = *moov.mvex(mp4) {
+            moov.remove_child(mvex);
+        }
+
+        let media_duration = self
+            .segments
+            .iter()
+            .map(|segment| segment.duration)
+            .sum::<u64>();
+        let mehd = Mehd {
+            version: 1,
+            flags: [0; 3],
+            fragment_duration: (media_duration as u128 * movie_timescale as u128
+                / self.timescale.max(1) as u128) as u64,
+        };
+        let trex = Trex {
+            version: 0,
+            flags: [0; 3],
+            track_id: self.track_id,
+            default_sample_description_index: 1,
+            default_sample_duration: 0,
+            default_sample_size: 0,
+            default_sample_flags: SampleFlags(0),
+        };
+
+        let mut mvex_body = Vec::new();
+        mehd.serialize(mp4, &mut mvex_body)?;
+        trex.serialize(mp4, &mut mvex_body)?;
+        let mut mvex = Vec::new();
+        write_atom(&mut mvex, MVEX, &mvex_body)?;
+        moov.push_child(&mvex, mp4)?;
+
+        moov.serialize(mp4, writer)
+    }
+
+    /// Writes the media segment with the given zero-based index: an `styp`
+    /// atom, a `sidx` atom if enabled, and a movie fragment with its media data
+    pub fn write_segment<R: BufRead + Seek, W: Write>(
+        &self,
+        mp4: &mut Mp4<'_, R>,
+        index: usize,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        let segment = match self.segments.get(index) {
+            Some(segment) => segment,
+            None => return Err(Mp4Error::MissingSegment { index }),
+        };
+        let samples = segment
+            .samples
+            .clone()
+            .filter_map(|index| self.table.sample(index))
+            .collect::<Vec<_>>();
+
+        let mut compatible_brands = vec![*b"msdh", *b"cmfs"];
+        if self.options.segment_index {
+            compatible_brands.push(*b"msix");
+        }
+        let styp = Styp {
+            major_brand: *b"msdh",
+            major_brand_version: [0; 4],
+            compatible_brands,
+        };
+        styp.serialize(mp4, writer)?;
+
+        let mdat_header_len = match segment.data_size + 8 > u32::MAX as u64 {
+            true => 16,
+            false => 8,
+        };
+
+        // the data offset doesn't change the size of the movie fragment, so it
+        // is found by writing the fragment once without it
+        let moof_len = self.movie_fragment(segment, &samples, 0, mp4)?.len() as i32;
+        let moof = self.movie_fragment(segment, &samples, moof_len + mdat_header_len, mp4)?;
+
+        if self.options.segment_index {
+            // the size field of a reference has 31 bits
+            let too_large = |reason| Mp4Error::InvalidSampleTable {
+                offset: self.trak.offset,
+                path: mp4.atom_path(),
+                reason,
+            };
+            let referenced_size =
+                u32::try_from(moof.len() as u64 + mdat_header_len as u64 + segment.data_size)
+                    .ok()
+                    .filter(|&size| size <= 0x7fff_ffff)
+                    .ok_or_else(|| too_large("segment is too large for its segment index"))?;
+            let subsegment_duration = u32::try_from(segment.duration)
+                .map_err(|_| too_large("segment is too long for its segment index"))?;
+
+            let earliest_presentation_time = samples
+                .iter()
+                .map(|sample| sample.presentation_time)
+                .min()
+                .unwrap_or(0);
+
+            let sidx = Sidx {
+                version: 1,
+                flags: [0; 3],
+                reference_id: self.track_id,
+                timescale: self.timescale,
+                earliest_presentation_time: earliest_presentation_time.max(0) as u64,
+                first_offset: 0,
+                references: vec![SidxReference {
+                    references_index: false,
+                    referenced_size,
+                    subsegment_duration,
+                    starts_with_sap: true,
+                    sap_type: 1,
+                    sap_delta_time: 0,
+                }],
+            };
+            sidx.serialize(mp4, writer)?;
+        }
+
+        writer.write_all(&moof)?;
+        write_atom_header(writer, MDAT, segment.data_size, false)?;
+
+        // samples that follow one another in the source file are copied in
+        // one go
+        let mut run = None::<(u64, u64)>;
+        for sample in &samples {
+            run = match run {
+                Some((offset, len)) if offset + len == sample.offset => {
+                    Some((offset, len + sample.size as u64))
+                }
+                Some((offset, len)) => {
+                    mp4.copy_range(offset, len, writer)?;
+                    Some((sample.offset, sample.size as u64))
+                }
+                None => Some((sample.offset, sample.size as u64)),
+            };
+        }
+        if let Some((offset, len)) = run {
+            mp4.copy_range(offset, len, writer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Serializes the `moof` atom of a segment, with its sample data starting
+    /// `data_offset` bytes from the start of the `moof` atom
+    fn movie_fragment<R: BufRead + Seek>(
+        &self,
+        segment: &MediaSegment,
+        samples: &[SampleInfo],
+        data_offset: i32,
+        mp4: &mut Mp4<'_, R>,
+    ) -> Mp4Result<Vec<u8>> {
+        let composition_offsets = samples
+            .iter()
+            .map(|sample| sample.presentation_time - sample.decode_time as i64)
+            .collect::<Vec<_>>();
+        let has_composition_offsets = composition_offsets.iter().any(|&offset| offset != 0);
+        let negative = composition_offsets.iter().any(|&offset| offset < 0);
+
+        let mfhd = Mfhd {
+            version: 0,
+            flags: [0; 3],
+            sequence_number: segment.sequence_number,
+        };
+        let tfhd = Tfhd {
+            version: 0,
+            // default-base-is-moof
+            flags: [0x02, 0, 0],
+            track_id: self.track_id,
+            base_data_offset: None,
+            sample_description_index: samples
+                .first()
+                .map(|sample| sample.sample_description_index),
+            default_sample_duration: None,
+            default_sample_size: None,
+            default_sample_flags: None,
+        };
+        let tfdt = Tfdt {
+            version: 1,
+            flags: [0; 3],
+            base_media_decode_time: segment.decode_time,
+        };
+        let trun = Trun {
+            version: negative as u8,
+            flags: [0; 3],
+            sample_count: samples.len() as u32,
+            data_offset: Some(data_offset),
+            first_sample_flags: None,
+            samples: samples
+                .iter()
+                .zip(composition_offsets)
+                .map(|(sample, composition_offset)| TrackRunSample {
+                    duration: Some(sample.duration),
+                    size: Some(sample.size),
+                    flags: Some(match sample.is_sync {
+                        true => SYNC_SAMPLE_FLAGS,
+                        false => NON_SYNC_SAMPLE_FLAGS,
+                    }),
+                    composition_offset: has_composition_offsets.then_some(composition_offset),
+                })
+                .collect(),
+        };
+
+        let mut traf = Vec::new();
+        tfhd.serialize(mp4, &mut traf)?;
+        tfdt.serialize(mp4, &mut traf)?;
+        trun.serialize(mp4, &mut traf)?;
+
+        let mut moof = Vec::new();
+        mfhd.serialize(mp4, &mut moof)?;
+        write_atom(&mut moof, TRAF, &traf)?;
+
+        let mut bytes = Vec::new();
+        write_atom(&mut bytes, MOOF, &moof)?;
+
+        Ok(bytes)
+    }
+}
+
+fn sample_table_atom<R: BufRead + Seek>(trak: &mut Trak, mp4: &mut Mp4<'_, R>) -> Mp4Result<Stbl> {
+    let mut mdia = trak.mdia(mp4)?.parse(mp4)?;
+    let mut minf = match *mdia.minf(mp4) {
+        Some(minf) => minf.parse(mp4)?,
+        None => {
+            return Err(Mp4Error::MissingAtom {
+                offset: mdia.offset,
+                path: mdia.path.clone(),
+                header: MINF,
+            })
+        }
+    };
+
+    match *minf.stbl(mp4) {
+        Some(stbl) => stbl.parse(mp4),
+        None => Err(Mp4Error::MissingAtom {
+            offset: minf.offset,
+            path: minf.path.clone(),
+            header: STBL,
+        }),
+    }
+}
+
+/// Splits the samples of a track into segments of at least `segment_duration`,
+/// each starting with a sync sample. A segment is also started whenever the
+/// sample description changes, as a track fragment has only one
+fn plan_segments(table: &SampleTable, segment_duration: u64) -> Vec<MediaSegment> {
+    let mut segments = Vec::<MediaSegment>::new();
+    let mut description = None;
+
+    for sample in table.iter() {
+        if let Some(segment) = segments.last_mut() {
+            let is_full = segment.duration >= segment_duration && sample.is_sync;
+
+            if !is_full && description == Some(sample.sample_description_index) {
+                segment.samples.end += 1;
+                segment.duration += sample.duration as u64;
+                segment.data_size += sample.size as u64;
+                continue;
+            }
+        }
+
+        description = Some(sample.sample_description_index);
+        segments.push(MediaSegment {
+            sequence_number: segments.len() as u32 + 1,
+            samples: sample.index..sample.index + 1,
+            decode_time: sample.decode_time,
+            duration: sample.duration as u64,
+            data_size: sample.size as u64,
+        });
+    }
+
+    segments
+}
+
+/// Empties the tables of `stbl` but for the sample descriptions, as the
+/// samples of a fragmented track are all described by its movie fragments
+fn empty_sample_table<R: BufRead + Seek>(stbl: &mut Stbl, mp4: &mut Mp4<'_, R>) -> Mp4Result<()> {
+    if let Some(stts) = *stbl.time_to_sample(mp4) {
+        let empty = Stts {
+            version: 0,
+            flags: [0; 3],
+            number_of_entries: 0,
+            time_to_sample_table: Vec::new(),
+        };
+        stbl.replace_child(stts, &empty, mp4)?;
+    }
+    if let Some(stsc) = *stbl.sample_to_chunk(mp4) {
+        let empty = Stsc {
+            version: 0,
+            flags: [0; 3],
+            number_of_entries: 0,
+            sample_to_chunk_table: Vec::new(),
+        };
+        stbl.replace_child(stsc, &empty, mp4)?;
+    }
+    if let Some(stsz) = *stbl.sample_size(mp4) {
+        let empty = Stsz {
+            version: 0,
+            flags: [0; 3],
+            sample_size: 0,
+            number_of_entries: 0,
+            sample_size_table: Vec::new(),
+        };
+        stbl.replace_child(stsz, &empty, mp4)?;
+    }
+
+    let empty_stco = Stco {
+        version: 0,
+        flags: [0; 3],
+        number_of_entries: 0,
+        chunk_offset_table: Vec::new(),
+    };
+    match (*stbl.chunk_offset(mp4), *stbl.chunk_offset_64(mp4)) {
+        (Some(stco), co64) => {
+            stbl.replace_child(stco, &empty_stco, mp4)?;
+            if let Some(co64) = co64 {
+                stbl.remove_child(co64);
+            }
+        }
+        (None, Some(co64)) => {
+            stbl.replace_child(co64, &empty_stco, mp4)?;
+        }
+        (None, None) => {}
+    }
+
+    if let Some(ctts) = *stbl.composition_offset(mp4) {
+        stbl.remove_child(ctts);
+    }
+    if let Some(cslg) = *stbl.cslg(mp4) {
+        stbl.remove_child(cslg);
+    }
+    if let Some(stss) = *stbl.sync_sample(mp4) {
+        stbl.remove_child(stss);
+    }
+    if let Some(stps) = *stbl.stps(mp4) {
+        stbl.remove_child(stps);
+    }
+    if let Some(stsh) = *stbl.shadow_sync(mp4) {
+        stbl.remove_child(stsh);
+    }
+    if let Some(sdtp) = *stbl.sdtp(mp4) {
+        stbl.remove_child(sdtp);
+    }
+    for sgpd in stbl.sgpd(mp4).clone() {
+        stbl.remove_child(sgpd);
+    }
+    for sbgp in stbl.sbgp(mp4).clone() {
+        stbl.remove_child(sbgp);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_util::{media_data, movie};
+
+    #[test]
+    fn fragments_at_sync_samples() {
+        // the movie in a single chunk, followed by its media data
+        let mut file = movie(movie(0, 10).len() as u32 + 8, 10);
+        file.extend(media_data());
+
+        let mut source = Mp4::new(Cursor::new(file));
+        let options = FragmentOptions {
+            segment_duration: Duration::from_millis(300),
+            segment_index: true,
+        };
+        let fragmenter = Fragmenter::new(&mut source, 1, options).unwrap();
+
+        let segments = fragmenter.segments();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[1].samples, 5..10);
+        assert_eq!(segments[1].decode_time, 500);
+
+        let mut written = Vec::new();
+        fragmenter
+            .write_init_segment(&mut source, &mut written)
+            .unwrap();
+        let init_len = written.len() as u64;
+        for index in 0..segments.len() {
+            fragmenter
+                .write_segment(&mut source, index, &mut written)
+                .unwrap();
+        }
+
+        // the segments read back as a fragmented movie
+        let mut mp4 = Mp4::new(Cursor::new(written.clone()));
+        let moov_len = init_len - 24;
+        let mut moov = Reference::<Moov>::new(24, moov_len)
+            .parse(&mut mp4)
+            .unwrap();
+        let mut trak = moov.trak(&mut mp4)[0].parse(&mut mp4).unwrap();
+        let table = SampleTable::for_track(&mut moov, &mut trak, &mut mp4).unwrap();
+
+        let samples = table
+            .iter()
+            .map(|sample| (written[sample.offset as usize], sample.is_sync))
+            .collect::<Vec<_>>();
+        assert_eq!(samples.len(), 10);
+        assert!(samples
+            .iter()
+            .enumerate()
+            .all(|(index, &(data, is_sync))| data == index as u8 && is_sync == (index % 5 == 0)));
+
+        assert_eq!(mp4.segment_indexes().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn rejects_segments_it_cannot_write() {
+        // samples lasting as long as a 32-bit duration allows
+        let mut file = movie(movie(0, 10).len() as u32 + 8, 10);
+        let stts = file
+            .windows(4)
+            .position(|header| header == b"stts")
+            .unwrap();
+        file[stts + 16..stts + 20].copy_from_slice(&u32::MAX.to_be_bytes());
+        file.extend(media_data());
+
+        let mut source = Mp4::new(Cursor::new(file));
+        let options = FragmentOptions {
+            segment_duration: Duration::from_millis(300),
+            segment_index: true,
+        };
+        let fragmenter = Fragmenter::new(&mut source, 1, options).unwrap();
+
+        assert!(matches!(
+            fragmenter.write_segment(&mut source, 2, &mut Vec::new()),
+            Err(Mp4Error::MissingSegment { index: 2 })
+        ));
+        assert!(matches!(
+            fragmenter.write_segment(&mut source, 0, &mut Vec::new()),
+            Err(Mp4Error::InvalidSampleTable {
+                reason: "segment is too long for its segment index",
+                ..
+            })
+        ));
+    }
+}
@@ -2,93 +2,278 @@
 #![feature(drain_filter)]
 #![deny(missing_debug_implementations)]
 
-// todo: fn for getting all unknown chunks, method for getting atom by header
-
 extern crate atom_macro;
 
 use std::{
-    io::{self, BufRead, Seek, SeekFrom},
-    marker::PhantomData,
+    collections::HashMap,
+    io::{self, BufRead, Seek, SeekFrom, Write},
+    mem,
 };
 
 pub use atom::*;
 use data_structures::Matrix;
+pub use error::*;
+pub use faststart::*;
+pub use fragmenter::*;
+pub use package::*;
 pub use reference::*;
+pub use root::*;
+pub use stream::*;
+pub(crate) use writer::*;
 
 mod atom;
 pub mod data_structures;
+mod error;
+mod faststart;
+mod fragmenter;
+mod package;
 mod reference;
+mod root;
+mod slice;
+mod stream;
+#[cfg(test)]
+mod test_util;
+mod trim;
+mod writer;
 
 pub type Fixed16 = u16;
 pub type Fixed32 = u32;
 
 pub trait Parse {
-    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> io::Result<Self>
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
     where
         Self: Sized;
 
-    fn peek_len<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> io::Result<u64> {
-        let len = mp4.read_atom_len()?;
+    fn peek_len<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<u64> {
+        let offset = mp4.position()?;
+        let (len, _) = mp4.read_atom_header()?;
 
-        mp4.reader.go_backwards(4)?;
+        mp4.jump_to(offset)?;
 
         Ok(len)
     }
 }
 
+/// The counterpart of [`Parse`], writing an atom or a structure within an atom
+/// back to bytes
+pub trait Serialize {
+    /// Writes `self` to `writer`. Atoms are written whole, header included,
+    /// while anything only referenced from `self`, such as the children of a
+    /// container atom, is copied from `mp4`
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()>;
+}
+
 impl<const N: usize> Parse for [u8; N] {
-    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> io::Result<Self>
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
     where
         Self: Sized,
     {
-        mp4.reader.read_bytes_const::<N>()
+        Ok(mp4.reader.read_bytes_const::<N>()?)
     }
 
-    fn peek_len<R: Seek + BufRead>(_mp4: &mut Mp4<'_, R>) -> io::Result<u64> {
+    fn peek_len<R: Seek + BufRead>(_mp4: &mut Mp4<'_, R>) -> Mp4Result<u64> {
         Ok(N as u64)
     }
 }
 
+impl<const N: usize> Serialize for [u8; N] {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        Ok(writer.write_all(self)?)
+    }
+}
+
 #[derive(Debug)]
 pub struct Mp4<'a, R: BufRead + Seek> {
-    _a: PhantomData<&'a ()>,
+    /// The whole input, when parsing from bytes held in memory
+    input: Option<&'a [u8]>,
     pub reader: Reader<R>,
+    /// Headers of the atoms currently being parsed, outermost first
+    path: Vec<Header>,
+    /// Where each of the atoms in `path` ends
+    ends: Vec<u64>,
+    /// The `path` and `ends` of the atoms enclosing each atom that was found
+    /// inside a parent, by offset, so that references parsed after the parent
+    /// has been left report where they are in the file
+    parents: HashMap<u64, (Vec<Header>, Vec<u64>)>,
 }
 
 impl<'a, R: BufRead + Seek> Mp4<'a, R> {
     pub fn new(buffer: R) -> Self {
         Self {
             reader: Reader::new(buffer),
-            _a: PhantomData,
+            path: Vec::new(),
+            ends: Vec::new(),
+            parents: HashMap::new(),
+            input: None,
         }
     }
 
-    #[track_caller]
-    fn expect_header(&mut self, header: Header) -> io::Result<()> {
-        assert_eq!(Header(self.reader.read_bytes_const::<4>()?), header);
+    pub(crate) fn position(&mut self) -> Mp4Result<u64> {
+        Ok(self.reader.buffer.stream_position()?)
+    }
 
-        Ok(())
+    pub(crate) fn atom_path(&self) -> AtomPath {
+        AtomPath(self.path.clone())
     }
 
-    #[track_caller]
-    fn read_atom_len(&mut self) -> io::Result<u64> {
+    /// Reads the size and type of the atom at the current position, leaving
+    /// the reader at the start of the atom's body
+    ///
+    /// The returned size is always the total size of the atom in bytes,
+    /// including its header, regardless of how it was encoded. An atom may not
+    /// extend past the end of its parent, or of the file at the top level
+    fn read_atom_header(&mut self) -> Mp4Result<(u64, Header)> {
+        let offset = self.position()?;
         let len = self.reader.read_u32()?;
+        let header = Header(self.reader.read_bytes_const::<4>()?);
+        let end = match self.ends.last() {
+            Some(&end) => end,
+            None => self.reader.buffer.stream_len()?,
+        };
+
+        let len = match len {
+            // allowed only for a top-level atom, designates the last atom in the
+            // file and indicates that the atom extends to the end of the file
+            0 if self.path.is_empty() => end.saturating_sub(offset),
+            // the actual size is given in the extended size field, an optional
+            // 64-bit field that follows the type field
+            1 => self.reader.read_u64()?,
+            len => len as u64,
+        };
+
+        if len < self.position()? - offset || offset.checked_add(len).is_none_or(|e| e > end) {
+            return Err(Mp4Error::InvalidAtomLength {
+                offset,
+                path: self.atom_path(),
+                len,
+            });
+        }
+
+        Ok((len, header))
+    }
+
+    /// Reads the header of an atom that must be of type `header`, returning
+    /// its total size
+    fn expect_atom(&mut self, header: Header) -> Mp4Result<u64> {
+        let offset = self.position()?;
+        let (len, found) = self.read_atom_header()?;
+
+        if found != header {
+            return Err(Mp4Error::UnexpectedHeader {
+                offset,
+                path: self.atom_path(),
+                expected: header,
+                found,
+            });
+        }
 
-        // allowed only for a top-level atom, designates the last atom in the
-        // file and indicates that the atom extends to the end of the file
-        assert_ne!(len, 0);
-        // the actual size is given in the extended size field, an optional 64-bit
-        // field that follows the type field
-        assert_ne!(len, 1);
+        Ok(len)
+    }
 
-        Ok(len as u64)
+    /// Runs `f` to parse the body of the atom starting at `offset`, recording
+    /// the atom in the current path and verifying that exactly `len` bytes
+    /// were consumed
+    pub(crate) fn in_atom<T>(
+        &mut self,
+        offset: u64,
+        len: u64,
+        header: Header,
+        f: impl FnOnce(&mut Self) -> Mp4Result<T>,
+    ) -> Mp4Result<T> {
+        self.path.push(header);
+        self.ends.push(offset.saturating_add(len));
+
+        let result = f(self)
+            .and_then(|value| {
+                let actual = self.position()?.saturating_sub(offset);
+
+                if actual != len {
+                    return Err(Mp4Error::LengthMismatch {
+                        offset,
+                        path: self.atom_path(),
+                        header,
+                        expected: len,
+                        actual,
+                    });
+                }
+
+                Ok(value)
+            })
+            .map_err(|e| match e {
+                Mp4Error::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    Mp4Error::TruncatedAtom {
+                        offset,
+                        path: self.atom_path(),
+                        header,
+                    }
+                }
+                e => e,
+            });
+
+        self.path.pop();
+        self.ends.pop();
+
+        result
     }
 
-    pub fn jump_to(&mut self, offset: u64) -> io::Result<u64> {
-        self.reader.buffer.seek(SeekFrom::Start(offset))
+    /// Remembers the atoms enclosing the atom at `offset`, which is a child of
+    /// the atom being parsed
+    pub(crate) fn remember_parents(&mut self, offset: u64) {
+        if !self.path.is_empty() {
+            self.parents
+                .insert(offset, (self.path.clone(), self.ends.clone()));
+        }
+    }
+
+    /// Runs `f` to parse the atom at `offset` inside the atoms that enclosed it
+    /// when it was found, as a reference to it may be parsed from anywhere
+    pub(crate) fn in_parents_of<T>(
+        &mut self,
+        offset: u64,
+        f: impl FnOnce(&mut Self) -> Mp4Result<T>,
+    ) -> Mp4Result<T> {
+        let (path, ends) = match self.parents.get(&offset) {
+            Some(parents) => parents.clone(),
+            None => return f(self),
+        };
+
+        let path = mem::replace(&mut self.path, path);
+        let ends = mem::replace(&mut self.ends, ends);
+        let result = f(self);
+        self.path = path;
+        self.ends = ends;
+
+        result
     }
 
-    pub fn read_matrix(&mut self) -> io::Result<Matrix> {
+    /// Scans every atom at the top level of the file, without parsing them
+    pub(crate) fn top_level_atoms(&mut self) -> Mp4Result<Vec<UnparsedAtom>> {
+        let end = self.reader.buffer.stream_len()?;
+
+        self.jump_to(0)?;
+
+        let mut atoms = Vec::new();
+
+        while self.position()? < end {
+            atoms.push(UnparsedAtom::parse(self)?);
+        }
+
+        Ok(atoms)
+    }
+
+    pub fn jump_to(&mut self, offset: u64) -> Mp4Result<u64> {
+        Ok(self.reader.buffer.seek(SeekFrom::Start(offset))?)
+    }
+
+    pub fn read_matrix(&mut self) -> Mp4Result<Matrix> {
         let a = self.reader.read_u32()?;
         let b = self.reader.read_u32()?;
         let u = self.reader.read_u32()?;
@@ -112,7 +297,7 @@ impl<'a, R: BufRead + Seek> Mp4<'a, R> {
         })
     }
 
-    pub fn read_parseable<P: Parse>(&mut self, offset: usize) -> io::Result<P> {
+    pub fn read_parseable<P: Parse>(&mut self, offset: usize) -> Mp4Result<P> {
         self.reader.buffer.seek(SeekFrom::Start(offset as u64))?;
 
         P::parse(self)
@@ -122,37 +307,43 @@ impl<'a, R: BufRead + Seek> Mp4<'a, R> {
         &mut self,
         base: Reference<BaseSampleDescriptionTable>,
         subtype: [u8; 4],
-    ) -> io::Result<SampleDescriptionTable> {
+    ) -> Mp4Result<SampleDescriptionTable> {
         self.jump_to(base.offset)?;
+        self.in_parents_of(base.offset, |mp4| mp4.parse_sample_entry(base, subtype))
+    }
+
+    fn parse_sample_entry(
+        &mut self,
+        base: Reference<BaseSampleDescriptionTable>,
+        subtype: [u8; 4],
+    ) -> Mp4Result<SampleDescriptionTable> {
         Ok(match &subtype {
             b"vide" => SampleDescriptionTable::Video(SampleVideoDescriptionTable::parse(self)?),
-            b"soun" => todo!(),
-            b"subt" => todo!(),
-            b"meta" => todo!(),
-            b"twen" => todo!(),
-            b"sprt" => todo!(),
-            b"MPEG" => todo!(),
-            b"musi" => todo!(),
-            b"sbtl" => todo!(),
-            b"clcp" => todo!(),
-            b"text" => todo!(),
-            b"qd3d" => todo!(),
-            b"strm" => todo!(),
-            _ => unimplemented!(),
+            b"soun" => SampleDescriptionTable::Sound(SampleSoundDescriptionTable::parse(self)?),
+            b"subt" | b"sbtl" | b"text" | b"clcp" => {
+                SampleDescriptionTable::Text(TextSampleDescription::parse(self)?)
+            }
+            // todo: meta, twen, sprt, MPEG, musi, qd3d, strm
+            _ => {
+                return Err(Mp4Error::UnsupportedSampleEntry {
+                    offset: base.offset,
+                    path: self.atom_path(),
+                    handler: subtype,
+                })
+            }
         })
     }
 
-    pub fn skip_chunk(&mut self) -> io::Result<u64> {
-        let len = self.reader.read_u32()?;
+    pub fn skip_chunk(&mut self) -> Mp4Result<u64> {
+        let offset = self.position()?;
+        let (len, _) = self.read_atom_header()?;
 
-        self.reader.skip(len as i64 - 4)
+        self.jump_to(offset + len)
     }
 
-    pub fn peek_header(&mut self) -> io::Result<Header> {
+    pub fn peek_header(&mut self) -> Mp4Result<Header> {
         let offset = self.reader.buffer.stream_position()?;
-        self.read_atom_len()?;
-
-        let header = Header(self.reader.read_bytes_const::<4>()?);
+        let (_, header) = self.read_atom_header()?;
 
         self.reader.buffer.seek(SeekFrom::Start(offset))?;
 
@@ -173,17 +364,27 @@ macro_rules! impl_from_bytes {
         }
 
         impl Parse for $type {
-            fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> io::Result<Self>
+            fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
             where
                 Self: Sized,
             {
-                mp4.reader.read_bytes::<$len, $type>()
+                Ok(mp4.reader.read_bytes::<$len, $type>()?)
             }
 
-            fn peek_len<R: Seek + BufRead>(_mp4: &mut Mp4<'_, R>) -> io::Result<u64> {
+            fn peek_len<R: Seek + BufRead>(_mp4: &mut Mp4<'_, R>) -> Mp4Result<u64> {
                 Ok($len as u64)
             }
         }
+
+        impl Serialize for $type {
+            fn serialize<R: Seek + BufRead, W: Write>(
+                &self,
+                _mp4: &mut Mp4<'_, R>,
+                writer: &mut W,
+            ) -> Mp4Result<()> {
+                Ok(writer.write_all(&self.to_be_bytes())?)
+            }
+        }
     };
 }
 
@@ -228,7 +429,6 @@ impl<R: BufRead + Seek> Reader<R> {
         self.read_bytes::<4, i32>()
     }
 
-    #[allow(dead_code)]
     pub fn read_u64(&mut self) -> io::Result<u64> {
         self.read_bytes::<8, u64>()
     }
@@ -264,3 +464,106 @@ impl<R: BufRead + Seek> Reader<R> {
         Ok(buf)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_util::{atom, full_atom, movie};
+
+    #[test]
+    fn rejects_atoms_larger_than_their_parent() {
+        // a 16-byte atom whose 64-bit size claims an exabyte
+        let mut stsz = vec![0, 0, 0, 1];
+        stsz.extend(b"stsz");
+        stsz.extend((1u64 << 60).to_be_bytes());
+
+        let mut mp4 = Mp4::new(Cursor::new(stsz.clone()));
+        assert!(matches!(
+            Stsz::parse(&mut mp4),
+            Err(Mp4Error::InvalidAtomLength { offset: 0, .. })
+        ));
+
+        let mut edts = 24u32.to_be_bytes().to_vec();
+        edts.extend(b"edts");
+        edts.extend(stsz);
+
+        let mut mp4 = Mp4::new(Cursor::new(edts));
+        assert!(matches!(
+            Edts::parse(&mut mp4),
+            Err(Mp4Error::InvalidAtomLength { offset: 8, .. })
+        ));
+    }
+
+    #[test]
+    fn reports_the_full_path_of_lazily_parsed_atoms() {
+        // a version 1 media header is too short for its 64-bit times
+        let mut file = movie(0, 10);
+        let mdhd = file
+            .windows(4)
+            .position(|header| header == b"mdhd")
+            .unwrap();
+        file[mdhd + 4] = 1;
+
+        let mut mp4 = Mp4::new(Cursor::new(file));
+        let mut moov = Reference::<Moov>::new(0, UnparsedAtom::parse(&mut mp4).unwrap().len)
+            .parse(&mut mp4)
+            .unwrap();
+        let mut trak = moov.trak(&mut mp4)[0].parse(&mut mp4).unwrap();
+        let mut mdia = trak.mdia(&mut mp4).unwrap().parse(&mut mp4).unwrap();
+
+        let error = mdia.mdhd(&mut mp4).unwrap().parse(&mut mp4).unwrap_err();
+        assert!(
+            matches!(error, Mp4Error::LengthMismatch { offset, .. } if offset == mdhd as u64 - 4)
+        );
+        assert_eq!(error.path().unwrap().to_string(), "moov/trak/mdia/mdhd");
+
+        // an atom parsed on its own has no parents
+        let mut mp4 = Mp4::new(Cursor::new(atom(b"mdhd", &[])));
+        assert_eq!(
+            Mdhd::parse(&mut mp4)
+                .unwrap_err()
+                .path()
+                .unwrap()
+                .to_string(),
+            "mdhd"
+        );
+    }
+
+    #[test]
+    fn reports_structural_errors() {
+        let mut mp4 = Mp4::new(Cursor::new(full_atom(b"stts", 0, &[0])));
+        let error = Stsz::parse(&mut mp4).unwrap_err();
+        assert!(matches!(
+            error,
+            Mp4Error::UnexpectedHeader {
+                expected: STSZ,
+                found: STTS,
+                ..
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "expected atom stsz but found stts at offset 0 (<root>)"
+        );
+
+        // a media header with four bytes to spare
+        let mut mp4 = Mp4::new(Cursor::new(full_atom(b"mdhd", 0, &[0; 6])));
+        assert!(matches!(
+            Mdhd::parse(&mut mp4),
+            Err(Mp4Error::LengthMismatch {
+                expected: 36,
+                actual: 32,
+                ..
+            })
+        ));
+
+        let mut mp4 = Mp4::new(Cursor::new(atom(b"stsd", &[])));
+        let entry = Reference::<BaseSampleDescriptionTable>::new(0, 8);
+        assert!(matches!(
+            entry.parse_sample_description(&mut mp4, *b"strm"),
+            Err(Mp4Error::UnsupportedSampleEntry { handler, .. }) if handler == *b"strm"
+        ));
+    }
+}
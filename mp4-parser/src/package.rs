@@ -0,0 +1,685 @@
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::{BufRead, BufWriter, Seek, Write},
+    path::Path,
+};
+
+use crate::{
+    FragmentOptions, Fragmenter, MediaSegment, Moov, Mp4, Mp4Error, Mp4Result,
+    SampleDescriptionTable, MOOV,
+};
+
+/// The name of the HLS master playlist written by [`Mp4::package`]
+pub const HLS_MASTER_PLAYLIST: &str = "master.m3u8";
+/// The name of the DASH manifest written by [`Mp4::package`]
+pub const DASH_MANIFEST: &str = "manifest.mpd";
+
+/// What a packaged track holds, and the properties manifests advertise for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackagedMedia {
+    Video {
+        width: u32,
+        height: u32,
+    },
+    Audio {
+        sample_rate: u32,
+        channel_count: u32,
+    },
+}
+
+/// A video or audio track that can be packaged, along with what manifests say
+/// about it
+#[derive(Debug, Clone)]
+struct Rendition {
+    track_id: u32,
+    media: PackagedMedia,
+    codecs: String,
+    language: Option<String>,
+}
+
+/// A track written out as CMAF segments by [`Mp4::package`]
+#[derive(Debug, Clone)]
+pub struct PackagedTrack {
+    pub track_id: u32,
+    pub media: PackagedMedia,
+    /// The codec as given to the `codecs` parameter of RFC 6381
+    pub codecs: String,
+    /// ISO 639-2/T code of the track's language, if it has one
+    pub language: Option<String>,
+    /// The time scale of the segments' timing
+    pub timescale: u32,
+    pub segments: Vec<MediaSegment>,
+    /// Size in bytes of each media segment file
+    pub segment_sizes: Vec<u64>,
+    /// The highest bit rate of any media segment, in bits per second
+    pub bandwidth: u64,
+    /// The bit rate over all media segments, in bits per second
+    pub average_bandwidth: u64,
+}
+
+impl PackagedTrack {
+    /// The name of the track's init segment file
+    pub fn init_segment_name(&self) -> String {
+        format!("track{}_init.mp4", self.track_id)
+    }
+
+    /// The name of the file of the media segment with the given sequence
+    /// number
+    pub fn media_segment_name(&self, sequence_number: u32) -> String {
+        format!("track{}_{}.m4s", self.track_id, sequence_number)
+    }
+
+    /// The name of the track's HLS media playlist
+    pub fn playlist_name(&self) -> String {
+        format!("track{}.m3u8", self.track_id)
+    }
+
+    /// The duration of the whole track in seconds
+    pub fn duration(&self) -> f64 {
+        self.segments
+            .iter()
+            .map(|segment| segment.duration)
+            .sum::<u64>() as f64
+            / self.timescale.max(1) as f64
+    }
+
+    fn is_video(&self) -> bool {
+        matches!(self.media, PackagedMedia::Video { .. })
+    }
+}
+
+impl<R: BufRead + Seek> Mp4<'_, R> {
+    /// Packages the video and audio tracks of a progressive file for adaptive
+    /// streaming, writing to `directory` an init segment and media segments
+    /// for every track, an HLS media playlist per track, an HLS master playlist
+    /// and a DASH manifest. Bandwidths are measured from the segments written
+    pub fn package(
+        &mut self,
+        directory: impl AsRef<Path>,
+        options: FragmentOptions,
+    ) -> Mp4Result<Vec<PackagedTrack>> {
+        let directory = directory.as_ref();
+        fs::create_dir_all(directory)?;
+
+        let mut tracks = Vec::new();
+
+        for rendition in self.streamable_tracks()? {
+            let fragmenter = Fragmenter::new(self, rendition.track_id, options)?;
+            if fragmenter.segments().is_empty() {
+                continue;
+            }
+
+            let mut track = PackagedTrack {
+                track_id: rendition.track_id,
+                media: rendition.media,
+                codecs: rendition.codecs,
+                language: rendition.language,
+                timescale: fragmenter.timescale(),
+                segments: fragmenter.segments().to_vec(),
+                segment_sizes: Vec::new(),
+                bandwidth: 0,
+                average_bandwidth: 0,
+            };
+
+            let mut writer =
+                BufWriter::new(File::create(directory.join(track.init_segment_name()))?);
+            fragmenter.write_init_segment(self, &mut writer)?;
+            writer.flush()?;
+
+            for (index, segment) in fragmenter.segments().iter().enumerate() {
+                let path = directory.join(track.media_segment_name(segment.sequence_number));
+                let mut writer = BufWriter::new(File::create(&path)?);
+                fragmenter.write_segment(self, index, &mut writer)?;
+                writer.flush()?;
+
+                track.segment_sizes.push(fs::metadata(&path)?.len());
+            }
+
+            let timescale = track.timescale.max(1) as f64;
+            track.bandwidth = track
+                .segments
+                .iter()
+                .zip(&track.segment_sizes)
+                .map(|(segment, &size)| bit_rate(size, segment.duration.max(1) as f64 / timescale))
+                .max()
+                .unwrap_or(0);
+            track.average_bandwidth = bit_rate(track.segment_sizes.iter().sum(), track.duration());
+
+            fs::write(
+                directory.join(track.playlist_name()),
+                hls_media_playlist(&track),
+            )?;
+            tracks.push(track);
+        }
+
+        fs::write(
+            directory.join(HLS_MASTER_PLAYLIST),
+            hls_master_playlist(&tracks),
+        )?;
+        fs::write(directory.join(DASH_MANIFEST), dash_manifest(&tracks))?;
+
+        Ok(tracks)
+    }
+
+    /// The video and audio tracks of the movie
+    fn streamable_tracks(&mut self) -> Mp4Result<Vec<Rendition>> {
+        let mut moov = match self
+            .top_level_atoms()?
+            .into_iter()
+            .find(|atom| atom.header == MOOV)
+        {
+            Some(moov) => moov.into_ref::<Moov>().parse(self)?,
+            None => {
+                return Err(Mp4Error::MissingAtom {
+                    offset: 0,
+                    path: self.atom_path(),
+                    header: MOOV,
+                })
+            }
+        };
+
+        let mut tracks = Vec::new();
+
+        for trak in moov.trak(self).clone() {
+            let mut trak = trak.parse(self)?;
+            let tkhd = trak.track_header(self)?.parse(self)?;
+            let mut mdia = trak.mdia(self)?.parse(self)?;
+            let language = mdia
+                .mdhd(self)?
+                .parse(self)?
+                .language_code()
+                .filter(|language| language != "und");
+
+            let handler = match *mdia.hdlr(self) {
+                Some(hdlr) => hdlr.parse(self)?.component_subtype,
+                None => continue,
+            };
+            if !matches!(&handler, b"vide" | b"soun") {
+                continue;
+            }
+
+            let mut minf = match *mdia.minf(self) {
+                Some(minf) => minf.parse(self)?,
+                None => continue,
+            };
+            let mut stbl = match *minf.stbl(self) {
+                Some(stbl) => stbl.parse(self)?,
+                None => continue,
+            };
+            let entry = match *stbl.sample_description(self) {
+                Some(stsd) => match stsd.parse(self)?.entries.first() {
+                    Some(&entry) => entry,
+                    None => continue,
+                },
+                None => continue,
+            };
+
+            let (media, codecs) = match entry.parse_sample_description(self, handler)? {
+                SampleDescriptionTable::Video(video) => {
+                    // the track's presentation size accounts for the pixel
+                    // aspect ratio, but isn't always set
+                    let (width, height) = match (tkhd.track_width >> 16, tkhd.track_height >> 16) {
+                        (0, _) | (_, 0) => (video.width as u32, video.height as u32),
+                        size => size,
+                    };
+                    (PackagedMedia::Video { width, height }, video.codec_string())
+                }
+                SampleDescriptionTable::Sound(sound) => (
+                    PackagedMedia::Audio {
+                        sample_rate: sound.sample_rate().round() as u32,
+                        channel_count: sound.channel_count(),
+                    },
+                    sound.codec_string(),
+                ),
+                SampleDescriptionTable::Text(_) => continue,
+            };
+
+            tracks.push(Rendition {
+                track_id: tkhd.track_id,
+                media,
+                codecs,
+                language,
+            });
+        }
+
+        Ok(tracks)
+    }
+}
+
+fn bit_rate(size: u64, seconds: f64) -> u64 {
+    match seconds > 0.0 {
+        true => (size as f64 * 8.0 / seconds).ceil() as u64,
+        false => 0,
+    }
+}
+
+fn hls_media_playlist(track: &PackagedTrack) -> String {
+    let timescale = track.timescale.max(1) as f64;
+    let target_duration = track
+        .segments
+        .iter()
+        .map(|segment| (segment.duration as f64 / timescale).ceil() as u64)
+        .max()
+        .unwrap_or(0);
+
+    let mut playlist = String::new();
+    writeln!(playlist, "#EXTM3U").unwrap();
+    writeln!(playlist, "#EXT-X-VERSION:7").unwrap();
+    writeln!(playlist, "#EXT-X-TARGETDURATION:{}", target_duration).unwrap();
+    writeln!(playlist, "#EXT-X-PLAYLIST-TYPE:VOD").unwrap();
+    writeln!(playlist, "#EXT-X-INDEPENDENT-SEGMENTS").unwrap();
+    writeln!(playlist, "#EXT-X-MAP:URI=\"{}\"", track.init_segment_name()).unwrap();
+
+    for segment in &track.segments {
+        writeln!(
+            playlist,
+            "#EXTINF:{:.6},",
+            segment.duration as f64 / timescale
+        )
+        .unwrap();
+        writeln!(
+            playlist,
+            "{}",
+            track.media_segment_name(segment.sequence_number)
+        )
+        .unwrap();
+    }
+
+    writeln!(playlist, "#EXT-X-ENDLIST").unwrap();
+
+    playlist
+}
+
+/// A master playlist offering every video track as a variant along with the
+/// audio tracks as alternative renditions, or every audio track as a variant
+/// if there is no video
+fn hls_master_playlist(tracks: &[PackagedTrack]) -> String {
+    let (video, audio): (Vec<_>, Vec<_>) = tracks.iter().partition(|track| track.is_video());
+
+    let mut playlist = String::new();
+    writeln!(playlist, "#EXTM3U").unwrap();
+    writeln!(playlist, "#EXT-X-VERSION:7").unwrap();
+    writeln!(playlist, "#EXT-X-INDEPENDENT-SEGMENTS").unwrap();
+
+    if video.is_empty() {
+        for track in audio {
+            writeln!(
+                playlist,
+                "#EXT-X-STREAM-INF:BANDWIDTH={},AVERAGE-BANDWIDTH={},CODECS=\"{}\"",
+                track.bandwidth, track.average_bandwidth, track.codecs
+            )
+            .unwrap();
+            writeln!(playlist, "{}", track.playlist_name()).unwrap();
+        }
+
+        return playlist;
+    }
+
+    for (index, track) in audio.iter().enumerate() {
+        let name = match &track.language {
+            Some(language) => language.clone(),
+            None => format!("Audio {}", index + 1),
+        };
+        let language = match &track.language {
+            Some(language) => format!(",LANGUAGE=\"{}\"", language),
+            None => String::new(),
+        };
+        let default = match index {
+            0 => "YES",
+            _ => "NO",
+        };
+
+        writeln!(
+            playlist,
+            "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",NAME=\"{}\"{},DEFAULT={},AUTOSELECT=YES,URI=\"{}\"",
+            name,
+            language,
+            default,
+            track.playlist_name()
+        )
+        .unwrap();
+    }
+
+    // a variant's bandwidth includes whichever audio rendition is played
+    // along with it
+    let audio_bandwidth = audio.iter().map(|track| track.bandwidth).max().unwrap_or(0);
+    let audio_average_bandwidth = audio
+        .iter()
+        .map(|track| track.average_bandwidth)
+        .max()
+        .unwrap_or(0);
+    let mut audio_codecs = Vec::new();
+    for track in &audio {
+        if !audio_codecs.contains(&&track.codecs) {
+            audio_codecs.push(&track.codecs);
+        }
+    }
+
+    for track in video {
+        let mut codecs = track.codecs.clone();
+        for audio_codec in &audio_codecs {
+            codecs.push(',');
+            codecs.push_str(audio_codec);
+        }
+
+        let mut attributes = format!(
+            "BANDWIDTH={},AVERAGE-BANDWIDTH={},CODECS=\"{}\"",
+            track.bandwidth + audio_bandwidth,
+            track.average_bandwidth + audio_average_bandwidth,
+            codecs
+        );
+        if let PackagedMedia::Video { width, height } = track.media {
+            write!(attributes, ",RESOLUTION={}x{}", width, height).unwrap();
+        }
+        if !audio.is_empty() {
+            write!(attributes, ",AUDIO=\"audio\"").unwrap();
+        }
+
+        writeln!(playlist, "#EXT-X-STREAM-INF:{}", attributes).unwrap();
+        writeln!(playlist, "{}", track.playlist_name()).unwrap();
+    }
+
+    playlist
+}
+
+/// A static manifest with an adaptation set per track, each addressing its
+/// segments through a segment template and timeline
+fn dash_manifest(tracks: &[PackagedTrack]) -> String {
+    let duration = tracks
+        .iter()
+        .map(PackagedTrack::duration)
+        .fold(0.0, f64::max);
+    let max_segment_duration = tracks
+        .iter()
+        .flat_map(|track| {
+            track
+                .segments
+                .iter()
+                .map(|segment| segment.duration as f64 / track.timescale.max(1) as f64)
+        })
+        .fold(0.0, f64::max);
+
+    let mut manifest = String::new();
+    writeln!(manifest, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(
+        manifest,
+        r#"<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" profiles="urn:mpeg:dash:profile:isoff-live:2011" type="static" mediaPresentationDuration="PT{:.3}S" maxSegmentDuration="PT{:.3}S" minBufferTime="PT{:.3}S">"#,
+        duration, max_segment_duration, max_segment_duration
+    )
+    .unwrap();
+    writeln!(manifest, r#"  <Period id="0" start="PT0S">"#).unwrap();
+
+    for track in tracks {
+        let (content_type, representation_attributes) = match track.media {
+            PackagedMedia::Video { width, height } => (
+                "video",
+                format!(r#" width="{}" height="{}""#, width, height),
+            ),
+            PackagedMedia::Audio { sample_rate, .. } => {
+                ("audio", format!(r#" audioSamplingRate="{}""#, sample_rate))
+            }
+        };
+        let language = match &track.language {
+            Some(language) => format!(r#" lang="{}""#, language),
+            None => String::new(),
+        };
+
+        writeln!(
+            manifest,
+            r#"    <AdaptationSet id="{}" contentType="{}" mimeType="{}/mp4"{} segmentAlignment="true" startWithSAP="1">"#,
+            track.track_id, content_type, content_type, language
+        )
+        .unwrap();
+        writeln!(
+            manifest,
+            r#"      <Representation id="{}" codecs="{}" bandwidth="{}"{}>"#,
+            track.track_id, track.codecs, track.bandwidth, representation_attributes
+        )
+        .unwrap();
+        if let PackagedMedia::Audio { channel_count, .. } = track.media {
+            writeln!(
+                manifest,
+                r#"        <AudioChannelConfiguration schemeIdUri="urn:mpeg:dash:23003:3:audio_channel_configuration:2011" value="{}"/>"#,
+                channel_count
+            )
+            .unwrap();
+        }
+        writeln!(
+            manifest,
+            r#"        <SegmentTemplate timescale="{}" initialization="track$RepresentationID$_init.mp4" media="track$RepresentationID$_$Number$.m4s" startNumber="1">"#,
+            track.timescale
+        )
+        .unwrap();
+        writeln!(manifest, "          <SegmentTimeline>").unwrap();
+
+        // runs of segments with the same duration share an entry
+        let mut index = 0;
+        while index < track.segments.len() {
+            let segment = &track.segments[index];
+            let repeats = track.segments[index + 1..]
+                .iter()
+                .take_while(|next| next.duration == segment.duration)
+                .count();
+
+            let time = match index {
+                0 => format!(r#" t="{}""#, segment.decode_time),
+                _ => String::new(),
+            };
+            let repeat = match repeats {
+                0 => String::new(),
+                repeats => format!(r#" r="{}""#, repeats),
+            };
+            writeln!(
+                manifest,
+                r#"            <S{} d="{}"{}/>"#,
+                time, segment.duration, repeat
+            )
+            .unwrap();
+
+            index += repeats + 1;
+        }
+
+        writeln!(manifest, "          </SegmentTimeline>").unwrap();
+        writeln!(manifest, "        </SegmentTemplate>").unwrap();
+        writeln!(manifest, "      </Representation>").unwrap();
+        writeln!(manifest, "    </AdaptationSet>").unwrap();
+    }
+
+    writeln!(manifest, "  </Period>").unwrap();
+    writeln!(manifest, "</MPD>").unwrap();
+
+    manifest
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, io::Cursor, process, time::Duration};
+
+    use super::*;
+    use crate::test_util::{atom, full_atom, media_data};
+
+    fn track(
+        track_id: u32,
+        media: PackagedMedia,
+        codecs: &str,
+        durations: &[u64],
+    ) -> PackagedTrack {
+        let mut decode_time = 0;
+        let segments = durations
+            .iter()
+            .enumerate()
+            .map(|(index, &duration)| {
+                let segment = MediaSegment {
+                    sequence_number: index as u32 + 1,
+                    samples: 0..0,
+                    decode_time,
+                    duration,
+                    data_size: 0,
+                };
+                decode_time += duration;
+                segment
+            })
+            .collect::<Vec<_>>();
+
+        PackagedTrack {
+            track_id,
+            media,
+            codecs: codecs.to_string(),
+            language: None,
+            timescale: 1000,
+            segment_sizes: vec![1000; segments.len()],
+            segments,
+            bandwidth: 8000,
+            average_bandwidth: 6000,
+        }
+    }
+
+    #[test]
+    fn describes_segments_in_manifests() {
+        let video = track(
+            1,
+            PackagedMedia::Video {
+                width: 1280,
+                height: 720,
+            },
+            "avc1.64001f",
+            &[2000, 2000, 1500],
+        );
+        let audio = track(
+            2,
+            PackagedMedia::Audio {
+                sample_rate: 48000,
+                channel_count: 2,
+            },
+            "mp4a.40.2",
+            &[2000, 1500],
+        );
+        let tracks = [video, audio];
+
+        let master = hls_master_playlist(&tracks);
+        assert!(master.contains(
+            "#EXT-X-STREAM-INF:BANDWIDTH=16000,AVERAGE-BANDWIDTH=12000,\
+             CODECS=\"avc1.64001f,mp4a.40.2\",RESOLUTION=1280x720,AUDIO=\"audio\"\ntrack1.m3u8\n"
+        ));
+
+        let playlist = hls_media_playlist(&tracks[0]);
+        assert!(playlist.contains("#EXT-X-TARGETDURATION:2\n"));
+        assert!(playlist.contains("#EXTINF:1.500000,\ntrack1_3.m4s\n"));
+
+        let manifest = dash_manifest(&tracks);
+        assert!(manifest.contains("<S t=\"0\" d=\"2000\" r=\"1\"/>\n"));
+        assert!(manifest.contains("<S d=\"1500\"/>\n"));
+    }
+
+    /// The `moov` atom of a movie of one H.264 video track of ten 100 ms
+    /// samples of 10 bytes each, with sync samples at 0 and 500 ms. Its frames
+    /// are 960x720, presented at 1280x720
+    fn video_movie(data_offset: u32) -> Vec<u8> {
+        let mut mvhd = vec![0; 12];
+        mvhd.extend(1000u32.to_be_bytes());
+        mvhd.extend([0; 84]);
+
+        let mut tkhd = vec![0; 12];
+        tkhd.extend(1u32.to_be_bytes());
+        tkhd.extend([0; 60]);
+        tkhd.extend((1280u32 << 16).to_be_bytes());
+        tkhd.extend((720u32 << 16).to_be_bytes());
+
+        let mut hdlr = vec![0; 8];
+        hdlr.extend(b"vide");
+        hdlr.extend([0; 13]);
+
+        // the record of an x264 High profile, level 3.1 stream
+        let sps = [
+            0x67, 0x64, 0x00, 0x1f, 0xac, 0xd9, 0x40, 0x50, 0x05, 0xbb, 0x01, 0x10, 0x00, 0x00,
+            0x03, 0x00, 0x10, 0x00, 0x00, 0x03, 0x03, 0xc0, 0xf1, 0x83, 0x19, 0x60,
+        ];
+        let pps = [0x68, 0xeb, 0xe3, 0xcb, 0x22, 0xc0];
+        let mut avcc = vec![0x01, 0x64, 0x00, 0x1f, 0xff, 0xe1];
+        avcc.extend((sps.len() as u16).to_be_bytes());
+        avcc.extend(sps);
+        avcc.push(1);
+        avcc.extend((pps.len() as u16).to_be_bytes());
+        avcc.extend(pps);
+
+        let mut avc1 = vec![0; 6];
+        avc1.extend(1u16.to_be_bytes());
+        avc1.extend([0; 16]);
+        avc1.extend(960u16.to_be_bytes());
+        avc1.extend(720u16.to_be_bytes());
+        avc1.extend([0x00, 0x48, 0x00, 0x00, 0x00, 0x48, 0x00, 0x00]);
+        avc1.extend([0; 4]);
+        avc1.extend(1u16.to_be_bytes());
+        avc1.extend([0; 32]);
+        avc1.extend(0x18u16.to_be_bytes());
+        avc1.extend((-1i16).to_be_bytes());
+        avc1.extend(atom(b"avcC", &avcc));
+
+        let mut stsd = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stsd.extend(atom(b"avc1", &avc1));
+        let stbl = [
+            atom(b"stsd", &stsd),
+            full_atom(b"stts", 0, &[1, 10, 100]),
+            full_atom(b"stss", 0, &[2, 1, 6]),
+            full_atom(b"stsc", 0, &[1, 1, 10, 1]),
+            full_atom(b"stsz", 0, &[10, 10]),
+            full_atom(b"stco", 0, &[1, data_offset]),
+        ]
+        .concat();
+
+        let mdia = [
+            full_atom(b"mdhd", 0, &[0, 0, 1000, 1000, 0]),
+            atom(b"hdlr", &hdlr),
+            atom(b"minf", &atom(b"stbl", &stbl)),
+        ]
+        .concat();
+        let trak = [atom(b"tkhd", &tkhd), atom(b"mdia", &mdia)].concat();
+
+        atom(
+            b"moov",
+            &[atom(b"mvhd", &mvhd), atom(b"trak", &trak)].concat(),
+        )
+    }
+
+    #[test]
+    fn packages_tracks_with_their_properties() {
+        let mut file = video_movie(video_movie(0).len() as u32 + 8);
+        file.extend(media_data());
+
+        let directory = env::temp_dir().join(format!("mp4-parser-package-{}", process::id()));
+        let options = FragmentOptions {
+            segment_duration: Duration::from_millis(300),
+            segment_index: false,
+        };
+        let tracks = Mp4::new(Cursor::new(file))
+            .package(&directory, options)
+            .unwrap();
+
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].codecs, "avc1.64001f");
+        assert_eq!(
+            tracks[0].media,
+            PackagedMedia::Video {
+                width: 1280,
+                height: 720,
+            }
+        );
+
+        // each segment lasts half a second
+        let sizes = ["track1_1.m4s", "track1_2.m4s"]
+            .map(|name| fs::metadata(directory.join(name)).unwrap().len());
+        assert_eq!(tracks[0].segment_sizes, sizes);
+        let bandwidth = sizes.iter().max().unwrap() * 16;
+        assert_eq!(tracks[0].bandwidth, bandwidth);
+
+        let master = fs::read_to_string(directory.join(HLS_MASTER_PLAYLIST)).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert!(master.contains(&format!(
+            "#EXT-X-STREAM-INF:BANDWIDTH={},AVERAGE-BANDWIDTH={},\
+             CODECS=\"avc1.64001f\",RESOLUTION=1280x720\ntrack1.m3u8\n",
+            bandwidth,
+            (sizes[0] + sizes[1]) * 8
+        )));
+    }
+}
@@ -1,9 +1,9 @@
 use std::{
-    io::{self, BufRead, Seek},
+    io::{BufRead, Seek},
     marker::PhantomData,
 };
 
-use crate::{BaseSampleDescriptionTable, Mp4, Parse, SampleDescriptionTable};
+use crate::{BaseSampleDescriptionTable, Mp4, Mp4Result, Parse, SampleDescriptionTable};
 
 #[derive(Debug, Clone)]
 pub struct Reference<P: Parse> {
@@ -15,11 +15,12 @@ pub struct Reference<P: Parse> {
 impl<P: Parse + Clone> Copy for Reference<P> {}
 
 impl<P: Parse> Parse for Reference<P> {
-    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> io::Result<Self>
+    fn parse<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<Self>
     where
         Self: Sized,
     {
         let offset = mp4.reader.buffer.stream_position()?;
+        mp4.remember_parents(offset);
         let len = P::peek_len(mp4)?;
         mp4.reader.skip(len as i64)?;
         Ok(Reference {
@@ -29,7 +30,7 @@ impl<P: Parse> Parse for Reference<P> {
         })
     }
 
-    fn peek_len<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> io::Result<u64> {
+    fn peek_len<R: Seek + BufRead>(mp4: &mut Mp4<'_, R>) -> Mp4Result<u64> {
         P::peek_len(mp4)
     }
 }
@@ -43,9 +44,9 @@ impl<P: Parse> Reference<P> {
         }
     }
 
-    pub fn parse<R: Seek + BufRead>(self, mp4: &mut Mp4<'_, R>) -> io::Result<P> {
-        mp4.jump_to(self.offset).unwrap();
-        P::parse(mp4)
+    pub fn parse<R: Seek + BufRead>(self, mp4: &mut Mp4<'_, R>) -> Mp4Result<P> {
+        mp4.jump_to(self.offset)?;
+        mp4.in_parents_of(self.offset, P::parse)
     }
 }
 
@@ -54,7 +55,7 @@ impl Reference<BaseSampleDescriptionTable> {
         self,
         mp4: &mut Mp4<'_, R>,
         subtype: [u8; 4],
-    ) -> io::Result<SampleDescriptionTable> {
+    ) -> Mp4Result<SampleDescriptionTable> {
         mp4.parse_sample_description(self, subtype)
     }
 }
@@ -0,0 +1,208 @@
+use std::io::{BufRead, Seek};
+
+use crate::{
+    Ftyp, Header, Mdat, Meta, Mfra, Moof, Moov, Mp4, Mp4Result, Parse, Pdin, Reference, Sidx, Styp,
+    UnparsedAtom, Uuid, FREE, FTYP, MDAT, META, MFRA, MOOF, MOOV, PDIN, SIDX, SKIP, STYP, UUID,
+    WIDE,
+};
+
+/// An atom at the top level of a file
+#[derive(Debug, Clone)]
+pub enum RootAtom {
+    Ftyp(Reference<Ftyp>),
+    Moov(Reference<Moov>),
+    Mdat(Reference<Mdat>),
+    Free(UnparsedAtom),
+    Skip(UnparsedAtom),
+    Wide(UnparsedAtom),
+    Pdin(Reference<Pdin>),
+    Uuid(Reference<Uuid>),
+    Meta(Reference<Meta>),
+    Moof(Reference<Moof>),
+    Styp(Reference<Styp>),
+    Sidx(Reference<Sidx>),
+    Mfra(Reference<Mfra>),
+    /// An atom this crate doesn't recognise at the top level
+    Unknown(UnparsedAtom),
+}
+
+impl RootAtom {
+    fn new(atom: UnparsedAtom) -> Self {
+        match atom.header {
+            FTYP => Self::Ftyp(atom.into_ref()),
+            MOOV => Self::Moov(atom.into_ref()),
+            MDAT => Self::Mdat(atom.into_ref()),
+            FREE => Self::Free(atom),
+            SKIP => Self::Skip(atom),
+            WIDE => Self::Wide(atom),
+            PDIN => Self::Pdin(atom.into_ref()),
+            UUID => Self::Uuid(atom.into_ref()),
+            META => Self::Meta(atom.into_ref()),
+            MOOF => Self::Moof(atom.into_ref()),
+            STYP => Self::Styp(atom.into_ref()),
+            SIDX => Self::Sidx(atom.into_ref()),
+            MFRA => Self::Mfra(atom.into_ref()),
+            _ => Self::Unknown(atom),
+        }
+    }
+
+    /// The atom's type and location
+    pub fn atom(&self) -> UnparsedAtom {
+        fn unparsed<P: Parse>(header: Header, atom: &Reference<P>) -> UnparsedAtom {
+            UnparsedAtom {
+                offset: atom.offset,
+                len: atom.len,
+                header,
+            }
+        }
+
+        match self {
+            Self::Ftyp(atom) => unparsed(FTYP, atom),
+            Self::Moov(atom) => unparsed(MOOV, atom),
+            Self::Mdat(atom) => unparsed(MDAT, atom),
+            Self::Pdin(atom) => unparsed(PDIN, atom),
+            Self::Uuid(atom) => unparsed(UUID, atom),
+            Self::Meta(atom) => unparsed(META, atom),
+            Self::Moof(atom) => unparsed(MOOF, atom),
+            Self::Styp(atom) => unparsed(STYP, atom),
+            Self::Sidx(atom) => unparsed(SIDX, atom),
+            Self::Mfra(atom) => unparsed(MFRA, atom),
+            Self::Free(atom) | Self::Skip(atom) | Self::Wide(atom) | Self::Unknown(atom) => {
+                atom.clone()
+            }
+        }
+    }
+}
+
+/// The family of formats a file belongs to, as told by its `ftyp` atom
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    /// An ISO base media file, such as an MPEG-4 file
+    IsoBmff,
+    /// A QuickTime movie
+    QuickTime,
+    /// A 3GPP or 3GPP2 file
+    ThreeGpp,
+    /// A HEIF image or image sequence, including AVIF
+    Heif,
+}
+
+impl Ftyp {
+    /// Classifies the file by its major brand. A file whose major brand is a
+    /// generic ISO brand is a HEIF file if it is compatible with one of the
+    /// HEIF structural brands
+    pub fn file_kind(&self) -> FileKind {
+        brand_kind(self.major_brand)
+            .or_else(|| {
+                self.compatible_brands
+                    .iter()
+                    .any(|brand| matches!(brand, b"mif1" | b"msf1"))
+                    .then_some(FileKind::Heif)
+            })
+            .unwrap_or(FileKind::IsoBmff)
+    }
+}
+
+fn brand_kind(brand: [u8; 4]) -> Option<FileKind> {
+    match &brand {
+        b"qt  " => Some(FileKind::QuickTime),
+        [b'3', b'g', b'p' | b'2', _] => Some(FileKind::ThreeGpp),
+        b"mif1" | b"msf1" | b"mif2" | b"heic" | b"heix" | b"heim" | b"heis" | b"hevc" | b"hevx"
+        | b"avif" | b"avis" => Some(FileKind::Heif),
+        _ => None,
+    }
+}
+
+impl<R: BufRead + Seek> Mp4<'_, R> {
+    /// Lists every atom at the top level of the file, in file order
+    pub fn root(&mut self) -> Mp4Result<Vec<RootAtom>> {
+        Ok(self
+            .top_level_atoms()?
+            .into_iter()
+            .map(RootAtom::new)
+            .collect())
+    }
+
+    /// The first atom at the top level of the file with the given header
+    pub fn root_atom(&mut self, header: Header) -> Mp4Result<Option<UnparsedAtom>> {
+        Ok(self
+            .top_level_atoms()?
+            .into_iter()
+            .find(|atom| atom.header == header))
+    }
+
+    /// Classifies the file by its `ftyp` atom. QuickTime movies predate `ftyp`,
+    /// so a file without one is taken to be a QuickTime movie
+    pub fn file_kind(&mut self) -> Mp4Result<FileKind> {
+        for atom in self.root()? {
+            if let RootAtom::Ftyp(ftyp) = atom {
+                return Ok(ftyp.parse(self)?.file_kind());
+            }
+        }
+
+        Ok(FileKind::QuickTime)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{test_util::atom, TRAK, UDTA};
+
+    #[test]
+    fn lists_top_level_and_unknown_atoms() {
+        let file = [
+            atom(b"ftyp", b"isom\0\0\0\0mif1heic"),
+            atom(b"free", &[]),
+            atom(b"uuid", &[7; 20]),
+            atom(b"moov", &[atom(b"udta", &[]), atom(b"zzzz", &[1])].concat()),
+            atom(b"junk", &[]),
+        ]
+        .concat();
+        let mut mp4 = Mp4::new(Cursor::new(file));
+
+        let root = mp4.root().unwrap();
+        assert!(matches!(
+            root.as_slice(),
+            [
+                RootAtom::Ftyp(_),
+                RootAtom::Free(_),
+                RootAtom::Uuid(_),
+                RootAtom::Moov(_),
+                RootAtom::Unknown(UnparsedAtom { offset: 85, .. })
+            ]
+        ));
+
+        let ftyp = match root[0] {
+            RootAtom::Ftyp(ftyp) => ftyp.parse(&mut mp4).unwrap(),
+            _ => unreachable!(),
+        };
+        assert_eq!(ftyp.compatible_brands, [*b"mif1", *b"heic"]);
+        assert_eq!(mp4.file_kind().unwrap(), FileKind::Heif);
+
+        let uuid = match root[2] {
+            RootAtom::Uuid(uuid) => uuid.parse(&mut mp4).unwrap(),
+            _ => unreachable!(),
+        };
+        assert_eq!(uuid.data, [7; 4]);
+
+        let mut moov = match root[3] {
+            RootAtom::Moov(moov) => moov.parse(&mut mp4).unwrap(),
+            _ => unreachable!(),
+        };
+        let unknown = moov.unknown_children();
+        assert_eq!(unknown.len(), 1);
+        assert_eq!(unknown[0].header, Header::new(*b"zzzz"));
+        assert_eq!(moov.child(UDTA).map(|udta| udta.offset), Some(68));
+        // still found once the typed accessor has looked for it
+        assert!(moov.udta(&mut mp4).is_some());
+        assert!(moov.child(UDTA).is_some());
+        assert!(moov.child(TRAK).is_none());
+
+        let junk = mp4.root_atom(Header::new(*b"junk")).unwrap().unwrap();
+        assert_eq!(junk.offset, 85);
+        assert!(mp4.root_atom(MFRA).unwrap().is_none());
+    }
+}
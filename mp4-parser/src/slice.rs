@@ -0,0 +1,98 @@
+use std::{
+    borrow::Cow,
+    io::{self, BufRead, Cursor, Seek},
+    ops::Range,
+};
+
+use crate::{Mdat, Mp4, Mp4Result, Parse, Reference, SampleInfo};
+
+/// Parsing from bytes held in memory, such as the contents of a memory-mapped
+/// file. Media data and sample payloads are borrowed from the input rather
+/// than copied out of it
+impl<'a> Mp4<'a, Cursor<&'a [u8]>> {
+    pub fn from_slice(data: &'a [u8]) -> Self {
+        Self {
+            input: Some(data),
+            ..Self::new(Cursor::new(data))
+        }
+    }
+
+    /// The whole input
+    pub fn data(&self) -> &'a [u8] {
+        self.reader.buffer.get_ref()
+    }
+
+    /// The bytes of the input within `range`, or `None` if it runs past the
+    /// end of the input
+    pub fn bytes(&self, range: Range<u64>) -> Option<&'a [u8]> {
+        let start = usize::try_from(range.start).ok()?;
+        let end = usize::try_from(range.end).ok()?;
+
+        self.data().get(start..end)
+    }
+
+    /// The whole of a referenced atom, header included
+    pub fn atom_bytes<P: Parse>(&self, atom: Reference<P>) -> Option<&'a [u8]> {
+        self.bytes(atom.offset..atom.offset.checked_add(atom.len)?)
+    }
+
+    /// The body of a media data atom
+    pub fn media_data(&self, mdat: &Mdat) -> Option<&'a [u8]> {
+        self.bytes(mdat.data_range())
+    }
+
+    /// The payload of a sample
+    pub fn sample_data(&self, sample: &SampleInfo) -> Option<&'a [u8]> {
+        self.bytes(sample.offset..sample.offset.checked_add(sample.size as u64)?)
+    }
+}
+
+impl<'a, R: BufRead + Seek> Mp4<'a, R> {
+    /// Reads `n` bytes, such as a sample payload, borrowing them from the
+    /// input when parsing from a slice and copying them out of the reader
+    /// otherwise
+    pub fn read_slice(&mut self, n: usize) -> Mp4Result<Cow<'a, [u8]>> {
+        let input = match self.input {
+            Some(input) => input,
+            None => return Ok(Cow::Owned(self.reader.read_bytes_dyn(n)?)),
+        };
+
+        let start = usize::try_from(self.position()?).unwrap_or(usize::MAX);
+        let bytes = start
+            .checked_add(n)
+            .and_then(|end| input.get(start..end))
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        self.reader.skip(n as i64)?;
+
+        Ok(Cow::Borrowed(bytes))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn borrows_media_data_from_input() {
+        let mut file = 16u32.to_be_bytes().to_vec();
+        file.extend(b"mdat");
+        file.extend(b"samples!");
+
+        let mut mp4 = Mp4::from_slice(&file);
+        let mdat = Mdat::parse(&mut mp4).unwrap();
+        assert_eq!(mdat.data_range(), 8..16);
+
+        let data = mp4.media_data(&mdat).unwrap();
+        assert_eq!(data, b"samples!");
+        assert!(std::ptr::eq(data, &file[8..]));
+
+        mp4.jump_to(12).unwrap();
+        assert!(matches!(mp4.read_slice(4).unwrap(), Cow::Borrowed(b"les!")));
+        assert!(mp4.read_slice(1).is_err());
+
+        // other readers have the bytes copied out of them
+        let mut mp4 = Mp4::new(Cursor::new(&file));
+        mp4.jump_to(8).unwrap();
+        assert!(matches!(mp4.read_slice(8).unwrap(), Cow::Owned(data) if data == b"samples!"));
+    }
+}
@@ -0,0 +1,646 @@
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, Cursor, Read, Seek, SeekFrom},
+};
+
+use crate::{
+    AtomPath, Header, Moof, Moov, Mp4, Mp4Error, Mp4Result, Parse, SampleInfo, SampleTable, Stbl,
+    Trak, Trex, FREE, MDAT, MOOF, MOOV, SKIP, WIDE,
+};
+
+/// A top-level atom read whole from a stream by [`Mp4Stream`]
+#[derive(Debug, Clone)]
+pub struct BufferedAtom {
+    pub header: Header,
+    /// Offset of the atom within the stream
+    pub offset: u64,
+    /// The whole atom, header included
+    pub bytes: Vec<u8>,
+}
+
+impl BufferedAtom {
+    /// A parser over this atom alone, positioned at its start. Offsets within
+    /// it, such as those of [`Reference`](crate::Reference)s, are offsets
+    /// within the stream as a whole
+    pub fn mp4(&self) -> Mp4<'_, AtomCursor<'_>> {
+        Mp4::new(AtomCursor {
+            offset: self.offset,
+            cursor: Cursor::new(&self.bytes),
+        })
+    }
+
+    /// Parses the atom, such as a [`Moov`] for a `moov` atom
+    pub fn parse<P: Parse>(&self) -> Mp4Result<P> {
+        P::parse(&mut self.mp4())
+    }
+}
+
+/// Reads the bytes of a [`BufferedAtom`] as though they were at the atom's
+/// offset within the stream
+#[derive(Debug)]
+pub struct AtomCursor<'a> {
+    offset: u64,
+    cursor: Cursor<&'a Vec<u8>>,
+}
+
+impl Read for AtomCursor<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.cursor.read(buf)
+    }
+}
+
+impl BufRead for AtomCursor<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.cursor.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.cursor.consume(amt)
+    }
+}
+
+impl Seek for AtomCursor<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(position) => {
+                SeekFrom::Start(position.checked_sub(self.offset).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "seek to before the start of the buffered atom",
+                    )
+                })?)
+            }
+            pos => pos,
+        };
+
+        Ok(self.cursor.seek(pos)? + self.offset)
+    }
+}
+
+/// A sample read from a stream by [`Mp4Stream`]
+#[derive(Debug, Clone)]
+pub struct StreamedSample {
+    pub track_id: u32,
+    /// The sample's location and timing. Samples from movie fragments are
+    /// indexed after every earlier sample of the track in the stream
+    pub info: SampleInfo,
+    pub data: Vec<u8>,
+}
+
+/// What [`Mp4Stream`] found next in the stream
+#[derive(Debug, Clone)]
+pub enum StreamEvent {
+    /// A top-level atom other than media data or free space, read whole.
+    /// `moov` and `moof` atoms are also used to locate the samples that follow
+    Atom(BufferedAtom),
+
+    /// The start of a top-level `mdat` atom, whose body isn't buffered. The
+    /// samples it holds follow as [`StreamEvent::Sample`]s, if an earlier
+    /// `moov` or `moof` atom described them. `len` is `None` if the atom
+    /// extends to the end of the stream
+    MediaData {
+        offset: u64,
+        len: Option<u64>,
+    },
+
+    /// A top-level `free`, `skip` or `wide` atom, whose body is skipped
+    FreeSpace {
+        header: Header,
+        offset: u64,
+        len: Option<u64>,
+    },
+
+    Sample(StreamedSample),
+}
+
+/// The samples a track has in the stream, as far as it has been read
+#[derive(Debug)]
+struct StreamTrack {
+    track_id: u32,
+    /// The samples described by `moov`, which every fragment extends
+    table: SampleTable,
+    trex: Option<Trex>,
+    /// Index of the track's next sample from a movie fragment
+    next_index: u32,
+    /// Decoding time of the track's next sample from a movie fragment, if the
+    /// fragment doesn't give one
+    next_decode_time: u64,
+}
+
+/// The size and type of a top-level atom, as read from the stream
+#[derive(Debug)]
+struct AtomHeader {
+    header: Header,
+    /// The bytes of the size and type fields
+    bytes: Vec<u8>,
+    /// The total size of the atom, or `None` if it extends to the end of the
+    /// stream
+    len: Option<u64>,
+}
+
+/// The `mdat` atom currently being read
+#[derive(Debug)]
+struct MediaDataState {
+    end: Option<u64>,
+    /// Samples within the atom, in stream order
+    samples: VecDeque<(u32, SampleInfo)>,
+}
+
+/// A forward-only parser over a stream that can't seek, such as a pipe, a
+/// socket or stdin
+///
+/// Top-level atoms are buffered whole, except for media data and free space,
+/// which are read through. The samples described by `moov` or by a movie
+/// fragment are read as the stream reaches them, so samples in media data that
+/// precedes its `moov` atom can't be located
+#[derive(Debug)]
+pub struct Mp4Stream<R: Read> {
+    reader: R,
+    /// Offset within the stream of the next byte to be read
+    position: u64,
+    tracks: Vec<StreamTrack>,
+    /// Located samples whose data hasn't been reached yet
+    pending: Vec<(u32, SampleInfo)>,
+    media_data: Option<MediaDataState>,
+    /// Set once the stream has ended or failed
+    finished: bool,
+}
+
+impl<R: Read> Mp4Stream<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            position: 0,
+            tracks: Vec::new(),
+            pending: Vec::new(),
+            media_data: None,
+            finished: false,
+        }
+    }
+
+    /// Reads up to the next event, returning `None` at the end of the stream
+    pub fn next_event(&mut self) -> Mp4Result<Option<StreamEvent>> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        let event = self.read_event();
+        if !matches!(event, Ok(Some(_))) {
+            self.finished = true;
+        }
+
+        event
+    }
+
+    fn read_event(&mut self) -> Mp4Result<Option<StreamEvent>> {
+        if let Some(sample) = self.read_sample()? {
+            return Ok(Some(StreamEvent::Sample(sample)));
+        }
+
+        let offset = self.position;
+        let atom_header = match self.read_atom_header()? {
+            Some(atom_header) => atom_header,
+            None => return Ok(None),
+        };
+        let (header, len) = (atom_header.header, atom_header.len);
+        let end = len.map(|len| offset + len);
+
+        match header {
+            MDAT => {
+                self.start_media_data(self.position, end);
+                Ok(Some(StreamEvent::MediaData { offset, len }))
+            }
+            FREE | SKIP | WIDE => {
+                self.skip_to(offset, header, end)?;
+                Ok(Some(StreamEvent::FreeSpace {
+                    header,
+                    offset,
+                    len,
+                }))
+            }
+            _ => {
+                let atom = self.read_atom(offset, atom_header)?;
+
+                match header {
+                    MOOV => self.add_movie(&atom)?,
+                    MOOF => self.add_fragment(&atom)?,
+                    _ => {}
+                }
+
+                Ok(Some(StreamEvent::Atom(atom)))
+            }
+        }
+    }
+
+    /// Reads the size and type of the next atom, returning `None` at the end of
+    /// the stream
+    fn read_atom_header(&mut self) -> Mp4Result<Option<AtomHeader>> {
+        let offset = self.position;
+
+        let mut bytes = [0; 8];
+        let mut filled = 0;
+        while filled < bytes.len() {
+            match self.reader.read(&mut bytes[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.position += filled as u64;
+
+        match filled {
+            0 => return Ok(None),
+            8 => {}
+            _ => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        }
+
+        let header = Header(bytes[4..].try_into().unwrap());
+        let mut bytes = bytes.to_vec();
+        let len = match u32::from_be_bytes(bytes[..4].try_into().unwrap()) {
+            0 => None,
+            1 => {
+                let mut len = [0; 8];
+                self.reader.read_exact(&mut len)?;
+                self.position += 8;
+                bytes.extend(len);
+                Some(u64::from_be_bytes(len))
+            }
+            len => Some(len as u64),
+        };
+
+        if let Some(len) = len {
+            if len < bytes.len() as u64 || offset.checked_add(len).is_none() {
+                return Err(Mp4Error::InvalidAtomLength {
+                    offset,
+                    path: AtomPath(Vec::new()),
+                    len,
+                });
+            }
+        }
+
+        Ok(Some(AtomHeader { header, bytes, len }))
+    }
+
+    /// Reads the rest of an atom whose header has just been read
+    fn read_atom(&mut self, offset: u64, atom_header: AtomHeader) -> Mp4Result<BufferedAtom> {
+        let AtomHeader {
+            header,
+            mut bytes,
+            len,
+        } = atom_header;
+        let end = len.map(|len| offset + len);
+
+        let read = match end {
+            Some(end) => (&mut self.reader)
+                .take(end - self.position)
+                .read_to_end(&mut bytes)?,
+            None => self.reader.read_to_end(&mut bytes)?,
+        };
+        self.position += read as u64;
+
+        if end.is_some_and(|end| self.position < end) {
+            return Err(Mp4Error::TruncatedAtom {
+                offset,
+                path: AtomPath(Vec::new()),
+                header,
+            });
+        }
+
+        Ok(BufferedAtom {
+            header,
+            offset,
+            bytes,
+        })
+    }
+
+    /// Discards the stream up to `end`, or to its end if `end` is `None`
+    fn skip_to(&mut self, offset: u64, header: Header, end: Option<u64>) -> Mp4Result<()> {
+        let skipped = match end {
+            Some(end) => io::copy(
+                &mut (&mut self.reader).take(end.saturating_sub(self.position)),
+                &mut io::sink(),
+            )?,
+            None => io::copy(&mut self.reader, &mut io::sink())?,
+        };
+        self.position += skipped;
+
+        if end.is_some_and(|end| self.position < end) {
+            return Err(Mp4Error::TruncatedAtom {
+                offset,
+                path: AtomPath(Vec::new()),
+                header,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Collects the located samples that lie within the `mdat` atom whose body
+    /// spans from `start` to `end`. Samples before the end of the atom can no
+    /// longer be reached afterwards, so they are dropped
+    fn start_media_data(&mut self, start: u64, end: Option<u64>) {
+        let mut samples = Vec::new();
+
+        self.pending.retain(|&(track_id, info)| {
+            let sample_end = info.offset + info.size as u64;
+
+            match end {
+                Some(end) if info.offset >= end => true,
+                _ => {
+                    if info.offset >= start && end.is_none_or(|end| sample_end <= end) {
+                        samples.push((track_id, info));
+                    }
+                    false
+                }
+            }
+        });
+
+        samples.sort_by_key(|(_, info)| info.offset);
+
+        self.media_data = Some(MediaDataState {
+            end,
+            samples: samples.into(),
+        });
+    }
+
+    /// Reads the next sample of the `mdat` atom being read, or reads through
+    /// the rest of the atom once there are none left
+    fn read_sample(&mut self) -> Mp4Result<Option<StreamedSample>> {
+        let media_data = match &mut self.media_data {
+            Some(media_data) => media_data,
+            None => return Ok(None),
+        };
+        let end = media_data.end;
+
+        // samples that share data with an earlier sample can't be read again
+        let next = loop {
+            match media_data.samples.pop_front() {
+                Some((_, info)) if info.offset < self.position => continue,
+                next => break next,
+            }
+        };
+
+        let (track_id, info) = match next {
+            Some(next) => next,
+            None => {
+                self.media_data = None;
+                self.skip_to(self.position, MDAT, end)?;
+                return Ok(None);
+            }
+        };
+
+        self.skip_to(self.position, MDAT, Some(info.offset))?;
+
+        let mut data = Vec::new();
+        (&mut self.reader)
+            .take(info.size as u64)
+            .read_to_end(&mut data)?;
+        self.position += data.len() as u64;
+
+        if data.len() < info.size as usize {
+            return Err(Mp4Error::TruncatedAtom {
+                offset: info.offset,
+                path: AtomPath(Vec::new()),
+                header: MDAT,
+            });
+        }
+
+        Ok(Some(StreamedSample {
+            track_id,
+            info,
+            data,
+        }))
+    }
+
+    /// Builds the sample table of every track described by `moov`
+    fn add_movie(&mut self, atom: &BufferedAtom) -> Mp4Result<()> {
+        let mut mp4 = atom.mp4();
+        let mut moov = Moov::parse(&mut mp4)?;
+
+        let mut trex = Vec::new();
+        if let Some(mvex) = *moov.mvex(&mut mp4) {
+            for candidate in mvex.parse(&mut mp4)?.trex(&mut mp4).clone() {
+                trex.push(candidate.parse(&mut mp4)?);
+            }
+        }
+
+        self.tracks.clear();
+        self.pending.clear();
+
+        for trak in moov.trak(&mut mp4).clone() {
+            let mut trak = trak.parse(&mut mp4)?;
+            let track_id = trak.track_header(&mut mp4)?.parse(&mut mp4)?.track_id;
+            let mut stbl = match sample_table_atom(&mut trak, &mut mp4)? {
+                Some(stbl) => stbl,
+                None => continue,
+            };
+            let table = SampleTable::new(&mut stbl, &mut mp4)?;
+
+            self.pending
+                .extend(table.iter().map(|sample| (track_id, sample)));
+
+            self.tracks.push(StreamTrack {
+                track_id,
+                next_index: table.len(),
+                next_decode_time: table.end_decode_time(),
+                table,
+                trex: trex.iter().find(|trex| trex.track_id == track_id).cloned(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Locates the samples of a movie fragment. Each track's fragment samples
+    /// are resolved against a copy of its `moov` sample table, so that only
+    /// the samples of the latest fragment are kept around
+    fn add_fragment(&mut self, atom: &BufferedAtom) -> Mp4Result<()> {
+        fn invalid(moof: &Moof) -> Mp4Error {
+            Mp4Error::InvalidSampleTable {
+                offset: moof.offset,
+                path: moof.path.clone(),
+                reason: "track fragment decode time is out of range",
+            }
+        }
+
+        let mut mp4 = atom.mp4();
+        let mut moof = Moof::parse(&mut mp4)?;
+
+        for track in &mut self.tracks {
+            let mut table = track.table.clone();
+            table.add_fragment(track.track_id, &mut moof, track.trex.as_ref(), &mut mp4)?;
+
+            // without a `tfdt` atom, the fragment's samples carry on from the
+            // track's previous fragment rather than from the `moov` samples
+            let shift = match has_decode_time(track.track_id, &mut moof, &mut mp4)? {
+                true => 0,
+                false => track
+                    .next_decode_time
+                    .saturating_sub(track.table.end_decode_time()),
+            };
+
+            for index in track.table.len()..table.len() {
+                let mut info = match table.sample(index) {
+                    Some(info) => info,
+                    None => break,
+                };
+                info.index = track.next_index;
+                info.decode_time = info
+                    .decode_time
+                    .checked_add(shift)
+                    .ok_or_else(|| invalid(&moof))?;
+                info.presentation_time = i64::try_from(shift)
+                    .ok()
+                    .and_then(|shift| info.presentation_time.checked_add(shift))
+                    .ok_or_else(|| invalid(&moof))?;
+
+                track.next_index += 1;
+                track.next_decode_time = info
+                    .decode_time
+                    .checked_add(info.duration as u64)
+                    .ok_or_else(|| invalid(&moof))?;
+                self.pending.push((track.track_id, info));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for Mp4Stream<R> {
+    type Item = Mp4Result<StreamEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event().transpose()
+    }
+}
+
+fn sample_table_atom<R: BufRead + Seek>(
+    trak: &mut Trak,
+    mp4: &mut Mp4<'_, R>,
+) -> Mp4Result<Option<Stbl>> {
+    let mut mdia = trak.mdia(mp4)?.parse(mp4)?;
+    let mut minf = match *mdia.minf(mp4) {
+        Some(minf) => minf.parse(mp4)?,
+        None => return Ok(None),
+    };
+
+    match *minf.stbl(mp4) {
+        Some(stbl) => Ok(Some(stbl.parse(mp4)?)),
+        None => Ok(None),
+    }
+}
+
+/// Whether the first track fragment of the track `track_id` gives the
+/// decoding time of its first sample
+fn has_decode_time<R: BufRead + Seek>(
+    track_id: u32,
+    moof: &mut Moof,
+    mp4: &mut Mp4<'_, R>,
+) -> Mp4Result<bool> {
+    for traf in moof.traf(mp4).clone() {
+        let mut traf = traf.parse(mp4)?;
+
+        if traf.tfhd(mp4)?.parse(mp4)?.track_id == track_id {
+            return Ok(traf.tfdt(mp4).is_some());
+        }
+    }
+
+    Ok(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{atom, full_atom};
+
+    /// A movie fragment holding two samples of track 1, whose decoding time
+    /// isn't given
+    fn fragment(sequence_number: u32, data: &[u8; 8]) -> Vec<u8> {
+        let tfhd = full_atom(b"tfhd", 0x020000, &[1]);
+        let trun = full_atom(b"trun", 0x000001, &[2, 76]);
+        let traf = atom(b"traf", &[tfhd, trun].concat());
+
+        let mut fragment = atom(
+            b"moof",
+            &[full_atom(b"mfhd", 0, &[sequence_number]), traf].concat(),
+        );
+        fragment.extend(atom(b"mdat", data));
+        fragment
+    }
+
+    /// Reads like a pipe, without being able to seek
+    struct Pipe<'a>(&'a [u8]);
+
+    impl Read for Pipe<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(5);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    /// The `moov` atom of a fragmented movie of one track, whose fragment
+    /// samples last 10 and hold 4 bytes by default
+    fn movie() -> Vec<u8> {
+        let mut tkhd = vec![0; 12];
+        tkhd.extend(1u32.to_be_bytes());
+        tkhd.extend([0; 68]);
+
+        let stbl = [
+            full_atom(b"stts", 0, &[0]),
+            full_atom(b"stsc", 0, &[0]),
+            full_atom(b"stsz", 0, &[0, 0]),
+            full_atom(b"stco", 0, &[0]),
+        ]
+        .concat();
+        let mdia = atom(b"mdia", &atom(b"minf", &atom(b"stbl", &stbl)));
+        let trak = atom(b"trak", &[atom(b"tkhd", &tkhd), mdia].concat());
+        let mvex = atom(b"mvex", &full_atom(b"trex", 0, &[1, 1, 10, 4, 0]));
+
+        atom(b"moov", &[trak, mvex].concat())
+    }
+
+    #[test]
+    fn reads_fragment_samples_in_order() {
+        let mut stream = movie();
+        stream.extend(fragment(1, b"abcdefgh"));
+        stream.extend(fragment(2, b"ijklmnop"));
+
+        let samples = Mp4Stream::new(Pipe(&stream))
+            .filter_map(|event| match event.unwrap() {
+                StreamEvent::Sample(sample) => Some(sample),
+                _ => None,
+            })
+            .map(|sample| (sample.info.index, sample.info.decode_time, sample.data))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            samples,
+            [
+                (0, 0, b"abcd".to_vec()),
+                (1, 10, b"efgh".to_vec()),
+                (2, 20, b"ijkl".to_vec()),
+                (3, 30, b"mnop".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_decode_times_out_of_range() {
+        let tfhd = full_atom(b"tfhd", 0x020000, &[1]);
+        let tfdt = full_atom(b"tfdt", 0x01000000, &[u32::MAX, u32::MAX - 5]);
+        let trun = full_atom(b"trun", 0x000001, &[2, 96]);
+        let traf = atom(b"traf", &[tfhd, tfdt, trun].concat());
+
+        let mut stream = movie();
+        stream.extend(atom(b"moof", &[full_atom(b"mfhd", 0, &[1]), traf].concat()));
+        stream.extend(atom(b"mdat", b"abcdefgh"));
+
+        let error = Mp4Stream::new(Pipe(&stream)).find_map(Result::err);
+        assert!(matches!(
+            error,
+            Some(Mp4Error::InvalidSampleTable { path, .. }) if path.to_string() == "moof"
+        ));
+    }
+}
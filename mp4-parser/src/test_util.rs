@@ -0,0 +1,60 @@
+//! Builders for the small files the tests parse
+
+pub fn atom(header: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut atom = (body.len() as u32 + 8).to_be_bytes().to_vec();
+    atom.extend(header);
+    atom.extend(body);
+    atom
+}
+
+/// A full atom whose version and flags are `flags`, followed by 32-bit fields
+pub fn full_atom(header: &[u8; 4], flags: u32, fields: &[u32]) -> Vec<u8> {
+    let mut body = flags.to_be_bytes().to_vec();
+    for field in fields {
+        body.extend(field.to_be_bytes());
+    }
+    atom(header, &body)
+}
+
+/// The `moov` atom of a movie of one track of ten 100 ms samples of 10 bytes
+/// each, with sync samples at 0 and 500 ms. The samples are stored back to back
+/// from `data_offset`, in chunks of `samples_per_chunk`
+pub fn movie(data_offset: u32, samples_per_chunk: u32) -> Vec<u8> {
+    let mut mvhd = vec![0; 12];
+    mvhd.extend(1000u32.to_be_bytes());
+    mvhd.extend([0; 84]);
+
+    let mut tkhd = vec![0; 12];
+    tkhd.extend(1u32.to_be_bytes());
+    tkhd.extend([0; 68]);
+
+    let chunks = 10 / samples_per_chunk;
+    let mut chunk_offsets = vec![chunks];
+    chunk_offsets.extend((0..chunks).map(|chunk| data_offset + chunk * samples_per_chunk * 10));
+
+    let mut stbl = full_atom(b"stsd", 0, &[0]);
+    stbl.extend(full_atom(b"stts", 0, &[1, 10, 100]));
+    stbl.extend(full_atom(b"stss", 0, &[2, 1, 6]));
+    stbl.extend(full_atom(b"stsc", 0, &[1, 1, samples_per_chunk, 1]));
+    stbl.extend(full_atom(b"stsz", 0, &[10, 10]));
+    stbl.extend(full_atom(b"stco", 0, &chunk_offsets));
+
+    let mut mdia = full_atom(b"mdhd", 0, &[0, 0, 1000, 1000, 0]);
+    mdia.extend(atom(b"minf", &atom(b"stbl", &stbl)));
+
+    let mut trak = atom(b"tkhd", &tkhd);
+    trak.extend(atom(b"mdia", &mdia));
+
+    let mut moov = atom(b"mvhd", &mvhd);
+    moov.extend(atom(b"trak", &trak));
+    atom(b"moov", &moov)
+}
+
+/// The `mdat` atom holding the samples of [`movie`], where every byte of a
+/// sample is its index
+pub fn media_data() -> Vec<u8> {
+    atom(
+        b"mdat",
+        &(0..10).flat_map(|sample| [sample; 10]).collect::<Vec<_>>(),
+    )
+}
@@ -0,0 +1,702 @@
+use std::{
+    io::{BufRead, Seek, Write},
+    ops::Range,
+    time::Duration,
+};
+
+use crate::{
+    clamp_time, write_atom_header, Co64, CompositionOffsetEntry, Ctts, EditList, EditListEntry,
+    EditSegment, Edts, Elst, Moov, Mp4, Mp4Error, Mp4Result, Reference, SampleTable,
+    SampleToChunkEntry, Sdtp, Serialize, Stbl, Stco, Stps, Stsc, Stss, Stsz, Stts,
+    TimeToSampleEntry, Trak, FTYP, MDAT, MOOV,
+};
+
+/// The part of a track that is kept by a trim
+struct TrackCut {
+    trak: Reference<Trak>,
+    table: SampleTable,
+    /// Zero-based indices of the samples kept, in decode order
+    samples: Range<u32>,
+    /// The edit list of the trimmed track, with media times relative to the
+    /// first sample kept
+    edits: Vec<EditListEntry>,
+}
+
+/// A run of samples of one track that are contiguous in the source file, and
+/// so can be copied in one go and described as one chunk
+struct Chunk {
+    /// Index of the track in the cuts
+    track: usize,
+    sample_count: u32,
+    sample_description_index: u32,
+    source_offset: u64,
+    len: u64,
+    offset: u64,
+}
+
+impl<R: BufRead + Seek> Mp4<'_, R> {
+    /// Writes a clip of the movie covering `range` to `writer` without
+    /// re-encoding it. Every track is cut back to the sync sample preceding
+    /// the start of the range, and given an edit list that starts playback at
+    /// exactly the requested time. The clip's media data holds only the samples
+    /// it references.
+    ///
+    /// Only the samples described by `moov` are considered, so movie fragments
+    /// are left out. Tracks with nothing to present within the range are left
+    /// out, as are the sample groups, shadow sync and `cslg` atoms of the
+    /// tracks that remain, which would no longer describe their samples
+    pub fn trim<W: Write>(&mut self, writer: &mut W, range: Range<Duration>) -> Mp4Result<()> {
+        let atoms = self.top_level_atoms()?;
+
+        let moov_ref = match atoms.iter().find(|atom| atom.header == MOOV) {
+            Some(moov) => moov.clone().into_ref::<Moov>(),
+            None => {
+                return Err(Mp4Error::MissingAtom {
+                    offset: 0,
+                    path: self.atom_path(),
+                    header: MOOV,
+                })
+            }
+        };
+        let ftyp = atoms.iter().find(|atom| atom.header == FTYP).cloned();
+
+        let mut moov = moov_ref.parse(self)?;
+        let movie_timescale = moov.movie_header(self)?.parse(self)?.time_scale;
+        let start = to_timescale(range.start, movie_timescale);
+        let end = to_timescale(range.end, movie_timescale);
+
+        let mut cuts = Vec::new();
+        for trak in moov.trak(self).clone() {
+            if let Some(cut) = self.cut_track(&mut moov, trak, start..end)? {
+                cuts.push(cut);
+            }
+        }
+
+        // chunks are laid out in the order of the source file, keeping the
+        // tracks interleaved as they were
+        let mut chunks = cuts
+            .iter()
+            .enumerate()
+            .flat_map(|(track, cut)| chunks(track, cut))
+            .collect::<Vec<_>>();
+        chunks.sort_by_key(|chunk| chunk.source_offset);
+
+        let ftyp_len = ftyp.as_ref().map_or(0, |ftyp| ftyp.len);
+        let mdat_body_len = chunks.iter().map(|chunk| chunk.len).sum::<u64>();
+        let mdat_header_len = match mdat_body_len + 8 > u32::MAX as u64 {
+            true => 16,
+            false => 8,
+        };
+
+        // as with a faststart, 64-bit chunk offsets grow `moov` and move the
+        // media data, so this repeats until the size of `moov` settles
+        let mut moov_len = moov_ref.len;
+        let moov_bytes = loop {
+            let mut offset = ftyp_len + moov_len + mdat_header_len;
+            for chunk in &mut chunks {
+                chunk.offset = offset;
+                offset += chunk.len;
+            }
+
+            let moov_bytes = self.trimmed_moov(moov_ref, &cuts, &chunks)?;
+            if moov_bytes.len() as u64 == moov_len {
+                break moov_bytes;
+            }
+            moov_len = moov_bytes.len() as u64;
+        };
+
+        if let Some(ftyp) = ftyp {
+            self.copy_range(ftyp.offset, ftyp.len, writer)?;
+        }
+        writer.write_all(&moov_bytes)?;
+        write_atom_header(writer, MDAT, mdat_body_len, false)?;
+        for chunk in &chunks {
+            self.copy_range(chunk.source_offset, chunk.len, writer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Works out which samples of a track are needed to present the movie
+    /// between `start` and `end`, given in the movie's time scale, and the
+    /// edits that present them
+    fn cut_track(
+        &mut self,
+        moov: &mut Moov,
+        trak_ref: Reference<Trak>,
+        range: Range<u64>,
+    ) -> Mp4Result<Option<TrackCut>> {
+        let mut trak = trak_ref.parse(self)?;
+        let edit_list = EditList::for_track(moov, &mut trak, self)?;
+
+        let mut stbl = match self.sample_table_atom(&mut trak)? {
+            Some(stbl) => stbl,
+            None => return Ok(None),
+        };
+        let table = SampleTable::new(&mut stbl, self)?;
+
+        let media_end = table
+            .iter()
+            .map(|sample| {
+                sample
+                    .presentation_time
+                    .saturating_add(sample.duration as i64)
+            })
+            .max()
+            .unwrap_or(0);
+
+        let media_to_movie = |media_time: u64| {
+            u64::try_from(
+                media_time as u128 * edit_list.movie_timescale as u128
+                    / edit_list.media_timescale.max(1) as u128,
+            )
+            .unwrap_or(u64::MAX)
+        };
+
+        // without an edit list, the media is presented as is from the start
+        let mut segments = edit_list.segments.clone();
+        if segments.is_empty() {
+            segments.push(EditSegment {
+                movie_start: 0,
+                duration: media_to_movie(media_end.max(0) as u64),
+                media_time: Some(0),
+                media_rate: 0x1_0000,
+            });
+        }
+
+        let mut edits = Vec::new();
+        for (index, segment) in segments.iter().enumerate() {
+            // reverse playback isn't supported by any real player
+            if segment.media_rate < 0 {
+                continue;
+            }
+
+            let segment_end = match index + 1 == segments.len() && segment.duration == 0 {
+                true => u64::MAX,
+                false => segment.movie_start.saturating_add(segment.duration),
+            };
+            let clip_start = segment.movie_start.max(range.start);
+            let clip_end = segment_end.min(range.end);
+            if clip_start >= clip_end {
+                continue;
+            }
+
+            let rate = segment.media_rate as i128;
+            let mut duration = clip_end - clip_start;
+            let media_time = segment
+                .media_time
+                .map(|media_time| match segment.is_dwell() {
+                    true => media_time,
+                    false => {
+                        let elapsed =
+                            edit_list.movie_to_media_timescale(clip_start - segment.movie_start);
+                        clamp_time(media_time as i128 + elapsed as i128 * rate / 0x1_0000)
+                    }
+                });
+
+            // an edit can't present more media than there is
+            if let Some(media_time) = media_time.filter(|_| !segment.is_dwell()) {
+                let available = media_to_movie(media_end.saturating_sub(media_time).max(0) as u64);
+                duration = duration
+                    .min(u64::try_from(available as i128 * 0x1_0000 / rate).unwrap_or(u64::MAX));
+            }
+            if duration == 0 {
+                continue;
+            }
+
+            edits.push(EditListEntry {
+                track_duration: duration,
+                media_time: media_time.unwrap_or(-1),
+                media_rate: segment.media_rate as u32,
+            });
+        }
+
+        while edits.last().is_some_and(|edit| edit.is_empty()) {
+            edits.pop();
+        }
+
+        // the span of media presented by the edits
+        let media_range = edits
+            .iter()
+            .filter(|edit| !edit.is_empty())
+            .map(|edit| match edit.media_rate {
+                0 => edit.media_time..edit.media_time.saturating_add(1),
+                rate => {
+                    let duration = edit_list.movie_to_media_timescale(edit.track_duration);
+                    let media_end = clamp_time(
+                        edit.media_time as i128 + duration as i128 * rate as i32 as i128 / 0x1_0000,
+                    );
+                    edit.media_time..media_end
+                }
+            })
+            .reduce(|a, b| a.start.min(b.start)..a.end.max(b.end));
+        let media_range = match media_range {
+            Some(media_range) => media_range,
+            None => return Ok(None),
+        };
+
+        let presented = table.iter().filter(|sample| {
+            sample.presentation_time < media_range.end
+                && sample
+                    .presentation_time
+                    .saturating_add(sample.duration as i64)
+                    > media_range.start
+        });
+        let (mut first, last) = match presented
+            .map(|sample| (sample.index, sample.index))
+            .reduce(|a, b| (a.0.min(b.0), a.1.max(b.1)))
+        {
+            Some(bounds) => bounds,
+            None => return Ok(None),
+        };
+
+        // decoding has to start from a sync sample
+        while first > 0 && !table.is_sync(first) {
+            first -= 1;
+        }
+
+        let origin = match table.sample(first) {
+            Some(sample) => sample.decode_time as i64,
+            None => return Ok(None),
+        };
+        for edit in edits.iter_mut().filter(|edit| !edit.is_empty()) {
+            edit.media_time -= origin;
+        }
+
+        Ok(Some(TrackCut {
+            trak: trak_ref,
+            table,
+            samples: first..last + 1,
+            edits,
+        }))
+    }
+
+    fn sample_table_atom(&mut self, trak: &mut Trak) -> Mp4Result<Option<Stbl>> {
+        let mut mdia = trak.mdia(self)?.parse(self)?;
+        let mut minf = match *mdia.minf(self) {
+            Some(minf) => minf.parse(self)?,
+            None => return Ok(None),
+        };
+
+        match *minf.stbl(self) {
+            Some(stbl) => Ok(Some(stbl.parse(self)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Serializes `moov` with only the tracks that were cut, each described by
+    /// its new sample table and edit list, and without `mvex`
+    fn trimmed_moov(
+        &mut self,
+        moov_ref: Reference<Moov>,
+        cuts: &[TrackCut],
+        chunks: &[Chunk],
+    ) -> Mp4Result<Vec<u8>> {
+        let mut moov = moov_ref.parse(self)?;
+        let mut movie_duration = 0;
+
+        for trak_ref in moov.trak(self).clone() {
+            let (track, cut) = match cuts
+                .iter()
+                .enumerate()
+                .find(|(_, cut)| cut.trak.offset == trak_ref.offset)
+            {
+                Some(cut) => cut,
+                None => {
+                    moov.remove_child(trak_ref);
+                    continue;
+                }
+            };
+            let track_chunks = chunks
+                .iter()
+                .filter(|chunk| chunk.track == track)
+                .collect::<Vec<_>>();
+
+            let mut trak = trak_ref.parse(self)?;
+
+            let track_duration = cut
+                .edits
+                .iter()
+                .map(|edit| edit.track_duration)
+                .sum::<u64>();
+            movie_duration = movie_duration.max(track_duration);
+
+            let tkhd_ref = *trak.track_header(self)?;
+            let mut tkhd = tkhd_ref.parse(self)?;
+            tkhd.duration = track_duration;
+            trak.replace_child(tkhd_ref, &tkhd, self)?;
+
+            let long = cut.edits.iter().any(|edit| {
+                edit.track_duration > u32::MAX as u64 || i32::try_from(edit.media_time).is_err()
+            });
+            let edts = Edts {
+                edit_list: vec![Elst {
+                    version: long as u8,
+                    flags: [0; 3],
+                    number_of_entries: cut.edits.len() as u32,
+                    edit_list_table: cut.edits.clone(),
+                }],
+            };
+            match *trak.edts(self) {
+                Some(edts_ref) => {
+                    trak.replace_child(edts_ref, &edts, self)?;
+                }
+                None => trak.push_child(&edts, self)?,
+            }
+
+            let mdia_ref = *trak.mdia(self)?;
+            let mut mdia = mdia_ref.parse(self)?;
+
+            let media_duration = cut
+                .samples
+                .clone()
+                .filter_map(|index| cut.table.sample(index))
+                .map(|sample| sample.duration as u64)
+                .sum::<u64>();
+            let mdhd_ref = *mdia.mdhd(self)?;
+            let mut mdhd = mdhd_ref.parse(self)?;
+            mdhd.duration = media_duration;
+            mdia.replace_child(mdhd_ref, &mdhd, self)?;
+
+            // tracks without a sample table are never cut
+            let minf_ref = match *mdia.minf(self) {
+                Some(minf) => minf,
+                None => continue,
+            };
+            let mut minf = minf_ref.parse(self)?;
+            let stbl_ref = match *minf.stbl(self) {
+                Some(stbl) => stbl,
+                None => continue,
+            };
+            let mut stbl = stbl_ref.parse(self)?;
+
+            self.trim_sample_table(&mut stbl, cut, &track_chunks)?;
+
+            minf.replace_child(stbl_ref, &stbl, self)?;
+            mdia.replace_child(minf_ref, &minf, self)?;
+            trak.replace_child(mdia_ref, &mdia, self)?;
+            moov.replace_child(trak_ref, &trak, self)?;
+        }
+
+        if let Some(mvex) = *moov.mvex(self) {
+            moov.remove_child(mvex);
+        }
+
+        let mvhd_ref = *moov.movie_header(self)?;
+        let mut mvhd = mvhd_ref.parse(self)?;
+        mvhd.duration = movie_duration;
+        moov.replace_child(mvhd_ref, &mvhd, self)?;
+
+        let mut bytes = Vec::new();
+        moov.serialize(self, &mut bytes)?;
+
+        Ok(bytes)
+    }
+
+    /// Replaces the tables of `stbl` with ones describing only the samples kept
+    /// by `cut`, stored in `chunks`
+    fn trim_sample_table(
+        &mut self,
+        stbl: &mut Stbl,
+        cut: &TrackCut,
+        chunks: &[&Chunk],
+    ) -> Mp4Result<()> {
+        let samples = cut
+            .samples
+            .clone()
+            .filter_map(|index| cut.table.sample(index))
+            .collect::<Vec<_>>();
+        let first = cut.samples.start;
+
+        if let Some(stts_ref) = *stbl.time_to_sample(self) {
+            let time_to_sample_table = runs(samples.iter().map(|sample| sample.duration))
+                .map(|(sample_count, sample_duration)| TimeToSampleEntry {
+                    sample_count,
+                    sample_duration,
+                })
+                .collect::<Vec<_>>();
+
+            let stts = Stts {
+                version: 0,
+                flags: [0; 3],
+                number_of_entries: time_to_sample_table.len() as u32,
+                time_to_sample_table,
+            };
+            stbl.replace_child(stts_ref, &stts, self)?;
+        }
+
+        if let Some(ctts_ref) = *stbl.composition_offset(self) {
+            let version = ctts_ref.parse(self)?.version;
+            let composition_offset_table = runs(
+                samples
+                    .iter()
+                    .map(|sample| (sample.presentation_time - sample.decode_time as i64) as i32),
+            )
+            .map(
+                |(sample_count, composition_offset)| CompositionOffsetEntry {
+                    sample_count,
+                    composition_offset,
+                },
+            )
+            .collect::<Vec<_>>();
+
+            let negative = composition_offset_table
+                .iter()
+                .any(|entry| entry.composition_offset < 0);
+            let ctts = Ctts {
+                version: version.max(negative as u8),
+                flags: [0; 3],
+                number_of_entries: composition_offset_table.len() as u32,
+                composition_offset_table,
+            };
+            stbl.replace_child(ctts_ref, &ctts, self)?;
+        }
+
+        if let Some(stss_ref) = *stbl.sync_sample(self) {
+            let sync_sample_table = cut
+                .samples
+                .clone()
+                .filter(|&index| cut.table.is_sync(index))
+                .map(|index| index - first + 1)
+                .collect::<Vec<_>>();
+
+            let stss = Stss {
+                version: 0,
+                flags: [0; 3],
+                number_of_entries: sync_sample_table.len() as u32,
+                sync_sample_table,
+            };
+            stbl.replace_child(stss_ref, &stss, self)?;
+        }
+
+        if let Some(stps_ref) = *stbl.stps(self) {
+            let partial_sync_sample_table = cut
+                .samples
+                .clone()
+                .filter(|&index| cut.table.is_partial_sync(index))
+                .map(|index| index - first + 1)
+                .collect::<Vec<_>>();
+
+            let stps = Stps {
+                version: 0,
+                flags: [0; 3],
+                number_of_entries: partial_sync_sample_table.len() as u32,
+                partial_sync_sample_table,
+            };
+            stbl.replace_child(stps_ref, &stps, self)?;
+        }
+
+        if let Some(sdtp_ref) = *stbl.sdtp(self) {
+            let sdtp = Sdtp {
+                version: 0,
+                flags: [0; 3],
+                sample_dependency_flags_table: cut
+                    .samples
+                    .clone()
+                    .filter_map(|index| cut.table.dependency_flags(index))
+                    .collect(),
+            };
+            stbl.replace_child(sdtp_ref, &sdtp, self)?;
+        }
+
+        if let Some(stsz_ref) = *stbl.sample_size(self) {
+            let uniform = samples
+                .first()
+                .map(|sample| sample.size)
+                .filter(|&size| samples.iter().all(|sample| sample.size == size));
+
+            let stsz = Stsz {
+                version: 0,
+                flags: [0; 3],
+                sample_size: uniform.unwrap_or(0),
+                number_of_entries: samples.len() as u32,
+                sample_size_table: match uniform {
+                    Some(_) => Vec::new(),
+                    None => samples.iter().map(|sample| sample.size).collect(),
+                },
+            };
+            stbl.replace_child(stsz_ref, &stsz, self)?;
+        }
+
+        if let Some(stsc_ref) = *stbl.sample_to_chunk(self) {
+            let mut sample_to_chunk_table = Vec::<SampleToChunkEntry>::new();
+            for (index, chunk) in chunks.iter().enumerate() {
+                if sample_to_chunk_table.last().is_some_and(|entry| {
+                    entry.samples_per_chunk == chunk.sample_count
+                        && entry.sample_description_id == chunk.sample_description_index
+                }) {
+                    continue;
+                }
+
+                sample_to_chunk_table.push(SampleToChunkEntry {
+                    first_chunk: index as u32 + 1,
+                    samples_per_chunk: chunk.sample_count,
+                    sample_description_id: chunk.sample_description_index,
+                });
+            }
+
+            let stsc = Stsc {
+                version: 0,
+                flags: [0; 3],
+                number_of_entries: sample_to_chunk_table.len() as u32,
+                sample_to_chunk_table,
+            };
+            stbl.replace_child(stsc_ref, &stsc, self)?;
+        }
+
+        let chunk_offset_table = chunks.iter().map(|chunk| chunk.offset).collect::<Vec<_>>();
+        let co64 = Co64 {
+            version: 0,
+            flags: [0; 3],
+            number_of_entries: chunk_offset_table.len() as u32,
+            chunk_offset_table,
+        };
+        let fits = co64
+            .chunk_offset_table
+            .iter()
+            .all(|&offset| offset <= u32::MAX as u64);
+
+        match (*stbl.chunk_offset(self), *stbl.chunk_offset_64(self)) {
+            (Some(stco_ref), None) if fits => {
+                let stco = Stco {
+                    version: 0,
+                    flags: [0; 3],
+                    number_of_entries: co64.number_of_entries,
+                    chunk_offset_table: co64
+                        .chunk_offset_table
+                        .iter()
+                        .map(|&offset| offset as u32)
+                        .collect(),
+                };
+                stbl.replace_child(stco_ref, &stco, self)?;
+            }
+            (Some(stco_ref), None) => {
+                stbl.replace_child(stco_ref, &co64, self)?;
+            }
+            (stco_ref, Some(co64_ref)) => {
+                if let Some(stco_ref) = stco_ref {
+                    stbl.remove_child(stco_ref);
+                }
+                stbl.replace_child(co64_ref, &co64, self)?;
+            }
+            (None, None) => {}
+        }
+
+        if let Some(cslg) = *stbl.cslg(self) {
+            stbl.remove_child(cslg);
+        }
+        if let Some(stsh) = *stbl.shadow_sync(self) {
+            stbl.remove_child(stsh);
+        }
+        for sgpd in stbl.sgpd(self).clone() {
+            stbl.remove_child(sgpd);
+        }
+        for sbgp in stbl.sbgp(self).clone() {
+            stbl.remove_child(sbgp);
+        }
+
+        Ok(())
+    }
+}
+
+/// Groups the samples kept by a cut into chunks of samples that are contiguous
+/// in the source file
+fn chunks(track: usize, cut: &TrackCut) -> Vec<Chunk> {
+    let mut chunks = Vec::<Chunk>::new();
+
+    for sample in cut
+        .samples
+        .clone()
+        .filter_map(|index| cut.table.sample(index))
+    {
+        if let Some(chunk) = chunks.last_mut() {
+            if chunk.source_offset + chunk.len == sample.offset
+                && chunk.sample_description_index == sample.sample_description_index
+            {
+                chunk.sample_count += 1;
+                chunk.len += sample.size as u64;
+                continue;
+            }
+        }
+
+        chunks.push(Chunk {
+            track,
+            sample_count: 1,
+            sample_description_index: sample.sample_description_index,
+            source_offset: sample.offset,
+            len: sample.size as u64,
+            offset: 0,
+        });
+    }
+
+    chunks
+}
+
+/// Run-length encodes `values` as pairs of a count and a value
+fn runs<T: PartialEq + Copy>(values: impl Iterator<Item = T>) -> impl Iterator<Item = (u32, T)> {
+    let mut runs = Vec::<(u32, T)>::new();
+
+    for value in values {
+        match runs.last_mut() {
+            Some((count, last)) if *last == value => *count += 1,
+            _ => runs.push((1, value)),
+        }
+    }
+
+    runs.into_iter()
+}
+
+/// Converts a duration to a number of units of a time scale
+fn to_timescale(duration: Duration, timescale: u32) -> u64 {
+    u64::try_from(duration.as_nanos() * timescale as u128 / 1_000_000_000).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_util::{atom, media_data, movie};
+
+    #[test]
+    fn cuts_back_to_sync_sample() {
+        let ftyp = atom(b"ftyp", b"isom\0\0\0\0");
+        let moov = movie((ftyp.len() + movie(0, 2).len() + 8) as u32, 2);
+        let mdat = media_data();
+
+        let mut file = ftyp.clone();
+        file.extend(moov);
+        file.extend(mdat);
+
+        let mut written = Vec::new();
+        let range = Duration::from_millis(620)..Duration::from_millis(750);
+        Mp4::new(Cursor::new(file))
+            .trim(&mut written, range)
+            .unwrap();
+
+        let mut mp4 = Mp4::new(Cursor::new(written.clone()));
+        let moov_len = u32::from_be_bytes(written[16..20].try_into().unwrap()) as u64;
+        let mut moov = Reference::<Moov>::new(16, moov_len)
+            .parse(&mut mp4)
+            .unwrap();
+        let mut trak = moov.trak(&mut mp4)[0].parse(&mut mp4).unwrap();
+
+        // decoding starts from the sync sample at 500 ms, and the edit skips
+        // the 120 ms before the start of the range
+        let edits = EditList::for_track(&mut moov, &mut trak, &mut mp4).unwrap();
+        assert_eq!(edits.segments.len(), 1);
+        assert_eq!(edits.segments[0].duration, 130);
+        assert_eq!(edits.segments[0].media_time, Some(120));
+
+        let table = SampleTable::for_track(&mut moov, &mut trak, &mut mp4).unwrap();
+        let samples = table
+            .iter()
+            .map(|sample| written[sample.offset as usize])
+            .collect::<Vec<_>>();
+        assert_eq!(samples, [5, 6, 7]);
+        assert!(table.is_sync(0));
+        assert!(!table.is_sync(1));
+
+        assert_eq!(written.len() as u64, 16 + moov_len + 8 + 30);
+    }
+}
@@ -0,0 +1,282 @@
+use std::io::{self, BufRead, Read, Seek, Write};
+
+use crate::{atom::UnparsedAtom, Header, Mp4, Mp4Result, Parse, Reference, Serialize};
+
+/// A child of a container atom, as it will be serialized
+#[derive(Debug, Clone)]
+pub(crate) enum ChildAtom {
+    /// An atom copied unchanged from the file the container was parsed from
+    Source(UnparsedAtom),
+    /// An atom that replaced one of the original children or was added to the
+    /// container, already serialized
+    Serialized(Vec<u8>),
+}
+
+impl ChildAtom {
+    fn len(&self) -> u64 {
+        match self {
+            ChildAtom::Source(atom) => atom.len,
+            ChildAtom::Serialized(bytes) => bytes.len() as u64,
+        }
+    }
+}
+
+/// Replaces or, if `atom` is `None`, removes the child at `offset`, returning
+/// whether there was one
+pub(crate) fn replace_child(
+    children: &mut Vec<ChildAtom>,
+    offset: u64,
+    atom: Option<Vec<u8>>,
+) -> bool {
+    let position = children
+        .iter()
+        .position(|child| matches!(child, ChildAtom::Source(source) if source.offset == offset));
+
+    match (position, atom) {
+        (Some(position), Some(atom)) => children[position] = ChildAtom::Serialized(atom),
+        (Some(position), None) => {
+            children.remove(position);
+        }
+        (None, _) => return false,
+    }
+
+    true
+}
+
+/// Writes the header of an atom whose body is `body_len` bytes long. A 64-bit
+/// size is used if the atom doesn't fit a 32-bit one or if `large` is set, so
+/// that atoms which had one in the source file keep it
+pub(crate) fn write_atom_header<W: Write>(
+    writer: &mut W,
+    header: Header,
+    body_len: u64,
+    large: bool,
+) -> io::Result<()> {
+    if large || body_len + 8 > u32::MAX as u64 {
+        writer.write_all(&1u32.to_be_bytes())?;
+        writer.write_all(&header.0)?;
+        writer.write_all(&(body_len + 16).to_be_bytes())
+    } else {
+        writer.write_all(&(body_len as u32 + 8).to_be_bytes())?;
+        writer.write_all(&header.0)
+    }
+}
+
+/// Writes an atom with the given body
+pub(crate) fn write_atom<W: Write>(writer: &mut W, header: Header, body: &[u8]) -> Mp4Result<()> {
+    write_atom_header(writer, header, body.len() as u64, false)?;
+
+    Ok(writer.write_all(body)?)
+}
+
+/// Writes a container atom, copying its unchanged children from `mp4`
+pub(crate) fn write_container<R: BufRead + Seek, W: Write>(
+    mp4: &mut Mp4<'_, R>,
+    writer: &mut W,
+    header: Header,
+    header_len: u64,
+    children: &[ChildAtom],
+) -> Mp4Result<()> {
+    let body_len = children.iter().map(ChildAtom::len).sum();
+
+    write_atom_header(writer, header, body_len, header_len == 16)?;
+
+    for child in children {
+        match child {
+            ChildAtom::Source(atom) => mp4.copy_range(atom.offset, atom.len, writer)?,
+            ChildAtom::Serialized(bytes) => writer.write_all(bytes)?,
+        }
+    }
+
+    Ok(())
+}
+
+impl<R: BufRead + Seek> Mp4<'_, R> {
+    /// Copies `len` bytes starting at `offset` of the file to `writer`
+    pub(crate) fn copy_range<W: Write>(
+        &mut self,
+        offset: u64,
+        len: u64,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        self.jump_to(offset)?;
+
+        let copied = io::copy(&mut (&mut self.reader.buffer).take(len), writer)?;
+        if copied != len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        Ok(())
+    }
+}
+
+impl<P: Parse> Serialize for Reference<P> {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        mp4.copy_range(self.offset, self.len, writer)
+    }
+}
+
+impl Serialize for UnparsedAtom {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        mp4.copy_range(self.offset, self.len, writer)
+    }
+}
+
+impl<T: Serialize> Serialize for Vec<T> {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        for item in self {
+            item.serialize(mp4, writer)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Serialize for String {
+    fn serialize<R: Seek + BufRead, W: Write>(
+        &self,
+        _mp4: &mut Mp4<'_, R>,
+        writer: &mut W,
+    ) -> Mp4Result<()> {
+        Ok(writer.write_all(self.as_bytes())?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use crate::{test_util::atom, Esds, Moov, Mp4, Reference, Serialize, Stts, Trak};
+
+    #[test]
+    fn round_trips_unmodified_atoms() {
+        let mut stts = vec![0; 4];
+        stts.extend([0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 2, 0, 0, 0, 0, 1, 0, 0, 1, 0]);
+        let stts = atom(b"stts", &stts);
+
+        let mut trak = atom(b"tkhd", &[0; 84]);
+        trak.extend(atom(b"free", b"padding"));
+        let trak = atom(b"trak", &trak);
+
+        // a container with a 64-bit size it doesn't need
+        let mut moov = 1u32.to_be_bytes().to_vec();
+        moov.extend(b"moov");
+        moov.extend((16 + trak.len() as u64 + stts.len() as u64).to_be_bytes());
+        moov.extend(&trak);
+        moov.extend(&stts);
+
+        let mut mp4 = Mp4::new(Cursor::new(moov.clone()));
+
+        let parsed = Reference::<Moov>::new(0, moov.len() as u64)
+            .parse(&mut mp4)
+            .unwrap();
+        let mut written = Vec::new();
+        parsed.serialize(&mut mp4, &mut written).unwrap();
+        assert_eq!(written, moov);
+
+        let stts_offset = 16 + trak.len() as u64;
+        let parsed = Reference::<Stts>::new(stts_offset, stts.len() as u64)
+            .parse(&mut mp4)
+            .unwrap();
+        let mut written = Vec::new();
+        parsed.serialize(&mut mp4, &mut written).unwrap();
+        assert_eq!(written, stts);
+    }
+
+    #[test]
+    fn writes_edited_children() {
+        let mut trak = atom(b"tkhd", &[0; 84]);
+        trak.extend(atom(b"free", b"padding"));
+        let trak = atom(b"trak", &trak);
+
+        let mut mp4 = Mp4::new(Cursor::new(trak.clone()));
+
+        let mut parsed = Reference::<Trak>::new(0, trak.len() as u64)
+            .parse(&mut mp4)
+            .unwrap();
+        let tkhd = *parsed.track_header(&mut mp4).unwrap();
+        let mut track_header = tkhd.parse(&mut mp4).unwrap();
+        track_header.track_id = 7;
+        assert!(parsed.replace_child(tkhd, &track_header, &mut mp4).unwrap());
+
+        let mut written = Vec::new();
+        parsed.serialize(&mut mp4, &mut written).unwrap();
+        assert_eq!(written.len(), trak.len());
+
+        let mut mp4 = Mp4::new(Cursor::new(written.clone()));
+        let mut parsed = Reference::<Trak>::new(0, written.len() as u64)
+            .parse(&mut mp4)
+            .unwrap();
+        let track_header = parsed.track_header(&mut mp4).unwrap().parse(&mut mp4);
+        assert_eq!(track_header.unwrap().track_id, 7);
+    }
+
+    #[test]
+    fn round_trips_esds() {
+        let esds = atom(
+            b"esds",
+            &[
+                0, 0, 0, 0, 0x03, 0x19, 0, 1, 0, 0x04, 0x11, 0x40, 0x15, 0, 0, 0, 0, 1, 0xf4, 0, 0,
+                1, 0xf4, 0, 0x05, 0x02, 0x12, 0x10, 0x06, 0x01, 0x02,
+            ],
+        );
+
+        let mut mp4 = Mp4::new(Cursor::new(esds.clone()));
+        let parsed = Reference::<Esds>::new(0, esds.len() as u64)
+            .parse(&mut mp4)
+            .unwrap();
+
+        let mut written = Vec::new();
+        parsed.serialize(&mut mp4, &mut written).unwrap();
+        assert_eq!(written, esds);
+    }
+
+    #[test]
+    fn round_trips_esds_with_padded_sizes() {
+        // sizes padded to four bytes, as many encoders write them
+        let descriptor = |tag: u8, body: &[u8]| {
+            let mut descriptor = vec![tag, 0x80, 0x80, 0x80, body.len() as u8];
+            descriptor.extend(body);
+            descriptor
+        };
+
+        let mut decoder_config = vec![0x40, 0x15, 0, 0, 0, 0, 1, 0xf4, 0, 0, 1, 0xf4, 0];
+        decoder_config.extend(descriptor(0x05, &[0x12, 0x10]));
+
+        let mut es = vec![0, 1, 0];
+        es.extend(descriptor(0x04, &decoder_config));
+        es.extend(descriptor(0x06, &[0x02]));
+        // a language descriptor, which isn't decoded
+        es.extend([0x0a, 0x03, b'e', b'n', b'g']);
+
+        let mut body = vec![0; 4];
+        body.extend(descriptor(0x03, &es));
+        let esds = atom(b"esds", &body);
+
+        let mut mp4 = Mp4::new(Cursor::new(esds.clone()));
+        let parsed = Reference::<Esds>::new(0, esds.len() as u64)
+            .parse(&mut mp4)
+            .unwrap();
+        assert_eq!(parsed.es_descriptor.other_descriptors.len(), 1);
+        assert_eq!(
+            parsed.audio_specific_config().unwrap().sampling_frequency,
+            44100
+        );
+
+        let mut written = Vec::new();
+        parsed.serialize(&mut mp4, &mut written).unwrap();
+        assert_eq!(written, esds);
+    }
+}
@@ -0,0 +1,94 @@
+use std::{
+    env,
+    fs::File,
+    io::{self, BufReader, Write},
+    process::ExitCode,
+};
+
+use mp4_parser::Mp4;
+use path::PathFilter;
+
+mod path;
+mod render;
+mod tree;
+
+const USAGE: &str = "\
+usage: mp4dump [--json] [--path <path>] <file>
+
+Prints the atom tree of an MP4 or QuickTime file, with the offset and size of
+each atom and either its parsed fields or a hex preview of its body.
+
+options:
+    --json           print the tree as JSON
+    --path <path>    print only the atoms at the path, e.g.
+                     moov/trak[1]/mdia/minf/stbl/stsd, where an index picks
+                     one of several atoms of the same type, counting from 0
+    -h, --help       print this message";
+
+struct Options {
+    json: bool,
+    path: Option<PathFilter>,
+    file: String,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut json = false;
+    let mut path = None;
+    let mut file = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--path" => {
+                let value = args.next().ok_or("`--path` needs a value")?;
+                path = Some(value.parse()?);
+            }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            arg if arg.starts_with('-') => return Err(format!("unknown option `{}`", arg)),
+            _ if file.is_some() => return Err("only one file can be dumped at a time".into()),
+            _ => file = Some(arg),
+        }
+    }
+
+    Ok(Options {
+        json,
+        path,
+        file: file.ok_or_else(|| USAGE.to_string())?,
+    })
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let file = File::open(&options.file).map_err(|e| format!("{}: {}", options.file, e))?;
+    let mut mp4 = Mp4::new(BufReader::new(file));
+
+    let (tree, error) = tree::read_tree(&mut mp4);
+    let nodes = match &options.path {
+        Some(path) => path.select(&tree),
+        None => tree.iter().collect(),
+    };
+
+    let out = match options.json {
+        true => render::json(&nodes),
+        false => render::text(&nodes),
+    };
+
+    io::stdout()
+        .lock()
+        .write_all(out.as_bytes())
+        .map_err(|e| e.to_string())?;
+
+    match error {
+        Some(e) => Err(format!("{}: {}", options.file, e)),
+        None => Ok(()),
+    }
+}
+
+fn main() -> ExitCode {
+    match parse_args(env::args().skip(1)).and_then(run) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
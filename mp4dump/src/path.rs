@@ -0,0 +1,132 @@
+use std::str::FromStr;
+
+use crate::tree::Node;
+
+/// Selects atoms by their path from the top level of the file, e.g.
+/// `moov/trak[1]/mdia/minf/stbl/stsd`. An index picks one of the atoms of that
+/// type under the same parent, counting from zero, while a segment without one
+/// matches every atom of that type
+#[derive(Debug, PartialEq, Eq)]
+pub struct PathFilter(Vec<Segment>);
+
+#[derive(Debug, PartialEq, Eq)]
+struct Segment {
+    header: String,
+    index: Option<usize>,
+}
+
+impl FromStr for PathFilter {
+    type Err = String;
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        path.trim_matches('/')
+            .split('/')
+            .map(|segment| {
+                let (header, index) = match segment.strip_suffix(']') {
+                    Some(rest) => {
+                        let (header, index) = rest
+                            .split_once('[')
+                            .ok_or_else(|| format!("unmatched `]` in `{}`", segment))?;
+                        let index = index
+                            .parse()
+                            .map_err(|_| format!("invalid index in `{}`", segment))?;
+
+                        (header, Some(index))
+                    }
+                    None => (segment, None),
+                };
+
+                if header.chars().count() != 4 {
+                    return Err(format!("`{}` is not a four-character atom type", header));
+                }
+
+                Ok(Segment {
+                    header: header.to_string(),
+                    index,
+                })
+            })
+            .collect::<Result<_, _>>()
+            .map(PathFilter)
+    }
+}
+
+impl PathFilter {
+    /// The atoms of the tree at the end of the path, in file order
+    pub fn select<'a>(&self, nodes: &'a [Node]) -> Vec<&'a Node> {
+        let mut selected = Vec::new();
+        select(&self.0, nodes, &mut selected);
+        selected
+    }
+}
+
+fn select<'a>(segments: &[Segment], nodes: &'a [Node], selected: &mut Vec<&'a Node>) {
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+
+    let matches = nodes
+        .iter()
+        .filter(|node| node.header.to_string() == segment.header)
+        .enumerate()
+        .filter(|(idx, _)| segment.index.is_none_or(|index| index == *idx))
+        .map(|(_, node)| node);
+
+    for node in matches {
+        match rest.is_empty() {
+            true => selected.push(node),
+            false => select(rest, &node.children, selected),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mp4_parser::Header;
+
+    use super::*;
+
+    fn node(header: &[u8; 4], offset: u64, children: Vec<Node>) -> Node {
+        Node {
+            header: Header::new(*header),
+            offset,
+            size: 0,
+            fields: None,
+            preview: None,
+            error: None,
+            children,
+        }
+    }
+
+    #[test]
+    fn selects_indexed_atoms() {
+        let stbl = |offset| node(b"minf", offset, vec![node(b"stbl", offset + 1, Vec::new())]);
+        let tree = [
+            node(b"ftyp", 0, Vec::new()),
+            node(
+                b"moov",
+                10,
+                vec![
+                    node(b"trak", 20, vec![stbl(30)]),
+                    node(b"udta", 40, Vec::new()),
+                    node(b"trak", 50, vec![stbl(60)]),
+                ],
+            ),
+        ];
+
+        let offsets = |path: &str| {
+            path.parse::<PathFilter>()
+                .unwrap()
+                .select(&tree)
+                .iter()
+                .map(|node| node.offset)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(offsets("moov/trak/minf/stbl"), [31, 61]);
+        assert_eq!(offsets("/moov/trak[1]/minf"), [60]);
+        assert_eq!(offsets("moov/trak[2]"), []);
+        assert!("moov/trak[x]".parse::<PathFilter>().is_err());
+        assert!("moov/tr".parse::<PathFilter>().is_err());
+    }
+}
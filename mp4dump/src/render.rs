@@ -0,0 +1,237 @@
+use std::fmt::Write;
+
+use crate::tree::Node;
+
+/// How many bytes each line of a hex preview shows
+const HEX_LINE_LEN: usize = 16;
+
+/// Writes the atoms as an indented tree, each with its parsed fields or a hex
+/// preview of its body
+pub fn text(nodes: &[&Node]) -> String {
+    let mut out = String::new();
+
+    for node in nodes {
+        text_node(node, 0, &mut out);
+    }
+
+    out
+}
+
+fn text_node(node: &Node, depth: usize, out: &mut String) {
+    let indent = "    ".repeat(depth);
+
+    writeln!(
+        out,
+        "{}[{}] offset={} size={}",
+        indent, node.header, node.offset, node.size
+    )
+    .unwrap();
+
+    if let Some(error) = &node.error {
+        writeln!(out, "{}  error: {}", indent, error).unwrap();
+    }
+
+    if let Some(fields) = &node.fields {
+        for line in fold_scalar_lists(&format!("{:#?}", fields)).lines() {
+            writeln!(out, "{}  {}", indent, line).unwrap();
+        }
+    }
+
+    if let Some(preview) = &node.preview {
+        for (idx, line) in preview.chunks(HEX_LINE_LEN).enumerate() {
+            let hex = line
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let ascii = line
+                .iter()
+                .map(|&byte| match byte.is_ascii_graphic() || byte == b' ' {
+                    true => byte as char,
+                    false => '.',
+                })
+                .collect::<String>();
+
+            writeln!(
+                out,
+                "{}  {:04x}  {:<width$}  |{}|",
+                indent,
+                idx * HEX_LINE_LEN,
+                hex,
+                ascii,
+                width = HEX_LINE_LEN * 3 - 1
+            )
+            .unwrap();
+        }
+    }
+
+    for child in &node.children {
+        text_node(child, depth + 1, out);
+    }
+}
+
+/// Puts each list of the pretty `Debug` output that holds only scalars, such
+/// as a byte array or a table of offsets, on a single line
+fn fold_scalar_lists(pretty: &str) -> String {
+    let mut out = String::new();
+    let mut lines = pretty.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        if !line.ends_with('[') {
+            writeln!(out, "{}", line).unwrap();
+            continue;
+        }
+
+        let indent = line.len() - line.trim_start().len();
+        let mut items = Vec::new();
+        let mut end = None;
+
+        while let Some(&item) = lines.peek() {
+            let trimmed = item.trim_start();
+
+            if item.len() - trimmed.len() == indent && trimmed.starts_with(']') {
+                end = Some(trimmed);
+                lines.next();
+                break;
+            }
+            if trimmed.ends_with(['[', '{', '(']) {
+                break;
+            }
+
+            items.push(trimmed.trim_end_matches(','));
+            lines.next();
+        }
+
+        match end {
+            Some(end) => writeln!(out, "{}{}{}", line, items.join(", "), end).unwrap(),
+            // the list holds more than scalars, so its lines are kept as they are
+            None => {
+                writeln!(out, "{}", line).unwrap();
+                for item in items {
+                    writeln!(out, "{}{},", " ".repeat(indent + 4), item).unwrap();
+                }
+            }
+        }
+    }
+
+    out
+}
+
+/// Writes the atoms as a JSON array of objects with `type`, `offset` and
+/// `size`, along with `fields` holding the `Debug` formatting of a parsed atom,
+/// `preview` holding the hex of the start of an unparsed atom's body, `error`
+/// and `children`, each only where there is one
+pub fn json(nodes: &[&Node]) -> String {
+    let mut out = String::new();
+
+    out.push('[');
+    for (idx, node) in nodes.iter().enumerate() {
+        if idx > 0 {
+            out.push(',');
+        }
+        json_node(node, &mut out);
+    }
+    out.push_str("]\n");
+
+    out
+}
+
+fn json_node(node: &Node, out: &mut String) {
+    write!(
+        out,
+        "{{\"type\":{},\"offset\":{},\"size\":{}",
+        json_string(&node.header.to_string()),
+        node.offset,
+        node.size
+    )
+    .unwrap();
+
+    if let Some(fields) = &node.fields {
+        write!(out, ",\"fields\":{}", json_string(&format!("{:?}", fields))).unwrap();
+    }
+
+    if let Some(preview) = &node.preview {
+        let hex = preview
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        write!(out, ",\"preview\":\"{}\"", hex).unwrap();
+    }
+
+    if let Some(error) = &node.error {
+        write!(out, ",\"error\":{}", json_string(error)).unwrap();
+    }
+
+    if !node.children.is_empty() {
+        out.push_str(",\"children\":[");
+        for (idx, child) in node.children.iter().enumerate() {
+            if idx > 0 {
+                out.push(',');
+            }
+            json_node(child, out);
+        }
+        out.push(']');
+    }
+
+    out.push('}');
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use mp4_parser::Header;
+
+    use super::*;
+
+    #[test]
+    fn writes_json_with_escaped_strings() {
+        let child = Node {
+            header: Header::new(*b"abcd"),
+            offset: 8,
+            size: 10,
+            fields: Some(Box::new("a\tb")),
+            preview: Some(vec![0xde, 0xad]),
+            error: None,
+            children: Vec::new(),
+        };
+        let root = Node {
+            header: Header::new(*b"moov"),
+            offset: 0,
+            size: 18,
+            fields: None,
+            preview: None,
+            error: Some("bad \"size\"\n\\ \u{1}".to_string()),
+            children: vec![child],
+        };
+
+        assert_eq!(
+            json(&[&root]),
+            concat!(
+                r#"[{"type":"moov","offset":0,"size":18,"error":"bad \"size\"\n\\ \u0001","#,
+                r#""children":[{"type":"abcd","offset":8,"size":10,"fields":"\"a\\tb\"","#,
+                r#""preview":"dead"}]}]"#,
+                "\n"
+            )
+        );
+        assert_eq!(json(&[]), "[]\n");
+    }
+}
@@ -0,0 +1,377 @@
+use std::{
+    fmt,
+    io::{BufRead, Seek, SeekFrom},
+    ops::Range,
+};
+
+use mp4_parser::*;
+
+/// How many bytes of the body of an unrecognised atom are shown
+const PREVIEW_LEN: u64 = 64;
+
+/// Atoms that hold nothing but other atoms
+const CONTAINERS: &[[u8; 4]] = &[
+    *b"moov", *b"trak", *b"mdia", *b"minf", *b"stbl", *b"dinf", *b"edts", *b"udta", *b"mvex",
+    *b"moof", *b"traf", *b"mfra", *b"gmhd", *b"rmra", *b"rmda", *b"tapt", *b"matt", *b"imap",
+    *b"sinf", *b"schi",
+];
+
+/// An atom of the file along with everything the dump shows about it
+pub struct Node {
+    pub header: Header,
+    pub offset: u64,
+    pub size: u64,
+    /// The parsed atom, for atoms this crate understands
+    pub fields: Option<Box<dyn fmt::Debug>>,
+    /// The first bytes of the body of an atom that couldn't be parsed
+    pub preview: Option<Vec<u8>>,
+    pub error: Option<String>,
+    pub children: Vec<Node>,
+}
+
+/// Reads the atom tree of the whole file. If the top level of the file can't
+/// be read to the end, the atoms before the error are returned along with it
+pub fn read_tree<R: BufRead + Seek>(mp4: &mut Mp4<'_, R>) -> (Vec<Node>, Option<Mp4Error>) {
+    let mut walker = Walker { handler: None };
+    let mut nodes = Vec::new();
+
+    let result = mp4
+        .reader
+        .buffer
+        .seek(SeekFrom::End(0))
+        .map_err(Mp4Error::from)
+        .and_then(|end| walker.scan(mp4, 0..end, None, &mut nodes));
+
+    (nodes, result.err())
+}
+
+struct Walker {
+    /// The handler type of the current track's media, which determines how
+    /// its sample entries are parsed
+    handler: Option<[u8; 4]>,
+}
+
+impl Walker {
+    fn node<R: BufRead + Seek>(
+        &mut self,
+        mp4: &mut Mp4<'_, R>,
+        atom: UnparsedAtom,
+        parent: Option<[u8; 4]>,
+    ) -> Node {
+        let mut node = Node {
+            header: atom.header,
+            offset: atom.offset,
+            size: atom.len,
+            fields: None,
+            preview: None,
+            error: None,
+            children: Vec::new(),
+        };
+
+        let fourcc = atom.header.fourcc();
+
+        // a track without a handler of its own mustn't have its sample entries
+        // parsed by the handler of the track before it
+        if fourcc == *b"trak" {
+            self.handler = None;
+        }
+
+        let result = match &fourcc {
+            fourcc if CONTAINERS.contains(fourcc) => {
+                self.children(mp4, &atom, 0, &mut node.children)
+            }
+            // an ISO metadata atom is a full atom, while a QuickTime one isn't
+            b"meta" => meta_prefix(mp4, &atom)
+                .and_then(|prefix| self.children(mp4, &atom, prefix, &mut node.children)),
+            b"stsd" => self.sample_entries(mp4, &atom, &mut node.children),
+            _ => match describe(mp4, &atom) {
+                Some(fields) => fields.map(|fields| node.fields = Some(fields)),
+                None => preview(mp4, &atom).map(|preview| node.preview = Some(preview)),
+            },
+        };
+
+        if fourcc == *b"hdlr" && parent == Some(*b"mdia") {
+            self.handler = atom
+                .clone()
+                .into_ref::<Hdlr>()
+                .parse(mp4)
+                .ok()
+                .map(|hdlr| hdlr.component_subtype);
+        }
+
+        if let Err(e) = result {
+            node.error = Some(e.to_string());
+
+            if node.fields.is_none() && node.children.is_empty() {
+                node.preview = preview(mp4, &atom).ok();
+            }
+        }
+
+        node
+    }
+
+    /// Reads the children of a container atom, whose body begins with
+    /// `prefix` bytes of other fields. Children read before any error are kept
+    fn children<R: BufRead + Seek>(
+        &mut self,
+        mp4: &mut Mp4<'_, R>,
+        atom: &UnparsedAtom,
+        prefix: u64,
+        nodes: &mut Vec<Node>,
+    ) -> Mp4Result<()> {
+        let start = atom.offset + header_len(mp4, atom)? + prefix;
+
+        self.scan(
+            mp4,
+            start..atom.offset + atom.len,
+            Some(atom.header.fourcc()),
+            nodes,
+        )
+    }
+
+    /// Reads the atoms that lie one after another within `range`
+    fn scan<R: BufRead + Seek>(
+        &mut self,
+        mp4: &mut Mp4<'_, R>,
+        range: Range<u64>,
+        parent: Option<[u8; 4]>,
+        nodes: &mut Vec<Node>,
+    ) -> Mp4Result<()> {
+        let mut atoms = Vec::new();
+        let result = child_atoms(mp4, range, &mut atoms);
+
+        for atom in atoms {
+            nodes.push(self.node(mp4, atom, parent));
+        }
+
+        attach_error(nodes, result)
+    }
+
+    /// Reads the entries of a sample description atom, each parsed according
+    /// to the handler of the track's media
+    fn sample_entries<R: BufRead + Seek>(
+        &mut self,
+        mp4: &mut Mp4<'_, R>,
+        atom: &UnparsedAtom,
+        nodes: &mut Vec<Node>,
+    ) -> Mp4Result<()> {
+        // version, flags and number of entries
+        let start = atom.offset + header_len(mp4, atom)? + 8;
+        let mut entries = Vec::new();
+        let result = child_atoms(mp4, start..atom.offset + atom.len, &mut entries);
+
+        for entry in entries {
+            let mut node = Node {
+                header: entry.header,
+                offset: entry.offset,
+                size: entry.len,
+                fields: None,
+                preview: None,
+                error: None,
+                children: Vec::new(),
+            };
+
+            match self.handler {
+                Some(handler) => {
+                    match mp4.parse_sample_description(entry.clone().into_ref(), handler) {
+                        Ok(description) => node.fields = Some(Box::new(description)),
+                        Err(e) => {
+                            node.error = Some(e.to_string());
+                            node.preview = preview(mp4, &entry).ok();
+                        }
+                    }
+                }
+                None => node.preview = preview(mp4, &entry).ok(),
+            }
+
+            nodes.push(node);
+        }
+
+        attach_error(nodes, result)
+    }
+}
+
+macro_rules! describe {
+    ($mp4:ident, $atom:ident, $($ty:ty),* $(,)?) => {
+        $(
+            if $atom.header == <$ty>::HEADER {
+                return Some(
+                    $atom
+                        .clone()
+                        .into_ref::<$ty>()
+                        .parse($mp4)
+                        .map(|atom| Box::new(atom) as Box<dyn fmt::Debug>),
+                );
+            }
+        )*
+    };
+}
+
+/// Parses an atom that isn't a container, if this crate understands it
+fn describe<R: BufRead + Seek>(
+    mp4: &mut Mp4<'_, R>,
+    atom: &UnparsedAtom,
+) -> Option<Mp4Result<Box<dyn fmt::Debug>>> {
+    describe!(
+        mp4, atom, Ftyp, Styp, Pdin, Uuid, Mdat, Mvhd, Tkhd, Tref, Elst, Mdhd, Elng, Hdlr, Vmhd,
+        Smhd, Gmin, Text, Dref, Stts, Ctts, Cslg, Stss, Stps, Stsc, Stsz, Stsh, Stco, Co64, Sgpd,
+        Sbgp, Sdtp, Clip, Crgn, Kmat, Ctab, Cmov, Rdrf, Rmdr, Rmcs, Rmvc, Rmcd, Rmqu, Clef, Prof,
+        Enof, Load, Txas, Keys, Ilst, Chpl, Mehd, Trex, Mfhd, Tfhd, Tfdt, Trun, Sidx, Tfra, Mfro,
+    );
+
+    None
+}
+
+/// Scans the atoms that lie one after another within `range`, without parsing
+/// them. An atom running past the end of the range is included before the
+/// error
+fn child_atoms<R: BufRead + Seek>(
+    mp4: &mut Mp4<'_, R>,
+    range: Range<u64>,
+    atoms: &mut Vec<UnparsedAtom>,
+) -> Mp4Result<()> {
+    let mut offset = range.start;
+
+    while offset < range.end {
+        mp4.jump_to(offset)?;
+        let atom = UnparsedAtom::parse(mp4)?;
+        let truncated = atom.offset + atom.len > range.end;
+
+        offset = atom.offset + atom.len;
+        atoms.push(atom.clone());
+
+        if truncated {
+            return Err(Mp4Error::TruncatedAtom {
+                offset: atom.offset,
+                path: AtomPath(Vec::new()),
+                header: atom.header,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Records an error about the last of `nodes` on that node, leaving any other
+/// error to be recorded on their parent
+fn attach_error(nodes: &mut [Node], result: Mp4Result<()>) -> Mp4Result<()> {
+    match (result, nodes.last_mut()) {
+        (Err(e), Some(last)) if e.offset() == Some(last.offset) => {
+            last.error = Some(e.to_string());
+            Ok(())
+        }
+        (result, _) => result,
+    }
+}
+
+/// The size of the atom's header, which is larger if it has a 64-bit size
+fn header_len<R: BufRead + Seek>(mp4: &mut Mp4<'_, R>, atom: &UnparsedAtom) -> Mp4Result<u64> {
+    mp4.jump_to(atom.offset)?;
+
+    Ok(match mp4.reader.read_u32()? {
+        1 => 16,
+        _ => 8,
+    })
+}
+
+/// The length of the version and flags preceding the children of a `meta`
+/// atom, which only ISO metadata atoms have. Where a QuickTime atom has the
+/// size of its first child, an ISO one has a version and flags of zero
+fn meta_prefix<R: BufRead + Seek>(mp4: &mut Mp4<'_, R>, atom: &UnparsedAtom) -> Mp4Result<u64> {
+    let header_len = header_len(mp4, atom)?;
+    mp4.jump_to(atom.offset + header_len)?;
+
+    Ok(match mp4.reader.read_u32()? {
+        0 => 4,
+        _ => 0,
+    })
+}
+
+/// The first bytes of the atom's body
+fn preview<R: BufRead + Seek>(mp4: &mut Mp4<'_, R>, atom: &UnparsedAtom) -> Mp4Result<Vec<u8>> {
+    let header_len = header_len(mp4, atom)?;
+    let len = atom.len.saturating_sub(header_len).min(PREVIEW_LEN);
+    mp4.jump_to(atom.offset + header_len)?;
+
+    Ok(mp4.reader.read_bytes_dyn(len as usize)?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn atom(header: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut atom = (body.len() as u32 + 8).to_be_bytes().to_vec();
+        atom.extend(header);
+        atom.extend(body);
+        atom
+    }
+
+    /// A track holding one sound sample entry, with a `hdlr` atom saying so
+    /// if `handler` is set
+    fn track(handler: bool) -> Vec<u8> {
+        let mut sowt = vec![0; 6];
+        sowt.extend(1u16.to_be_bytes());
+        sowt.extend([0; 8]);
+        sowt.extend(2u16.to_be_bytes());
+        sowt.extend(16u16.to_be_bytes());
+        sowt.extend([0; 4]);
+        sowt.extend((44_100u32 << 16).to_be_bytes());
+
+        let mut stsd = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stsd.extend(atom(b"sowt", &sowt));
+
+        let mut mdia = Vec::new();
+        if handler {
+            let mut hdlr = vec![0; 8];
+            hdlr.extend(b"soun");
+            hdlr.extend([0; 13]);
+            mdia.extend(atom(b"hdlr", &hdlr));
+        }
+        mdia.extend(atom(b"minf", &atom(b"stbl", &atom(b"stsd", &stsd))));
+
+        atom(b"trak", &atom(b"mdia", &mdia))
+    }
+
+    /// The node reached by following the first child of each type in `path`
+    fn descendant<'a>(mut node: &'a Node, path: &[&[u8; 4]]) -> &'a Node {
+        for header in path {
+            node = node
+                .children
+                .iter()
+                .find(|child| child.header.fourcc() == **header)
+                .unwrap();
+        }
+        node
+    }
+
+    #[test]
+    fn reads_the_atom_tree() {
+        let mut file = atom(b"moov", &[track(true), track(false)].concat());
+        file.extend(atom(b"abcd", b"hello"));
+
+        let mut mp4 = Mp4::new(Cursor::new(file));
+        let (nodes, error) = read_tree(&mut mp4);
+        assert!(error.is_none());
+
+        let headers = nodes
+            .iter()
+            .map(|node| (node.header.fourcc(), node.offset, node.size))
+            .collect::<Vec<_>>();
+        assert_eq!(headers, [(*b"moov", 0, 209), (*b"abcd", 209, 13)]);
+
+        // sample entries are parsed by the handler of their own track only
+        let stsd = [b"mdia", b"minf", b"stbl", b"stsd"];
+        let sound = &descendant(&nodes[0].children[0], &stsd).children[0];
+        assert!(format!("{:?}", sound.fields.as_ref().unwrap()).starts_with("Sound("));
+        let unknown = &descendant(&nodes[0].children[1], &stsd).children[0];
+        assert!(unknown.fields.is_none());
+        assert_eq!(unknown.preview.as_ref().unwrap().len(), 28);
+
+        assert!(nodes[1].fields.is_none());
+        assert_eq!(nodes[1].preview.as_deref(), Some(&b"hello"[..]));
+        assert!(nodes[1].error.is_none());
+    }
+}